@@ -0,0 +1,186 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Resolution of conflicting arguments.
+
+LLVM programs generally implement *last wins* semantics for options that
+override each other. e.g. `clang -O0 -O2` compiles with `-O2` and
+`clang -fno-PIC -fPIC` compiles with `-fPIC`.
+
+This module groups options into *families* of mutually exclusive options
+and resolves which parsed argument in each family is effective.
+ */
+
+use {
+    crate::{CommandOptions, OptionKind, ParsedArgument, ParsedArguments, ProgramOption},
+    std::collections::{BTreeMap, HashSet},
+};
+
+/// Clang option groups whose members override each other.
+///
+/// Members of descendant groups are part of the same family. e.g. `-g0` is
+/// in `gN_Group`, which is a child of `g_Group`.
+pub const CLANG_EXCLUSIVE_GROUPS: &[&str] =
+    &["Action_Group", "LongDouble_Group", "O_Group", "g_Group"];
+
+/// A family of mutually exclusive options.
+///
+/// Only the last argument belonging to a family is effective.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum OptionFamily {
+    /// Options that are members of a mutually exclusive option group.
+    ///
+    /// The inner value is the internal name of the group.
+    Group(String),
+
+    /// A flag and its `no-` negated counterpart.
+    ///
+    /// The inner value is the argument name of the positive flag. e.g. `fPIC`.
+    Negatable(String),
+}
+
+/// Resolves the [OptionFamily] of options for a given [CommandOptions].
+pub struct ConflictResolver<'a> {
+    options: &'a CommandOptions,
+    exclusive_groups: HashSet<&'a str>,
+    flag_names: HashSet<&'a str>,
+}
+
+impl<'a> ConflictResolver<'a> {
+    /// Construct an instance treating the given groups as mutually exclusive.
+    pub fn new(options: &'a CommandOptions, exclusive_groups: &[&'a str]) -> Self {
+        let flag_names = options
+            .iter_options()
+            .filter(|option| option.kind == OptionKind::Flag)
+            .map(|option| option.name.as_str())
+            .collect::<HashSet<_>>();
+
+        Self {
+            options,
+            exclusive_groups: exclusive_groups.iter().copied().collect(),
+            flag_names,
+        }
+    }
+
+    /// Construct an instance using clang's mutually exclusive groups.
+    pub fn clang(options: &'a CommandOptions) -> Self {
+        Self::new(options, CLANG_EXCLUSIVE_GROUPS)
+    }
+
+    /// Resolve the canonical option for an option that may be an alias.
+    fn canonical<'b>(&'b self, option: &'b ProgramOption) -> &'b ProgramOption {
+        if let Some(alias) = &option.alias {
+            self.options
+                .iter_options()
+                .find(|candidate| &candidate.option_name == alias)
+                .unwrap_or(option)
+        } else {
+            option
+        }
+    }
+
+    /// Obtain the [OptionFamily] an option belongs to, if any.
+    ///
+    /// Aliases are resolved to their canonical option before evaluation.
+    /// Membership in an exclusive group takes precedence over negation pairs.
+    /// When multiple ancestor groups are exclusive, the outermost one wins.
+    pub fn option_family(&self, option: &ProgramOption) -> Option<OptionFamily> {
+        let option = self.canonical(option);
+
+        if let Some(group) = &option.group {
+            if let Some(group) = self
+                .options
+                .group_ancestors(group)
+                .into_iter()
+                .rev()
+                .find(|name| self.exclusive_groups.contains(name))
+            {
+                return Some(OptionFamily::Group(group.to_string()));
+            }
+        }
+
+        if option.kind != OptionKind::Flag {
+            return None;
+        }
+
+        let name = option.name.as_str();
+
+        // `no-` may appear at the beginning (`--no-gc-sections`) or after a
+        // single character namespace (`-fno-PIC`).
+        for pos in [0, 1] {
+            if !name.is_char_boundary(pos) {
+                continue;
+            }
+
+            let (head, tail) = name.split_at(pos);
+
+            if let Some(positive) = tail.strip_prefix("no-") {
+                let positive = format!("{}{}", head, positive);
+
+                if self.flag_names.contains(positive.as_str()) {
+                    return Some(OptionFamily::Negatable(positive));
+                }
+            } else {
+                let negative = format!("{}no-{}", head, tail);
+
+                if self.flag_names.contains(negative.as_str()) {
+                    return Some(OptionFamily::Negatable(name.to_string()));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Resolve the effective argument for each [OptionFamily].
+    ///
+    /// Arguments not belonging to a family are ignored.
+    pub fn effective_arguments<'b>(
+        &self,
+        args: &'b ParsedArguments,
+    ) -> BTreeMap<OptionFamily, &'b ParsedArgument> {
+        let mut res = BTreeMap::new();
+
+        for arg in args.iter_parsed() {
+            if let Some(family) = arg.option().and_then(|o| self.option_family(o)) {
+                res.insert(family, arg);
+            }
+        }
+
+        res
+    }
+
+    /// Resolve the effective argument for a single [OptionFamily].
+    pub fn effective_argument<'b>(
+        &self,
+        args: &'b ParsedArguments,
+        family: &OptionFamily,
+    ) -> Option<&'b ParsedArgument> {
+        args.iter_parsed()
+            .filter(|arg| arg.option().and_then(|o| self.option_family(o)).as_ref() == Some(family))
+            .last()
+    }
+
+    /// Obtain arguments with overridden members of option families removed.
+    ///
+    /// Arguments not belonging to a family are always retained. Relative
+    /// ordering of arguments is preserved.
+    pub fn without_overridden<'b>(&self, args: &'b ParsedArguments) -> Vec<&'b ParsedArgument> {
+        let families = args
+            .iter_parsed()
+            .map(|arg| arg.option().and_then(|o| self.option_family(o)))
+            .collect::<Vec<_>>();
+
+        args.iter_parsed()
+            .enumerate()
+            .filter(|(i, _)| match &families[*i] {
+                Some(family) => !families[i + 1..]
+                    .iter()
+                    .any(|other| other.as_ref() == Some(family)),
+                None => true,
+            })
+            .map(|(_, arg)| arg)
+            .collect::<Vec<_>>()
+    }
+}
@@ -25,10 +25,16 @@ into structs that convey the meaning of each invocation, such as whether we're
 invoking a compiler, linker, etc.
  */
 
+mod conflict;
+pub use conflict::*;
 mod llvm;
 pub use llvm::*;
 
-use {once_cell::sync::Lazy, std::collections::BTreeMap, thiserror::Error};
+use {
+    once_cell::sync::Lazy,
+    std::{borrow::Cow, collections::BTreeMap, sync::RwLock},
+    thiserror::Error,
+};
 
 const CLANG_13_JSON: &[u8] = include_bytes!("tablegen/llvm-13/clang.json");
 const DSYMUTIL_13_JSON: &[u8] = include_bytes!("tablegen/llvm-13/dsymutil.json");
@@ -51,31 +57,43 @@ const LLVM_SIZE_13_JSON: &[u8] = include_bytes!("tablegen/llvm-13/llvm-size.json
 const LLVM_STRINGS_13_JSON: &[u8] = include_bytes!("tablegen/llvm-13/llvm-strings.json");
 const LLVM_SYMBOLIZER_13_JSON: &[u8] = include_bytes!("tablegen/llvm-13/llvm-symbolizer.json");
 
-/// Raw tablegen JSON for commands in LLVM version 13.
-pub static LLVM_13_JSON: Lazy<BTreeMap<&str, &[u8]>> = Lazy::new(|| {
-    BTreeMap::from_iter([
-        ("clang", CLANG_13_JSON),
-        ("dsymutil", DSYMUTIL_13_JSON),
-        ("lld-coff", LLD_COFF_13_JSON),
-        ("lld-darwin-ld", LLD_DARWIN_LD_13_JSON),
-        ("lld-elf", LLD_ELF_13_JSON),
-        ("lld-macho", LLD_MACHO_13_JSON),
-        ("lld-mingw", LLD_MINGW_13_JSON),
-        ("lld-wasm", LLD_WASM_13_JSON),
-        ("llvm-cvtres", LLVM_CVTRES_13_JSON),
-        ("llvm-cxxfilt", LLVM_CXXFILT_13_JSON),
-        ("llvm-dlltool", LLVM_DLLTOOL_13_JSON),
-        ("llvm-lib", LLVM_LIB_13_JSON),
-        ("llvm-ml", LLVM_ML_13_JSON),
-        ("llvm-mt", LLVM_MT_13_JSON),
-        ("llvm-nm", LLVM_NM_13_JSON),
-        ("llvm-cxxfilt", LLVM_CXXFILT_13_JSON),
-        ("llvm-rc", LLVM_RC_13_JSON),
-        ("llvm-readobj", LLVM_READOBJ_13_JSON),
-        ("llvm-size", LLVM_SIZE_13_JSON),
-        ("llvm-strings", LLVM_STRINGS_13_JSON),
-        ("llvm-symbolizer", LLVM_SYMBOLIZER_13_JSON),
-    ])
+/// Tablegen JSON for commands shipped with the crate.
+///
+/// Entries are (LLVM major version, command name, JSON data).
+const BUILTIN_JSON: &[(u32, &str, &[u8])] = &[
+    (13, "clang", CLANG_13_JSON),
+    (13, "dsymutil", DSYMUTIL_13_JSON),
+    (13, "lld-coff", LLD_COFF_13_JSON),
+    (13, "lld-darwin-ld", LLD_DARWIN_LD_13_JSON),
+    (13, "lld-elf", LLD_ELF_13_JSON),
+    (13, "lld-macho", LLD_MACHO_13_JSON),
+    (13, "lld-mingw", LLD_MINGW_13_JSON),
+    (13, "lld-wasm", LLD_WASM_13_JSON),
+    (13, "llvm-cvtres", LLVM_CVTRES_13_JSON),
+    (13, "llvm-cxxfilt", LLVM_CXXFILT_13_JSON),
+    (13, "llvm-dlltool", LLVM_DLLTOOL_13_JSON),
+    (13, "llvm-lib", LLVM_LIB_13_JSON),
+    (13, "llvm-ml", LLVM_ML_13_JSON),
+    (13, "llvm-mt", LLVM_MT_13_JSON),
+    (13, "llvm-nm", LLVM_NM_13_JSON),
+    (13, "llvm-rc", LLVM_RC_13_JSON),
+    (13, "llvm-readobj", LLVM_READOBJ_13_JSON),
+    (13, "llvm-size", LLVM_SIZE_13_JSON),
+    (13, "llvm-strings", LLVM_STRINGS_13_JSON),
+    (13, "llvm-symbolizer", LLVM_SYMBOLIZER_13_JSON),
+];
+
+/// Tablegen JSON keyed by (LLVM major version, command name).
+type JsonRegistry = BTreeMap<(u32, String), Cow<'static, [u8]>>;
+
+/// Registry of tablegen JSON.
+///
+/// Seeded with built-in data. Additional entries can be added at run-time
+/// via [register_command_json].
+static REGISTRY: Lazy<RwLock<JsonRegistry>> = Lazy::new(|| {
+    RwLock::new(BTreeMap::from_iter(BUILTIN_JSON.iter().map(
+        |(version, command, data)| ((*version, command.to_string()), Cow::Borrowed(*data)),
+    )))
 });
 
 #[derive(Debug, Error)]
@@ -99,24 +117,78 @@ pub enum Error {
     AliasMissing(String, String),
 }
 
-/// Obtain [CommandOptions] for a named command in LLVM version 13.
+/// Obtain the (LLVM major version, command name) pairs with option tables available.
 ///
-/// Tablegen JSON data for LLVM commands is embedded in the crate and
-/// available to be parsed at run-time. Calling this function will trigger
-/// the parsing of this data for the given command.
-pub fn llvm_13_options(command: &str) -> Option<CommandOptions> {
-    if let Some(data) = LLVM_13_JSON.get(command) {
-        let cursor = std::io::Cursor::new(data);
+/// This includes built-in tables and tables registered via [register_command_json].
+pub fn commands() -> Vec<(u32, String)> {
+    REGISTRY
+        .read()
+        .expect("registry lock should not be poisoned")
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>()
+}
+
+/// Obtain LLVM major versions having at least 1 option table available.
+pub fn versions() -> Vec<u32> {
+    let mut versions = commands()
+        .into_iter()
+        .map(|(version, _)| version)
+        .collect::<Vec<_>>();
+    versions.dedup();
+
+    versions
+}
 
-        let options =
-            CommandOptions::from_json(cursor).expect("built-in JSON should parse successfully");
+/// Register tablegen JSON for a command.
+///
+/// The JSON is the output of `llvm-tblgen --dump-json` and is validated by
+/// parsing it before it is registered. Existing entries, including built-in
+/// ones, are replaced.
+pub fn register_command_json(
+    version: u32,
+    command: impl ToString,
+    data: impl Into<Vec<u8>>,
+) -> Result<(), Error> {
+    let data = data.into();
+
+    CommandOptions::from_json(std::io::Cursor::new(&data))?;
+
+    REGISTRY
+        .write()
+        .expect("registry lock should not be poisoned")
+        .insert((version, command.to_string()), Cow::Owned(data));
+
+    Ok(())
+}
 
-        Some(options)
+/// Obtain [CommandOptions] for a named command in a given LLVM version.
+///
+/// Returns `Ok(None)` if no option table is registered for the command.
+/// Calling this function will trigger the parsing of the tablegen JSON data.
+pub fn command_options(version: u32, command: &str) -> Result<Option<CommandOptions>, Error> {
+    let registry = REGISTRY
+        .read()
+        .expect("registry lock should not be poisoned");
+
+    if let Some(data) = registry.get(&(version, command.to_string())) {
+        Ok(Some(CommandOptions::from_json(std::io::Cursor::new(
+            data.as_ref(),
+        ))?))
     } else {
-        None
+        Ok(None)
     }
 }
 
+/// Obtain [CommandOptions] for a named command in LLVM version 13.
+///
+/// Tablegen JSON for LLVM commands is embedded in the crate and
+/// available to be parsed at run-time. Calling this function will trigger
+/// the parsing of this data for the given command.
+pub fn llvm_13_options(command: &str) -> Option<CommandOptions> {
+    command_options(13, command).expect("registered JSON should parse successfully")
+}
+
 /// Obtain LLVM option definitions for Clang version 13.
 pub fn clang_13_options() -> CommandOptions {
     llvm_13_options("clang").expect("clang options should be available")
@@ -124,17 +196,41 @@ pub fn clang_13_options() -> CommandOptions {
 
 #[cfg(test)]
 mod test {
-    use super::*;
+    use {super::*, std::ffi::OsStr};
 
     #[test]
     fn parse_all() {
-        for command in LLVM_13_JSON.keys() {
-            let options = llvm_13_options(command).unwrap();
+        for (version, command) in commands() {
+            let options = command_options(version, &command).unwrap().unwrap();
             options.options_by_group();
             options.options_by_flag();
         }
     }
 
+    #[test]
+    fn registry() -> Result<(), Error> {
+        assert!(versions().contains(&13));
+        assert!(commands().contains(&(13, "clang".to_string())));
+        assert_eq!(
+            commands()
+                .into_iter()
+                .filter(|(_, command)| command == "llvm-cxxfilt")
+                .count(),
+            1
+        );
+        assert!(command_options(13, "does-not-exist")?.is_none());
+
+        assert!(register_command_json(99, "bad", b"[]".to_vec()).is_err());
+        assert!(!versions().contains(&99));
+
+        register_command_json(99, "llvm-size", LLVM_SIZE_13_JSON)?;
+        assert!(versions().contains(&99));
+        assert!(commands().contains(&(99, "llvm-size".to_string())));
+        assert!(command_options(99, "llvm-size")?.is_some());
+
+        Ok(())
+    }
+
     #[test]
     fn clang_13() -> Result<(), Error> {
         let options = clang_13_options();
@@ -143,6 +239,19 @@ mod test {
         assert_eq!(options.options[1].option_name, "CC");
         assert_eq!(options.options.last().unwrap().option_name, "y");
 
+        let output = options
+            .iter_options()
+            .find(|o| o.option_name == "o")
+            .unwrap();
+        assert_eq!(output.help.as_deref(), Some("Write output to <file>"));
+        assert_eq!(
+            options
+                .aliases_of("o")
+                .map(|o| o.option_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["_output", "_output_EQ"]
+        );
+
         Ok(())
     }
 
@@ -203,4 +312,96 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn parse_iter() -> Result<(), Error> {
+        let options = clang_13_options();
+
+        let raw = [
+            "clang",
+            "-DDEBUG",
+            "-D",
+            "NDEBUG",
+            "-Xarch_arm64",
+            "-v",
+            "-pthread",
+        ];
+        let args = options.parse_iter(&raw).collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(args.len(), 5);
+        assert_eq!(args[0], ParsedArgumentRef::Positional(OsStr::new("clang")));
+        assert_eq!(args[1].name(), Some("D"));
+        assert_eq!(args[1].values(), vec!["DEBUG"]);
+        assert_eq!(args[2].name(), Some("D"));
+        assert_eq!(args[2].values(), vec!["NDEBUG"]);
+        assert!(matches!(
+            args[3],
+            ParsedArgumentRef::SingleValueKeyed(_, _, _)
+        ));
+        assert_eq!(args[3].values(), vec!["-v"]);
+        assert_eq!(args[4].name(), Some("pthread"));
+
+        // Values borrow from the input strings.
+        assert!(std::ptr::eq(args[2].values()[0], OsStr::new(raw[3])));
+
+        // Results are equivalent to owned parsing.
+        let owned = options.parse_arguments(raw)?;
+        assert_eq!(
+            args.iter()
+                .map(|arg| arg.to_owned_argument())
+                .collect::<Vec<_>>(),
+            owned.parsed
+        );
+
+        // Missing values are reported.
+        assert!(options.parse_iter(&["-D"]).next().unwrap().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn conflict_resolution() -> Result<(), Error> {
+        let options = clang_13_options();
+        let resolver = ConflictResolver::clang(&options);
+
+        let args = options
+            .parse_arguments(vec![
+                "-O0", "-fPIC", "-g", "-c", "-Ofast", "-fno-PIC", "-g0", "-pthread",
+            ])?
+            .resolve_aliases(&options)?;
+        let effective = resolver.effective_arguments(&args);
+
+        let o = &effective[&OptionFamily::Group("O_Group".into())];
+        assert_eq!(o.name(), Some("Ofast"));
+
+        let pic = &effective[&OptionFamily::Negatable("fPIC".into())];
+        assert_eq!(pic.name(), Some("fno_PIC"));
+
+        let g = &effective[&OptionFamily::Group("g_Group".into())];
+        assert_eq!(g.name(), Some("g0"));
+
+        let action = &effective[&OptionFamily::Group("Action_Group".into())];
+        assert_eq!(action.name(), Some("c"));
+
+        // `-no-pthread` negates `-pthread`.
+        let pthread = &effective[&OptionFamily::Negatable("pthread".into())];
+        assert_eq!(pthread.name(), Some("pthread"));
+
+        assert_eq!(effective.len(), 5);
+
+        assert_eq!(
+            resolver
+                .effective_argument(&args, &OptionFamily::Negatable("fPIC".into()))
+                .and_then(|arg| arg.name()),
+            Some("fno_PIC")
+        );
+
+        let names = resolver
+            .without_overridden(&args)
+            .into_iter()
+            .filter_map(|arg| arg.name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["c", "Ofast", "fno_PIC", "g0", "pthread"]);
+
+        Ok(())
+    }
 }
@@ -37,7 +37,7 @@ fn argument_matches_prefix<'a>(arg: &ProgramOption, s: &'a OsStr) -> Option<&'a
         let search = prefix.with_name(&arg.name);
 
         if s_bytes.starts_with(search.as_bytes()) {
-            return Some(OsStr::from_bytes(&s_bytes[search.as_bytes().len()..]));
+            return Some(OsStr::from_bytes(&s_bytes[search.len()..]));
         }
     }
 
@@ -51,6 +51,7 @@ struct JsonOption {
     alias: Option<JsonOptionAlias>,
     flags: Option<Vec<JsonOptionFlag>>,
     group: Option<JsonOptionGroup>,
+    help_text: Option<String>,
     kind: Option<JsonOptionKind>,
     name: Option<String>,
     num_args: Option<usize>,
@@ -64,29 +65,21 @@ struct JsonOption {
 #[derive(Clone, Debug, Deserialize)]
 struct JsonOptionAlias {
     def: String,
-    kind: String,
-    printable: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 struct JsonOptionFlag {
     def: String,
-    kind: String,
-    printable: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 struct JsonOptionGroup {
     def: String,
-    kind: String,
-    printable: String,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 struct JsonOptionKind {
     def: String,
-    kind: String,
-    printable: String,
 }
 
 /// The prefix for an argument.
@@ -119,23 +112,22 @@ impl FromStr for ArgumentPrefix {
     }
 }
 
-impl ToString for ArgumentPrefix {
-    fn to_string(&self) -> String {
-        match self {
+impl std::fmt::Display for ArgumentPrefix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
             Self::SingleDash => "-",
             Self::DoubleDash => "--",
             Self::SingleDashQuestion => "-?",
             Self::Slash => "/",
             Self::SlashQuestion => "/?",
-        }
-        .to_string()
+        })
     }
 }
 
 impl ArgumentPrefix {
     /// Format the prefix with a given argument name after it.
     pub fn with_name(&self, name: &str) -> String {
-        format!("{}{}", self.to_string(), name)
+        format!("{}{}", self, name)
     }
 }
 
@@ -249,31 +241,33 @@ pub struct ProgramOption {
     pub flags: Vec<String>,
     /// The group this option is part of.
     pub group: Option<String>,
+    /// Help text shown by the program's `--help`.
+    pub help: Option<String>,
 }
 
-impl PartialOrd for ProgramOption {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+impl Ord for ProgramOption {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         let a_matches_with_equals = self.kind.syntax_matches_with_equals();
         let b_matches_with_equals = other.kind.syntax_matches_with_equals();
 
         if a_matches_with_equals && !b_matches_with_equals {
-            Some(std::cmp::Ordering::Less)
+            std::cmp::Ordering::Less
         } else if !a_matches_with_equals && b_matches_with_equals {
-            Some(std::cmp::Ordering::Greater)
+            std::cmp::Ordering::Greater
         } else if !a_matches_with_equals
             && !b_matches_with_equals
             && self.name.len() != other.name.len()
         {
-            Some(other.name.len().cmp(&self.name.len()))
+            other.name.len().cmp(&self.name.len())
         } else {
-            Some(self.option_name.cmp(&other.option_name))
+            self.option_name.cmp(&other.option_name)
         }
     }
 }
 
-impl Ord for ProgramOption {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+impl PartialOrd for ProgramOption {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
     }
 }
 
@@ -292,11 +286,26 @@ impl ProgramOption {
     }
 }
 
+/// A named group of options.
+///
+/// Groups are defined in tablegen via `OptionGroup` and can themselves be
+/// members of a parent group.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OptionGroup {
+    /// Internal name from TBD.
+    pub group_name: String,
+    /// Human readable name of the group.
+    pub name: String,
+    /// The group this group is part of.
+    pub parent: Option<String>,
+}
+
 /// A collection of options that can be passed to an LLVM program.
 ///
 /// Instances are likely obtained by parsing LLVM tablegen definitions.
 pub struct CommandOptions {
     pub(crate) options: Vec<ProgramOption>,
+    pub(crate) groups: Vec<OptionGroup>,
 }
 
 impl CommandOptions {
@@ -307,10 +316,11 @@ impl CommandOptions {
     /// `llvm-tblgen --dump-json clang/include/clang/Driver/Options.td -I llvm/include`.
     pub fn from_json<R: std::io::Read>(reader: R) -> Result<Self, Error> {
         let options: Value = serde_json::from_reader(reader)?;
+        let options = options
+            .as_object()
+            .ok_or_else(|| Error::JsonParse("JSON input should be an Object".into()))?;
 
         let mut program_options = options
-            .as_object()
-            .ok_or_else(|| Error::JsonParse("JSON input should be an Object".into()))?
             .iter()
             .filter_map(|(k, v)| {
                 if k.is_empty() || k.starts_with('!') {
@@ -358,6 +368,7 @@ impl CommandOptions {
                     .map(|v| v.def)
                     .collect::<Vec<_>>();
                 let group = json_option.group.map(|group| group.def);
+                let help = json_option.help_text;
 
                 Ok(ProgramOption {
                     option_name,
@@ -367,6 +378,7 @@ impl CommandOptions {
                     alias,
                     flags,
                     group,
+                    help,
                 })
             })
             .collect::<Result<Vec<_>, Error>>()?;
@@ -375,8 +387,25 @@ impl CommandOptions {
         // ambiguous for joined options that can share a prefix.
         program_options.sort();
 
+        let groups = options
+            .iter()
+            .filter(|(k, _)| !k.is_empty() && !k.starts_with('!'))
+            .filter_map(|(_, v)| serde_json::from_value::<JsonOption>(v.clone()).ok())
+            .filter(|json_option| {
+                json_option
+                    .super_classes
+                    .contains(&"OptionGroup".to_string())
+            })
+            .map(|json_option| OptionGroup {
+                group_name: json_option.raw_name,
+                name: json_option.name.unwrap_or_default(),
+                parent: json_option.group.map(|group| group.def),
+            })
+            .collect::<Vec<_>>();
+
         Ok(Self {
             options: program_options,
+            groups,
         })
     }
 
@@ -385,6 +414,47 @@ impl CommandOptions {
         self.options.iter()
     }
 
+    /// Iterate over LLVM program option group definitions.
+    pub fn iter_groups(&self) -> impl Iterator<Item = &OptionGroup> {
+        self.groups.iter()
+    }
+
+    /// Obtain options which are aliases of the named option.
+    pub fn aliases_of<'a>(
+        &'a self,
+        option_name: &'a str,
+    ) -> impl Iterator<Item = &'a ProgramOption> {
+        self.options
+            .iter()
+            .filter(move |o| o.alias.as_deref() == Some(option_name))
+    }
+
+    /// Obtain the chain of groups an option group belongs to.
+    ///
+    /// The first element is the named group itself, followed by its parent,
+    /// its parent's parent, etc.
+    pub fn group_ancestors<'a>(&'a self, group_name: &'a str) -> Vec<&'a str> {
+        let mut res = vec![];
+        let mut current = Some(group_name);
+
+        while let Some(name) = current {
+            // Guard against cycles in malformed input.
+            if res.contains(&name) {
+                break;
+            }
+
+            res.push(name);
+
+            current = self
+                .groups
+                .iter()
+                .find(|group| group.group_name == name)
+                .and_then(|group| group.parent.as_deref());
+        }
+
+        res
+    }
+
     /// Obtain all options indexed by their flags.
     ///
     /// Options without flags are not returned.
@@ -450,21 +520,99 @@ impl CommandOptions {
         I: Iterator<Item = T>,
         T: Into<OsString> + Clone,
     {
-        let arg = if let Some(arg) = args.next() {
+        let arg: OsString = if let Some(arg) = args.next() {
             arg.into()
         } else {
             return Ok(None);
         };
 
+        let mut next_value = || args.next().map(|x| x.into());
+
+        Ok(Some(match self.match_argument(&arg) {
+            ArgumentMatch::Positional => ParsedArgument::Positional(arg.clone()),
+            ArgumentMatch::Unknown => ParsedArgument::Unknown(arg.clone()),
+            ArgumentMatch::Flag(definition) => ParsedArgument::Flag(definition.clone()),
+            ArgumentMatch::Joined(definition, value) => {
+                ParsedArgument::SingleValue(definition.clone(), value.to_os_string())
+            }
+            ArgumentMatch::CommaJoined(definition, value) => {
+                ParsedArgument::CommaValues(definition.clone(), value.to_os_string())
+            }
+            ArgumentMatch::Separate(definition) => {
+                if let Some(value) = next_value() {
+                    ParsedArgument::SingleValue(definition.clone(), value)
+                } else {
+                    return Err(Error::ParseNoArgumentValue(definition.option_name.clone()));
+                }
+            }
+            ArgumentMatch::JoinedAndSeparate(definition, key) => {
+                if let Some(value) = next_value() {
+                    ParsedArgument::SingleValueKeyed(definition.clone(), key.to_os_string(), value)
+                } else {
+                    return Err(Error::ParseNoArgumentValue(definition.option_name.clone()));
+                }
+            }
+            ArgumentMatch::MultiArg(definition, expected_arg_count) => {
+                let values = std::iter::from_fn(&mut next_value)
+                    .take(expected_arg_count)
+                    .collect::<Vec<_>>();
+
+                if values.len() != expected_arg_count {
+                    return Err(Error::ParseMultipleValuesMissing(
+                        definition.option_name.clone(),
+                        expected_arg_count,
+                        values.len(),
+                    ));
+                }
+
+                ParsedArgument::MultipleValues(definition.clone(), values)
+            }
+            ArgumentMatch::RemainingArgs(definition) => ParsedArgument::MultipleValues(
+                definition.clone(),
+                std::iter::from_fn(next_value).collect::<Vec<_>>(),
+            ),
+            ArgumentMatch::RemainingArgsJoined(definition, key) => {
+                ParsedArgument::MultipleValuesKeyed(
+                    definition.clone(),
+                    key.to_os_string(),
+                    std::iter::from_fn(next_value).collect::<Vec<_>>(),
+                )
+            }
+        }))
+    }
+
+    /// Parse arguments without copying them.
+    ///
+    /// This is an alternative to [Self::parse_arguments] for processing large
+    /// volumes of arguments. The returned iterator lazily parses arguments and
+    /// emits [ParsedArgumentRef] instances which borrow from the input strings
+    /// and from this instance. No argument data or option definitions are
+    /// copied.
+    pub fn parse_iter<'a, I, T>(&'a self, args: I) -> ParseIter<'a, I::IntoIter>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: AsRef<OsStr> + ?Sized + 'a,
+    {
+        ParseIter {
+            options: self,
+            args: args.into_iter(),
+        }
+    }
+
+    /// Resolve how a single argument string matches an option definition.
+    ///
+    /// This only looks at the given argument. It is up to the caller to
+    /// consume additional values from the argument stream as necessary.
+    fn match_argument<'a, 's>(&'a self, arg: &'s OsStr) -> ArgumentMatch<'a, 's> {
         // TODO expand response files, which have form `@path`.
         // Response files content is treated as regular command line parameters.
 
         if arg == "-" {
-            return Ok(Some(ParsedArgument::Positional(arg)));
+            return ArgumentMatch::Positional;
         }
 
         if !arg.to_string_lossy().starts_with('-') {
-            return Ok(Some(ParsedArgument::Positional(arg)));
+            return ArgumentMatch::Positional;
         }
 
         for definition in &self.options {
@@ -472,140 +620,239 @@ impl CommandOptions {
                 OptionKind::Input => {}
                 OptionKind::Unknown => {}
                 OptionKind::Flag => {
-                    if definition.matches_exact(&arg) {
-                        return Ok(Some(ParsedArgument::Flag(definition.clone())));
+                    if definition.matches_exact(arg) {
+                        return ArgumentMatch::Flag(definition);
                     }
                 }
                 // Joined values look like `name=<value>`.
                 OptionKind::Joined => {
-                    if let Some(remaining) = definition.matches_prefix(&arg) {
-                        return Ok(Some(ParsedArgument::SingleValue(
-                            definition.clone(),
-                            remaining.to_os_string(),
-                        )));
+                    if let Some(remaining) = definition.matches_prefix(arg) {
+                        return ArgumentMatch::Joined(definition, remaining);
                     }
                 }
                 OptionKind::CommaJoined => {
-                    if let Some(remaining) = definition.matches_prefix(&arg) {
-                        return Ok(Some(ParsedArgument::CommaValues(
-                            definition.clone(),
-                            remaining.to_os_string(),
-                        )));
+                    if let Some(remaining) = definition.matches_prefix(arg) {
+                        return ArgumentMatch::CommaJoined(definition, remaining);
                     }
                 }
                 // Separate takes value from next argument.
                 OptionKind::Separate => {
-                    if definition.matches_exact(&arg) {
-                        if let Some(value) = args.next() {
-                            let value = value.into();
-
-                            return Ok(Some(ParsedArgument::SingleValue(
-                                definition.clone(),
-                                value,
-                            )));
-                        } else {
-                            return Err(Error::ParseNoArgumentValue(
-                                definition.option_name.clone(),
-                            ));
-                        }
+                    if definition.matches_exact(arg) {
+                        return ArgumentMatch::Separate(definition);
                     }
                 }
                 // Takes form `-name value` or `-namevalue`. e.g. `-l`.
                 OptionKind::JoinedOrSeparate => {
-                    if let Some(remaining) = definition.matches_prefix(&arg) {
+                    if let Some(remaining) = definition.matches_prefix(arg) {
                         // Empty remaining means we consumed the full argument and the
                         // value is the next argument.
-                        if remaining.is_empty() {
-                            if let Some(value) = args.next() {
-                                let value = value.into();
-
-                                return Ok(Some(ParsedArgument::SingleValue(
-                                    definition.clone(),
-                                    value,
-                                )));
-                            } else {
-                                return Err(Error::ParseNoArgumentValue(
-                                    definition.option_name.clone(),
-                                ));
-                            }
+                        return if remaining.is_empty() {
+                            ArgumentMatch::Separate(definition)
                         } else {
-                            return Ok(Some(ParsedArgument::SingleValue(
-                                definition.clone(),
-                                remaining.to_os_string(),
-                            )));
-                        }
+                            ArgumentMatch::Joined(definition, remaining)
+                        };
                     }
                 }
 
                 // Takes form `-name=<key> value`.
                 OptionKind::JoinedAndSeparate => {
-                    if let Some(remaining) = definition.matches_prefix(&arg) {
-                        if let Some(value) = args.next() {
-                            let value = value.into();
-
-                            return Ok(Some(ParsedArgument::SingleValueKeyed(
-                                definition.clone(),
-                                remaining.to_os_string(),
-                                value,
-                            )));
-                        } else {
-                            return Err(Error::ParseNoArgumentValue(
-                                definition.option_name.clone(),
-                            ));
-                        }
+                    if let Some(remaining) = definition.matches_prefix(arg) {
+                        return ArgumentMatch::JoinedAndSeparate(definition, remaining);
                     }
                 }
 
                 OptionKind::MultiArg(expected_arg_count) => {
-                    if definition.matches_exact(&arg) {
-                        let values = args
-                            .take(expected_arg_count)
-                            .map(|x| x.into())
-                            .collect::<Vec<_>>();
-
-                        if values.len() != expected_arg_count {
-                            return Err(Error::ParseMultipleValuesMissing(
-                                definition.option_name.clone(),
-                                expected_arg_count,
-                                values.len(),
-                            ));
-                        }
-
-                        return Ok(Some(ParsedArgument::MultipleValues(
-                            definition.clone(),
-                            values,
-                        )));
+                    if definition.matches_exact(arg) {
+                        return ArgumentMatch::MultiArg(definition, expected_arg_count);
                     }
                 }
 
                 // Consumes all remaining arguments as-is,
                 OptionKind::RemainingArgs => {
-                    if definition.matches_exact(&arg) {
-                        let values = args.map(|x| x.into()).collect::<Vec<_>>();
-
-                        return Ok(Some(ParsedArgument::MultipleValues(
-                            definition.clone(),
-                            values,
-                        )));
+                    if definition.matches_exact(arg) {
+                        return ArgumentMatch::RemainingArgs(definition);
                     }
                 }
 
                 // Consumes remaining arguments after a joined value.
                 OptionKind::RemainingArgsJoined => {
-                    if let Some(remaining) = definition.matches_prefix(&arg) {
-                        let values = args.map(|x| x.into()).collect::<Vec<_>>();
-
-                        return Ok(Some(ParsedArgument::MultipleValuesKeyed(
-                            definition.clone(),
-                            remaining.to_os_string(),
-                            values,
-                        )));
+                    if let Some(remaining) = definition.matches_prefix(arg) {
+                        return ArgumentMatch::RemainingArgsJoined(definition, remaining);
                     }
                 }
             }
         }
 
-        Ok(Some(ParsedArgument::Unknown(arg)))
+        ArgumentMatch::Unknown
+    }
+}
+
+/// How a single argument string matched an option definition.
+///
+/// `'a` is the lifetime of the option definitions and `'s` the lifetime of
+/// the argument string.
+enum ArgumentMatch<'a, 's> {
+    Positional,
+    Unknown,
+    Flag(&'a ProgramOption),
+    /// Value is joined to the option name.
+    Joined(&'a ProgramOption, &'s OsStr),
+    CommaJoined(&'a ProgramOption, &'s OsStr),
+    /// Value is the next argument.
+    Separate(&'a ProgramOption),
+    /// Key is joined to the option name and value is the next argument.
+    JoinedAndSeparate(&'a ProgramOption, &'s OsStr),
+    MultiArg(&'a ProgramOption, usize),
+    RemainingArgs(&'a ProgramOption),
+    RemainingArgsJoined(&'a ProgramOption, &'s OsStr),
+}
+
+/// Iterator over [ParsedArgumentRef] produced by [CommandOptions::parse_iter].
+pub struct ParseIter<'a, I> {
+    options: &'a CommandOptions,
+    args: I,
+}
+
+impl<'a, I, T> Iterator for ParseIter<'a, I>
+where
+    I: Iterator<Item = &'a T>,
+    T: AsRef<OsStr> + ?Sized + 'a,
+{
+    type Item = Result<ParsedArgumentRef<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let arg = self.args.next()?.as_ref();
+        let args = &mut self.args;
+        let mut next_value = || args.next().map(|x| x.as_ref());
+
+        Some(Ok(match self.options.match_argument(arg) {
+            ArgumentMatch::Positional => ParsedArgumentRef::Positional(arg),
+            ArgumentMatch::Unknown => ParsedArgumentRef::Unknown(arg),
+            ArgumentMatch::Flag(definition) => ParsedArgumentRef::Flag(definition),
+            ArgumentMatch::Joined(definition, value) => {
+                ParsedArgumentRef::SingleValue(definition, value)
+            }
+            ArgumentMatch::CommaJoined(definition, value) => {
+                ParsedArgumentRef::CommaValues(definition, value)
+            }
+            ArgumentMatch::Separate(definition) => {
+                if let Some(value) = next_value() {
+                    ParsedArgumentRef::SingleValue(definition, value)
+                } else {
+                    return Some(Err(Error::ParseNoArgumentValue(
+                        definition.option_name.clone(),
+                    )));
+                }
+            }
+            ArgumentMatch::JoinedAndSeparate(definition, key) => {
+                if let Some(value) = next_value() {
+                    ParsedArgumentRef::SingleValueKeyed(definition, key, value)
+                } else {
+                    return Some(Err(Error::ParseNoArgumentValue(
+                        definition.option_name.clone(),
+                    )));
+                }
+            }
+            ArgumentMatch::MultiArg(definition, expected_arg_count) => {
+                let values = std::iter::from_fn(&mut next_value)
+                    .take(expected_arg_count)
+                    .collect::<Vec<_>>();
+
+                if values.len() != expected_arg_count {
+                    return Some(Err(Error::ParseMultipleValuesMissing(
+                        definition.option_name.clone(),
+                        expected_arg_count,
+                        values.len(),
+                    )));
+                }
+
+                ParsedArgumentRef::MultipleValues(definition, values)
+            }
+            ArgumentMatch::RemainingArgs(definition) => ParsedArgumentRef::MultipleValues(
+                definition,
+                std::iter::from_fn(next_value).collect::<Vec<_>>(),
+            ),
+            ArgumentMatch::RemainingArgsJoined(definition, key) => {
+                ParsedArgumentRef::MultipleValuesKeyed(
+                    definition,
+                    key,
+                    std::iter::from_fn(next_value).collect::<Vec<_>>(),
+                )
+            }
+        }))
+    }
+}
+
+/// A parsed argument borrowing from its source data.
+///
+/// This is the borrowed equivalent of [ParsedArgument]. Variants have the
+/// same meaning.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParsedArgumentRef<'a> {
+    Unknown(&'a OsStr),
+    Positional(&'a OsStr),
+    Flag(&'a ProgramOption),
+    SingleValue(&'a ProgramOption, &'a OsStr),
+    SingleValueKeyed(&'a ProgramOption, &'a OsStr, &'a OsStr),
+    CommaValues(&'a ProgramOption, &'a OsStr),
+    MultipleValues(&'a ProgramOption, Vec<&'a OsStr>),
+    MultipleValuesKeyed(&'a ProgramOption, &'a OsStr, Vec<&'a OsStr>),
+}
+
+impl<'a> ParsedArgumentRef<'a> {
+    /// Obtain the [ProgramOption] for this parsed argument, if available.
+    pub fn option(&self) -> Option<&'a ProgramOption> {
+        match self {
+            Self::Unknown(_) | Self::Positional(_) => None,
+            Self::Flag(d) => Some(d),
+            Self::SingleValue(d, _) => Some(d),
+            Self::SingleValueKeyed(d, _, _) => Some(d),
+            Self::CommaValues(d, _) => Some(d),
+            Self::MultipleValues(d, _) => Some(d),
+            Self::MultipleValuesKeyed(d, _, _) => Some(d),
+        }
+    }
+
+    /// The clang internal name of this parsed argument, if available.
+    pub fn name(&self) -> Option<&'a str> {
+        self.option().map(|d| d.option_name.as_str())
+    }
+
+    /// Values for this argument.
+    ///
+    /// Arguments without values return an empty vec.
+    pub fn values(&self) -> Vec<&'a OsStr> {
+        match self {
+            Self::Unknown(_) | Self::Positional(_) | Self::Flag(_) => vec![],
+            Self::SingleValue(_, value) => vec![value],
+            Self::SingleValueKeyed(_, _, value) => vec![value],
+            Self::CommaValues(_, value) => vec![value],
+            Self::MultipleValues(_, values) => values.clone(),
+            Self::MultipleValuesKeyed(_, _, values) => values.clone(),
+        }
+    }
+
+    /// Convert to an owned [ParsedArgument].
+    pub fn to_owned_argument(&self) -> ParsedArgument {
+        match self {
+            Self::Unknown(a) => ParsedArgument::Unknown(a.to_os_string()),
+            Self::Positional(a) => ParsedArgument::Positional(a.to_os_string()),
+            Self::Flag(d) => ParsedArgument::Flag((*d).clone()),
+            Self::SingleValue(d, a) => ParsedArgument::SingleValue((*d).clone(), a.to_os_string()),
+            Self::SingleValueKeyed(d, a, b) => {
+                ParsedArgument::SingleValueKeyed((*d).clone(), a.to_os_string(), b.to_os_string())
+            }
+            Self::CommaValues(d, a) => ParsedArgument::CommaValues((*d).clone(), a.to_os_string()),
+            Self::MultipleValues(d, a) => ParsedArgument::MultipleValues(
+                (*d).clone(),
+                a.iter().map(|x| x.to_os_string()).collect::<Vec<_>>(),
+            ),
+            Self::MultipleValuesKeyed(d, a, b) => ParsedArgument::MultipleValuesKeyed(
+                (*d).clone(),
+                a.to_os_string(),
+                b.iter().map(|x| x.to_os_string()).collect::<Vec<_>>(),
+            ),
+        }
     }
 }
 
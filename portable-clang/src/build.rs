@@ -4,28 +4,75 @@
 
 /*! High level build logic. */
 
-use crate::tar::tar_from_directory;
 use {
-    crate::docker::ZSTD_COMPRESSION_LEVEL,
+    crate::{
+        config::Config,
+        docker::{
+            ArtifactFormat, BaseDistribution, BuilderPlatform, ClangInputs, ClangStage,
+            ContainerSettings, DockerRuntime, FileTransferMode, ImageSettings, UserMapping,
+            ZstdSettings,
+        },
+        downloads::{DownloadSettings, ProxySettings, RateLimiter},
+        git::GitSource,
+        glibc::{BuildFilter, UnifySettings, UnifySummary},
+        glibc_headers::HeaderConflict,
+        lock::CacheLock,
+        progress::ArchiveProgressBar,
+        runtime::ContainerRuntime,
+        tar::TarMetadata,
+    },
     anyhow::{anyhow, Context, Result},
+    futures_util::stream::StreamExt,
     slog::{warn, Logger},
     std::{
-        io::Cursor,
+        num::NonZeroU64,
         path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
     },
 };
 
 pub const GLIBC_GIT_URL: &str = "git://sourceware.org/git/glibc.git";
 
+/// Parse a `KEY=VALUE` Docker build argument.
+pub fn parse_build_arg(arg: &str) -> Result<(String, String)> {
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| anyhow!("build argument must be of form KEY=VALUE: {}", arg))?;
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Read a file of lines to insert into generated Dockerfiles.
+fn read_dockerfile_fragment(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("reading Dockerfile fragment {}", path.display()))
+}
+
+#[derive(Clone)]
 pub struct Environment {
     logger: Logger,
     cache_dir: PathBuf,
+    docker_socket: Option<String>,
+    image_settings: ImageSettings,
+    container_settings: ContainerSettings,
+    /// Whether to wait for locks held by other processes instead of failing.
+    lock_wait: bool,
+    /// Destination directory of build commands if none is given.
+    default_dest_dir: Option<PathBuf>,
+    /// `COMPILER:GLIBC` pairs to build if none are given.
+    targets: Vec<String>,
 }
 
 impl Environment {
-    pub fn new(logger: Logger) -> Result<Self> {
+    /// Resolve settings from `config` and `PCLANG_*` environment variables.
+    ///
+    /// Environment variables take precedence over the config file.
+    pub fn new(logger: Logger, config: &Config) -> Result<Self> {
         let cache_dir = if let Ok(p) = std::env::var("PCLANG_CACHE_DIR") {
             PathBuf::from(p)
+        } else if let Some(cache_dir) = &config.cache_dir {
+            cache_dir.clone()
         } else if let Some(cache_dir) = dirs::cache_dir() {
             cache_dir.join("pclang")
         } else {
@@ -37,25 +84,718 @@ impl Environment {
                 .join("cache")
         };
 
-        Ok(Self { logger, cache_dir })
+        let docker_socket = std::env::var("PCLANG_DOCKER_SOCKET")
+            .ok()
+            .or_else(|| config.docker.socket.clone());
+
+        // Report errors in user download manifests before records are needed.
+        crate::downloads::load_downloads(crate::downloads::user_downloads_path().as_deref())?;
+
+        let mut image_settings = ImageSettings::default();
+
+        if let Some(value) = std::env::var("PCLANG_PLATFORM")
+            .ok()
+            .or_else(|| config.docker.platform.clone())
+        {
+            image_settings.platform = value.parse()?;
+        }
+
+        if let Some(value) = std::env::var("PCLANG_BASE_DISTRIBUTION")
+            .ok()
+            .or_else(|| config.docker.base_distribution.clone())
+        {
+            image_settings.base = Some(value.parse()?);
+        }
+
+        image_settings.registry = std::env::var("PCLANG_REGISTRY")
+            .ok()
+            .or_else(|| config.docker.registry.clone());
+        image_settings.push = std::env::var("PCLANG_PUSH_IMAGES").is_ok();
+
+        if std::env::var("PCLANG_CACHE_IMAGES").is_ok() {
+            image_settings.image_cache_dir = Some(cache_dir.join("images"));
+        }
+
+        for path in config
+            .docker
+            .dockerfile_fragments
+            .iter()
+            .cloned()
+            .chain(std::env::var_os("PCLANG_DOCKERFILE_EXTRA").map(PathBuf::from))
+        {
+            image_settings
+                .dockerfile_fragments
+                .push(read_dockerfile_fragment(&path)?);
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_DEBIAN_SNAPSHOT") {
+            image_settings.debian_snapshot = crate::docker::parse_debian_snapshot(&value)?;
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_BUILD_ARGS") {
+            for arg in value.split_whitespace() {
+                let (key, value) = parse_build_arg(arg)?;
+                image_settings.build_args.insert(key, value);
+            }
+        }
+
+        image_settings.downloads.keyring = crate::downloads::user_keyring_path();
+        image_settings.downloads.require_signatures =
+            std::env::var("PCLANG_REQUIRE_SIGNATURES").is_ok();
+
+        image_settings.downloads.offline_dir =
+            std::env::var_os("PCLANG_OFFLINE_DIR").map(PathBuf::from);
+
+        if let Some(value) = std::env::var("PCLANG_LLVM_VERSION")
+            .ok()
+            .or_else(|| config.llvm_version.clone())
+        {
+            crate::downloads::llvm_version_record(&value)?;
+            image_settings.downloads.llvm_version = value;
+        }
+
+        image_settings.downloads.proxy = ProxySettings {
+            http: std::env::var("PCLANG_HTTP_PROXY").ok(),
+            https: std::env::var("PCLANG_HTTPS_PROXY").ok(),
+            no_proxy: std::env::var("PCLANG_NO_PROXY").ok(),
+        };
+
+        if let Ok(value) = std::env::var("PCLANG_LLVM_GIT") {
+            image_settings.downloads.llvm_git =
+                Some(GitSource::parse(&value).context("parsing PCLANG_LLVM_GIT")?);
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_GLIBC_GIT") {
+            image_settings.downloads.glibc_git =
+                Some(GitSource::parse(&value).context("parsing PCLANG_GLIBC_GIT")?);
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_DOWNLOAD_RETRIES") {
+            image_settings.downloads.retries = value
+                .parse()
+                .context("parsing PCLANG_DOWNLOAD_RETRIES as a number")?;
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_DOWNLOAD_BACKOFF") {
+            image_settings.downloads.backoff = Duration::from_secs(
+                value
+                    .parse()
+                    .context("parsing PCLANG_DOWNLOAD_BACKOFF as seconds")?,
+            );
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_DOWNLOAD_TIMEOUT") {
+            image_settings.downloads.timeout = Some(Duration::from_secs(
+                value
+                    .parse()
+                    .context("parsing PCLANG_DOWNLOAD_TIMEOUT as seconds")?,
+            ));
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_DOWNLOAD_RATE_LIMIT") {
+            image_settings.downloads.rate_limit =
+                Some(Arc::new(RateLimiter::new(value.parse().context(
+                    "parsing PCLANG_DOWNLOAD_RATE_LIMIT as bytes per second",
+                )?)));
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_DOWNLOAD_JOBS") {
+            image_settings.downloads.jobs = value
+                .parse()
+                .context("parsing PCLANG_DOWNLOAD_JOBS as a number")?;
+        }
+
+        let mut container_settings = ContainerSettings {
+            log_dir: Some(cache_dir.join("logs")),
+            proxy: image_settings.downloads.proxy.clone(),
+            sccache_env: config.sccache.env.clone(),
+            ..Default::default()
+        };
+
+        if let Some(value) = std::env::var("PCLANG_DOCKER_TRANSFER_MODE")
+            .ok()
+            .or_else(|| config.docker.transfer_mode.clone())
+        {
+            container_settings.transfer_mode = value.parse()?;
+        }
+
+        container_settings.sccache_volume = std::env::var("PCLANG_SCCACHE_VOLUME")
+            .ok()
+            .or_else(|| config.sccache.volume.clone());
+
+        if let Ok(value) = std::env::var("PCLANG_SECURITY_OPT") {
+            for opt in value.split_whitespace() {
+                container_settings
+                    .security_opts
+                    .push(crate::docker::resolve_security_opt(opt)?);
+            }
+        }
+        container_settings.privileged = std::env::var("PCLANG_PRIVILEGED").is_ok();
+        container_settings.keep_failed = std::env::var("PCLANG_KEEP_FAILED").is_ok();
+        container_settings.raw_output = std::env::var("PCLANG_RAW_OUTPUT").is_ok();
+        container_settings.network_mode = std::env::var("PCLANG_NETWORK")
+            .ok()
+            .or_else(|| config.docker.network.clone());
+        if let Ok(value) = std::env::var("PCLANG_CAP_ADD") {
+            container_settings
+                .cap_add
+                .extend(value.split(',').filter(|x| !x.is_empty()).map(String::from));
+        }
+
+        if let Ok(value) = std::env::var("PCLANG_CONTAINER_TIMEOUT") {
+            container_settings.timeout = Some(Duration::from_secs(
+                value
+                    .parse()
+                    .context("parsing PCLANG_CONTAINER_TIMEOUT as seconds")?,
+            ));
+        }
+
+        Ok(Self {
+            logger,
+            cache_dir,
+            docker_socket,
+            image_settings,
+            container_settings,
+            lock_wait: std::env::var("PCLANG_NO_WAIT").is_err(),
+            default_dest_dir: config.dest_dir.clone(),
+            targets: config.targets.clone(),
+        })
+    }
+
+    /// Override the directory downloads, caches, and logs are kept in.
+    ///
+    /// This should be called before other settings derived from the cache
+    /// directory, such as the image cache and user mapping, are set.
+    pub fn with_cache_dir(mut self, path: Option<PathBuf>) -> Self {
+        if let Some(path) = path {
+            if self.image_settings.image_cache_dir.is_some() {
+                self.image_settings.image_cache_dir = Some(path.join("images"));
+            }
+            self.container_settings.log_dir = Some(path.join("logs"));
+            self.cache_dir = path;
+        }
+
+        self
+    }
+
+    /// Override the address of the Docker daemon to connect to.
+    pub fn with_docker_socket(mut self, socket: Option<String>) -> Self {
+        if socket.is_some() {
+            self.docker_socket = socket;
+        }
+
+        self
+    }
+
+    /// Override the platform builder images target.
+    pub fn with_platform(mut self, platform: Option<BuilderPlatform>) -> Self {
+        if let Some(platform) = platform {
+            self.image_settings.platform = platform;
+        }
+
+        self
+    }
+
+    /// Override the base distribution of builder images.
+    pub fn with_base_distribution(mut self, base: Option<BaseDistribution>) -> Self {
+        if base.is_some() {
+            self.image_settings.base = base;
+        }
+
+        self
+    }
+
+    /// Share builder images via a registry.
+    ///
+    /// Images missing locally are pulled from `registry` instead of being built.
+    /// If `push` is set, newly built images are pushed to it.
+    pub fn with_registry(mut self, registry: Option<String>, push: bool) -> Self {
+        if registry.is_some() {
+            self.image_settings.registry = registry;
+        }
+        if push {
+            self.image_settings.push = true;
+        }
+
+        self
+    }
+
+    /// Save builder images to the cache directory and load them from there.
+    ///
+    /// Images are keyed by the digest of their inputs, so stale images are
+    /// never loaded.
+    pub fn with_image_cache(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.image_settings.image_cache_dir = Some(self.cache_dir.join("images"));
+        }
+
+        self
+    }
+
+    /// Inject lines from files into generated Dockerfiles.
+    ///
+    /// Fragments are inserted after the base image header, after those from
+    /// the config file and `PCLANG_DOCKERFILE_EXTRA`, in the order given.
+    pub fn with_dockerfile_fragments(
+        mut self,
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> Result<Self> {
+        for path in paths {
+            self.image_settings
+                .dockerfile_fragments
+                .push(read_dockerfile_fragment(&path)?);
+        }
+
+        Ok(self)
+    }
+
+    /// Override the snapshot.debian.org timestamp Debian based images use.
+    pub fn with_debian_snapshot(mut self, snapshot: Option<&str>) -> Result<Self> {
+        if let Some(snapshot) = snapshot {
+            self.image_settings.debian_snapshot = crate::docker::parse_debian_snapshot(snapshot)?;
+        }
+
+        Ok(self)
+    }
+
+    /// Set values of Dockerfile `ARG`s when building images.
+    pub fn with_build_args(mut self, args: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.image_settings.build_args.extend(args);
+
+        self
+    }
+
+    /// Override how files are exchanged with containers.
+    pub fn with_transfer_mode(mut self, mode: Option<FileTransferMode>) -> Self {
+        if let Some(mode) = mode {
+            self.container_settings.transfer_mode = mode;
+        }
+
+        self
+    }
+
+    /// Override the maximum time a single container may run for.
+    pub fn with_container_timeout(mut self, timeout: Option<Duration>) -> Self {
+        if timeout.is_some() {
+            self.container_settings.timeout = timeout;
+        }
+
+        self
+    }
+
+    /// Override how artifacts and images are zstd compressed.
+    pub fn with_zstd(
+        mut self,
+        level: Option<i32>,
+        threads: Option<u32>,
+        long_distance_matching: bool,
+    ) -> Result<Self> {
+        let mut zstd = ZstdSettings::default();
+
+        if let Some(level) = level {
+            if !(1..=22).contains(&level) {
+                return Err(anyhow!("zstd level must be between 1 and 22: {}", level));
+            }
+
+            zstd.level = level;
+        }
+        if let Some(threads) = threads {
+            zstd.threads = threads;
+        }
+        zstd.long_distance_matching = long_distance_matching;
+
+        self.image_settings.zstd = zstd;
+        self.container_settings.zstd = zstd;
+
+        Ok(self)
+    }
+
+    /// Override which file metadata artifacts produced by containers record.
+    pub fn with_tar_metadata(mut self, metadata: Option<TarMetadata>) -> Self {
+        if let Some(metadata) = metadata {
+            self.container_settings.tar_metadata = metadata;
+        }
+
+        self
+    }
+
+    /// Override the format of final toolchain artifacts.
+    ///
+    /// `xz_level` applies to xz artifacts only.
+    pub fn with_artifact_format(
+        mut self,
+        format: Option<ArtifactFormat>,
+        xz_level: Option<u32>,
+    ) -> Result<Self> {
+        let format = match (
+            format.unwrap_or(self.container_settings.artifact_format),
+            xz_level,
+        ) {
+            (ArtifactFormat::Xz(_), Some(level)) if level > 9 => {
+                return Err(anyhow!("xz level must be between 0 and 9: {}", level));
+            }
+            (ArtifactFormat::Xz(_), Some(level)) => ArtifactFormat::Xz(level),
+            (ArtifactFormat::Zstd, Some(_)) => {
+                return Err(anyhow!("xz level requires the xz artifact format"));
+            }
+            (format, None) => format,
+        };
+
+        self.container_settings.artifact_format = format;
+
+        Ok(self)
+    }
+
+    /// Override the maximum number of files to download concurrently.
+    pub fn with_download_jobs(mut self, jobs: Option<usize>) -> Self {
+        if let Some(jobs) = jobs {
+            self.image_settings.downloads.jobs = jobs;
+        }
+
+        self
+    }
+
+    /// Override how many times failed downloads are retried.
+    pub fn with_download_retries(mut self, retries: Option<u32>) -> Self {
+        if let Some(retries) = retries {
+            self.image_settings.downloads.retries = retries;
+        }
+
+        self
+    }
+
+    /// Override the delay before the first retry of a failed download.
+    pub fn with_download_backoff(mut self, backoff: Option<Duration>) -> Self {
+        if let Some(backoff) = backoff {
+            self.image_settings.downloads.backoff = backoff;
+        }
+
+        self
+    }
+
+    /// Limit the combined rate of downloads, in bytes per second.
+    pub fn with_download_rate_limit(mut self, bytes_per_second: Option<NonZeroU64>) -> Self {
+        if let Some(rate) = bytes_per_second {
+            self.image_settings.downloads.rate_limit = Some(Arc::new(RateLimiter::new(rate)));
+        }
+
+        self
+    }
+
+    /// Override the maximum time a single download request may take.
+    pub fn with_download_timeout(mut self, timeout: Option<Duration>) -> Self {
+        if timeout.is_some() {
+            self.image_settings.downloads.timeout = timeout;
+        }
+
+        self
+    }
+
+    /// Override the LLVM major version to build.
+    pub fn with_llvm_version(mut self, version: Option<&str>) -> Result<Self> {
+        if let Some(version) = version {
+            crate::downloads::llvm_version_record(version)?;
+            self.image_settings.downloads.llvm_version = version.to_string();
+        }
+
+        Ok(self)
+    }
+
+    /// Access the network through proxies, for both downloads and containers.
+    pub fn with_proxy(mut self, proxy: ProxySettings) -> Self {
+        let settings = &mut self.image_settings.downloads.proxy;
+        settings.http = proxy.http.or_else(|| settings.http.take());
+        settings.https = proxy.https.or_else(|| settings.https.take());
+        settings.no_proxy = proxy.no_proxy.or_else(|| settings.no_proxy.take());
+        self.container_settings.proxy = settings.clone();
+
+        self
+    }
+
+    /// Build LLVM from a commit of a git repository, given as `<url>#<commit>`.
+    pub fn with_llvm_git(mut self, source: Option<&str>) -> Result<Self> {
+        if let Some(source) = source {
+            self.image_settings.downloads.llvm_git = Some(GitSource::parse(source)?);
+        }
+
+        Ok(self)
+    }
+
+    /// Build glibc from a commit of a git repository, given as `<url>#<commit>`.
+    pub fn with_glibc_git(mut self, source: Option<&str>) -> Result<Self> {
+        if let Some(source) = source {
+            self.image_settings.downloads.glibc_git = Some(GitSource::parse(source)?);
+        }
+
+        Ok(self)
+    }
+
+    /// Resolve downloads from a directory of previously downloaded files instead of the network.
+    pub fn with_offline_dir(mut self, dir: Option<PathBuf>) -> Self {
+        if dir.is_some() {
+            self.image_settings.downloads.offline_dir = dir;
+        }
+
+        self
+    }
+
+    /// Override the keyring to verify download signatures with.
+    pub fn with_keyring(mut self, keyring: Option<PathBuf>) -> Self {
+        if keyring.is_some() {
+            self.image_settings.downloads.keyring = keyring;
+        }
+
+        self
+    }
+
+    /// Require every download to have a verified signature.
+    pub fn with_require_signatures(mut self, require: bool) -> Self {
+        if require {
+            self.image_settings.downloads.require_signatures = true;
+        }
+
+        self
+    }
+
+    /// Override whether to wait for locks held by other processes sharing the cache directory.
+    pub fn with_lock_wait(mut self, wait: Option<bool>) -> Self {
+        if let Some(wait) = wait {
+            self.lock_wait = wait;
+        }
+
+        self
+    }
+
+    /// Stream container output unmodified to the terminal, if attached to one.
+    pub fn with_raw_output(mut self, raw: bool) -> Self {
+        if raw {
+            self.container_settings.raw_output = true;
+        }
+
+        self
+    }
+
+    /// Keep containers and temporary directories of failed builds for debugging.
+    pub fn with_keep_failed(mut self, keep: bool) -> Self {
+        if keep {
+            self.container_settings.keep_failed = true;
+        }
+
+        self
+    }
+
+    /// Store the sccache cache in a named Docker volume instead of the cache directory.
+    pub fn with_sccache_volume(mut self, volume: Option<String>) -> Self {
+        if volume.is_some() {
+            self.container_settings.sccache_volume = volume;
+        }
+
+        self
+    }
+
+    /// Add security options, capabilities, and privileges to build containers.
+    ///
+    /// Security options are resolved via [crate::docker::resolve_security_opt].
+    pub fn with_container_security(
+        mut self,
+        security_opts: impl IntoIterator<Item = String>,
+        privileged: bool,
+        cap_add: impl IntoIterator<Item = String>,
+    ) -> Result<Self> {
+        for opt in security_opts {
+            self.container_settings
+                .security_opts
+                .push(crate::docker::resolve_security_opt(&opt)?);
+        }
+        if privileged {
+            self.container_settings.privileged = true;
+        }
+        self.container_settings.cap_add.extend(cap_add);
+
+        Ok(self)
+    }
+
+    /// Override the network mode of build containers.
+    pub fn with_network_mode(mut self, network_mode: Option<String>) -> Self {
+        if network_mode.is_some() {
+            self.container_settings.network_mode = network_mode;
+        }
+
+        self
+    }
+
+    /// Resolve how the build user in containers maps to the host user.
+    ///
+    /// `mode` is one of `auto`, `fixed`, `host`, or `rootless`. If not given,
+    /// `PCLANG_USER_MAPPING` is consulted before falling back to `auto`, which
+    /// detects the mapping from the daemon address and transfer mode. This
+    /// should be called after the daemon address and transfer mode are set.
+    pub fn with_user_mapping(mut self, mode: Option<&str>) -> Result<Self> {
+        let mode = mode
+            .map(|x| x.to_string())
+            .or_else(|| std::env::var("PCLANG_USER_MAPPING").ok())
+            .unwrap_or_else(|| "auto".to_string());
+
+        std::fs::create_dir_all(&self.cache_dir).context("creating cache directory")?;
+
+        let mapping = match mode.as_str() {
+            "auto" => UserMapping::detect(
+                &crate::docker::resolve_docker_host(self.docker_socket.as_deref()),
+                self.container_settings.transfer_mode,
+                &self.cache_dir,
+            ),
+            "fixed" => UserMapping::Fixed,
+            "host" => UserMapping::host(&self.cache_dir)
+                .ok_or_else(|| anyhow!("unable to determine host user"))?,
+            "rootless" => UserMapping::Rootless,
+            _ => return Err(anyhow!("unsupported user mapping: {}", mode)),
+        };
+
+        let (uid, gid) = mapping.build_ids();
+        self.image_settings.build_uid = uid;
+        self.image_settings.build_gid = gid;
+        self.container_settings.user_mapping = mapping;
+
+        Ok(self)
     }
 
     pub fn logger(&self) -> &Logger {
         &self.logger
     }
 
+    /// Directory caches, downloads, and logs are kept in.
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    /// Resolve the destination directory of a build command.
+    ///
+    /// Falls back to `dest_dir` of the config file.
+    pub fn dest_dir(&self, dest: Option<&Path>) -> Result<PathBuf> {
+        dest.or(self.default_dest_dir.as_deref())
+            .map(Path::to_path_buf)
+            .ok_or_else(|| anyhow!("no destination given and no dest_dir configured"))
+    }
+
+    /// `COMPILER:GLIBC` pairs from the `targets` of the config file.
+    pub fn targets(&self) -> &[String] {
+        &self.targets
+    }
+
+    /// Directory downloads are cached in.
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.cache_dir.join("downloads")
+    }
+
+    /// Settings for downloading source and support artifacts.
+    pub fn download_settings(&self) -> &DownloadSettings {
+        &self.image_settings.downloads
+    }
+
+    /// Tear down containers left running by an interrupted operation.
+    pub async fn cleanup_running_containers(&self) -> Result<()> {
+        crate::docker::cleanup_running_containers(&self.logger, &self.docker_client()?).await;
+
+        Ok(())
+    }
+
     fn docker_client(&self) -> Result<bollard::Docker> {
-        crate::docker::docker_client()
+        crate::docker::docker_client(self.docker_socket.as_deref())
+    }
+
+    /// Lock a resource in the cache directory against use by other processes.
+    ///
+    /// `images` covers building and loading images, which includes downloads
+    /// into the cache directory. `sccache` covers running containers that use
+    /// the sccache cache.
+    async fn lock(&self, name: &str) -> Result<CacheLock> {
+        CacheLock::acquire(
+            &self.logger,
+            &self.cache_dir.join("locks"),
+            name,
+            self.lock_wait,
+        )
+        .await
+    }
+
+    /// The container runtime images are built and run with.
+    fn runtime(&self) -> Result<Box<dyn ContainerRuntime>> {
+        Ok(Box::new(DockerRuntime::new(self.docker_client()?)))
+    }
+
+    /// Import a zstd compressed image archive into the container runtime.
+    async fn import_image(&self, runtime: &dyn ContainerRuntime, path: &Path) -> Result<String> {
+        let fh = std::fs::File::open(path).context("opening image archive")?;
+
+        runtime
+            .import_image(&self.logger, Box::new(fh))
+            .await
+            .context("loading Docker image")
+    }
+
+    /// Export an image to a zstd compressed archive if a destination is given.
+    async fn export_image(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        image_id: &str,
+        dest_path: Option<&Path>,
+    ) -> Result<()> {
+        if let Some(dest_path) = dest_path {
+            let (in_size, out_size) = runtime
+                .export_image(image_id, dest_path, &self.image_settings.zstd)
+                .await
+                .context("exporting Docker image to file")?;
+            warn!(
+                &self.logger,
+                "wrote {}; compressed {} -> {} bytes",
+                dest_path.display(),
+                in_size,
+                out_size
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Run preflight checks against the Docker daemon and build environment.
+    pub async fn preflight(&self) -> Result<crate::preflight::PreflightReport> {
+        let host = crate::docker::resolve_docker_host(self.docker_socket.as_deref());
+
+        Ok(crate::preflight::preflight(
+            &self.docker_client()?,
+            &host,
+            &self.container_settings,
+            &self.cache_dir,
+        )
+        .await)
     }
 
+    /// Run preflight checks, failing if any check fails.
+    pub async fn ensure_preflight(&self) -> Result<()> {
+        self.preflight().await?.into_result()
+    }
+
+    /// Build clang, writing artifacts to `dest_dir`.
+    ///
+    /// If `stage` is set, only that stage runs. Stage 1 writes `clang-stage1.tar.zst`,
+    /// which stage 2 reads back from `dest_dir`. So a failed stage 2 can be
+    /// retried without rebuilding stage 1. Stage 2 also defaults to reading the
+    /// GCC toolchain from `dest_dir`.
     pub async fn build_clang(
         &self,
         dest_dir: impl AsRef<Path>,
         image_path: Option<impl AsRef<Path>>,
         bootstrap_dir: Option<impl AsRef<Path>>,
+        stage: Option<ClangStage>,
     ) -> Result<()> {
         let dest_dir = dest_dir.as_ref();
-        let bootstrap_dir = bootstrap_dir.map(|x| x.as_ref().to_path_buf());
+        let bootstrap_dir = bootstrap_dir.map(|x| x.as_ref().to_path_buf()).or_else(|| {
+            if stage == Some(ClangStage::Stage2) {
+                Some(dest_dir.to_path_buf())
+            } else {
+                None
+            }
+        });
 
         std::fs::create_dir_all(dest_dir)?;
 
@@ -77,31 +817,96 @@ impl Environment {
             self.build_gcc(dest_dir, None).await?
         };
 
-        let docker = self.docker_client()?;
+        let runtime = self.runtime()?;
 
-        let image_id = if let Some(image_path) = image_path {
-            let image_path = image_path.as_ref();
-            let fh = std::fs::File::open(image_path).context("opening image archive")?;
+        let image_id = {
+            let _lock = self.lock("images").await?;
 
-            crate::docker::load_image_tar_zst(&self.logger, &docker, fh)
-                .await
-                .context("loading Docker image")?
+            if let Some(image_path) = image_path {
+                self.import_image(runtime.as_ref(), image_path.as_ref())
+                    .await?
+            } else {
+                crate::docker::build_image_clang(
+                    &self.logger,
+                    runtime.as_ref(),
+                    &self.image_settings,
+                    &self.downloads_dir(),
+                )
+                .await?
+            }
+        };
+
+        let _lock = self.lock("sccache").await?;
+
+        let stage1_path = dest_dir.join("clang-stage1.tar.zst");
+
+        let stage1_tar_zst = if stage == Some(ClangStage::Stage2) {
+            warn!(
+                &self.logger,
+                "reading clang stage 1 from {}",
+                stage1_path.display()
+            );
+            std::fs::read(&stage1_path).with_context(|| {
+                format!("reading {}; run clang stage 1 first", stage1_path.display())
+            })?
         } else {
-            crate::docker::build_image_clang(&self.logger, &docker, &self.cache_dir).await?
+            let inputs = ClangInputs {
+                binutils: &binutils_tar,
+                gcc: &gcc_tar,
+                stage1: None,
+            };
+
+            let stage1_tar_zst = crate::docker::bootstrap_clang(
+                &self.logger,
+                runtime.as_ref(),
+                &self.container_settings,
+                &image_id,
+                ClangStage::Stage1,
+                &inputs,
+                &self.cache_dir,
+            )
+            .await?;
+
+            std::fs::write(&stage1_path, &stage1_tar_zst)?;
+            warn!(&self.logger, "wrote {}", stage1_path.display());
+
+            stage1_tar_zst
+        };
+
+        if stage == Some(ClangStage::Stage1) {
+            return Ok(());
+        }
+
+        let inputs = ClangInputs {
+            binutils: &binutils_tar,
+            gcc: &gcc_tar,
+            stage1: Some(&stage1_tar_zst),
         };
 
         let clang_tar_zst = crate::docker::bootstrap_clang(
             &self.logger,
-            &docker,
+            runtime.as_ref(),
+            &self.container_settings,
             &image_id,
-            &binutils_tar,
-            &gcc_tar,
+            ClangStage::Stage2,
+            &inputs,
             &self.cache_dir,
         )
-        .await?;
+        .await
+        .with_context(|| {
+            format!(
+                "building clang stage 2; retry with --stage stage2 to reuse {}",
+                stage1_path.display()
+            )
+        })?;
 
-        let clang_path = dest_dir.join("clang.tar.zst");
-        std::fs::write(&clang_path, &clang_tar_zst)?;
+        let clang_path = self.container_settings.artifact_format.write_tar_zst(
+            &clang_tar_zst,
+            dest_dir,
+            "clang",
+            &ArchiveProgressBar::new(&self.logger),
+        )?;
+        warn!(&self.logger, "wrote {}", clang_path.display());
 
         Ok(())
     }
@@ -115,85 +920,118 @@ impl Environment {
 
         std::fs::create_dir_all(dest_dir)?;
 
-        let image_id = if let Some(image_path) = image_path {
-            let fh = std::fs::File::open(image_path).context("opening image archive")?;
+        let runtime = self.runtime()?;
 
-            crate::docker::load_image_tar_zst(&self.logger, &self.docker_client()?, fh)
-                .await
-                .context("loading Docker image")?
-        } else {
-            crate::docker::build_image_gcc(&self.logger, &self.docker_client()?, &self.cache_dir)
+        let image_id = {
+            let _lock = self.lock("images").await?;
+
+            if let Some(image_path) = image_path {
+                self.import_image(runtime.as_ref(), image_path).await?
+            } else {
+                crate::docker::build_image_gcc(
+                    &self.logger,
+                    runtime.as_ref(),
+                    &self.image_settings,
+                    &self.downloads_dir(),
+                )
                 .await?
+            }
         };
 
+        let _lock = self.lock("sccache").await?;
+
         let (binutils, gcc) = crate::docker::bootstrap_gcc(
             &self.logger,
-            &self.docker_client()?,
+            runtime.as_ref(),
+            &self.container_settings,
             &image_id,
             &self.cache_dir,
         )
         .await?;
 
         let binutils_path = dest_dir.join("binutils.tar.zst");
-        let gcc_path = dest_dir.join("gcc.tar.zst");
-
-        std::fs::write(&binutils_path, &binutils)?;
-        std::fs::write(&gcc_path, &gcc)?;
-
-        Ok((binutils, gcc))
-    }
-
-    pub async fn docker_image_clang(&self, dest_dir: Option<impl AsRef<Path>>) -> Result<()> {
-        let image_id =
-            crate::docker::build_image_clang(&self.logger, &self.docker_client()?, &self.cache_dir)
-                .await?;
-
-        if let Some(dest_path) = dest_dir {
-            let dest_path = dest_path.as_ref();
-            let (in_size, out_size) = crate::docker::export_image_to_tar_zst(
-                &self.docker_client()?,
-                &image_id,
-                dest_path,
-            )
-            .await
-            .context("exporting Docker image to file")?;
-            warn!(
-                &self.logger,
-                "wrote {}; compressed {} -> {} bytes",
-                dest_path.display(),
-                in_size,
-                out_size
-            );
+        let gcc_path = dest_dir.join("gcc.tar.zst");
+
+        std::fs::write(&binutils_path, &binutils)?;
+        warn!(&self.logger, "wrote {}", binutils_path.display());
+        std::fs::write(&gcc_path, &gcc)?;
+        warn!(&self.logger, "wrote {}", gcc_path.display());
+
+        Ok((binutils, gcc))
+    }
+
+    /// Export an image to an OCI image layout if a destination is given.
+    async fn export_image_oci(
+        &self,
+        docker: &bollard::Docker,
+        image_id: &str,
+        name: &str,
+        dest_path: Option<&Path>,
+    ) -> Result<()> {
+        if let Some(dest_path) = dest_path {
+            crate::docker::export_image_to_oci(docker, image_id, name, dest_path)
+                .await
+                .context("exporting Docker image to OCI layout")?;
+            warn!(&self.logger, "wrote OCI layout to {}", dest_path.display());
         }
 
         Ok(())
     }
 
-    pub async fn docker_image_gcc(&self, dest_dir: Option<impl AsRef<Path>>) -> Result<()> {
-        let docker = self.docker_client()?;
+    pub async fn docker_image_clang(
+        &self,
+        dest_dir: Option<impl AsRef<Path>>,
+        oci_dest: Option<&Path>,
+    ) -> Result<()> {
+        let _lock = self.lock("images").await?;
+        let runtime = self.runtime()?;
+
+        let image_id = crate::docker::build_image_clang(
+            &self.logger,
+            runtime.as_ref(),
+            &self.image_settings,
+            &self.downloads_dir(),
+        )
+        .await?;
 
-        let image_id =
-            crate::docker::build_image_gcc(&self.logger, &docker, &self.cache_dir).await?;
+        self.export_image_oci(&self.docker_client()?, &image_id, "clang", oci_dest)
+            .await?;
+        self.export_image(
+            runtime.as_ref(),
+            &image_id,
+            dest_dir.as_ref().map(|x| x.as_ref()),
+        )
+        .await
+    }
 
-        if let Some(dest_path) = dest_dir {
-            let dest_path = dest_path.as_ref();
-            let (in_size, out_size) =
-                crate::docker::export_image_to_tar_zst(&docker, &image_id, dest_path)
-                    .await
-                    .context("exporting Docker image to file")?;
-            warn!(
-                &self.logger,
-                "wrote {}; compressed {} -> {} bytes",
-                dest_path.display(),
-                in_size,
-                out_size
-            );
-        }
+    pub async fn docker_image_gcc(
+        &self,
+        dest_dir: Option<impl AsRef<Path>>,
+        oci_dest: Option<&Path>,
+    ) -> Result<()> {
+        let _lock = self.lock("images").await?;
+        let runtime = self.runtime()?;
 
-        Ok(())
+        let image_id = crate::docker::build_image_gcc(
+            &self.logger,
+            runtime.as_ref(),
+            &self.image_settings,
+            &self.downloads_dir(),
+        )
+        .await?;
+
+        self.export_image_oci(&self.docker_client()?, &image_id, "gcc", oci_dest)
+            .await?;
+        self.export_image(
+            runtime.as_ref(),
+            &image_id,
+            dest_dir.as_ref().map(|x| x.as_ref()),
+        )
+        .await
     }
 
-    pub fn fetch_glibc_git(&self) -> Result<()> {
+    pub async fn fetch_glibc_git(&self) -> Result<()> {
+        let _lock = self.lock("glibc-git").await?;
         let repo_path = self.cache_dir.join("glibc.git");
 
         let repo = if repo_path.exists() {
@@ -230,7 +1068,7 @@ impl Environment {
 
         let refs = remote
             .list()?
-            .into_iter()
+            .iter()
             .filter(|head| head.name().starts_with("refs/tags/glibc-"))
             .filter(|head| !head.name().contains('^'))
             .filter(|head| repo.refname_to_id(head.name()) != Ok(head.oid()))
@@ -291,52 +1129,354 @@ impl Environment {
         Ok(())
     }
 
-    pub async fn docker_image_glibc(&self, dest_dir: Option<&Path>) -> Result<String> {
+    pub async fn docker_image_glibc(
+        &self,
+        dest_dir: Option<&Path>,
+        oci_dest: Option<&Path>,
+    ) -> Result<String> {
+        let _lock = self.lock("images").await?;
+        let runtime = self.runtime()?;
+
+        let image_id = crate::docker::build_image_glibc(
+            &self.logger,
+            runtime.as_ref(),
+            &self.image_settings,
+            &self.downloads_dir(),
+        )
+        .await?;
+
+        self.export_image_oci(&self.docker_client()?, &image_id, "glibc", oci_dest)
+            .await?;
+        self.export_image(runtime.as_ref(), &image_id, dest_dir)
+            .await?;
+
+        Ok(image_id)
+    }
+
+    /// Run an interactive shell in a builder image, building it if needed.
+    ///
+    /// Returns the exit code of the shell.
+    pub async fn docker_shell(&self, image: &str) -> Result<i64> {
         let docker = self.docker_client()?;
+        let runtime = DockerRuntime::new(docker.clone());
+
+        let image_lock = self.lock("images").await?;
+        let image_id = match image {
+            "clang" => {
+                crate::docker::build_image_clang(
+                    &self.logger,
+                    &runtime,
+                    &self.image_settings,
+                    &self.downloads_dir(),
+                )
+                .await?
+            }
+            "gcc" => {
+                crate::docker::build_image_gcc(
+                    &self.logger,
+                    &runtime,
+                    &self.image_settings,
+                    &self.downloads_dir(),
+                )
+                .await?
+            }
+            "glibc" => {
+                crate::docker::build_image_glibc(
+                    &self.logger,
+                    &runtime,
+                    &self.image_settings,
+                    &self.downloads_dir(),
+                )
+                .await?
+            }
+            _ => return Err(anyhow!("unknown builder image: {}", image)),
+        };
+        drop(image_lock);
+
+        let _lock = self.lock("sccache").await?;
+
+        crate::docker::interactive_shell(
+            &self.logger,
+            &docker,
+            &self.container_settings,
+            &image_id,
+            &self.cache_dir,
+        )
+        .await
+    }
+
+    /// Obtain the provenance labels of a builder image.
+    pub async fn docker_image_provenance(
+        &self,
+        image: &str,
+    ) -> Result<std::collections::BTreeMap<String, String>> {
+        crate::docker::image_provenance(&self.docker_client()?, image).await
+    }
+
+    fn sccache_volume_name(&self) -> &str {
+        self.container_settings
+            .sccache_volume
+            .as_deref()
+            .unwrap_or(crate::docker::DEFAULT_SCCACHE_VOLUME)
+    }
+
+    /// Print information about the sccache Docker volume.
+    pub async fn docker_sccache_inspect(&self) -> Result<()> {
+        crate::docker::inspect_sccache_volume(
+            &self.logger,
+            &self.docker_client()?,
+            self.sccache_volume_name(),
+        )
+        .await
+    }
+
+    /// Remove the sccache Docker volume.
+    pub async fn docker_sccache_prune(&self) -> Result<()> {
+        let _lock = self.lock("sccache").await?;
+
+        crate::docker::remove_sccache_volume(
+            &self.logger,
+            &self.docker_client()?,
+            self.sccache_volume_name(),
+        )
+        .await
+    }
+
+    /// Remove stale builder images created by previous runs.
+    pub async fn docker_gc(&self, dry_run: bool, prune_dangling: bool) -> Result<()> {
+        let _lock = self.lock("images").await?;
+
+        let reclaimed = crate::docker::garbage_collect_images(
+            &self.logger,
+            &self.docker_client()?,
+            dry_run,
+            prune_dangling,
+        )
+        .await?;
+
+        if !dry_run {
+            warn!(&self.logger, "reclaimed {} bytes", reclaimed);
+        }
+
+        Ok(())
+    }
+
+    /// Write a bundle of all downloads needed to build toolchains, fetching them if necessary.
+    ///
+    /// The bundle can seed the cache of another machine with [Self::import_sources].
+    pub fn bundle_sources(&self, dest_path: &Path) -> Result<()> {
+        let records = crate::downloads::required_records(
+            &self.image_settings.downloads,
+            self.image_settings.platform.arch(),
+        )?;
+
+        crate::downloads::fetch_records(
+            &self.logger,
+            &self.image_settings.downloads,
+            &records,
+            &self.downloads_dir(),
+        )?;
+
+        let fh = std::fs::File::create(dest_path)
+            .with_context(|| format!("creating {}", dest_path.display()))?;
+        crate::downloads::write_source_bundle(&records, &self.downloads_dir(), fh)
+            .context("writing source bundle")?;
+
+        warn!(
+            &self.logger,
+            "wrote {} downloads to {}",
+            records.len(),
+            dest_path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Seed the download cache from a bundle written by [Self::bundle_sources].
+    pub fn import_sources(&self, bundle_path: &Path) -> Result<()> {
+        let fh = std::fs::File::open(bundle_path)
+            .with_context(|| format!("opening {}", bundle_path.display()))?;
+
+        let imported =
+            crate::downloads::import_source_bundle(&self.logger, fh, &self.downloads_dir())
+                .context("importing source bundle")?;
+
+        warn!(&self.logger, "imported {} files", imported.len());
+
+        Ok(())
+    }
+
+    /// Verify the content of every file in the download cache.
+    ///
+    /// Returns whether all files are valid, or were deleted if `delete` is set.
+    pub fn verify_downloads(&self, delete: bool) -> Result<bool> {
+        let dir = self.downloads_dir();
+        if !dir.exists() {
+            return Ok(true);
+        }
+
+        let res = crate::downloads::verify_downloads(
+            &self.logger,
+            crate::downloads::DOWNLOADS.values(),
+            &dir,
+            delete,
+        )?;
+
+        warn!(
+            &self.logger,
+            "{} valid, {} corrupt, {} incomplete downloads",
+            res.valid,
+            res.corrupt.len(),
+            res.partial.len()
+        );
+
+        Ok(delete || res.corrupt.is_empty())
+    }
+
+    /// Remove downloaded files in the cache directory not referenced by the download manifest.
+    ///
+    /// Downloads stored directly in the cache directory by older versions are
+    /// also removed.
+    pub fn prune_downloads(&self, dry_run: bool) -> Result<()> {
+        let mut reclaimed = 0;
+
+        for dir in [self.downloads_dir(), self.cache_dir.clone()] {
+            if dir.exists() {
+                reclaimed += crate::downloads::prune_downloads(
+                    &self.logger,
+                    crate::downloads::DOWNLOADS.values(),
+                    &dir,
+                    dry_run,
+                )?;
+            }
+        }
+
+        if dry_run {
+            warn!(&self.logger, "would reclaim {} bytes", reclaimed);
+        } else {
+            warn!(&self.logger, "reclaimed {} bytes", reclaimed);
+        }
+
+        Ok(())
+    }
+
+    /// Check downloads for newer upstream versions, writing an updated manifest.
+    ///
+    /// Only records named in `names` are checked, or all records if it is
+    /// empty. The manifest is written to `dest_path` or stdout.
+    pub fn update_downloads(&self, names: &[&str], dest_path: Option<&Path>) -> Result<()> {
+        for name in names {
+            if !crate::downloads::DOWNLOADS.contains_key(*name) {
+                return Err(anyhow!("download record {} does not exist", name));
+            }
+        }
+
+        let records = crate::upstream::update_records(
+            &self.logger,
+            &self.image_settings.downloads,
+            crate::downloads::DOWNLOADS
+                .values()
+                .filter(|record| names.is_empty() || names.contains(&record.name.as_str())),
+        )?;
+
+        let manifest = toml::to_string_pretty(&records).context("serializing manifest")?;
+
+        if let Some(path) = dest_path {
+            std::fs::write(path, manifest)
+                .with_context(|| format!("writing {}", path.display()))?;
+        } else {
+            print!("{}", manifest);
+        }
+
+        Ok(())
+    }
+
+    /// Write glibc ABI metadata to a tar.zst file and/or a directory of JSON files.
+    pub async fn glibc_abis(
+        &self,
+        dest_path: Option<&Path>,
+        dest_dir: Option<&Path>,
+        image_path: Option<&Path>,
+    ) -> Result<()> {
+        let runtime = self.runtime()?;
+        let image_id = self.glibc_image_id(runtime.as_ref(), image_path).await?;
+
+        let abis = crate::docker::glibc_abis(
+            &self.logger,
+            runtime.as_ref(),
+            &self.container_settings,
+            &image_id,
+        )
+        .await
+        .context("collecting glibc ABIs")?;
 
-        let image_id =
-            crate::docker::build_image_glibc(&self.logger, &docker, &self.cache_dir).await?;
+        // Ensure the output is usable before writing it.
+        crate::glibc_abi::GlibcAbiDatabase::from_manifest(&abis).context("parsing glibc ABIs")?;
+
+        let tar_data = crate::tar::TarBuilder::from(abis).as_vec()?;
 
-        if let Some(dest_path) = dest_dir {
-            let (in_size, out_size) =
-                crate::docker::export_image_to_tar_zst(&docker, &image_id, dest_path)
-                    .await
-                    .context("exporting Docker image to file")?;
+        if let Some(dest_dir) = dest_dir {
+            let count = crate::extract::extract_tar(tar_data.as_slice(), dest_dir, |_| true)
+                .context("writing glibc ABI files")?;
             warn!(
                 &self.logger,
-                "wrote {}; compressed {} -> {} bytes",
-                dest_path.display(),
-                in_size,
-                out_size
+                "wrote {} ABI files to {}",
+                count,
+                dest_dir.display()
             );
         }
 
-        Ok(image_id)
-    }
+        if let Some(dest_path) = dest_path {
+            let tar_data = self.container_settings.zstd.encode_all(&tar_data)?;
+            std::fs::write(dest_path, &tar_data).context("writing glibc ABI tar.zst file")?;
+        }
 
-    /// Write glibc ABI metadata to a tar.zst file.
-    pub async fn glibc_abis(&self, dest_path: &Path, image_path: Option<&Path>) -> Result<()> {
-        let docker = self.docker_client()?;
+        Ok(())
+    }
 
-        let image_id = if let Some(image_path) = image_path {
-            let fh = std::fs::File::open(image_path).context("opening image archive")?;
+    /// Resolve the glibc Docker image, loading it from a file if provided.
+    async fn glibc_image_id(
+        &self,
+        runtime: &dyn ContainerRuntime,
+        image_path: Option<&Path>,
+    ) -> Result<String> {
+        if let Some(image_path) = image_path {
+            let _lock = self.lock("images").await?;
 
-            crate::docker::load_image_tar_zst(&self.logger, &docker, fh)
-                .await
-                .context("loading Docker image")?
+            self.import_image(runtime, image_path).await
         } else {
-            self.docker_image_glibc(None)
+            self.docker_image_glibc(None, None)
                 .await
-                .context("building glibc Docker image")?
-        };
-
-        let abis = crate::docker::glibc_abis(&self.logger, &docker, &image_id)
-            .await
-            .context("collecting glibc ABIs")?;
+                .context("building glibc Docker image")
+        }
+    }
 
-        let tar_data = crate::tar::TarBuilder::from(abis).as_vec()?;
-        let tar_data = zstd::encode_all(Cursor::new(tar_data), ZSTD_COMPRESSION_LEVEL)?;
-        std::fs::write(dest_path, &tar_data).context("writing glibc ABI tar.zst file")?;
+    /// Build a glibc configuration in a container and write its tar.zst to a directory.
+    async fn glibc_build_to_dir(
+        &self,
+        logger: &Logger,
+        runtime: &dyn ContainerRuntime,
+        image_id: &str,
+        dest_dir: &Path,
+        compiler: &str,
+        glibc: &str,
+    ) -> Result<()> {
+        let tar_data = crate::docker::glibc_build_single(
+            logger,
+            runtime,
+            &self.container_settings,
+            image_id,
+            compiler,
+            glibc,
+        )
+        .await
+        .context("building glibc in container")?;
+        let tar_data = crate::seekable::encode_tar(
+            &tar_data,
+            &self.container_settings.zstd,
+            &ArchiveProgressBar::new(logger),
+        )?;
+        std::fs::write(dest_dir.join(format!("glibc-{}.tar.zst", glibc)), &tar_data)?;
 
         Ok(())
     }
@@ -349,67 +1489,225 @@ impl Environment {
         glibc: &str,
         image_path: Option<&Path>,
     ) -> Result<()> {
-        let docker = self.docker_client()?;
+        let runtime = self.runtime()?;
+        let image_id = self.glibc_image_id(runtime.as_ref(), image_path).await?;
 
-        let image_id = if let Some(image_path) = image_path {
-            let fh = std::fs::File::open(image_path).context("opening image archive")?;
+        self.glibc_build_to_dir(
+            &self.logger,
+            runtime.as_ref(),
+            &image_id,
+            dest_dir,
+            compiler,
+            glibc,
+        )
+        .await
+    }
 
-            crate::docker::load_image_tar_zst(&self.logger, &docker, fh)
-                .await
-                .context("loading Docker image")?
+    /// Build multiple configurations of glibc concurrently.
+    ///
+    /// Each `(compiler, glibc)` pair is built in its own container, with at most
+    /// `parallelism` containers running at once. Log lines are prefixed with the
+    /// glibc configuration they belong to. All configurations are attempted even
+    /// if some of them fail.
+    pub async fn glibc_build_matrix(
+        &self,
+        dest_dir: &Path,
+        configs: &[(String, String)],
+        parallelism: usize,
+        image_path: Option<&Path>,
+    ) -> Result<()> {
+        let runtime = self.runtime()?;
+        let image_id = self.glibc_image_id(runtime.as_ref(), image_path).await?;
+
+        std::fs::create_dir_all(dest_dir).context("creating destination directory")?;
+
+        let results = futures_util::stream::iter(configs)
+            .map(|(compiler, glibc)| {
+                let logger = self.logger.new(slog::o!("glibc" => glibc.clone()));
+                let runtime = runtime.as_ref();
+                let image_id = &image_id;
+
+                async move {
+                    let res = self
+                        .glibc_build_to_dir(&logger, runtime, image_id, dest_dir, compiler, glibc)
+                        .await;
+
+                    (compiler, glibc, res)
+                }
+            })
+            .buffer_unordered(parallelism.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut failures = vec![];
+
+        for (compiler, glibc, res) in results {
+            match res {
+                Ok(()) => {
+                    warn!(&self.logger, "built glibc {} with {}", glibc, compiler);
+                }
+                Err(e) => {
+                    warn!(
+                        &self.logger,
+                        "failed to build glibc {} with {}: {:?}", glibc, compiler, e
+                    );
+                    failures.push(glibc.as_str());
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
         } else {
-            self.docker_image_glibc(None)
-                .await
-                .context("building glibc Docker image")?
-        };
+            Err(anyhow!(
+                "{} of {} glibc configurations failed: {}",
+                failures.len(),
+                configs.len(),
+                failures.join(", ")
+            ))
+        }
+    }
 
-        let tar_data =
-            crate::docker::glibc_build_single(&self.logger, &docker, &image_id, compiler, glibc)
-                .await
-                .context("building glibc in container")?;
-        let tar_data = zstd::encode_all(Cursor::new(tar_data), ZSTD_COMPRESSION_LEVEL)?;
-        std::fs::write(dest_dir.join(format!("glibc-{}.tar.zst", glibc)), &tar_data)?;
+    /// Extract the glibc builds selected by `filter` from source archives.
+    fn extract_glibc_builds(
+        &self,
+        source_archives: &[&Path],
+        filter: &BuildFilter,
+        dest_dir: &Path,
+    ) -> Result<()> {
+        std::fs::create_dir_all(dest_dir)?;
+
+        for source_archive in source_archives {
+            warn!(&self.logger, "extracting {}", source_archive.display());
+
+            crate::extract::extract_tar_zst(source_archive, dest_dir, |path| {
+                path.iter()
+                    .next()
+                    .is_none_or(|build| filter.matches(&build.to_string_lossy()))
+            })?;
+        }
 
         Ok(())
     }
 
     /// Unify glibc builds from source archives.
+    ///
+    /// The unified tree is written to `dest_dir` and/or streamed into
+    /// `dest_tar_zst`. File digests are cached in the cache directory, so
+    /// only files from new or rebuilt archives are hashed. Only builds selected
+    /// by the filter in `settings` are extracted and unified.
     pub fn glibc_unify(
         &self,
         source_archives: &[&Path],
         dest_dir: Option<&Path>,
         dest_tar_zst: Option<&Path>,
-        headers_only: bool,
+        settings: &UnifySettings,
+    ) -> Result<UnifySummary> {
+        let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        self.extract_glibc_builds(source_archives, &settings.filter, &source_dir)?;
+
+        crate::glibc::unify_glibc(
+            &self.logger,
+            &source_dir,
+            dest_dir,
+            dest_tar_zst,
+            Some(&self.cache_dir.join("glibc-unify-index.json")),
+            settings,
+            &self.container_settings.zstd,
+        )
+        .context("unifying glibc")
+    }
+
+    /// Write archives of the startup objects and linker scripts of glibc builds.
+    pub fn glibc_startup_files(
+        &self,
+        source_archives: &[&Path],
+        dest_dir: &Path,
+        filter: &BuildFilter,
     ) -> Result<()> {
         let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
-        let temp_root = temp_dir.path();
-        let source_dir = temp_root.join("glibcs");
-        let temp_unified_dir = temp_root.join("unified");
+        let source_dir = temp_dir.path().join("glibcs");
+        self.extract_glibc_builds(source_archives, filter, &source_dir)?;
 
-        let dest_dir = dest_dir.unwrap_or(&temp_unified_dir);
+        crate::glibc::extract_startup_files(
+            &self.logger,
+            &source_dir,
+            dest_dir,
+            filter,
+            &self.container_settings.zstd,
+        )
+        .context("extracting glibc startup files")?;
 
-        for source_archive in source_archives {
-            warn!(&self.logger, "extracting {}", source_archive.display());
-            let fh = std::fs::File::open(source_archive).context("opening glibc source archive")?;
-            let stream = zstd::stream::Decoder::new(fh).context("creating zstd decompressor")?;
-            let mut archive = tar::Archive::new(stream);
+        Ok(())
+    }
 
-            archive
-                .unpack(&source_dir)
-                .context("extracting tar archive")?;
-        }
+    /// Find headers whose content differs between glibc builds.
+    pub fn glibc_header_conflicts(
+        &self,
+        source_archives: &[&Path],
+        filter: &BuildFilter,
+    ) -> Result<Vec<HeaderConflict>> {
+        let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        self.extract_glibc_builds(source_archives, filter, &source_dir)?;
 
-        crate::glibc::unify_glibc(&self.logger, &source_dir, dest_dir, headers_only)
-            .context("unifying glibc")?;
+        crate::glibc_headers::find_header_conflicts(&source_dir, filter)
+            .context("comparing glibc headers")
+    }
 
-        if let Some(dest_tar_zst) = dest_tar_zst {
-            let tar_data = tar_from_directory(&self.logger, dest_dir, Some(Path::new("glibcs")))
-                .context("creating tar of unified glibc")?;
+    /// Write a multiarch include tree serving multiple glibc builds.
+    pub fn glibc_multiarch_headers(
+        &self,
+        source_archives: &[&Path],
+        dest_dir: &Path,
+        filter: &BuildFilter,
+    ) -> Result<()> {
+        let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        self.extract_glibc_builds(source_archives, filter, &source_dir)?;
 
-            let fh = std::fs::File::create(dest_tar_zst)?;
-            zstd::stream::copy_encode(Cursor::new(tar_data), fh, ZSTD_COMPRESSION_LEVEL)?;
-        }
+        crate::glibc::write_multiarch_headers(&self.logger, &source_dir, dest_dir, filter)
+            .context("writing multiarch headers")?;
 
         Ok(())
     }
+
+    /// Verify a unified glibc directory.
+    ///
+    /// If source archives are given, the builds selected by the filter in
+    /// `settings` must be fully represented in the directory.
+    ///
+    /// Returns whether the directory is valid.
+    pub fn glibc_verify_unified(
+        &self,
+        unified_dir: &Path,
+        source_archives: &[&Path],
+        settings: &UnifySettings,
+    ) -> Result<bool> {
+        let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+
+        let source_dir = if source_archives.is_empty() {
+            None
+        } else {
+            self.extract_glibc_builds(source_archives, &settings.filter, &source_dir)?;
+            Some(source_dir.as_path())
+        };
+
+        let res = crate::glibc::verify_unified(&self.logger, unified_dir, source_dir, settings)
+            .context("verifying unified glibc")?;
+
+        warn!(
+            &self.logger,
+            "{} checked, {} broken symlinks, {} corrupt, {} missing, {} mismatched",
+            res.checked,
+            res.broken_symlinks.len(),
+            res.corrupt.len(),
+            res.missing.len(),
+            res.mismatched.len()
+        );
+
+        Ok(res.is_valid())
+    }
 }
@@ -3,12 +3,29 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
-    crate::build::Environment,
+    crate::{
+        build::{parse_build_arg, Environment},
+        docker::{ArtifactFormat, BaseDistribution, BuilderPlatform, ClangStage, FileTransferMode},
+        downloads::ProxySettings,
+        glibc::{BuildFilter, FileFilter, UnifySettings},
+        glibc_abi::{GlibcAbiDatabase, GlibcVersion},
+        glibc_headers::ConflictSeverity,
+        tar::{diff_tar_files, TarDifference, TarMetadata},
+    },
     anyhow::{anyhow, Context, Result},
     clap::{App, AppSettings, Arg, ArgMatches, SubCommand},
-    std::path::{Path, PathBuf},
+    llvm_option_parser::{CommandOptions, OptionKind, ProgramOption},
+    slog::{debug, warn},
+    std::{
+        num::NonZeroU64,
+        path::{Path, PathBuf},
+        time::Duration,
+    },
 };
 
+#[cfg(target_family = "unix")]
+use tokio::signal::unix::SignalKind;
+
 const PCLANG_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Tool names that the clang frontend responds to.
@@ -43,12 +60,345 @@ pub fn run() -> Result<i32> {
 
 /// Run the main `pclang` CLI.
 pub fn run_pclang() -> Result<i32> {
-    let logger = crate::logging::logger();
+    // Only offer platforms and operating systems we have support tools for.
+    let platforms = BuilderPlatform::available()
+        .into_iter()
+        .map(|platform| platform.docker_platform())
+        .collect::<Vec<_>>();
+    let support_oses = crate::downloads::support_oses();
 
     let app = App::new("pclang")
         .setting(AppSettings::ArgRequiredElseHelp)
         .version(PCLANG_VERSION)
-        .author("Gregory Szorc <gregory.szorc@gmail.com>");
+        .author("Gregory Szorc <gregory.szorc@gmail.com>")
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("--verbose")
+                .multiple(true)
+                .global(true)
+                .help("Log more detail (-vv for trace output; overrides PCLANG_LOG)"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("--quiet")
+                .global(true)
+                .conflicts_with("verbose")
+                .help("Only log errors"),
+        )
+        .arg(
+            Arg::with_name("docker_socket")
+                .long("--docker-socket")
+                .takes_value(true)
+                .global(true)
+                .help("Address of Docker compatible daemon (e.g. unix:///run/podman/podman.sock)"),
+        )
+        .arg(
+            Arg::with_name("platform")
+                .long("--platform")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&platforms)
+                .help("Platform to build Docker images for (defaults to the host's)"),
+        )
+        .arg(
+            Arg::with_name("base_distribution")
+                .long("--base-distribution")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&[
+                    "debian-jessie",
+                    "debian-bullseye",
+                    "ubuntu-18.04",
+                    "centos-7",
+                ])
+                .help("Linux distribution to base builder images on"),
+        )
+        .arg(
+            Arg::with_name("transfer_mode")
+                .long("--transfer-mode")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["bind", "archive"])
+                .help("How files are exchanged with containers (archive supports remote daemons)"),
+        )
+        .arg(
+            Arg::with_name("container_timeout")
+                .long("--container-timeout")
+                .takes_value(true)
+                .global(true)
+                .help("Maximum number of seconds a single container may run for"),
+        )
+        .arg(
+            Arg::with_name("zstd_level")
+                .long("--zstd-level")
+                .takes_value(true)
+                .global(true)
+                .help("zstd compression level of artifacts and images (1-22; default 8)"),
+        )
+        .arg(
+            Arg::with_name("zstd_threads")
+                .long("--zstd-threads")
+                .takes_value(true)
+                .global(true)
+                .help("Number of zstd compression threads (0 to compress on the main thread; defaults to the number of CPUs)"),
+        )
+        .arg(
+            Arg::with_name("zstd_long")
+                .long("--zstd-long")
+                .global(true)
+                .help("Enable zstd long distance matching for better compression of large archives"),
+        )
+        .arg(
+            Arg::with_name("artifact_format")
+                .long("--artifact-format")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["zstd", "xz"])
+                .help("Compression format of the final clang toolchain archive (default zstd)"),
+        )
+        .arg(
+            Arg::with_name("xz_level")
+                .long("--xz-level")
+                .takes_value(true)
+                .global(true)
+                .help("xz compression level of artifacts with --artifact-format xz (0-9; default 6)"),
+        )
+        .arg(
+            Arg::with_name("tar_metadata")
+                .long("--tar-metadata")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["normalized", "preserve", "preserve-owners"])
+                .help("File metadata recorded in artifacts (preserve keeps modes except setuid/setgid and mtimes)"),
+        )
+        .arg(
+            Arg::with_name("download_jobs")
+                .long("--download-jobs")
+                .takes_value(true)
+                .global(true)
+                .help("Maximum number of files to download concurrently"),
+        )
+        .arg(
+            Arg::with_name("download_retries")
+                .long("--download-retries")
+                .takes_value(true)
+                .global(true)
+                .help("Number of times to retry failed downloads"),
+        )
+        .arg(
+            Arg::with_name("download_backoff")
+                .long("--download-backoff")
+                .takes_value(true)
+                .global(true)
+                .help("Seconds to wait before the first retry of a failed download; doubles with each retry"),
+        )
+        .arg(
+            Arg::with_name("download_timeout")
+                .long("--download-timeout")
+                .takes_value(true)
+                .global(true)
+                .help("Maximum number of seconds a single download may take"),
+        )
+        .arg(
+            Arg::with_name("download_rate_limit")
+                .long("--download-rate-limit")
+                .takes_value(true)
+                .global(true)
+                .help("Maximum combined rate of downloads in bytes per second"),
+        )
+        .arg(
+            Arg::with_name("http_proxy")
+                .long("--http-proxy")
+                .takes_value(true)
+                .global(true)
+                .help("Proxy for HTTP requests by downloads and containers (http or socks5 URL)"),
+        )
+        .arg(
+            Arg::with_name("https_proxy")
+                .long("--https-proxy")
+                .takes_value(true)
+                .global(true)
+                .help("Proxy for HTTPS requests by downloads and containers (http or socks5 URL)"),
+        )
+        .arg(
+            Arg::with_name("no_proxy")
+                .long("--no-proxy")
+                .takes_value(true)
+                .global(true)
+                .help("Comma separated hosts and domains to access without a proxy"),
+        )
+        .arg(
+            Arg::with_name("llvm_git")
+                .long("--llvm-git")
+                .takes_value(true)
+                .global(true)
+                .help("Build LLVM from a git commit instead of release tarballs (<url>#<commit>)"),
+        )
+        .arg(
+            Arg::with_name("glibc_git")
+                .long("--glibc-git")
+                .takes_value(true)
+                .global(true)
+                .help("Build glibc from a git commit instead of its release branch (<url>#<commit>)"),
+        )
+        .arg(
+            Arg::with_name("llvm_version")
+                .long("--llvm-version")
+                .takes_value(true)
+                .global(true)
+                .help("LLVM major version to build (e.g. 16)"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("--offline")
+                .takes_value(true)
+                .global(true)
+                .help("Directory of previously downloaded files to use instead of the network"),
+        )
+        .arg(
+            Arg::with_name("keyring")
+                .long("--keyring")
+                .takes_value(true)
+                .global(true)
+                .help("OpenPGP keyring to verify signatures of downloads with (requires GnuPG's gpgv)"),
+        )
+        .arg(
+            Arg::with_name("require_signatures")
+                .long("--require-signatures")
+                .global(true)
+                .help("Fail unless every download has a signature verified by the keyring"),
+        )
+        .arg(
+            Arg::with_name("sccache_volume")
+                .long("--sccache-volume")
+                .takes_value(true)
+                .global(true)
+                .help("Named Docker volume to hold the sccache cache (e.g. pclang-sccache)"),
+        )
+        .arg(
+            Arg::with_name("debian_snapshot")
+                .long("--debian-snapshot")
+                .takes_value(true)
+                .global(true)
+                .help("snapshot.debian.org timestamp to install Debian packages from (e.g. 20211107T145307Z)"),
+        )
+        .arg(
+            Arg::with_name("user_mapping")
+                .long("--user-mapping")
+                .takes_value(true)
+                .global(true)
+                .possible_values(&["auto", "fixed", "host", "rootless"])
+                .help("How the container build user maps to the host user"),
+        )
+        .arg(
+            Arg::with_name("network")
+                .long("--network")
+                .takes_value(true)
+                .global(true)
+                .help("Network mode of build containers (e.g. none or a custom network name)"),
+        )
+        .arg(
+            Arg::with_name("security_opt")
+                .long("--security-opt")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true)
+                .help("Security option for build containers (e.g. seccomp=profile.json)"),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .long("--wait")
+                .global(true)
+                .help("Wait for other pclang processes using the cache directory (default)"),
+        )
+        .arg(
+            Arg::with_name("no_wait")
+                .long("--no-wait")
+                .global(true)
+                .conflicts_with("wait")
+                .help("Fail instead of waiting for other pclang processes using the cache directory"),
+        )
+        .arg(
+            Arg::with_name("raw_output")
+                .long("--raw-output")
+                .global(true)
+                .help("Stream container output with colors and progress to the terminal instead of logging lines"),
+        )
+        .arg(
+            Arg::with_name("keep_failed")
+                .long("--keep-failed")
+                .global(true)
+                .help("Keep containers and temporary directories of failed builds for debugging"),
+        )
+        .arg(
+            Arg::with_name("privileged")
+                .long("--privileged")
+                .global(true)
+                .help("Run build containers in privileged mode"),
+        )
+        .arg(
+            Arg::with_name("cap_add")
+                .long("--cap-add")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true)
+                .help("Linux capability to add to build containers (e.g. SYS_PTRACE)"),
+        )
+        .arg(
+            Arg::with_name("build_arg")
+                .long("--build-arg")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true)
+                .help("Override a Dockerfile ARG such as LLVM_VERSION (KEY=VALUE)"),
+        )
+        .arg(
+            Arg::with_name("dockerfile_fragment")
+                .long("--dockerfile-fragment")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .global(true)
+                .help("File of extra lines to insert into generated Dockerfiles"),
+        )
+        .arg(
+            Arg::with_name("registry")
+                .long("--registry")
+                .takes_value(true)
+                .global(true)
+                .help("Registry to pull prebuilt builder images from (e.g. ghcr.io/example)"),
+        )
+        .arg(
+            Arg::with_name("cache_dir")
+                .long("--cache-dir")
+                .takes_value(true)
+                .global(true)
+                .help("Directory to keep downloads, caches, and logs in (overrides PCLANG_CACHE_DIR)"),
+        )
+        .arg(
+            Arg::with_name("config_file")
+                .long("--config-file")
+                .takes_value(true)
+                .global(true)
+                .help("pclang.toml file to read settings from (overrides PCLANG_CONFIG)"),
+        )
+        .arg(
+            Arg::with_name("cache_images")
+                .long("--cache-images")
+                .global(true)
+                .help("Save builder images to the cache directory and load them from there"),
+        )
+        .arg(
+            Arg::with_name("push_images")
+                .long("--push-images")
+                .global(true)
+                .help("Push newly built builder images to the registry"),
+        );
 
     let app = app.subcommand(
         SubCommand::with_name("build-clang")
@@ -59,6 +409,13 @@ pub fn run_pclang() -> Result<i32> {
                     .takes_value(true)
                     .help("Directory containing gcc toolchain artifact used to bootstrap clang"),
             )
+            .arg(
+                Arg::with_name("stage")
+                    .long("--stage")
+                    .takes_value(true)
+                    .possible_values(&["stage1", "stage2"])
+                    .help("Only run the given stage of the clang build"),
+            )
             .arg(
                 Arg::with_name("image")
                     .long("--image")
@@ -66,10 +423,15 @@ pub fn run_pclang() -> Result<i32> {
                     .help("Docker image tar.zst to use"),
             )
             .arg(
-                Arg::with_name("dest")
-                    .required(true)
+                Arg::with_name("dest_dir")
+                    .long("--dest")
+                    .takes_value(true)
+                    .conflicts_with("dest")
                     .help("Destination directory to write artifacts to"),
-            ),
+            )
+            .arg(Arg::with_name("dest").help(
+                "Destination directory to write artifacts to (defaults to dest_dir of pclang.toml)",
+            )),
     );
 
     let app = app.subcommand(
@@ -82,10 +444,15 @@ pub fn run_pclang() -> Result<i32> {
                     .help("Docker image tar.zst to use"),
             )
             .arg(
-                Arg::with_name("dest")
-                    .required(true)
-                    .help("Destination directory to write artifacts to"),
-            ),
+                Arg::with_name("dest_dir")
+                    .long("--dest")
+                    .takes_value(true)
+                    .conflicts_with("dest")
+                    .help("Destination directory to write binutils.tar.zst and gcc.tar.zst to"),
+            )
+            .arg(Arg::with_name("dest").help(
+                "Destination directory to write artifacts to (defaults to dest_dir of pclang.toml)",
+            )),
     );
 
     let app = app.subcommand(
@@ -111,43 +478,197 @@ pub fn run_pclang() -> Result<i32> {
                     .takes_value(true)
                     .help("Docker image tar.zst to use"),
             )
+            .arg(Arg::with_name("dest").help(
+                "Destination directory to write artifacts to (defaults to dest_dir of pclang.toml)",
+            )),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("build-glibc-matrix")
+            .about("Build multiple glibc configurations concurrently")
+            .arg(
+                Arg::with_name("config")
+                    .long("--config")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("COMPILER:GLIBC pair to build (can be specified multiple times; defaults to targets of pclang.toml)"),
+            )
+            .arg(
+                Arg::with_name("jobs")
+                    .long("--jobs")
+                    .short("j")
+                    .takes_value(true)
+                    .default_value("2")
+                    .help("Maximum number of containers to run concurrently"),
+            )
+            .arg(
+                Arg::with_name("image")
+                    .long("--image")
+                    .takes_value(true)
+                    .help("Docker image tar.zst to use"),
+            )
             .arg(
                 Arg::with_name("dest")
-                    .required(true)
-                    .help("Destination directory to write artifacts to"),
+                    .help("Destination directory to write artifacts to (defaults to dest_dir of pclang.toml)"),
             ),
     );
 
     let app = app.subcommand(
-        SubCommand::with_name("docker-image-clang")
-            .about("Build Docker image for building Clang")
+        SubCommand::with_name("bundle-sources")
+            .about(
+                "Write an archive of all downloads needed to build, for use without network access",
+            )
             .arg(
                 Arg::with_name("dest")
-                    .long("--dest")
-                    .takes_value(true)
-                    .help("Destination file to write zstd compressed image to"),
+                    .required(true)
+                    .help("Path of tar file to write"),
             ),
     );
 
     let app = app.subcommand(
-        SubCommand::with_name("docker-image-gcc")
-            .about("Build Docker image for building GCC")
+        SubCommand::with_name("docker-gc")
+            .about("Remove stale builder Docker images")
             .arg(
-                Arg::with_name("dest")
-                    .long("--dest")
-                    .takes_value(true)
-                    .help("Destination file to write zstd compressed image to"),
+                Arg::with_name("dry_run")
+                    .long("--dry-run")
+                    .help("Only list images that would be removed"),
+            )
+            .arg(
+                Arg::with_name("prune_dangling")
+                    .long("--prune-dangling")
+                    .help("Also remove all dangling images and their layers"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("docker-sccache-inspect")
+            .about("Show information about the sccache Docker volume"),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("docker-sccache-prune").about("Remove the sccache Docker volume"),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("docker-shell")
+            .about("Run an interactive shell in a builder Docker image")
+            .arg(
+                Arg::with_name("image")
+                    .required(true)
+                    .possible_values(&["clang", "gcc", "glibc"])
+                    .help("Builder image to run"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("doctor")
+            .about("Verify the Docker daemon and host are ready for builds"),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("docker-image")
+            .about("Build a builder Docker image")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(docker_image_command(
+                "clang",
+                "Build Docker image for building Clang",
+            ))
+            .subcommand(docker_image_command(
+                "gcc",
+                "Build Docker image for building GCC",
+            ))
+            .subcommand(docker_image_command(
+                "glibc",
+                "Build Docker image for building glibc",
+            )),
+    );
+
+    // Spellings predating `docker-image <name>`, kept for existing scripts.
+    let app = app
+        .subcommand(
+            docker_image_command(
+                "docker-image-clang",
+                "Build Docker image for building Clang",
+            )
+            .setting(AppSettings::Hidden),
+        )
+        .subcommand(
+            docker_image_command("docker-image-gcc", "Build Docker image for building GCC")
+                .setting(AppSettings::Hidden),
+        )
+        .subcommand(
+            docker_image_command(
+                "docker-image-glibc",
+                "Build Docker image for building glibc",
+            )
+            .setting(AppSettings::Hidden),
+        );
+
+    let app = app.subcommand(
+        SubCommand::with_name("docker-image-provenance")
+            .about("Show how a builder Docker image was built")
+            .arg(
+                Arg::with_name("image")
+                    .required(true)
+                    .help("Image ID or reference (e.g. portable-clang:clang)"),
             ),
     );
 
     let app = app.subcommand(
-        SubCommand::with_name("docker-image-glibc")
-            .about("Build Docker image for building glibc")
+        SubCommand::with_name("extract-file")
+            .about(
+                "Extract a single file from a toolchain artifact without decompressing all of it",
+            )
+            .arg(
+                Arg::with_name("list")
+                    .long("--list")
+                    .help("List files in the artifact instead of extracting one"),
+            )
+            .arg(
+                Arg::with_name("archive")
+                    .required(true)
+                    .help("Path to a seekable tar.zst artifact"),
+            )
+            .arg(
+                Arg::with_name("path")
+                    .required_unless("list")
+                    .help("Path of the file in the artifact (e.g. clang/bin/clang)"),
+            )
             .arg(
                 Arg::with_name("dest")
-                    .long("--dest")
+                    .required_unless("list")
+                    .help("Path to write the file to"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("fetch-all")
+            .about("Fetch GCC sources, LLVM sources, and support artifacts with a summary")
+            .arg(
+                Arg::with_name("os")
+                    .long("--os")
+                    .takes_value(true)
+                    .possible_values(&support_oses)
+                    .default_value("linux")
+                    .help("Operating system to fetch tools for"),
+            )
+            .arg(
+                Arg::with_name("arch")
+                    .long("--arch")
                     .takes_value(true)
-                    .help("Destination file to write zstd compressed image to"),
+                    .default_value("x86_64")
+                    .help("Machine architecture to fetch tools for (e.g. aarch64)"),
+            )
+            .arg(
+                Arg::with_name("parallel")
+                    .long("--parallel")
+                    .help("Fetch the groups of artifacts concurrently"),
+            )
+            .arg(
+                Arg::with_name("dest")
+                    .required(true)
+                    .help("Directory to write gcc, llvm, and support subdirectories to"),
             ),
     );
 
@@ -169,6 +690,31 @@ pub fn run_pclang() -> Result<i32> {
     let app = app.subcommand(
         SubCommand::with_name("fetch-support")
             .about("Fetch support artifacts needed to build")
+            .arg(
+                Arg::with_name("os")
+                    .long("--os")
+                    .takes_value(true)
+                    .possible_values(&support_oses)
+                    .default_value("linux")
+                    .help("Operating system to fetch tools for"),
+            )
+            .arg(
+                Arg::with_name("arch")
+                    .long("--arch")
+                    .takes_value(true)
+                    .default_value("x86_64")
+                    .help("Machine architecture to fetch tools for (e.g. aarch64)"),
+            )
+            .arg(
+                Arg::with_name("dest")
+                    .required(true)
+                    .help("Directory to write files to"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("fetch-linux-headers")
+            .about("Download the Linux kernel source tarball providing headers")
             .arg(
                 Arg::with_name("dest")
                     .required(true)
@@ -186,6 +732,16 @@ pub fn run_pclang() -> Result<i32> {
             ),
     );
 
+    let app = app.subcommand(
+        SubCommand::with_name("fetch-musl-sources")
+            .about("Download musl libc source tarballs")
+            .arg(
+                Arg::with_name("dest")
+                    .required(true)
+                    .help("Directory to write files to"),
+            ),
+    );
+
     let app = app.subcommand(
         SubCommand::with_name("fetch-secure")
             .about("Download a URL while checking its SHA-256")
@@ -202,6 +758,27 @@ pub fn run_pclang() -> Result<i32> {
             ),
     );
 
+    let app = app.subcommand(
+        SubCommand::with_name("glibc-abi-query")
+            .about("Query which glibc releases provide symbols")
+            .arg(
+                Arg::with_name("abis")
+                    .required(true)
+                    .help("glibc ABIs tar.zst file written by glibc-abis"),
+            )
+            .arg(
+                Arg::with_name("target")
+                    .required(true)
+                    .help("glibc target to query (e.g. x86_64-linux-gnu)"),
+            )
+            .arg(
+                Arg::with_name("symbols")
+                    .multiple(true)
+                    .required(true)
+                    .help("Symbols to query, optionally qualified as symbol@VERSION"),
+            ),
+    );
+
     let app = app.subcommand(
         SubCommand::with_name("glibc-abis")
             .about("Compute glibc ABIs")
@@ -211,32 +788,111 @@ pub fn run_pclang() -> Result<i32> {
                     .takes_value(true)
                     .help("Docker image tar.zst to use"),
             )
+            .arg(
+                Arg::with_name("dest_dir")
+                    .long("--dest-dir")
+                    .takes_value(true)
+                    .help("Directory to write the individual ABI JSON files to"),
+            )
             .arg(
                 Arg::with_name("dest")
+                    .required_unless("dest_dir")
+                    .help("Destination tar.zst filename to write ABIs to"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("glibc-header-conflicts")
+            .about("Report headers whose content differs between glibc builds")
+            .arg(
+                Arg::with_name("include")
+                    .long("--include")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only compare builds whose name matches this pattern (e.g. x86_64-*)"),
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .long("--exclude")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Skip builds whose name matches this pattern"),
+            )
+            .arg(
+                Arg::with_name("report")
+                    .long("--report")
+                    .takes_value(true)
+                    .help("JSON file to write a report of all conflicts to"),
+            )
+            .arg(
+                Arg::with_name("zstd_archives")
+                    .multiple(true)
                     .required(true)
-                    .help("Destination filename to write file to"),
+                    .help("Paths to tar.zst archives to process"),
             ),
     );
 
     let app = app.subcommand(
-        SubCommand::with_name("glibc-unify")
-            .about("Unify a directory tree of glibc files")
+        SubCommand::with_name("glibc-multiarch-headers")
+            .about("Write an include tree serving multiple glibc builds")
             .arg(
-                Arg::with_name("headers_only")
-                    .long("--headers-only")
-                    .help("Whether to only operate on usr/include directory"),
+                Arg::with_name("include")
+                    .long("--include")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only process builds whose name matches this pattern (e.g. x86_64-*)"),
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .long("--exclude")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Skip builds whose name matches this pattern"),
             )
             .arg(
                 Arg::with_name("dest_dir")
                     .long("--dest-dir")
                     .takes_value(true)
-                    .help("Directory to write output to"),
+                    .required(true)
+                    .help("Directory to write the include tree to"),
             )
             .arg(
-                Arg::with_name("dest_tar_zst")
-                    .long("--dest-tar-zst")
+                Arg::with_name("zstd_archives")
+                    .multiple(true)
+                    .required(true)
+                    .help("Paths to tar.zst archives to process"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("glibc-startup-files")
+            .about("Write archives of the startup objects and linker scripts of glibc builds")
+            .arg(
+                Arg::with_name("include")
+                    .long("--include")
                     .takes_value(true)
-                    .help("Destination tar.zst file to write to"),
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only process builds whose name matches this pattern (e.g. x86_64-*)"),
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .long("--exclude")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Skip builds whose name matches this pattern"),
+            )
+            .arg(
+                Arg::with_name("dest_dir")
+                    .long("--dest-dir")
+                    .takes_value(true)
+                    .required(true)
+                    .help("Directory to write archives to"),
             )
             .arg(
                 Arg::with_name("zstd_archives")
@@ -246,50 +902,803 @@ pub fn run_pclang() -> Result<i32> {
             ),
     );
 
-    let matches = app.get_matches();
-
-    let env = Environment::new(logger)?;
-
-    tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap()
-        .block_on(async {
-            match matches.subcommand() {
-                ("build-clang", Some(args)) => command_build_clang(env, args).await,
-                ("build-gcc", Some(args)) => command_build_gcc(env, args).await,
-                ("build-glibc", Some(args)) => command_build_glibc(env, args).await,
-                ("docker-image-clang", Some(args)) => command_docker_image_clang(env, args).await,
-                ("docker-image-gcc", Some(args)) => command_docker_image_gcc(env, args).await,
-                ("docker-image-glibc", Some(args)) => command_docker_image_glibc(env, args).await,
-                ("fetch-gcc-sources", Some(args)) => command_fetch_gcc_sources(env, args).await,
-                ("fetch-glibc-git", Some(_)) => command_fetch_glibc_git(env).await,
-                ("fetch-llvm-sources", Some(args)) => command_fetch_llvm_sources(env, args).await,
-                ("fetch-secure", Some(args)) => command_fetch_secure(env, args).await,
-                ("fetch-support", Some(args)) => command_fetch_support(env, args).await,
-                ("glibc-abis", Some(args)) => command_glibc_abis(env, args).await,
-                ("glibc-unify", Some(args)) => command_glibc_unify(env, args).await,
-                _ => Err(anyhow!("invalid sub-command")),
-            }
-        })
-}
-
-async fn command_build_clang<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
-    let dest_dir = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
-    let bootstrap_dir = args.value_of_os("bootstrap_dir").map(PathBuf::from);
-    let image_path = args.value_of_os("image").map(Path::new);
-
-    env.build_clang(&dest_dir, image_path, bootstrap_dir)
-        .await?;
-
-    Ok(0)
-}
-
-async fn command_build_gcc<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
-    let dest_dir = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
-    let image_path = args.value_of_os("image").map(Path::new);
+    let app = app.subcommand(
+        SubCommand::with_name("glibc-stub-libraries")
+            .about("Build stub shared libraries for linking against an older glibc")
+            .arg(
+                Arg::with_name("clang")
+                    .long("--clang")
+                    .takes_value(true)
+                    .default_value("clang")
+                    .help("clang executable to assemble and link stubs with"),
+            )
+            .arg(
+                Arg::with_name("include")
+                    .long("--include")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only process targets whose name matches this pattern (e.g. x86_64-*)"),
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .long("--exclude")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Skip targets whose name matches this pattern"),
+            )
+            .arg(
+                Arg::with_name("abis")
+                    .required(true)
+                    .help("glibc ABIs tar.zst file written by glibc-abis"),
+            )
+            .arg(
+                Arg::with_name("glibc")
+                    .required(true)
+                    .help("glibc version to define symbols of (e.g. 2.17)"),
+            )
+            .arg(
+                Arg::with_name("dest_dir")
+                    .required(true)
+                    .help("Directory to write a directory of libraries per target to"),
+            ),
+    );
 
-    env.build_gcc(&dest_dir, image_path).await?;
+    let app = app.subcommand(
+        SubCommand::with_name("glibc-symver-headers")
+            .about("Generate headers pinning glibc symbols to an older glibc")
+            .arg(
+                Arg::with_name("abis")
+                    .required(true)
+                    .help("glibc ABIs tar.zst file written by glibc-abis"),
+            )
+            .arg(
+                Arg::with_name("glibc")
+                    .required(true)
+                    .help("glibc version to pin symbols to (e.g. 2.17)"),
+            )
+            .arg(
+                Arg::with_name("dest_dir")
+                    .required(true)
+                    .help("Directory to write a header per target to"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("glibc-unify")
+            .about("Unify a directory tree of glibc files")
+            .arg(
+                Arg::with_name("headers_only")
+                    .long("--headers-only")
+                    .help("Only unify headers (shorthand for --include-files '**/*.h')"),
+            )
+            .arg(
+                Arg::with_name("include_files")
+                    .long("--include-files")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only unify files in builds matching this glob (e.g. usr/include/**)"),
+            )
+            .arg(
+                Arg::with_name("exclude_files")
+                    .long("--exclude-files")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Skip files in builds matching this glob (e.g. usr/share/**)"),
+            )
+            .arg(
+                Arg::with_name("duplicates")
+                    .long("--duplicates")
+                    .takes_value(true)
+                    .possible_values(&["symlink", "copy"])
+                    .default_value("symlink")
+                    .help("Whether duplicate files are symlinked to a common copy or copied"),
+            )
+            .arg(
+                Arg::with_name("digest")
+                    .long("--digest")
+                    .takes_value(true)
+                    .possible_values(&["sha256", "blake3", "xxh3"])
+                    .default_value("sha256")
+                    .help("Digest algorithm used to find duplicate files"),
+            )
+            .arg(
+                Arg::with_name("dest_dir")
+                    .long("--dest-dir")
+                    .takes_value(true)
+                    .help("Directory to write output to"),
+            )
+            .arg(
+                Arg::with_name("dest_tar_zst")
+                    .long("--dest-tar-zst")
+                    .takes_value(true)
+                    .help("Destination tar.zst file to write to"),
+            )
+            .arg(
+                Arg::with_name("include")
+                    .long("--include")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only unify builds whose name matches this pattern (e.g. x86_64-*)"),
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .long("--exclude")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Skip builds whose name matches this pattern"),
+            )
+            .arg(
+                Arg::with_name("min_glibc")
+                    .long("--min-glibc")
+                    .takes_value(true)
+                    .help("Skip builds of glibc releases older than this (e.g. 2.17)"),
+            )
+            .arg(
+                Arg::with_name("max_glibc")
+                    .long("--max-glibc")
+                    .takes_value(true)
+                    .help("Skip builds of glibc releases newer than this"),
+            )
+            .arg(
+                Arg::with_name("report")
+                    .long("--report")
+                    .takes_value(true)
+                    .help("JSON file to write a report of deduplicated files to"),
+            )
+            .arg(
+                Arg::with_name("zstd_archives")
+                    .multiple(true)
+                    .required(true)
+                    .help("Paths to tar.zst archives to process"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("glibc-verify-unified")
+            .about("Verify a unified directory tree of glibc files")
+            .arg(
+                Arg::with_name("headers_only")
+                    .long("--headers-only")
+                    .help("Whether the tree only contains headers"),
+            )
+            .arg(
+                Arg::with_name("include_files")
+                    .long("--include-files")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("--include-files glob the tree was unified with"),
+            )
+            .arg(
+                Arg::with_name("exclude_files")
+                    .long("--exclude-files")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("--exclude-files glob the tree was unified with"),
+            )
+            .arg(
+                Arg::with_name("digest")
+                    .long("--digest")
+                    .takes_value(true)
+                    .possible_values(&["sha256", "blake3", "xxh3"])
+                    .default_value("sha256")
+                    .help("Digest algorithm the tree was unified with"),
+            )
+            .arg(
+                Arg::with_name("include")
+                    .long("--include")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only check builds whose name matches this pattern (e.g. x86_64-*)"),
+            )
+            .arg(
+                Arg::with_name("exclude")
+                    .long("--exclude")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Skip builds whose name matches this pattern"),
+            )
+            .arg(
+                Arg::with_name("min_glibc")
+                    .long("--min-glibc")
+                    .takes_value(true)
+                    .help("--min-glibc the tree was unified with"),
+            )
+            .arg(
+                Arg::with_name("max_glibc")
+                    .long("--max-glibc")
+                    .takes_value(true)
+                    .help("--max-glibc the tree was unified with"),
+            )
+            .arg(
+                Arg::with_name("dir")
+                    .required(true)
+                    .help("Unified directory to verify"),
+            )
+            .arg(
+                Arg::with_name("zstd_archives")
+                    .multiple(true)
+                    .help("glibc builds that must be represented in the directory"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("import-sources")
+            .about("Seed the download cache from an archive written by bundle-sources")
+            .arg(
+                Arg::with_name("bundle")
+                    .required(true)
+                    .help("Path of tar file to import"),
+            ),
+    );
+
+    let app =
+        app.subcommand(
+            SubCommand::with_name("join-archive")
+                .about("Reassemble an artifact split by split-archive")
+                .arg(Arg::with_name("extract").long("--extract").help(
+                    "Extract the tar.zst artifact into the destination instead of joining it",
+                ))
+                .arg(
+                    Arg::with_name("manifest")
+                        .required(true)
+                        .help("Path to the .parts.json manifest of the artifact"),
+                )
+                .arg(
+                    Arg::with_name("dest_dir")
+                        .required(true)
+                        .help("Directory to write the artifact to"),
+                ),
+        );
+
+    let app = app.subcommand(
+        SubCommand::with_name("options")
+            .about("List command line options of an LLVM command (uses --llvm-version)")
+            .arg(
+                Arg::with_name("search")
+                    .long("--search")
+                    .takes_value(true)
+                    .help("Only list options whose name or help text contains this text"),
+            )
+            .arg(
+                Arg::with_name("command")
+                    .required(true)
+                    .help("LLVM command to list options of (e.g. clang or lld-elf)"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("prune-downloads")
+            .about("Remove downloaded files no longer referenced by the download manifest")
+            .arg(
+                Arg::with_name("dry_run")
+                    .long("--dry-run")
+                    .help("Only list files that would be removed"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("split-archive")
+            .about("Split a large artifact into parts described by a manifest")
+            .arg(
+                Arg::with_name("part_size")
+                    .long("--part-size")
+                    .takes_value(true)
+                    .help("Maximum size of parts (e.g. 500MiB; default 2GiB)"),
+            )
+            .arg(
+                Arg::with_name("archive")
+                    .required(true)
+                    .help("Path to the artifact to split"),
+            )
+            .arg(
+                Arg::with_name("dest_dir")
+                    .required(true)
+                    .help("Directory to write parts and the manifest to"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("tar-diff")
+            .about("Compare the entries of two tar or tar.zst archives")
+            .arg(
+                Arg::with_name("report")
+                    .long("--report")
+                    .takes_value(true)
+                    .help("JSON file to write a report of all differences to"),
+            )
+            .arg(
+                Arg::with_name("old")
+                    .required(true)
+                    .help("Path to the first archive"),
+            )
+            .arg(
+                Arg::with_name("new")
+                    .required(true)
+                    .help("Path to the second archive"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("update-downloads")
+            .about("Find newer upstream versions of downloads and write an updated manifest for review")
+            .arg(
+                Arg::with_name("dest")
+                    .long("--dest")
+                    .takes_value(true)
+                    .help("File to write the manifest to instead of stdout"),
+            )
+            .arg(
+                Arg::with_name("names")
+                    .multiple(true)
+                    .help("Download records to check (default: all)"),
+            ),
+    );
+
+    let app = app.subcommand(
+        SubCommand::with_name("verify-downloads")
+            .about("Verify the content of every file in the download cache")
+            .arg(
+                Arg::with_name("delete")
+                    .long("--delete")
+                    .help("Delete corrupt files and incomplete downloads"),
+            ),
+    );
+
+    let matches = app.get_matches();
+
+    // Global arguments are only propagated to the sub-command matches. Those of
+    // nested sub-commands like `docker-image clang` have them all.
+    let mut subcommand_matches = matches.subcommand().1;
+    while let Some(args) = subcommand_matches.and_then(|args| args.subcommand().1) {
+        subcommand_matches = Some(args);
+    }
+
+    let verbose = subcommand_matches
+        .map(|args| args.occurrences_of("verbose"))
+        .unwrap_or(0)
+        .max(matches.occurrences_of("verbose"));
+    let quiet = subcommand_matches
+        .map(|args| args.is_present("quiet"))
+        .unwrap_or(false)
+        || matches.is_present("quiet");
+    let log_level =
+        crate::logging::resolve_level(verbose, quiet, std::env::var("PCLANG_LOG").ok().as_deref())
+            .context("resolving PCLANG_LOG")?;
+    let logger = crate::logging::logger(log_level);
+
+    let cache_dir = subcommand_matches
+        .and_then(|args| args.value_of_os("cache_dir"))
+        .or_else(|| matches.value_of_os("cache_dir"))
+        .map(PathBuf::from);
+
+    let docker_socket = subcommand_matches
+        .and_then(|args| args.value_of("docker_socket"))
+        .or_else(|| matches.value_of("docker_socket"))
+        .map(|x| x.to_string());
+
+    let platform = subcommand_matches
+        .and_then(|args| args.value_of("platform"))
+        .or_else(|| matches.value_of("platform"))
+        .map(|x| x.parse::<BuilderPlatform>())
+        .transpose()?;
+
+    let base_distribution = subcommand_matches
+        .and_then(|args| args.value_of("base_distribution"))
+        .or_else(|| matches.value_of("base_distribution"))
+        .map(|x| x.parse::<BaseDistribution>())
+        .transpose()?;
+
+    let transfer_mode = subcommand_matches
+        .and_then(|args| args.value_of("transfer_mode"))
+        .or_else(|| matches.value_of("transfer_mode"))
+        .map(|x| x.parse::<FileTransferMode>())
+        .transpose()?;
+
+    let container_timeout = subcommand_matches
+        .and_then(|args| args.value_of("container_timeout"))
+        .or_else(|| matches.value_of("container_timeout"))
+        .map(|x| {
+            x.parse::<u64>()
+                .map(Duration::from_secs)
+                .context("parsing --container-timeout")
+        })
+        .transpose()?;
+
+    let download_jobs = subcommand_matches
+        .and_then(|args| args.value_of("download_jobs"))
+        .or_else(|| matches.value_of("download_jobs"))
+        .map(|x| x.parse::<usize>().context("parsing --download-jobs"))
+        .transpose()?;
+
+    let zstd_level = subcommand_matches
+        .and_then(|args| args.value_of("zstd_level"))
+        .or_else(|| matches.value_of("zstd_level"))
+        .map(|x| x.parse::<i32>().context("parsing --zstd-level"))
+        .transpose()?;
+    let zstd_threads = subcommand_matches
+        .and_then(|args| args.value_of("zstd_threads"))
+        .or_else(|| matches.value_of("zstd_threads"))
+        .map(|x| x.parse::<u32>().context("parsing --zstd-threads"))
+        .transpose()?;
+    let zstd_long = subcommand_matches
+        .map(|args| args.is_present("zstd_long"))
+        .unwrap_or(false)
+        || matches.is_present("zstd_long");
+
+    let artifact_format = subcommand_matches
+        .and_then(|args| args.value_of("artifact_format"))
+        .or_else(|| matches.value_of("artifact_format"))
+        .map(|x| x.parse::<ArtifactFormat>())
+        .transpose()?;
+    let xz_level = subcommand_matches
+        .and_then(|args| args.value_of("xz_level"))
+        .or_else(|| matches.value_of("xz_level"))
+        .map(|x| x.parse::<u32>().context("parsing --xz-level"))
+        .transpose()?;
+
+    let tar_metadata = subcommand_matches
+        .and_then(|args| args.value_of("tar_metadata"))
+        .or_else(|| matches.value_of("tar_metadata"))
+        .map(|x| x.parse::<TarMetadata>())
+        .transpose()?;
+
+    let download_retries = subcommand_matches
+        .and_then(|args| args.value_of("download_retries"))
+        .or_else(|| matches.value_of("download_retries"))
+        .map(|x| x.parse::<u32>().context("parsing --download-retries"))
+        .transpose()?;
+
+    let download_backoff = subcommand_matches
+        .and_then(|args| args.value_of("download_backoff"))
+        .or_else(|| matches.value_of("download_backoff"))
+        .map(|x| {
+            x.parse::<u64>()
+                .map(Duration::from_secs)
+                .context("parsing --download-backoff")
+        })
+        .transpose()?;
+
+    let download_timeout = subcommand_matches
+        .and_then(|args| args.value_of("download_timeout"))
+        .or_else(|| matches.value_of("download_timeout"))
+        .map(|x| {
+            x.parse::<u64>()
+                .map(Duration::from_secs)
+                .context("parsing --download-timeout")
+        })
+        .transpose()?;
+
+    let download_rate_limit = subcommand_matches
+        .and_then(|args| args.value_of("download_rate_limit"))
+        .or_else(|| matches.value_of("download_rate_limit"))
+        .map(|x| {
+            x.parse::<NonZeroU64>()
+                .context("parsing --download-rate-limit")
+        })
+        .transpose()?;
+
+    let llvm_version = subcommand_matches
+        .and_then(|args| args.value_of("llvm_version"))
+        .or_else(|| matches.value_of("llvm_version"));
+
+    let proxy = ProxySettings {
+        http: subcommand_matches
+            .and_then(|args| args.value_of("http_proxy"))
+            .or_else(|| matches.value_of("http_proxy"))
+            .map(|x| x.to_string()),
+        https: subcommand_matches
+            .and_then(|args| args.value_of("https_proxy"))
+            .or_else(|| matches.value_of("https_proxy"))
+            .map(|x| x.to_string()),
+        no_proxy: subcommand_matches
+            .and_then(|args| args.value_of("no_proxy"))
+            .or_else(|| matches.value_of("no_proxy"))
+            .map(|x| x.to_string()),
+    };
+
+    let llvm_git = subcommand_matches
+        .and_then(|args| args.value_of("llvm_git"))
+        .or_else(|| matches.value_of("llvm_git"));
+
+    let glibc_git = subcommand_matches
+        .and_then(|args| args.value_of("glibc_git"))
+        .or_else(|| matches.value_of("glibc_git"));
+
+    let offline_dir = subcommand_matches
+        .and_then(|args| args.value_of_os("offline"))
+        .or_else(|| matches.value_of_os("offline"))
+        .map(PathBuf::from);
+
+    let keyring = subcommand_matches
+        .and_then(|args| args.value_of_os("keyring"))
+        .or_else(|| matches.value_of_os("keyring"))
+        .map(PathBuf::from);
+
+    let require_signatures = subcommand_matches
+        .map(|args| args.is_present("require_signatures"))
+        .unwrap_or(false)
+        || matches.is_present("require_signatures");
+
+    let sccache_volume = subcommand_matches
+        .and_then(|args| args.value_of("sccache_volume"))
+        .or_else(|| matches.value_of("sccache_volume"))
+        .map(|x| x.to_string());
+
+    let user_mapping = subcommand_matches
+        .and_then(|args| args.value_of("user_mapping"))
+        .or_else(|| matches.value_of("user_mapping"));
+
+    let debian_snapshot = subcommand_matches
+        .and_then(|args| args.value_of("debian_snapshot"))
+        .or_else(|| matches.value_of("debian_snapshot"));
+
+    let network_mode = subcommand_matches
+        .and_then(|args| args.value_of("network"))
+        .or_else(|| matches.value_of("network"))
+        .map(|x| x.to_string());
+
+    let security_opts = subcommand_matches
+        .and_then(|args| args.values_of("security_opt"))
+        .or_else(|| matches.values_of("security_opt"))
+        .into_iter()
+        .flatten()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    let privileged = subcommand_matches
+        .map(|args| args.is_present("privileged"))
+        .unwrap_or(false)
+        || matches.is_present("privileged");
+    let no_wait = subcommand_matches
+        .map(|args| args.is_present("no_wait"))
+        .unwrap_or(false)
+        || matches.is_present("no_wait");
+    let wait = subcommand_matches
+        .map(|args| args.is_present("wait"))
+        .unwrap_or(false)
+        || matches.is_present("wait");
+    let lock_wait = if no_wait {
+        Some(false)
+    } else if wait {
+        Some(true)
+    } else {
+        None
+    };
+
+    let raw_output = subcommand_matches
+        .map(|args| args.is_present("raw_output"))
+        .unwrap_or(false)
+        || matches.is_present("raw_output");
+    let keep_failed = subcommand_matches
+        .map(|args| args.is_present("keep_failed"))
+        .unwrap_or(false)
+        || matches.is_present("keep_failed");
+    let cap_add = subcommand_matches
+        .and_then(|args| args.values_of("cap_add"))
+        .or_else(|| matches.values_of("cap_add"))
+        .into_iter()
+        .flatten()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+
+    let dockerfile_fragments = subcommand_matches
+        .and_then(|args| args.values_of_os("dockerfile_fragment"))
+        .or_else(|| matches.values_of_os("dockerfile_fragment"))
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+
+    let build_args = subcommand_matches
+        .and_then(|args| args.values_of("build_arg"))
+        .or_else(|| matches.values_of("build_arg"))
+        .into_iter()
+        .flatten()
+        .map(parse_build_arg)
+        .collect::<Result<Vec<_>>>()?;
+
+    let registry = subcommand_matches
+        .and_then(|args| args.value_of("registry"))
+        .or_else(|| matches.value_of("registry"))
+        .map(|x| x.to_string());
+    let push_images = subcommand_matches
+        .map(|args| args.is_present("push_images"))
+        .unwrap_or(false)
+        || matches.is_present("push_images");
+
+    let cache_images = subcommand_matches
+        .map(|args| args.is_present("cache_images"))
+        .unwrap_or(false)
+        || matches.is_present("cache_images");
+
+    let config_file = subcommand_matches
+        .and_then(|args| args.value_of_os("config_file"))
+        .or_else(|| matches.value_of_os("config_file"))
+        .map(Path::new);
+    let config = crate::config::load_config(config_file)?;
+
+    let env = Environment::new(logger, &config)?
+        .with_cache_dir(cache_dir)
+        .with_docker_socket(docker_socket)
+        .with_platform(platform)
+        .with_base_distribution(base_distribution)
+        .with_transfer_mode(transfer_mode)
+        .with_container_timeout(container_timeout)
+        .with_zstd(zstd_level, zstd_threads, zstd_long)?
+        .with_artifact_format(artifact_format, xz_level)?
+        .with_tar_metadata(tar_metadata)
+        .with_download_jobs(download_jobs)
+        .with_download_retries(download_retries)
+        .with_download_backoff(download_backoff)
+        .with_download_rate_limit(download_rate_limit)
+        .with_download_timeout(download_timeout)
+        .with_offline_dir(offline_dir)
+        .with_keyring(keyring)
+        .with_require_signatures(require_signatures)
+        .with_keep_failed(keep_failed)
+        .with_raw_output(raw_output)
+        .with_lock_wait(lock_wait)
+        .with_sccache_volume(sccache_volume)
+        .with_network_mode(network_mode)
+        .with_registry(registry, push_images)
+        .with_image_cache(cache_images)
+        .with_build_args(build_args)
+        .with_dockerfile_fragments(dockerfile_fragments)?
+        .with_debian_snapshot(debian_snapshot)?
+        .with_llvm_version(llvm_version)?
+        .with_llvm_git(llvm_git)?
+        .with_proxy(proxy)
+        .with_glibc_git(glibc_git)?
+        .with_container_security(security_opts, privileged, cap_add)?
+        .with_user_mapping(user_mapping)?;
+
+    debug!(
+        env.logger(),
+        "using cache directory {}",
+        env.cache_dir().display()
+    );
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let cleanup_env = env.clone();
+
+            // Dropping the command future on interruption drops any temporary
+            // directories it holds, which deletes them.
+            tokio::select! {
+                res = run_command(env, &matches) => res,
+                signal = shutdown_signal() => {
+                    let (name, code) = signal?;
+                    warn!(cleanup_env.logger(), "received {}; cleaning up", name);
+                    cleanup_env.cleanup_running_containers().await?;
+
+                    Ok(code)
+                }
+            }
+        })
+}
+
+/// Resolve when the process receives SIGINT or SIGTERM.
+///
+/// Resolves to the name of the signal and the exit code to use.
+async fn shutdown_signal() -> Result<(&'static str, i32)> {
+    #[cfg(target_family = "unix")]
+    {
+        let mut terminate = tokio::signal::unix::signal(SignalKind::terminate())
+            .context("installing SIGTERM handler")?;
+
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => {
+                res.context("installing SIGINT handler")?;
+                Ok(("SIGINT", 130))
+            }
+            _ = terminate.recv() => Ok(("SIGTERM", 143)),
+        }
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    {
+        tokio::signal::ctrl_c()
+            .await
+            .context("installing Ctrl-C handler")?;
+
+        Ok(("Ctrl-C", 130))
+    }
+}
+
+async fn run_command<'a>(env: Environment, matches: &ArgMatches<'a>) -> Result<i32> {
+    match matches.subcommand() {
+        ("build-clang", Some(args)) => command_build_clang(env, args).await,
+        ("build-gcc", Some(args)) => command_build_gcc(env, args).await,
+        ("build-glibc", Some(args)) => command_build_glibc(env, args).await,
+        ("build-glibc-matrix", Some(args)) => command_build_glibc_matrix(env, args).await,
+        ("bundle-sources", Some(args)) => command_bundle_sources(env, args).await,
+        ("docker-gc", Some(args)) => command_docker_gc(env, args).await,
+        ("docker-image", Some(args)) => match args.subcommand() {
+            ("clang", Some(args)) => command_docker_image_clang(env, args).await,
+            ("gcc", Some(args)) => command_docker_image_gcc(env, args).await,
+            ("glibc", Some(args)) => command_docker_image_glibc(env, args).await,
+            _ => Err(anyhow!("invalid sub-command")),
+        },
+        ("docker-image-clang", Some(args)) => command_docker_image_clang(env, args).await,
+        ("docker-image-gcc", Some(args)) => command_docker_image_gcc(env, args).await,
+        ("docker-image-glibc", Some(args)) => command_docker_image_glibc(env, args).await,
+        ("docker-image-provenance", Some(args)) => command_docker_image_provenance(env, args).await,
+        ("docker-sccache-inspect", Some(_)) => command_docker_sccache_inspect(env).await,
+        ("docker-sccache-prune", Some(_)) => command_docker_sccache_prune(env).await,
+        ("docker-shell", Some(args)) => command_docker_shell(env, args).await,
+        ("doctor", Some(_)) => command_doctor(env).await,
+        ("extract-file", Some(args)) => command_extract_file(env, args).await,
+        ("fetch-all", Some(args)) => command_fetch_all(env, args).await,
+        ("fetch-gcc-sources", Some(args)) => command_fetch_gcc_sources(env, args).await,
+        ("fetch-glibc-git", Some(_)) => command_fetch_glibc_git(env).await,
+        ("fetch-linux-headers", Some(args)) => command_fetch_linux_headers(env, args).await,
+        ("fetch-llvm-sources", Some(args)) => command_fetch_llvm_sources(env, args).await,
+        ("fetch-musl-sources", Some(args)) => command_fetch_musl_sources(env, args).await,
+        ("fetch-secure", Some(args)) => command_fetch_secure(env, args).await,
+        ("fetch-support", Some(args)) => command_fetch_support(env, args).await,
+        ("glibc-abi-query", Some(args)) => command_glibc_abi_query(env, args).await,
+        ("glibc-abis", Some(args)) => command_glibc_abis(env, args).await,
+        ("glibc-header-conflicts", Some(args)) => command_glibc_header_conflicts(env, args).await,
+        ("glibc-multiarch-headers", Some(args)) => command_glibc_multiarch_headers(env, args).await,
+        ("glibc-startup-files", Some(args)) => command_glibc_startup_files(env, args).await,
+        ("glibc-stub-libraries", Some(args)) => command_glibc_stub_libraries(env, args).await,
+        ("glibc-symver-headers", Some(args)) => command_glibc_symver_headers(env, args).await,
+        ("glibc-unify", Some(args)) => command_glibc_unify(env, args).await,
+        ("glibc-verify-unified", Some(args)) => command_glibc_verify_unified(env, args).await,
+        ("import-sources", Some(args)) => command_import_sources(env, args).await,
+        ("join-archive", Some(args)) => command_join_archive(env, args).await,
+        ("options", Some(args)) => command_options(env, args).await,
+        ("prune-downloads", Some(args)) => command_prune_downloads(env, args).await,
+        ("split-archive", Some(args)) => command_split_archive(env, args).await,
+        ("tar-diff", Some(args)) => command_tar_diff(env, args).await,
+        ("update-downloads", Some(args)) => command_update_downloads(env, args).await,
+        ("verify-downloads", Some(args)) => command_verify_downloads(env, args).await,
+        _ => Err(anyhow!("invalid sub-command")),
+    }
+}
+
+async fn command_build_clang<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dest_dir = env.dest_dir(
+        args.value_of_os("dest_dir")
+            .or_else(|| args.value_of_os("dest"))
+            .map(Path::new),
+    )?;
+    let bootstrap_dir = args.value_of_os("bootstrap_dir").map(PathBuf::from);
+    let image_path = args.value_of_os("image").map(Path::new);
+    let stage = args
+        .value_of("stage")
+        .map(|x| x.parse::<ClangStage>())
+        .transpose()?;
+
+    env.ensure_preflight().await?;
+    env.build_clang(&dest_dir, image_path, bootstrap_dir, stage)
+        .await?;
+
+    Ok(0)
+}
+
+async fn command_build_gcc<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dest_dir = env.dest_dir(
+        args.value_of_os("dest_dir")
+            .or_else(|| args.value_of_os("dest"))
+            .map(Path::new),
+    )?;
+    let image_path = args.value_of_os("image").map(Path::new);
+
+    env.ensure_preflight().await?;
+    env.build_gcc(&dest_dir, image_path).await?;
 
     Ok(0)
 }
@@ -299,7 +1708,7 @@ async fn command_build_glibc<'a>(env: Environment, args: &ArgMatches<'a>) -> Res
         .value_of("compiler")
         .expect("compiler argument is required");
     let glibc = args.value_of("glibc").expect("glibc argument is required");
-    let dest_dir = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
+    let dest_dir = env.dest_dir(args.value_of_os("dest").map(Path::new))?;
     let image_path = args.value_of_os("image").map(Path::new);
 
     env.glibc_build_single(&dest_dir, compiler, glibc, image_path)
@@ -308,21 +1717,194 @@ async fn command_build_glibc<'a>(env: Environment, args: &ArgMatches<'a>) -> Res
     Ok(0)
 }
 
+async fn command_build_glibc_matrix<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let configs = args
+        .values_of("config")
+        .map(|values| values.map(String::from).collect::<Vec<_>>())
+        .unwrap_or_else(|| env.targets().to_vec());
+    if configs.is_empty() {
+        return Err(anyhow!("no --config given and no targets configured"));
+    }
+    let configs = configs
+        .iter()
+        .map(|value| {
+            value
+                .split_once(':')
+                .map(|(compiler, glibc)| (compiler.to_string(), glibc.to_string()))
+                .ok_or_else(|| anyhow!("config must be of the form COMPILER:GLIBC: {}", value))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let jobs = args
+        .value_of("jobs")
+        .expect("jobs argument has default")
+        .parse::<usize>()
+        .context("parsing --jobs")?;
+    let dest_dir = env.dest_dir(args.value_of_os("dest").map(Path::new))?;
+    let image_path = args.value_of_os("image").map(Path::new);
+
+    env.glibc_build_matrix(&dest_dir, &configs, jobs, image_path)
+        .await?;
+
+    Ok(0)
+}
+
+async fn command_bundle_sources<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
+
+    env.bundle_sources(&dest)?;
+
+    Ok(0)
+}
+
+async fn command_docker_gc<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    env.docker_gc(
+        args.is_present("dry_run"),
+        args.is_present("prune_dangling"),
+    )
+    .await?;
+
+    Ok(0)
+}
+
 async fn command_docker_image_clang<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
-    env.docker_image_clang(args.value_of_os("dest")).await?;
+    env.docker_image_clang(
+        args.value_of_os("dest"),
+        args.value_of_os("oci").map(Path::new),
+    )
+    .await?;
 
     Ok(0)
 }
 
 async fn command_docker_image_gcc<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
-    env.docker_image_gcc(args.value_of_os("dest")).await?;
+    env.docker_image_gcc(
+        args.value_of_os("dest"),
+        args.value_of_os("oci").map(Path::new),
+    )
+    .await?;
 
     Ok(0)
 }
 
 async fn command_docker_image_glibc<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
-    env.docker_image_glibc(args.value_of("dest").map(Path::new))
-        .await?;
+    env.docker_image_glibc(
+        args.value_of("dest").map(Path::new),
+        args.value_of_os("oci").map(Path::new),
+    )
+    .await?;
+
+    Ok(0)
+}
+
+async fn command_docker_image_provenance<'a>(
+    env: Environment,
+    args: &ArgMatches<'a>,
+) -> Result<i32> {
+    let image = args.value_of("image").expect("image argument is required");
+
+    let labels = env.docker_image_provenance(image).await?;
+
+    if labels.is_empty() {
+        warn!(env.logger(), "{} has no provenance labels", image);
+        return Ok(1);
+    }
+
+    for (key, value) in labels {
+        warn!(env.logger(), "{}: {}", key, value);
+    }
+
+    Ok(0)
+}
+
+async fn command_docker_sccache_inspect(env: Environment) -> Result<i32> {
+    env.docker_sccache_inspect().await?;
+
+    Ok(0)
+}
+
+async fn command_docker_sccache_prune(env: Environment) -> Result<i32> {
+    env.docker_sccache_prune().await?;
+
+    Ok(0)
+}
+
+async fn command_docker_shell<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let image = args.value_of("image").expect("image argument is required");
+
+    Ok(env.docker_shell(image).await? as i32)
+}
+
+async fn command_doctor(env: Environment) -> Result<i32> {
+    let report = env.preflight().await?;
+
+    for check in &report.checks {
+        match &check.result {
+            Ok(detail) => warn!(env.logger(), "ok    {}: {}", check.name, detail),
+            Err(e) => warn!(env.logger(), "FAIL  {}: {:#}", check.name, e),
+        }
+    }
+
+    Ok(if report.is_ok() { 0 } else { 1 })
+}
+
+async fn command_extract_file<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let archive = Path::new(
+        args.value_of_os("archive")
+            .expect("archive argument is required"),
+    );
+
+    if args.is_present("list") {
+        let archive = crate::seekable::SeekableArchive::open(archive)?;
+
+        for (path, entry) in archive.entries() {
+            match &entry.link_name {
+                Some(target) => warn!(env.logger(), "{} -> {}", path.display(), target.display()),
+                None => warn!(
+                    env.logger(),
+                    "{:o} {:>12} {}",
+                    entry.mode,
+                    entry.size,
+                    path.display()
+                ),
+            }
+        }
+
+        return Ok(0);
+    }
+
+    let path = Path::new(args.value_of_os("path").expect("path argument is required"));
+    let dest = Path::new(args.value_of_os("dest").expect("dest argument is required"));
+
+    let data = crate::seekable::extract_file(archive, path)?;
+    std::fs::write(dest, &data).with_context(|| format!("writing {}", dest.display()))?;
+    warn!(
+        env.logger(),
+        "wrote {} ({} bytes)",
+        dest.display(),
+        data.len()
+    );
+
+    Ok(0)
+}
+
+async fn command_fetch_all<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
+    let os = args.value_of("os").expect("os argument has default");
+    let arch = args.value_of("arch").expect("arch argument has default");
+
+    let summary = crate::downloads::fetch_all(
+        env.logger(),
+        env.download_settings(),
+        os,
+        arch,
+        &env.downloads_dir(),
+        &dest,
+        args.is_present("parallel"),
+    )?;
+
+    for (name, files) in &summary {
+        warn!(env.logger(), "{}: {} files", name, files.len());
+    }
 
     Ok(0)
 }
@@ -330,13 +1912,33 @@ async fn command_docker_image_glibc<'a>(env: Environment, args: &ArgMatches<'a>)
 async fn command_fetch_gcc_sources<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
     let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
 
-    crate::downloads::fetch_gcc_sources(env.logger(), &dest).context("fetching GCC sources")?;
+    let paths = crate::downloads::fetch_gcc_sources(
+        env.logger(),
+        env.download_settings(),
+        &env.downloads_dir(),
+    )
+    .context("fetching GCC sources")?;
+    crate::downloads::copy_to_dir(&paths, &dest)?;
 
     Ok(0)
 }
 
 async fn command_fetch_glibc_git(env: Environment) -> Result<i32> {
-    env.fetch_glibc_git()?;
+    env.fetch_glibc_git().await?;
+
+    Ok(0)
+}
+
+async fn command_fetch_linux_headers<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
+
+    let path = crate::downloads::fetch_linux_headers(
+        env.logger(),
+        env.download_settings(),
+        &env.downloads_dir(),
+    )
+    .context("fetching Linux headers")?;
+    crate::downloads::copy_to_dir(&[path], &dest)?;
 
     Ok(0)
 }
@@ -344,7 +1946,27 @@ async fn command_fetch_glibc_git(env: Environment) -> Result<i32> {
 async fn command_fetch_llvm_sources<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
     let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
 
-    crate::downloads::fetch_llvm_sources(env.logger(), &dest).context("fetching LLVM sources")?;
+    let paths = crate::downloads::fetch_llvm_sources(
+        env.logger(),
+        env.download_settings(),
+        &env.downloads_dir(),
+    )
+    .context("fetching LLVM sources")?;
+    crate::downloads::copy_to_dir(&paths, &dest)?;
+
+    Ok(0)
+}
+
+async fn command_fetch_musl_sources<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
+
+    let paths = crate::downloads::fetch_musl_sources(
+        env.logger(),
+        env.download_settings(),
+        &env.downloads_dir(),
+    )
+    .context("fetching musl sources")?;
+    crate::downloads::copy_to_dir(&paths, &dest)?;
 
     Ok(0)
 }
@@ -382,35 +2004,514 @@ async fn command_fetch_secure<'a>(env: Environment, args: &ArgMatches<'a>) -> Re
 async fn command_fetch_support<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
     let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
 
-    crate::downloads::fetch_linux_x86_64_support(env.logger(), &dest)
-        .context("fetching support artifacts")?;
+    let os = args.value_of("os").expect("os argument has default");
+    let arch = args.value_of("arch").expect("arch argument has default");
+
+    let paths = match os {
+        "macos" => crate::downloads::fetch_macos_support(
+            env.logger(),
+            env.download_settings(),
+            arch,
+            &env.downloads_dir(),
+        ),
+        _ => crate::downloads::fetch_linux_support(
+            env.logger(),
+            env.download_settings(),
+            arch,
+            &env.downloads_dir(),
+        ),
+    }
+    .context("fetching support artifacts")?;
+    crate::downloads::copy_to_dir(&paths, &dest)?;
+
+    Ok(0)
+}
+
+async fn command_glibc_abi_query<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let abis = Path::new(args.value_of_os("abis").expect("abis argument is required"));
+    let target = args
+        .value_of("target")
+        .expect("target argument is required");
+    let symbols = args
+        .values_of("symbols")
+        .expect("symbols argument is required")
+        .collect::<Vec<_>>();
+
+    let db = GlibcAbiDatabase::from_tar_zst(abis).context("loading glibc ABIs")?;
+
+    for symbol in &symbols {
+        let name = symbol.split_once('@').map_or(*symbol, |(name, _)| name);
+
+        match db.first_version(target, name)? {
+            Some(version) => warn!(
+                env.logger(),
+                "{}: first provided by glibc {}", name, version
+            ),
+            None => warn!(env.logger(), "{}: not provided", name),
+        }
+    }
+
+    match db.max_version(target, symbols)? {
+        Some(version) => warn!(env.logger(), "requires glibc {}", version),
+        None => warn!(env.logger(), "requires no versioned glibc symbols"),
+    }
 
     Ok(0)
 }
 
 async fn command_glibc_abis<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
     let image_path = args.value_of_os("image").map(Path::new);
-    let dest = PathBuf::from(args.value_of_os("dest").expect("dest argument is required"));
+    let dest = args.value_of_os("dest").map(Path::new);
+    let dest_dir = args.value_of_os("dest_dir").map(Path::new);
 
-    env.glibc_abis(&dest, image_path)
+    env.glibc_abis(dest, dest_dir, image_path)
         .await
         .context("resolving glibc ABIs")?;
 
     Ok(0)
 }
 
+async fn command_glibc_header_conflicts<'a>(
+    env: Environment,
+    args: &ArgMatches<'a>,
+) -> Result<i32> {
+    let report = args.value_of_os("report").map(Path::new);
+    let zstd_archives = args
+        .values_of_os("zstd_archives")
+        .expect("zstd_archives argument is required")
+        .map(Path::new)
+        .collect::<Vec<_>>();
+
+    let conflicts = env.glibc_header_conflicts(&zstd_archives, &build_filter(args))?;
+
+    for conflict in &conflicts {
+        if conflict.severity == ConflictSeverity::Cosmetic {
+            continue;
+        }
+
+        warn!(
+            env.logger(),
+            "{}: {:?} differences between {} variants{}",
+            conflict.path.display(),
+            conflict.severity,
+            conflict.variants.len(),
+            if conflict.symbols.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", conflict.symbols.join(", "))
+            }
+        );
+    }
+
+    if let Some(report) = report {
+        let data = serde_json::to_vec_pretty(&conflicts)?;
+        std::fs::write(report, data).with_context(|| format!("writing {}", report.display()))?;
+    }
+
+    Ok(0)
+}
+
+async fn command_glibc_multiarch_headers<'a>(
+    env: Environment,
+    args: &ArgMatches<'a>,
+) -> Result<i32> {
+    let dest_dir = Path::new(
+        args.value_of_os("dest_dir")
+            .expect("dest_dir argument is required"),
+    );
+    let zstd_archives = args
+        .values_of_os("zstd_archives")
+        .expect("zstd_archives argument is required")
+        .map(Path::new)
+        .collect::<Vec<_>>();
+
+    env.glibc_multiarch_headers(&zstd_archives, dest_dir, &build_filter(args))?;
+
+    Ok(0)
+}
+
+async fn command_glibc_startup_files<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dest_dir = Path::new(
+        args.value_of_os("dest_dir")
+            .expect("dest_dir argument is required"),
+    );
+    let zstd_archives = args
+        .values_of_os("zstd_archives")
+        .expect("zstd_archives argument is required")
+        .map(Path::new)
+        .collect::<Vec<_>>();
+
+    env.glibc_startup_files(&zstd_archives, dest_dir, &build_filter(args))?;
+
+    Ok(0)
+}
+
+async fn command_glibc_stub_libraries<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let clang = Path::new(
+        args.value_of_os("clang")
+            .expect("clang has a default value"),
+    );
+    let abis = Path::new(args.value_of_os("abis").expect("abis argument is required"));
+    let glibc = args
+        .value_of("glibc")
+        .expect("glibc argument is required")
+        .parse::<GlibcVersion>()?;
+    let dest_dir = Path::new(
+        args.value_of_os("dest_dir")
+            .expect("dest_dir argument is required"),
+    );
+
+    let db = GlibcAbiDatabase::from_tar_zst(abis).context("loading glibc ABIs")?;
+
+    crate::glibc_abi::build_stub_libraries(
+        env.logger(),
+        &db,
+        &glibc,
+        clang,
+        &build_filter(args),
+        dest_dir,
+    )
+    .context("building glibc stub libraries")?;
+
+    Ok(0)
+}
+
+async fn command_glibc_symver_headers<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let abis = Path::new(args.value_of_os("abis").expect("abis argument is required"));
+    let glibc = args
+        .value_of("glibc")
+        .expect("glibc argument is required")
+        .parse::<GlibcVersion>()?;
+    let dest_dir = PathBuf::from(
+        args.value_of_os("dest_dir")
+            .expect("dest_dir argument is required"),
+    );
+
+    let db = GlibcAbiDatabase::from_tar_zst(abis).context("loading glibc ABIs")?;
+
+    std::fs::create_dir_all(&dest_dir).context("creating destination directory")?;
+
+    for target in db.targets() {
+        let dest_path = dest_dir.join(format!("{}.h", target));
+        warn!(env.logger(), "writing {}", dest_path.display());
+        std::fs::write(&dest_path, db.symver_header(target, &glibc)?)
+            .with_context(|| format!("writing {}", dest_path.display()))?;
+    }
+
+    Ok(0)
+}
+
+/// A sub-command building a builder image, optionally exporting it.
+fn docker_image_command<'a, 'b>(name: &str, about: &'b str) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .arg(
+            Arg::with_name("dest")
+                .long("--export")
+                .alias("dest")
+                .takes_value(true)
+                .help("Destination file to write zstd compressed image to"),
+        )
+        .arg(
+            Arg::with_name("oci")
+                .long("--oci")
+                .takes_value(true)
+                .help("Write image as an OCI layout to this directory or .tar file"),
+        )
+}
+
+fn patterns(args: &ArgMatches, name: &str) -> Vec<String> {
+    args.values_of(name)
+        .map(|values| values.map(String::from).collect::<Vec<_>>())
+        .unwrap_or_default()
+}
+
+/// Resolve the glibc builds selected by `--include` and `--exclude`.
+fn build_filter(args: &ArgMatches) -> BuildFilter {
+    BuildFilter {
+        include: patterns(args, "include"),
+        exclude: patterns(args, "exclude"),
+    }
+}
+
+/// Resolve glibc unification settings from arguments.
+fn unify_settings(args: &ArgMatches) -> Result<UnifySettings> {
+    let mut include_files = patterns(args, "include_files");
+    if args.is_present("headers_only") {
+        include_files.push("**/*.h".to_string());
+    }
+
+    Ok(UnifySettings {
+        files: FileFilter::new(&include_files, &patterns(args, "exclude_files"))?,
+        duplicates: args
+            .value_of("duplicates")
+            .map(|x| x.parse())
+            .transpose()?
+            .unwrap_or_default(),
+        filter: build_filter(args),
+        digest: args
+            .value_of("digest")
+            .expect("digest has a default value")
+            .parse()?,
+        min_glibc: args.value_of("min_glibc").map(|x| x.parse()).transpose()?,
+        max_glibc: args.value_of("max_glibc").map(|x| x.parse()).transpose()?,
+    })
+}
+
 async fn command_glibc_unify<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
-    let headers_only = args.is_present("headers_only");
     let dest_dir = args.value_of_os("dest_dir").map(Path::new);
     let dest_tar_zst = args.value_of_os("dest_tar_zst").map(Path::new);
+    let report = args.value_of_os("report").map(Path::new);
+    let settings = unify_settings(args)?;
     let zstd_archives = args
         .values_of_os("zstd_archives")
         .expect("zstd_archives argument is required")
-        .into_iter()
         .map(Path::new)
         .collect::<Vec<_>>();
 
-    env.glibc_unify(&zstd_archives, dest_dir, dest_tar_zst, headers_only)?;
+    let summary = env.glibc_unify(&zstd_archives, dest_dir, dest_tar_zst, &settings)?;
+
+    if let Some(report) = report {
+        let data = serde_json::to_vec_pretty(&summary)?;
+        std::fs::write(report, data).with_context(|| format!("writing {}", report.display()))?;
+    }
+
+    Ok(0)
+}
+
+async fn command_glibc_verify_unified<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let dir = Path::new(args.value_of_os("dir").expect("dir argument is required"));
+    let zstd_archives = args
+        .values_of_os("zstd_archives")
+        .map(|values| values.map(Path::new).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let ok = env.glibc_verify_unified(dir, &zstd_archives, &unify_settings(args)?)?;
+
+    Ok(if ok { 0 } else { 1 })
+}
+
+async fn command_import_sources<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let bundle = PathBuf::from(
+        args.value_of_os("bundle")
+            .expect("bundle argument is required"),
+    );
+
+    env.import_sources(&bundle)?;
+
+    Ok(0)
+}
+
+async fn command_join_archive<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let manifest = Path::new(
+        args.value_of_os("manifest")
+            .expect("manifest argument is required"),
+    );
+    let dest_dir = Path::new(
+        args.value_of_os("dest_dir")
+            .expect("dest_dir argument is required"),
+    );
+
+    if args.is_present("extract") {
+        let count = crate::split::extract_parts(manifest, dest_dir)?;
+        warn!(
+            env.logger(),
+            "extracted {} entries to {}",
+            count,
+            dest_dir.display()
+        );
+    } else {
+        let path = crate::split::join_parts(manifest, dest_dir)?;
+        warn!(env.logger(), "wrote {}", path.display());
+    }
+
+    Ok(0)
+}
+
+/// Format the spellings of an option, e.g. `-o` or `/out:, -out:`.
+fn option_spelling(option: &ProgramOption) -> String {
+    option
+        .prefixes
+        .iter()
+        .map(|prefix| prefix.with_name(&option.name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Format the spelling of an option by its internal name.
+fn option_name_spelling(options: &CommandOptions, option_name: &str) -> String {
+    options
+        .iter_options()
+        .find(|o| o.option_name == option_name)
+        .map(option_spelling)
+        .unwrap_or_else(|| option_name.to_string())
+}
+
+async fn command_options<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let command = args
+        .value_of("command")
+        .expect("command argument is required");
+    let search = args.value_of("search").map(|x| x.to_lowercase());
+
+    let version = env
+        .download_settings()
+        .llvm_version
+        .parse::<u32>()
+        .context("parsing LLVM major version")?;
+
+    let options = llvm_option_parser::command_options(version, command)?.ok_or_else(|| {
+        anyhow!(
+            "no option definitions for {} in LLVM {} (available: {})",
+            command,
+            version,
+            llvm_option_parser::commands()
+                .into_iter()
+                .filter(|(v, _)| *v == version)
+                .map(|(_, command)| command)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })?;
+
+    let mut matched = options
+        .iter_options()
+        .filter(|o| !matches!(o.kind, OptionKind::Input | OptionKind::Unknown))
+        .filter(|o| match &search {
+            Some(search) => {
+                option_spelling(o).to_lowercase().contains(search)
+                    || o.help
+                        .iter()
+                        .any(|help| help.to_lowercase().contains(search))
+            }
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    matched.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for option in &matched {
+        warn!(
+            env.logger(),
+            "{} ({:?})",
+            option_spelling(option),
+            option.kind
+        );
+
+        if let Some(alias) = &option.alias {
+            warn!(
+                env.logger(),
+                "    alias of {}",
+                option_name_spelling(&options, alias)
+            );
+        }
+
+        let aliases = options
+            .aliases_of(&option.option_name)
+            .map(option_spelling)
+            .collect::<Vec<_>>();
+        if !aliases.is_empty() {
+            warn!(env.logger(), "    aliases: {}", aliases.join(", "));
+        }
+
+        if let Some(help) = &option.help {
+            warn!(env.logger(), "    {}", help);
+        }
+    }
+
+    warn!(
+        env.logger(),
+        "{} options of {} in LLVM {} matched",
+        matched.len(),
+        command,
+        version
+    );
+
+    Ok(if matched.is_empty() { 1 } else { 0 })
+}
+
+async fn command_prune_downloads<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    env.prune_downloads(args.is_present("dry_run"))?;
+
+    Ok(0)
+}
+
+async fn command_split_archive<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let part_size = args
+        .value_of("part_size")
+        .map(|x| crate::split::parse_size(x).context("parsing --part-size"))
+        .transpose()?
+        .unwrap_or(crate::split::DEFAULT_PART_SIZE);
+    let archive = Path::new(
+        args.value_of_os("archive")
+            .expect("archive argument is required"),
+    );
+    let dest_dir = Path::new(
+        args.value_of_os("dest_dir")
+            .expect("dest_dir argument is required"),
+    );
+
+    let manifest = crate::split::split_file(archive, dest_dir, part_size)?;
+    warn!(
+        env.logger(),
+        "split {} into {} parts",
+        archive.display(),
+        manifest.parts.len()
+    );
+
+    Ok(0)
+}
+
+async fn command_tar_diff<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let old = Path::new(args.value_of_os("old").expect("old argument is required"));
+    let new = Path::new(args.value_of_os("new").expect("new argument is required"));
+    let report = args.value_of_os("report").map(Path::new);
+
+    let differences = diff_tar_files(old, new)?;
+
+    for difference in &differences {
+        match difference {
+            TarDifference::Removed { path, .. } => {
+                warn!(env.logger(), "removed: {}", path.display());
+            }
+            TarDifference::Added { path, .. } => {
+                warn!(env.logger(), "added: {}", path.display());
+            }
+            TarDifference::Changed { path, old, new } => {
+                warn!(
+                    env.logger(),
+                    "changed: {} ({} {:o} {} bytes -> {} {:o} {} bytes)",
+                    path.display(),
+                    old.entry_type,
+                    old.mode,
+                    old.size,
+                    new.entry_type,
+                    new.mode,
+                    new.size
+                );
+            }
+        }
+    }
+
+    if let Some(report) = report {
+        let data = serde_json::to_vec_pretty(&differences)?;
+        std::fs::write(report, data).with_context(|| format!("writing {}", report.display()))?;
+    }
+
+    Ok(if differences.is_empty() { 0 } else { 1 })
+}
+
+async fn command_update_downloads<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let names = args
+        .values_of("names")
+        .map(|values| values.collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    env.update_downloads(&names, args.value_of("dest").map(Path::new))?;
 
     Ok(0)
 }
+
+async fn command_verify_downloads<'a>(env: Environment, args: &ArgMatches<'a>) -> Result<i32> {
+    let ok = env.verify_downloads(args.is_present("delete"))?;
+
+    Ok(if ok { 0 } else { 1 })
+}
@@ -0,0 +1,189 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! The `pclang.toml` configuration file.
+
+Settings that would otherwise have to be repeated as environment variables
+or command line arguments on every invocation can be kept in a TOML file:
+
+```toml
+cache_dir = "/var/cache/pclang"
+llvm_version = "14.0.6"
+dest_dir = "dist"
+targets = ["gcc-10:glibc-2.17", "gcc-10:glibc-2.31"]
+
+[docker]
+socket = "unix:///run/user/1000/docker.sock"
+platform = "linux/amd64"
+network = "none"
+dockerfile_fragments = ["docker/corporate-ca.dockerfile"]
+
+[sccache]
+volume = "pclang-sccache"
+
+[sccache.env]
+SCCACHE_BUCKET = "my-bucket"
+SCCACHE_REGION = "us-east-1"
+```
+
+Relative paths are resolved against the directory of the file. `PCLANG_*`
+environment variables override values from the file and command line
+arguments override both.
+*/
+
+use {
+    anyhow::{Context, Result},
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Name of the configuration file looked up in the current directory.
+pub const CONFIG_FILE: &str = "pclang.toml";
+
+/// Docker related settings.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DockerConfig {
+    /// Address of the Docker daemon.
+    pub socket: Option<String>,
+    /// Platform builder images target. e.g. `linux/amd64`.
+    pub platform: Option<String>,
+    /// Base distribution of builder images.
+    pub base_distribution: Option<String>,
+    /// How files are exchanged with containers.
+    pub transfer_mode: Option<String>,
+    /// Network mode of containers.
+    pub network: Option<String>,
+    /// Registry to pull prebuilt builder images from.
+    pub registry: Option<String>,
+    /// Files of extra lines to insert into generated Dockerfiles, in order.
+    pub dockerfile_fragments: Vec<PathBuf>,
+}
+
+/// Settings of the sccache backend used by builds.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SccacheConfig {
+    /// Named Docker volume to keep the local cache in.
+    pub volume: Option<String>,
+    /// `SCCACHE_*` and credential variables configuring a remote backend.
+    ///
+    /// Variables in the environment of `pclang` take precedence.
+    pub env: BTreeMap<String, String>,
+}
+
+/// Content of a `pclang.toml` file.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory to keep downloads, caches, and logs in.
+    pub cache_dir: Option<PathBuf>,
+    /// LLVM version to build.
+    pub llvm_version: Option<String>,
+    /// Default destination directory of build commands.
+    pub dest_dir: Option<PathBuf>,
+    /// `COMPILER:GLIBC` pairs `build-glibc-matrix` builds by default.
+    pub targets: Vec<String>,
+    pub docker: DockerConfig,
+    pub sccache: SccacheConfig,
+}
+
+impl Config {
+    /// Parse configuration, resolving relative paths against `base_dir`.
+    pub fn parse(data: &str, base_dir: &Path) -> Result<Self> {
+        let mut config = toml::from_str::<Self>(data)?;
+
+        for path in [&mut config.cache_dir, &mut config.dest_dir]
+            .into_iter()
+            .flatten()
+            .chain(config.docker.dockerfile_fragments.iter_mut())
+        {
+            *path = base_dir.join(&*path);
+        }
+
+        Ok(config)
+    }
+
+    /// Read configuration from a file.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let data =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+
+        Self::parse(&data, path.parent().unwrap_or_else(|| Path::new("")))
+            .with_context(|| format!("parsing {}", path.display()))
+    }
+}
+
+/// Path of the configuration file to use.
+///
+/// An explicit path takes precedence over `PCLANG_CONFIG`, `pclang.toml` in
+/// the current directory, and `pclang/pclang.toml` in the user's config
+/// directory (e.g. `~/.config` on Linux), in that order.
+pub fn config_path(explicit: Option<&Path>) -> Option<PathBuf> {
+    if let Some(path) = explicit {
+        Some(path.to_path_buf())
+    } else if let Some(path) = std::env::var_os("PCLANG_CONFIG") {
+        Some(PathBuf::from(path))
+    } else if Path::new(CONFIG_FILE).exists() {
+        Some(PathBuf::from(CONFIG_FILE))
+    } else {
+        dirs::config_dir()
+            .map(|dir| dir.join("pclang").join(CONFIG_FILE))
+            .filter(|path| path.exists())
+    }
+}
+
+/// Load the configuration file, if any. See [config_path].
+pub fn load_config(explicit: Option<&Path>) -> Result<Config> {
+    match config_path(explicit) {
+        Some(path) => Config::from_path(&path),
+        None => Ok(Config::default()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let config = Config::parse(
+            r#"
+            cache_dir = "cache"
+            dest_dir = "/dist"
+            targets = ["gcc-10:glibc-2.17"]
+
+            [docker]
+            platform = "linux/arm64"
+            dockerfile_fragments = ["ca.dockerfile", "/etc/pclang/proxy.dockerfile"]
+
+            [sccache.env]
+            SCCACHE_BUCKET = "bucket"
+            "#,
+            Path::new("/project"),
+        )?;
+
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/project/cache")));
+        assert_eq!(config.dest_dir, Some(PathBuf::from("/dist")));
+        assert_eq!(config.targets, vec!["gcc-10:glibc-2.17".to_string()]);
+        assert_eq!(config.docker.platform.as_deref(), Some("linux/arm64"));
+        assert_eq!(
+            config.docker.dockerfile_fragments,
+            vec![
+                PathBuf::from("/project/ca.dockerfile"),
+                PathBuf::from("/etc/pclang/proxy.dockerfile")
+            ]
+        );
+        assert_eq!(config.sccache.env["SCCACHE_BUCKET"], "bucket");
+        assert_eq!(config.llvm_version, None);
+
+        // Typos are reported instead of silently ignored.
+        assert!(Config::parse("[docker]\nsocet = \"x\"\n", Path::new("")).is_err());
+
+        Ok(())
+    }
+}
@@ -5,167 +5,908 @@
 /*! Docker functionality. */
 
 use {
-    crate::tar::{tar_from_directory, TarBuilder},
+    crate::{
+        dockerfile::DockerfileBuilder,
+        downloads::{DownloadSettings, ProxySettings},
+        progress::{bytes_progress, ArchiveProgress, ArchiveProgressBar, LayerProgress},
+        resources::ContainerMonitor,
+        runtime::ContainerRuntime,
+        tar::{tar_from_directory, TarBuilder, TarMetadata},
+    },
     anyhow::{anyhow, Context, Result},
     bollard::{
+        auth::DockerCredentials,
         container::{
-            Config as ContainerConfig, CreateContainerOptions, LogsOptions, StartContainerOptions,
+            Config as ContainerConfig, CreateContainerOptions, DownloadFromContainerOptions,
+            LogOutput, LogsOptions, RemoveContainerOptions, StartContainerOptions,
+            StopContainerOptions, UploadToContainerOptions, WaitContainerOptions,
+        },
+        exec::{CreateExecOptions, ResizeExecOptions, StartExecResults},
+        image::{
+            BuildImageOptions, CreateImageOptions, ImportImageOptions, ListImagesOptions,
+            PruneImagesOptions, PushImageOptions, RemoveImageOptions, TagImageOptions,
         },
-        image::{BuildImageOptions, ImportImageOptions},
         models::HostConfig,
+        volume::RemoveVolumeOptions,
         Docker,
     },
-    futures_util::stream::TryStreamExt,
+    futures_util::{
+        future::{FutureExt, LocalBoxFuture},
+        stream::TryStreamExt,
+    },
     hyper::body::Body,
-    indoc::indoc,
-    slog::{warn, Logger},
+    once_cell::sync::Lazy,
+    sha2::Digest,
+    slog::{info, o, warn, Logger},
     std::{
-        collections::HashMap,
-        io::{Cursor, Read, Write},
-        path::Path,
+        collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+        io::{Read, Seek, SeekFrom, Write},
+        path::{Path, PathBuf},
+        str::FromStr,
+        sync::Mutex,
+        time::Duration,
     },
+    thiserror::Error,
+    tokio::io::AsyncWriteExt,
+    tokio_util::io::ReaderStream,
     tugger_file_manifest::{FileEntry, FileManifest},
 };
 
 #[cfg(target_family = "unix")]
-use std::os::unix::fs::PermissionsExt;
-
-pub const ZSTD_COMPRESSION_LEVEL: i32 = 8;
-
-const DEBIAN_JESSIE_HEADER: &str = indoc! {r#"
-    FROM debian@sha256:32ad5050caffb2c7e969dac873bce2c370015c2256ff984b70c1c08b3a2816a0
-    MAINTAINER Gregory Szorc <gregory.szorc@gmail.com>
-
-    RUN groupadd -g 1000 build && \
-        useradd -u 1000 -g 1000 -d /build -s /bin/bash -m build && \
-        chown -R build:build /build
-
-    ENV HOME=/build \
-        SHELL=/bin/bash \
-        USER=build \
-        LOGNAME=build \
-        HOSTNAME=builder \
-        DEBIAN_FRONTEND=noninteractive
-
-    CMD ["/bin/bash", "--login"]
-    WORKDIR '/build'
-
-    RUN for s in debian_jessie debian_jessie-updates debian-security_jessie/updates; do \
-          echo "deb http://snapshot.debian.org/archive/${s%_*}/20211107T145307Z/ ${s#*_} main"; \
-        done > /etc/apt/sources.list && \
-        ( echo 'quiet "true";'; \
-          echo 'APT::Get::Assume-Yes "true";'; \
-          echo 'APT::Install-Recommends "false";'; \
-          echo 'Acquire::Check-Valid-Until "false";'; \
-          echo 'Acquire::Retries "5";'; \
-        ) > /etc/apt/apt.conf.d/99portable-clang
-
-    RUN apt-get update
-"#};
-
-const DEBIAN_JESSIE_FOOTER: &str = indoc! {r#"
-    COPY files/* /build/
-    COPY scripts/ /usr/bin/
-    USER build:build
-"#};
-
-const DEBIAN_BULLSEYE_HEADER: &str = indoc! {r#"
-    FROM debian@sha256:4d6ab716de467aad58e91b1b720f0badd7478847ec7a18f66027d0f8a329a43c
-    MAINTAINER Gregory Szorc <gregory.szorc@gmail.com>
-
-    RUN groupadd -g 1000 build && \
-        useradd -u 1000 -g 1000 -d /build -s /bin/bash -m build && \
-        chown -R build:build /build
-
-    ENV HOME=/build \
-        SHELL=/bin/bash \
-        USER=build \
-        LOGNAME=build \
-        HOSTNAME=builder \
-        DEBIAN_FRONTEND=noninteractive
-
-    CMD ["/bin/bash", "--login"]
-    WORKDIR '/build'
-
-    RUN for s in debian_bullseye debian_bullseye-updates; do \
-          echo "deb http://snapshot.debian.org/archive/${s%_*}/20211107T145307Z/ ${s#*_} main"; \
-        done > /etc/apt/sources.list && \
-        ( echo 'quiet "true";'; \
-          echo 'APT::Get::Assume-Yes "true";'; \
-          echo 'APT::Install-Recommends "false";'; \
-          echo 'Acquire::Check-Valid-Until "false";'; \
-          echo 'Acquire::Retries "5";'; \
-        ) > /etc/apt/apt.conf.d/99portable-clang
-
-    RUN apt-get update
-"#};
-
-const CLANG_DOCKERFILE: &str = indoc! {r#"
-    RUN mkdir /toolchains && chown build:build /toolchains
-    RUN apt-get install \
-        ca-certificates \
-        libc6-dev \
-        patch \
-        tar \
-        xz-utils \
-        unzip \
-        zlib1g-dev
-"#};
-
-const GCC_DOCKERFILE: &str = indoc! {r#"
-    RUN mkdir /toolchains && chown build:build /toolchains
-    RUN apt-get install \
-        autoconf \
-        automake \
-        bison \
-        build-essential \
-        ca-certificates \
-        gawk \
-        gcc \
-        gcc-multilib \
-        libtool \
-        make \
-        tar \
-        texinfo \
-        xz-utils \
-        unzip
-"#};
-
-const GLIBC_DOCKERFILE: &str = indoc! {r#"
-    RUN mkdir /toolchains && chown build:build /toolchains
-    RUN apt-get install \
-        autoconf \
-        automake \
-        bison \
-        build-essential \
-        ca-certificates \
-        flex \
-        gawk \
-        git \
-        procps \
-        python3 \
-        rsync \
-        texinfo \
-        watch
-
-    # We do this one as a one-off because it takes a while to run and caching the layer is
-    # useful for iterative development.
-    COPY scripts/docker-glibc-init.sh /usr/bin/
-    COPY files/build-many-glibcs* /build/
-    RUN /usr/bin/docker-glibc-init.sh
-
-    COPY files/* /build/
-    COPY scripts/* /usr/bin/
-
-    USER build:build
-"#};
-
-pub fn docker_client() -> Result<Docker> {
-    Ok(Docker::connect_with_socket(
-        "unix:///var/run/docker.sock",
-        600,
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+/// Make a directory writable by the build user in containers.
+///
+/// Permissions don't apply to files shared with Docker Desktop on Windows.
+#[cfg(target_family = "unix")]
+fn make_world_writable(path: &Path) -> Result<()> {
+    let mut permissions = path
+        .metadata()
+        .context("retrieving directory metadata")?
+        .permissions();
+    permissions.set_mode(0o0777);
+    std::fs::set_permissions(path, permissions).context("setting directory permissions")
+}
+
+#[cfg(not(target_family = "unix"))]
+fn make_world_writable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Make a host directory writable by containers.
+///
+/// This is only needed if the build user isn't mapped to the host user.
+fn prepare_output_dir(settings: &ContainerSettings, path: &Path) -> Result<()> {
+    if settings.user_mapping == UserMapping::Fixed {
+        make_world_writable(path)
+    } else {
+        Ok(())
+    }
+}
+
+/// Default zstd compression level.
+const ZSTD_COMPRESSION_LEVEL: i32 = 8;
+
+/// How artifacts and images are zstd compressed.
+#[derive(Clone, Copy, Debug)]
+pub struct ZstdSettings {
+    /// Compression level, from 1 to 22.
+    pub level: i32,
+
+    /// Number of compression worker threads. 0 compresses on the calling thread.
+    ///
+    /// Output is identical for any nonzero number of threads, but differs from
+    /// single threaded output.
+    pub threads: u32,
+
+    /// Whether to enable long distance matching.
+    ///
+    /// This finds repetition across large windows, which is common in
+    /// toolchain archives, at the cost of memory.
+    pub long_distance_matching: bool,
+}
+
+impl Default for ZstdSettings {
+    fn default() -> Self {
+        Self {
+            level: ZSTD_COMPRESSION_LEVEL,
+            threads: std::thread::available_parallelism()
+                .map(|x| x.get() as u32)
+                .unwrap_or(1),
+            long_distance_matching: false,
+        }
+    }
+}
+
+impl ZstdSettings {
+    /// Create an encoder writing to `writer`.
+    pub fn encoder<W: Write>(&self, writer: W) -> Result<zstd::Encoder<'static, W>> {
+        let mut encoder =
+            zstd::Encoder::new(writer, self.level).context("creating zstd encoder")?;
+
+        if self.threads > 0 {
+            encoder
+                .multithread(self.threads)
+                .context("enabling multithreaded compression")?;
+        }
+        if self.long_distance_matching {
+            encoder
+                .long_distance_matching(true)
+                .context("enabling long distance matching")?;
+        }
+
+        Ok(encoder)
+    }
+
+    /// Compress data in memory.
+    pub fn encode_all(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = self.encoder(vec![])?;
+        encoder.write_all(data).context("compressing data")?;
+
+        encoder.finish().context("finishing zstd encoder")
+    }
+}
+
+/// Default xz compression level.
+const XZ_COMPRESSION_LEVEL: u32 = 6;
+
+/// Compression format of final toolchain artifacts.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ArtifactFormat {
+    /// `.tar.zst`, compressed as configured by [ZstdSettings].
+    #[default]
+    Zstd,
+
+    /// `.tar.xz`, compressed at the given level from 0 to 9.
+    Xz(u32),
+}
+
+impl FromStr for ArtifactFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "zstd" => Ok(Self::Zstd),
+            "xz" => Ok(Self::Xz(XZ_COMPRESSION_LEVEL)),
+            _ => Err(anyhow!("unsupported artifact format: {}", s)),
+        }
+    }
+}
+
+impl ArtifactFormat {
+    /// File extension of artifacts in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Zstd => "tar.zst",
+            Self::Xz(_) => "tar.xz",
+        }
+    }
+
+    /// Write a tar.zst produced by a container as `<name>.<extension>` in a directory.
+    ///
+    /// Returns the path of the written artifact. `progress` receives the bytes
+    /// of the tar archive recompressed, if any.
+    pub fn write_tar_zst(
+        &self,
+        tar_zst: &[u8],
+        dest_dir: &Path,
+        name: &str,
+        progress: &dyn ArchiveProgress,
+    ) -> Result<PathBuf> {
+        let path = dest_dir.join(format!("{}.{}", name, self.extension()));
+
+        match self {
+            Self::Zstd => {
+                std::fs::write(&path, tar_zst)
+                    .with_context(|| format!("writing {}", path.display()))?;
+            }
+            Self::Xz(level) => {
+                let fh = std::fs::File::create(&path)
+                    .with_context(|| format!("creating {}", path.display()))?;
+                let tar = zstd::stream::decode_all(tar_zst).context("decompressing archive")?;
+                let mut encoder = xz2::write::XzEncoder::new(fh, *level);
+
+                progress.start("compressing", tar.len() as u64);
+                for chunk in tar.chunks(1024 * 1024) {
+                    encoder
+                        .write_all(chunk)
+                        .with_context(|| format!("writing {}", path.display()))?;
+                    progress.advance(chunk.len() as u64, None);
+                }
+
+                encoder.finish().context("finishing xz encoder")?;
+                progress.finish();
+            }
+        }
+
+        Ok(path)
+    }
+}
+
+/// Default UID and GID of the `build` user in builder images.
+const BUILD_UID: u64 = 1000;
+
+/// How the `build` user in containers relates to the host user.
+///
+/// This determines who owns files written to bind mounted directories.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UserMapping {
+    /// The build user has a fixed UID and GID unrelated to the host user.
+    ///
+    /// Bind mounted output directories are made world writable.
+    Fixed,
+
+    /// The build user has the UID and GID of the host user.
+    Host { uid: u32, gid: u32 },
+
+    /// The daemon is rootless and maps root in containers to the host user.
+    ///
+    /// Containers run as root so their files are owned by the host user.
+    Rootless,
+}
+
+impl UserMapping {
+    /// Determine the user mapping appropriate for a daemon.
+    ///
+    /// `probe_dir` is a directory owned by the host user.
+    pub fn detect(host: &str, transfer_mode: FileTransferMode, probe_dir: &Path) -> Self {
+        // Archives carry their own ownership, so the host user doesn't matter.
+        if transfer_mode == FileTransferMode::Archive || !host.starts_with("unix://") {
+            return Self::Fixed;
+        }
+
+        // Rootless daemons put their socket in the user's runtime directory.
+        let runtime_dir = std::env::var("XDG_RUNTIME_DIR").unwrap_or_default();
+        let socket = host.trim_start_matches("unix://");
+        if socket.starts_with("/run/user/")
+            || (!runtime_dir.is_empty() && socket.starts_with(&runtime_dir))
+        {
+            return Self::Rootless;
+        }
+
+        Self::host(probe_dir).unwrap_or(Self::Fixed)
+    }
+
+    /// Map the build user to the owner of `probe_dir`.
+    ///
+    /// Returns `None` if the owner is root or ownership is not available.
+    #[cfg(target_family = "unix")]
+    pub fn host(probe_dir: &Path) -> Option<Self> {
+        let metadata = probe_dir.metadata().ok()?;
+
+        if metadata.uid() == 0 {
+            None
+        } else {
+            Some(Self::Host {
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+            })
+        }
+    }
+
+    #[cfg(not(target_family = "unix"))]
+    pub fn host(_probe_dir: &Path) -> Option<Self> {
+        None
+    }
+
+    /// The UID and GID of the build user in images.
+    pub fn build_ids(&self) -> (u32, u32) {
+        match self {
+            Self::Host { uid, gid } => (*uid, *gid),
+            Self::Fixed | Self::Rootless => (BUILD_UID as u32, BUILD_UID as u32),
+        }
+    }
+}
+
+/// A platform builder images can target.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BuilderPlatform {
+    LinuxX86_64,
+    LinuxAarch64,
+}
+
+impl BuilderPlatform {
+    /// The platform matching the architecture of the running binary.
+    ///
+    /// Falls back to x86_64 on architectures we don't have support tool
+    /// records for.
+    pub fn native() -> Self {
+        Self::available()
+            .into_iter()
+            .find(|platform| platform.arch() == std::env::consts::ARCH)
+            .unwrap_or(Self::LinuxX86_64)
+    }
+
+    /// Platforms having records for all support tools.
+    ///
+    /// The built-in download manifest only defines x86_64 tools. Others
+    /// become available once their records are added to a download manifest.
+    pub fn available() -> Vec<Self> {
+        [Self::LinuxX86_64, Self::LinuxAarch64]
+            .into_iter()
+            .filter(|platform| crate::downloads::has_support_records("linux", platform.arch()))
+            .collect()
+    }
+
+    /// The platform string as understood by Docker.
+    pub fn docker_platform(&self) -> &'static str {
+        match self {
+            Self::LinuxX86_64 => "linux/amd64",
+            Self::LinuxAarch64 => "linux/arm64",
+        }
+    }
+
+    /// The machine architecture name.
+    pub fn arch(&self) -> &'static str {
+        match self {
+            Self::LinuxX86_64 => "x86_64",
+            Self::LinuxAarch64 => "aarch64",
+        }
+    }
+
+    /// The target triple of binaries running on this platform.
+    pub fn triple(&self) -> &'static str {
+        match self {
+            Self::LinuxX86_64 => "x86_64-unknown-linux-gnu",
+            Self::LinuxAarch64 => "aarch64-unknown-linux-gnu",
+        }
+    }
+
+    /// Derive the name of an image for this platform.
+    ///
+    /// x86_64 images retain their historical unsuffixed names.
+    pub fn image_name(&self, name: &str) -> String {
+        match self {
+            Self::LinuxX86_64 => name.to_string(),
+            _ => format!("{}-{}", name, self.arch()),
+        }
+    }
+}
+
+/// Base distribution of builder images.
+///
+/// Older distributions produce binaries compatible with older glibc versions.
+/// Newer distributions have more and newer packages available.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BaseDistribution {
+    DebianJessie,
+    DebianBullseye,
+    Ubuntu1804,
+    Centos7,
+}
+
+impl BaseDistribution {
+    /// The Docker image the distribution is based on.
+    fn image(&self) -> &'static str {
+        match self {
+            Self::DebianJessie => {
+                "debian@sha256:32ad5050caffb2c7e969dac873bce2c370015c2256ff984b70c1c08b3a2816a0"
+            }
+            Self::DebianBullseye => {
+                "debian@sha256:4d6ab716de467aad58e91b1b720f0badd7478847ec7a18f66027d0f8a329a43c"
+            }
+            Self::Ubuntu1804 => "ubuntu:18.04",
+            Self::Centos7 => "centos:7",
+        }
+    }
+
+    /// Debian archives and suites to pin to a snapshot date.
+    ///
+    /// The snapshot is a Dockerfile `ARG` and can be overridden with `--build-arg`.
+    fn debian_snapshot_suites(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::DebianJessie => &[
+                ("debian", "jessie"),
+                ("debian", "jessie-updates"),
+                ("debian-security", "jessie/updates"),
+            ],
+            Self::DebianBullseye => &[("debian", "bullseye"), ("debian", "bullseye-updates")],
+            Self::Ubuntu1804 | Self::Centos7 => &[],
+        }
+    }
+
+    /// Start a Dockerfile defining the base image, build user, and package manager config.
+    ///
+    /// IDs may collide with groups in the base image, so non-unique IDs are allowed.
+    /// `debian_snapshot` is the default snapshot.debian.org timestamp for Debian
+    /// based distributions.
+    fn dockerfile_header(&self, uid: u32, gid: u32, debian_snapshot: &str) -> DockerfileBuilder {
+        let mut env = vec![
+            ("HOME", "/build"),
+            ("SHELL", "/bin/bash"),
+            ("USER", "build"),
+            ("LOGNAME", "build"),
+            ("HOSTNAME", "builder"),
+        ];
+        if *self != Self::Centos7 {
+            env.push(("DEBIAN_FRONTEND", "noninteractive"));
+        }
+
+        let mut builder = DockerfileBuilder::from_image(self.image())
+            .maintainer("Gregory Szorc <gregory.szorc@gmail.com>")
+            .run_all([
+                format!("groupadd -o -g {} build", gid),
+                format!(
+                    "useradd -o -u {} -g {} -d /build -s /bin/bash -m build",
+                    uid, gid
+                ),
+                "chown -R build:build /build".to_string(),
+            ])
+            .env(env)
+            .cmd(&["/bin/bash", "--login"])
+            .workdir("/build");
+
+        match self {
+            Self::DebianJessie | Self::DebianBullseye | Self::Ubuntu1804 => {
+                let mut apt_config = vec![
+                    "quiet \"true\";",
+                    "APT::Get::Assume-Yes \"true\";",
+                    "APT::Install-Recommends \"false\";",
+                ];
+                let mut commands = vec![];
+
+                let suites = self.debian_snapshot_suites();
+                if !suites.is_empty() {
+                    builder = builder.arg("DEBIAN_SNAPSHOT", Some(debian_snapshot));
+
+                    // Snapshot archives are never updated, so release files expire.
+                    apt_config.push("Acquire::Check-Valid-Until \"false\";");
+
+                    commands.push(echo_lines_command(
+                        suites.iter().map(|(archive, suite)| {
+                            format!(
+                                "deb http://snapshot.debian.org/archive/{}/${{DEBIAN_SNAPSHOT}}/ {} main",
+                                archive, suite
+                            )
+                        }),
+                        "/etc/apt/sources.list",
+                    ));
+                }
+
+                apt_config.push("Acquire::Retries \"5\";");
+                commands.push(echo_lines_command(
+                    apt_config,
+                    "/etc/apt/apt.conf.d/99portable-clang",
+                ));
+
+                builder.run_all(commands).run("apt-get update")
+            }
+            Self::Centos7 => builder
+                .comment("CentOS 7 is end of life. Its packages only remain on the vault.")
+                .run_all([CENTOS_7_USE_VAULT, "echo 'retries=5' >> /etc/yum.conf"])
+                .run("yum makecache"),
+        }
+    }
+
+    /// Resolve a Debian package name to names of packages in this distribution.
+    fn package_names(&self, package: &'static str) -> Vec<&'static str> {
+        match (self, package) {
+            (Self::Centos7, "build-essential") => vec!["gcc", "gcc-c++", "make"],
+            (Self::Centos7, "gcc-multilib") => vec!["glibc-devel.i686", "libstdc++-devel.i686"],
+            (Self::Centos7, "libc6-dev") => vec!["glibc-devel"],
+            (Self::Centos7, "procps") => vec!["procps-ng"],
+            // Provided by procps-ng.
+            (Self::Centos7, "watch") => vec![],
+            (Self::Centos7, "xz-utils") => vec!["xz"],
+            (Self::Centos7, "zlib1g-dev") => vec!["zlib-devel"],
+            _ => vec![package],
+        }
+    }
+
+    /// Add an instruction installing packages given by their Debian names.
+    fn install_packages(
+        &self,
+        builder: DockerfileBuilder,
+        packages: &[&'static str],
+    ) -> DockerfileBuilder {
+        let names = packages
+            .iter()
+            .flat_map(|package| self.package_names(package))
+            .collect::<Vec<_>>();
+
+        let command = match self {
+            Self::Centos7 => "yum install -y",
+            _ => "apt-get install",
+        };
+
+        builder.run_with_args(command, names)
+    }
+}
+
+/// Shell command writing lines to a file.
+///
+/// Lines are double quoted so Dockerfile `ARG`s referenced as `${NAME}` expand.
+fn echo_lines_command<S: AsRef<str>>(lines: impl IntoIterator<Item = S>, path: &str) -> String {
+    let echos = lines
+        .into_iter()
+        .map(|line| {
+            format!(
+                "echo \"{}\";",
+                line.as_ref().replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .collect::<Vec<_>>();
+
+    format!("( {} ) > {}", echos.join(" "), path)
+}
+
+impl FromStr for BaseDistribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "debian-jessie" | "jessie" => Ok(Self::DebianJessie),
+            "debian-bullseye" | "bullseye" => Ok(Self::DebianBullseye),
+            "ubuntu-18.04" | "bionic" => Ok(Self::Ubuntu1804),
+            "centos-7" | "centos7" => Ok(Self::Centos7),
+            _ => Err(anyhow!("unsupported base distribution: {}", s)),
+        }
+    }
+}
+
+/// Settings influencing how builder images are built.
+#[derive(Clone, Debug)]
+pub struct ImageSettings {
+    /// Platform to build images for.
+    pub platform: BuilderPlatform,
+
+    /// Extra Dockerfile lines to inject after the base image header.
+    ///
+    /// Useful for installing additional packages or configuring proxies and
+    /// CA certificates before any other commands run.
+    pub dockerfile_fragments: Vec<String>,
+
+    /// Base distribution to use instead of each image's default.
+    pub base: Option<BaseDistribution>,
+
+    /// Registry to pull prebuilt images from. e.g. `ghcr.io/example`.
+    pub registry: Option<String>,
+
+    /// Whether to push images to [Self::registry] after building them.
+    pub push: bool,
+
+    /// Values for Dockerfile `ARG`s, overriding their defaults.
+    ///
+    /// Tool versions and the Debian snapshot date are defined as `ARG`s.
+    pub build_args: BTreeMap<String, String>,
+
+    /// Directory to save built images to and load them from before building.
+    ///
+    /// Useful on CI runners without a registry that can persist a directory.
+    pub image_cache_dir: Option<PathBuf>,
+
+    /// snapshot.debian.org timestamp Debian based images install packages from.
+    ///
+    /// See [parse_debian_snapshot] for the format.
+    pub debian_snapshot: String,
+
+    /// UID of the `build` user. See [UserMapping::build_ids].
+    pub build_uid: u32,
+
+    /// GID of the `build` user.
+    pub build_gid: u32,
+
+    /// How to download source and support artifacts added to images.
+    pub downloads: DownloadSettings,
+
+    /// How images saved to [Self::image_cache_dir] are compressed.
+    pub zstd: ZstdSettings,
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        Self {
+            platform: BuilderPlatform::native(),
+            dockerfile_fragments: vec![],
+            base: None,
+            registry: None,
+            push: false,
+            build_args: BTreeMap::new(),
+            image_cache_dir: None,
+            debian_snapshot: DEBIAN_SNAPSHOT.to_string(),
+            build_uid: BUILD_UID as u32,
+            build_gid: BUILD_UID as u32,
+            downloads: DownloadSettings::default(),
+            zstd: ZstdSettings::default(),
+        }
+    }
+}
+
+impl ImageSettings {
+    /// Start a Dockerfile with a base header, custom fragments, and packages installed.
+    ///
+    /// `default_base` is used unless a base distribution is explicitly configured.
+    fn dockerfile(
+        &self,
+        default_base: BaseDistribution,
+        packages: &[&'static str],
+    ) -> DockerfileBuilder {
+        let base = self.base.unwrap_or(default_base);
+
+        let builder = self
+            .dockerfile_fragments
+            .iter()
+            .fold(
+                base.dockerfile_header(self.build_uid, self.build_gid, &self.debian_snapshot),
+                |builder, fragment| builder.raw(fragment),
+            )
+            .run("mkdir /toolchains /sccache && chown build:build /toolchains /sccache");
+
+        base.install_packages(builder, packages)
+    }
+}
+
+impl FromStr for BuilderPlatform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let platform = match s {
+            "linux/amd64" | "linux/x86_64" | "amd64" | "x86_64" => Self::LinuxX86_64,
+            "linux/arm64" | "linux/aarch64" | "arm64" | "aarch64" => Self::LinuxAarch64,
+            _ => return Err(anyhow!("unsupported builder platform: {}", s)),
+        };
+
+        if Self::available().contains(&platform) {
+            Ok(platform)
+        } else {
+            Err(anyhow!(
+                "builder platform {} requires {} records in a download manifest",
+                s,
+                crate::downloads::SUPPORT_TOOLS
+                    .iter()
+                    .map(|tool| format!("{}-linux_{}", tool, platform.arch()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        }
+    }
+}
+
+/// Command pointing CentOS 7 repositories at vault.centos.org.
+const CENTOS_7_USE_VAULT: &str = concat!(
+    "sed -i -e 's/^mirrorlist=/#mirrorlist=/' ",
+    "-e 's|^#baseurl=http://mirror.centos.org|baseurl=http://vault.centos.org|' ",
+    "/etc/yum.repos.d/CentOS-*.repo"
+);
+
+/// Default date of the Debian archive snapshot used by Debian based images.
+pub const DEBIAN_SNAPSHOT: &str = "20211107T145307Z";
+
+/// Validate a snapshot.debian.org timestamp like `20211107T145307Z`.
+pub fn parse_debian_snapshot(value: &str) -> Result<String> {
+    let valid = value.len() == 16
+        && value.char_indices().all(|(i, c)| match i {
+            8 => c == 'T',
+            15 => c == 'Z',
+            _ => c.is_ascii_digit(),
+        });
+
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err(anyhow!(
+            "invalid Debian snapshot {}; expected a timestamp like {}",
+            value,
+            DEBIAN_SNAPSHOT
+        ))
+    }
+}
+
+/// Packages needed by the clang builder, using Debian package names.
+const CLANG_PACKAGES: &[&str] = &[
+    "ca-certificates",
+    "libc6-dev",
+    "make",
+    "patch",
+    "tar",
+    "xz-utils",
+    "unzip",
+];
+
+/// Packages needed by the GCC builder, using Debian package names.
+const GCC_PACKAGES: &[&str] = &[
+    "autoconf",
+    "automake",
+    "bison",
+    "build-essential",
+    "ca-certificates",
+    "gawk",
+    "gcc",
+    "libtool",
+    "make",
+    "tar",
+    "texinfo",
+    "xz-utils",
+    "unzip",
+];
+
+/// Packages needed to build 32-bit multilib GCC runtimes, which only exist on x86_64.
+const GCC_MULTILIB_PACKAGES: &[&str] = &["gcc-multilib"];
+
+/// Packages needed by the glibc builder, using Debian package names.
+const GLIBC_PACKAGES: &[&str] = &[
+    "autoconf",
+    "automake",
+    "bison",
+    "build-essential",
+    "ca-certificates",
+    "flex",
+    "gawk",
+    "git",
+    "procps",
+    "python3",
+    "rsync",
+    "texinfo",
+    "watch",
+];
+
+/// Add instructions copying build files and switching to the build user.
+fn add_builder_footer(builder: DockerfileBuilder) -> DockerfileBuilder {
+    builder
+        .copy("files/*", "/build/")
+        .copy("scripts/", "/usr/bin/")
+        .user("build:build")
+}
+
+/// Socket of the Docker daemon when nothing else is configured.
+const DOCKER_DEFAULT_HOST: &str = "unix:///var/run/docker.sock";
+
+/// Address of the Docker Desktop daemon on Windows.
+const DOCKER_WINDOWS_HOST: &str = "npipe:////./pipe/docker_engine";
+
+/// Timeout in seconds for requests to the Docker daemon.
+const DOCKER_TIMEOUT: u64 = 600;
+
+/// Normalize a daemon address so it has a URL scheme.
+///
+/// Bare filesystem paths are treated as Unix sockets and bare pipe paths
+/// (`//./pipe/...`) as Windows named pipes.
+fn normalize_docker_host(host: &str) -> String {
+    if host.starts_with("//./pipe/") || host.starts_with(r"\\.\pipe\") {
+        format!("npipe://{}", host.replace('\\', "/"))
+    } else if host.starts_with('/') {
+        format!("unix://{}", host)
+    } else {
+        host.to_string()
+    }
+}
+
+/// Resolve the address of the Docker compatible daemon to connect to.
+///
+/// An explicit address (from `--docker-socket` or `PCLANG_DOCKER_SOCKET`)
+/// has highest priority, followed by `DOCKER_HOST`. Otherwise we probe for
+/// the Docker socket followed by the rootless and rootful Podman sockets.
+pub fn resolve_docker_host(explicit: Option<&str>) -> String {
+    if let Some(host) = explicit {
+        return normalize_docker_host(host);
+    }
+
+    if let Ok(host) = std::env::var("DOCKER_HOST") {
+        if !host.is_empty() {
+            return normalize_docker_host(&host);
+        }
+    }
+
+    // Docker Desktop on Windows listens on a named pipe.
+    if cfg!(windows) {
+        return DOCKER_WINDOWS_HOST.to_string();
+    }
+
+    let mut candidates = vec![PathBuf::from("/var/run/docker.sock")];
+
+    if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
+        candidates.push(
+            PathBuf::from(runtime_dir)
+                .join("podman")
+                .join("podman.sock"),
+        );
+    }
+
+    candidates.push(PathBuf::from("/run/podman/podman.sock"));
+
+    candidates
+        .into_iter()
+        .find(|path| path.exists())
+        .map(|path| format!("unix://{}", path.display()))
+        .unwrap_or_else(|| DOCKER_DEFAULT_HOST.to_string())
+}
+
+/// Whether TLS should be used to connect to a TCP daemon.
+///
+/// Mirrors the Docker CLI, which enables TLS when `DOCKER_TLS_VERIFY` is set
+/// to a non-empty value.
+fn docker_tls_verify() -> bool {
+    matches!(std::env::var("DOCKER_TLS_VERIFY"), Ok(value) if !value.is_empty() && value != "0")
+}
+
+/// Resolve the directory holding TLS client certificates.
+///
+/// This is `DOCKER_CERT_PATH` if set or `~/.docker` otherwise.
+fn docker_cert_path() -> Result<PathBuf> {
+    if let Ok(path) = std::env::var("DOCKER_CERT_PATH") {
+        Ok(PathBuf::from(path))
+    } else {
+        Ok(dirs::home_dir()
+            .ok_or_else(|| anyhow!("could not resolve home directory to find Docker certificates"))?
+            .join(".docker"))
+    }
+}
+
+/// Connect to a daemon listening on a Windows named pipe.
+#[cfg(windows)]
+fn docker_client_named_pipe(host: &str) -> Result<Docker> {
+    Docker::connect_with_named_pipe(host, DOCKER_TIMEOUT, bollard::API_DEFAULT_VERSION)
+        .with_context(|| format!("connecting to Docker daemon at {}", host))
+}
+
+#[cfg(not(windows))]
+fn docker_client_named_pipe(host: &str) -> Result<Docker> {
+    Err(anyhow!(
+        "named pipe Docker host {} is only supported on Windows",
+        host
+    ))
+}
+
+/// Connect to a remote daemon over TLS using client certificates.
+///
+/// The certificate directory must contain `key.pem`, `cert.pem`, and `ca.pem`,
+/// just like the Docker CLI expects.
+fn docker_client_tls(host: &str) -> Result<Docker> {
+    let cert_path = docker_cert_path()?;
+
+    let key = cert_path.join("key.pem");
+    let cert = cert_path.join("cert.pem");
+    let ca = cert_path.join("ca.pem");
+
+    for path in [&key, &cert, &ca] {
+        if !path.exists() {
+            return Err(anyhow!(
+                "TLS certificate file {} does not exist; set DOCKER_CERT_PATH",
+                path.display()
+            ));
+        }
+    }
+
+    Docker::connect_with_ssl(
+        host,
+        &key,
+        &cert,
+        &ca,
+        DOCKER_TIMEOUT,
         bollard::API_DEFAULT_VERSION,
-    )?)
+    )
+    .with_context(|| format!("connecting to Docker daemon at {} over TLS", host))
+}
+
+/// Obtain a client to the Docker compatible daemon.
+///
+/// See [resolve_docker_host] for how the daemon address is resolved.
+///
+/// TCP daemons are connected to over TLS if `DOCKER_TLS_VERIFY` is set or
+/// the address has an `https://` scheme. Client certificates are loaded from
+/// `DOCKER_CERT_PATH`.
+pub fn docker_client(host: Option<&str>) -> Result<Docker> {
+    let host = resolve_docker_host(host);
+
+    if host.starts_with("npipe://") {
+        docker_client_named_pipe(&host)
+    } else if host.starts_with("unix://") {
+        Ok(Docker::connect_with_socket(
+            &host,
+            DOCKER_TIMEOUT,
+            bollard::API_DEFAULT_VERSION,
+        )?)
+    } else if host.starts_with("https://") || (host.starts_with("tcp://") && docker_tls_verify()) {
+        docker_client_tls(&host)
+    } else if host.starts_with("tcp://") || host.starts_with("http://") {
+        Ok(Docker::connect_with_http(
+            &host,
+            DOCKER_TIMEOUT,
+            bollard::API_DEFAULT_VERSION,
+        )?)
+    } else {
+        Err(anyhow!("unsupported Docker host: {}", host))
+    }
 }
 
 /// Build a Docker image with context.
@@ -176,18 +917,20 @@ pub async fn build_image(
     body: Body,
 ) -> Result<String> {
     let mut stream = docker.build_image(options, None, Some(body));
+    let mut progress = LayerProgress::new(logger, "build");
 
     while let Some(info) = stream.try_next().await? {
         if let Some(stream) = info.stream {
             for part in stream.split('\n').filter(|s| !s.is_empty()) {
-                warn!(logger, "{}", part);
+                progress.println(part);
             }
         } else if let Some(status) = info.status {
-            if let Some(progress) = info.progress {
-                warn!(logger, "{} {}", status, progress);
-            } else {
-                warn!(logger, "{}", status);
-            }
+            progress.update(
+                info.id.as_deref(),
+                &status,
+                info.progress.as_deref(),
+                info.progress_detail.as_ref(),
+            );
         } else if let Some(image_id) = info.aux {
             return image_id.id.ok_or_else(|| anyhow!("image ID not set"));
         }
@@ -196,128 +939,1804 @@ pub async fn build_image(
     Err(anyhow!("error building image"))
 }
 
-/// Load image tar data.
-pub async fn load_image_tar(logger: &Logger, docker: &Docker, tar_data: Vec<u8>) -> Result<String> {
-    let options = ImportImageOptions::default();
-    let mut stream = docker.import_image(options, Body::from(tar_data), None);
+/// Prefix of labels describing how `portable-clang` images were built.
+const PROVENANCE_LABEL_PREFIX: &str = "portable-clang.";
 
-    while let Some(info) = stream.try_next().await? {
-        if let Some(stream) = info.stream {
-            for part in stream.split('\n').filter(|s| !s.is_empty()) {
-                warn!(logger, "{}", part);
+/// Labels attached to built images recording their provenance.
+///
+/// `digest` covers all inputs to the image build. Standard OCI annotation keys
+/// are used where one exists. The git commit is only known if the
+/// `PCLANG_GIT_COMMIT` environment variable was set when pclang was compiled.
+fn provenance_labels(
+    name: &str,
+    settings: &ImageSettings,
+    digest: &str,
+) -> HashMap<String, String> {
+    let mut labels = HashMap::new();
+
+    labels.insert(
+        "org.opencontainers.image.title".to_string(),
+        format!("portable-clang:{}", name),
+    );
+    labels.insert(
+        "org.opencontainers.image.version".to_string(),
+        env!("CARGO_PKG_VERSION").to_string(),
+    );
+    if let Some(commit) = option_env!("PCLANG_GIT_COMMIT") {
+        labels.insert(
+            "org.opencontainers.image.revision".to_string(),
+            commit.to_string(),
+        );
+    }
+
+    let mut add = |key: String, value: &str| {
+        labels.insert(
+            format!("{}{}", PROVENANCE_LABEL_PREFIX, key),
+            value.to_string(),
+        );
+    };
+
+    add("image".into(), name);
+    add("platform".into(), settings.platform.docker_platform());
+    add("input-digest".into(), digest);
+    add("debian-snapshot".into(), &settings.debian_snapshot);
+    add("llvm-version".into(), &settings.downloads.llvm_version);
+
+    if let Some(source) = &settings.downloads.llvm_git {
+        add(
+            "llvm-git".into(),
+            &format!("{}#{}", source.url, source.commit),
+        );
+    }
+    if let Some(source) = &settings.downloads.glibc_git {
+        add(
+            "glibc-git".into(),
+            &format!("{}#{}", source.url, source.commit),
+        );
+    }
+
+    if let Some(base) = settings.base {
+        add("base".into(), base.image());
+    }
+
+    for (key, value) in &settings.build_args {
+        add(format!("build-arg.{}", key), value);
+    }
+
+    for record in crate::downloads::DOWNLOADS.values() {
+        add(format!("{}.version", record.name), &record.version);
+        add(format!("{}.sha256", record.name), &record.sha256);
+    }
+
+    labels
+}
+
+/// Obtain the provenance labels of an image.
+///
+/// `image` is an image ID or reference. Images not built by `portable-clang`
+/// have no provenance labels.
+pub async fn image_provenance(docker: &Docker, image: &str) -> Result<BTreeMap<String, String>> {
+    let image = docker
+        .inspect_image(image)
+        .await
+        .with_context(|| format!("inspecting image {}", image))?;
+
+    Ok(image
+        .config
+        .and_then(|config| config.labels)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(key, _)| {
+            key.starts_with(PROVENANCE_LABEL_PREFIX) || key.starts_with("org.opencontainers.image.")
+        })
+        .collect())
+}
+
+/// Build a named `portable-clang` image unless an identical one already exists.
+///
+/// Images are tagged `portable-clang:<name>-<digest>`, where the digest covers
+/// the Dockerfile and all files in the build context. If an image with that tag
+/// exists, the build is skipped. The image is also tagged `portable-clang:<name>`.
+///
+/// Missing images are loaded from [ImageSettings::image_cache_dir] or pulled
+/// from a registry if configured before falling back to building them. Built
+/// images are pushed if requested and saved to the image cache directory.
+///
+/// `name` should already be qualified for the platform via [BuilderPlatform::image_name].
+pub async fn build_image_cached(
+    logger: &Logger,
+    docker: &Docker,
+    name: &str,
+    settings: &ImageSettings,
+    tar: &TarBuilder,
+) -> Result<String> {
+    let digest = tar
+        .content_digest()
+        .context("computing build context digest")?;
+
+    // Build arguments change the image without changing the build context.
+    let digest = if settings.build_args.is_empty() {
+        digest
+    } else {
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(digest.as_bytes());
+        for (key, value) in &settings.build_args {
+            hasher.update(format!("\0{}={}", key, value).as_bytes());
+        }
+
+        hex::encode(hasher.finalize())
+    };
+
+    let tag = format!("{}-{}", name, &digest[0..16]);
+    let reference = format!("portable-clang:{}", tag);
+
+    let mut image_id = if let Ok(image) = docker.inspect_image(&reference).await {
+        warn!(logger, "image {} is up to date; skipping build", reference);
+
+        Some(image.id)
+    } else {
+        None
+    };
+
+    if image_id.is_none() {
+        if let Some(cache_dir) = &settings.image_cache_dir {
+            match load_cached_image(logger, docker, cache_dir, &tag).await {
+                Ok(Some(id)) => {
+                    image_id = Some(id);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!(logger, "unable to load cached image {}: {:?}", tag, e);
+                }
+            }
+        }
+    }
+
+    if image_id.is_none() {
+        if let Some(registry) = &settings.registry {
+            match pull_image_from_registry(logger, docker, registry, &tag).await {
+                Ok(id) => {
+                    image_id = Some(id);
+                }
+                Err(e) => {
+                    warn!(logger, "unable to pull {} from {}: {:?}", tag, registry, e);
+                }
+            }
+        }
+    }
+
+    let image_id = if let Some(image_id) = image_id {
+        image_id
+    } else {
+        let body = tar.as_body().context("building tar content")?;
+
+        let options = BuildImageOptions::<String> {
+            t: reference.clone(),
+            platform: settings.platform.docker_platform().to_string(),
+            // Docker predefines proxy build arguments. They don't invalidate the
+            // build cache, so they aren't part of the digest either.
+            buildargs: HashMap::from_iter(
+                settings
+                    .build_args
+                    .clone()
+                    .into_iter()
+                    .chain(settings.downloads.proxy.envs()),
+            ),
+            labels: provenance_labels(name, settings, &digest),
+            ..Default::default()
+        };
+
+        let image_id = build_image(logger, docker, options, body).await?;
+
+        if settings.push {
+            let registry = settings
+                .registry
+                .as_ref()
+                .ok_or_else(|| anyhow!("pushing images requires a registry"))?;
+
+            push_image_to_registry(logger, docker, registry, &tag).await?;
+        }
+
+        image_id
+    };
+
+    if let Some(cache_dir) = &settings.image_cache_dir {
+        // The image cache is an optimization. So failures aren't fatal.
+        if let Err(e) = save_cached_image(
+            logger,
+            docker,
+            cache_dir,
+            name,
+            &tag,
+            &image_id,
+            &settings.zstd,
+        )
+        .await
+        {
+            warn!(logger, "unable to save image {} to cache: {:?}", tag, e);
+        }
+    }
+
+    let options = TagImageOptions {
+        repo: "portable-clang",
+        tag: name,
+    };
+    docker
+        .tag_image(&image_id, Some(options))
+        .await
+        .with_context(|| format!("tagging image portable-clang:{}", name))?;
+
+    Ok(image_id)
+}
+
+/// Path of an image in an image cache directory.
+fn cached_image_path(cache_dir: &Path, tag: &str) -> PathBuf {
+    cache_dir.join(format!("{}.tar.zst", tag))
+}
+
+/// Whether a file in an image cache directory holds a version of image `name`.
+///
+/// Image names can be prefixes of other names, so the suffix must be a digest.
+fn is_cached_image_file(file_name: &str, name: &str) -> bool {
+    file_name
+        .strip_suffix(".tar.zst")
+        .and_then(|stem| stem.strip_prefix(name))
+        .and_then(|rest| rest.strip_prefix('-'))
+        .map(|digest| digest.len() == 16 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false)
+}
+
+/// Load a `portable-clang:<tag>` image from an image cache directory.
+///
+/// Returns `None` if the cache doesn't have the image.
+async fn load_cached_image(
+    logger: &Logger,
+    docker: &Docker,
+    cache_dir: &Path,
+    tag: &str,
+) -> Result<Option<String>> {
+    let path = cached_image_path(cache_dir, tag);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    warn!(logger, "loading image {} from {}", tag, path.display());
+    let fh = std::fs::File::open(&path).context("opening cached image")?;
+    let image_id = load_image_tar_zst(logger, docker, fh).await?;
+
+    // Exported images don't retain their tags.
+    let options = TagImageOptions {
+        repo: "portable-clang",
+        tag,
+    };
+    docker
+        .tag_image(&image_id, Some(options))
+        .await
+        .with_context(|| format!("tagging image portable-clang:{}", tag))?;
+
+    Ok(Some(image_id))
+}
+
+/// Save an image to an image cache directory unless it is already there.
+///
+/// Other versions of the image are removed from the cache.
+async fn save_cached_image(
+    logger: &Logger,
+    docker: &Docker,
+    cache_dir: &Path,
+    name: &str,
+    tag: &str,
+    image_id: &str,
+    zstd: &ZstdSettings,
+) -> Result<()> {
+    let path = cached_image_path(cache_dir, tag);
+    if path.exists() {
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(cache_dir).context("creating image cache directory")?;
+
+    for entry in std::fs::read_dir(cache_dir)? {
+        let entry = entry?;
+
+        if is_cached_image_file(&entry.file_name().to_string_lossy(), name) {
+            warn!(
+                logger,
+                "removing stale cached image {}",
+                entry.path().display()
+            );
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    // Write to a temporary file so an interrupted export isn't mistaken for an image.
+    let temp_path = cache_dir.join(format!("{}.tar.zst.tmp", tag));
+    warn!(logger, "saving image {} to {}", tag, path.display());
+    export_image_to_tar_zst(docker, image_id, &temp_path, zstd).await?;
+    std::fs::rename(&temp_path, &path).context("renaming cached image")?;
+
+    Ok(())
+}
+
+/// Resolve credentials for a registry from the environment.
+///
+/// Credentials are read from `PCLANG_REGISTRY_USERNAME` and `PCLANG_REGISTRY_PASSWORD`.
+fn registry_credentials(registry: &str) -> Option<DockerCredentials> {
+    let username = std::env::var("PCLANG_REGISTRY_USERNAME").ok()?;
+    let password = std::env::var("PCLANG_REGISTRY_PASSWORD").ok()?;
+
+    Some(DockerCredentials {
+        username: Some(username),
+        password: Some(password),
+        serveraddress: Some(registry.split('/').next().unwrap_or(registry).to_string()),
+        ..Default::default()
+    })
+}
+
+/// Push a `portable-clang:<tag>` image to a registry.
+///
+/// The image is pushed as `<registry>/portable-clang:<tag>`.
+pub async fn push_image_to_registry(
+    logger: &Logger,
+    docker: &Docker,
+    registry: &str,
+    tag: &str,
+) -> Result<()> {
+    let repo = format!("{}/portable-clang", registry.trim_end_matches('/'));
+
+    let options = TagImageOptions {
+        repo: repo.as_str(),
+        tag,
+    };
+    docker
+        .tag_image(&format!("portable-clang:{}", tag), Some(options))
+        .await
+        .with_context(|| format!("tagging image {}:{}", repo, tag))?;
+
+    warn!(logger, "pushing {}:{}", repo, tag);
+
+    let options = PushImageOptions { tag };
+    let mut stream = docker.push_image(&repo, Some(options), registry_credentials(registry));
+    let mut progress = LayerProgress::new(logger, "push");
+
+    while let Some(info) = stream
+        .try_next()
+        .await
+        .with_context(|| format!("pushing {}:{}", repo, tag))?
+    {
+        if let Some(error) = info.error {
+            return Err(anyhow!("error pushing {}:{}: {}", repo, tag, error));
+        } else if let Some(status) = info.status {
+            progress.update(
+                None,
+                &status,
+                info.progress.as_deref(),
+                info.progress_detail.as_ref(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Pull a `portable-clang:<tag>` image from a registry.
+///
+/// The pulled image is also tagged `portable-clang:<tag>` locally. Returns the
+/// image ID.
+pub async fn pull_image_from_registry(
+    logger: &Logger,
+    docker: &Docker,
+    registry: &str,
+    tag: &str,
+) -> Result<String> {
+    let repo = format!("{}/portable-clang", registry.trim_end_matches('/'));
+
+    warn!(logger, "pulling {}:{}", repo, tag);
+
+    let options = CreateImageOptions {
+        from_image: repo.as_str(),
+        tag,
+        ..Default::default()
+    };
+    let mut stream = docker.create_image(Some(options), None, registry_credentials(registry));
+    let mut progress = LayerProgress::new(logger, "pull");
+
+    while let Some(info) = stream
+        .try_next()
+        .await
+        .with_context(|| format!("pulling {}:{}", repo, tag))?
+    {
+        if let Some(error) = info.error {
+            return Err(anyhow!("error pulling {}:{}: {}", repo, tag, error));
+        } else if let Some(status) = info.status {
+            progress.update(
+                info.id.as_deref(),
+                &status,
+                info.progress.as_deref(),
+                info.progress_detail.as_ref(),
+            );
+        }
+    }
+
+    let reference = format!("{}:{}", repo, tag);
+    let image_id = docker
+        .inspect_image(&reference)
+        .await
+        .with_context(|| format!("inspecting {}", reference))?
+        .id;
+
+    let options = TagImageOptions {
+        repo: "portable-clang",
+        tag,
+    };
+    docker
+        .tag_image(&image_id, Some(options))
+        .await
+        .with_context(|| format!("tagging image portable-clang:{}", tag))?;
+
+    Ok(image_id)
+}
+
+/// Whether a `portable-clang` image tag is a content addressed tag.
+///
+/// These are tags like `clang-0123456789abcdef` produced by [build_image_cached].
+fn is_content_tag(tag: &str) -> bool {
+    tag.rsplit_once('-')
+        .map(|(_, digest)| digest.len() == 16 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .unwrap_or(false)
+}
+
+/// Remove stale `portable-clang:*` images.
+///
+/// An image is stale if it isn't referenced by an unversioned alias tag like
+/// `portable-clang:clang`, which always points at the most recently built image.
+/// If `prune_dangling` is set, all dangling images are also pruned.
+///
+/// With `dry_run`, stale images are only reported. Returns the number of bytes
+/// reclaimed.
+pub async fn garbage_collect_images(
+    logger: &Logger,
+    docker: &Docker,
+    dry_run: bool,
+    prune_dangling: bool,
+) -> Result<u64> {
+    let options = ListImagesOptions {
+        filters: HashMap::from_iter([("reference", vec!["portable-clang"])]),
+        ..Default::default()
+    };
+    let images = docker
+        .list_images(Some(options))
+        .await
+        .context("listing images")?;
+
+    let mut reclaimed = 0;
+
+    for image in images {
+        let tags = image
+            .repo_tags
+            .iter()
+            .filter_map(|tag| tag.strip_prefix("portable-clang:"))
+            .collect::<Vec<_>>();
+
+        if tags.iter().any(|tag| !is_content_tag(tag)) {
+            continue;
+        }
+
+        for tag in tags {
+            warn!(logger, "removing stale image portable-clang:{}", tag);
+
+            if dry_run {
+                continue;
+            }
+
+            let deleted = docker
+                .remove_image(
+                    &format!("portable-clang:{}", tag),
+                    Some(RemoveImageOptions::default()),
+                    None,
+                )
+                .await
+                .with_context(|| format!("removing image portable-clang:{}", tag))?;
+
+            if deleted.iter().any(|item| item.deleted.is_some()) {
+                reclaimed += image.size.max(0) as u64;
+            }
+        }
+    }
+
+    if prune_dangling && !dry_run {
+        let options = PruneImagesOptions {
+            filters: HashMap::from_iter([("dangling", vec!["true"])]),
+        };
+        let response = docker
+            .prune_images(Some(options))
+            .await
+            .context("pruning dangling images")?;
+
+        for image in response.images_deleted.unwrap_or_default() {
+            if let Some(id) = image.deleted {
+                warn!(logger, "removed dangling image {}", id);
+            }
+        }
+
+        reclaimed += response.space_reclaimed.unwrap_or_default().max(0) as u64;
+    }
+
+    Ok(reclaimed)
+}
+
+/// Load image tar data.
+///
+/// The tar data is streamed to Docker from the given [Body].
+pub async fn load_image_tar(logger: &Logger, docker: &Docker, tar_data: Body) -> Result<String> {
+    let options = ImportImageOptions::default();
+    let mut stream = docker.import_image(options, tar_data, None);
+
+    let mut progress = LayerProgress::new(logger, "load");
+
+    while let Some(info) = stream.try_next().await? {
+        if let Some(stream) = info.stream {
+            for part in stream.split('\n').filter(|s| !s.is_empty()) {
+                progress.println(part);
+
+                // For some reason we don't get the image ID reported in any aux responses.
+                // So parse it from stdout. This is extremely hacky.
+                if let Some((_, id)) = part.split_once("Loaded image ID: ") {
+                    return Ok(id.to_string());
+                }
+            }
+        } else if let Some(status) = info.status {
+            progress.update(
+                info.id.as_deref(),
+                &status,
+                info.progress.as_deref(),
+                info.progress_detail.as_ref(),
+            );
+        } else if let Some(image_id) = info.aux {
+            return image_id.id.ok_or_else(|| anyhow!("image ID not set"));
+        }
+    }
+
+    Err(anyhow!("error loading image"))
+}
+
+/// Load a tar.zst file into Docker and return the image id.
+///
+/// Images can be several gigabytes. So rather than decompressing into memory,
+/// the tar is decompressed to a temporary file, which is streamed to Docker.
+pub async fn load_image_tar_zst(
+    logger: &Logger,
+    docker: &Docker,
+    reader: impl Read,
+) -> Result<String> {
+    let mut spool = tempfile::Builder::new()
+        .prefix("pclang-image-")
+        .suffix(".tar")
+        .tempfile()
+        .context("creating temporary file for image tar")?;
+
+    zstd::stream::copy_decode(reader, spool.as_file_mut())
+        .context("zstd decompressing image data")?;
+
+    // In CI we see JSON decode errors intermittently. The root cause is unknown.
+    // https://github.com/fussybeaver/bollard/issues/171. We retry the operation
+    // multiple times as a workaround.
+    for attempt in 0..5 {
+        let fh = tokio::fs::File::open(spool.path())
+            .await
+            .context("opening image tar")?;
+        let body = Body::wrap_stream(ReaderStream::new(fh));
+
+        match load_image_tar(logger, docker, body).await {
+            Ok(image) => {
+                return Ok(image);
+            }
+            Err(e) => {
+                warn!(logger, "attempt #{}: image load failed: {:?}", attempt, e);
+            }
+        }
+    }
+
+    Err(anyhow!("image load failed multiple times"))
+}
+
+/// Number of trailing container log lines to retain for error reporting.
+const CONTAINER_ERROR_LOG_LINES: usize = 50;
+
+/// A container exited with a non-zero exit code.
+#[derive(Debug, Error)]
+#[error("container exited with code {exit_code}; last output:\n{}", last_lines.join("\n"))]
+pub struct ContainerExitError {
+    /// Exit code of the container's main process.
+    pub exit_code: i64,
+    /// The last lines of output from the container.
+    pub last_lines: Vec<String>,
+}
+
+/// A container didn't finish within its allotted time.
+#[derive(Debug, Error)]
+#[error(
+    "container timed out after {} seconds; last output:\n{}",
+    timeout.as_secs(),
+    last_lines.join("\n")
+)]
+pub struct ContainerTimeoutError {
+    /// The timeout that expired.
+    pub timeout: Duration,
+    /// The last lines of output from the container.
+    pub last_lines: Vec<String>,
+}
+
+/// Stop a container that exceeded its timeout and collect its final output.
+async fn stop_timed_out_container(
+    logger: &Logger,
+    docker: &Docker,
+    container_id: &str,
+    timeout: Duration,
+) -> ContainerTimeoutError {
+    warn!(
+        logger,
+        "container {} exceeded timeout of {} seconds; stopping",
+        container_id,
+        timeout.as_secs()
+    );
+
+    if let Err(e) = docker
+        .stop_container(container_id, Some(StopContainerOptions { t: 10 }))
+        .await
+    {
+        warn!(logger, "failed to stop container {}: {:?}", container_id, e);
+    }
+
+    let tail = CONTAINER_ERROR_LOG_LINES.to_string();
+    let options = LogsOptions::<String> {
+        stdout: true,
+        stderr: true,
+        tail,
+        ..Default::default()
+    };
+
+    let last_lines = match docker
+        .logs(container_id, Some(options))
+        .try_collect::<Vec<_>>()
+        .await
+    {
+        Ok(output) => output
+            .iter()
+            .flat_map(|output| {
+                output
+                    .to_string()
+                    .split('\n')
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            warn!(
+                logger,
+                "failed to collect logs of {}: {:?}", container_id, e
+            );
+            vec![]
+        }
+    };
+
+    ContainerTimeoutError {
+        timeout,
+        last_lines,
+    }
+}
+
+/// IDs of containers created by this process that haven't finished running.
+///
+/// Tracked so containers can be torn down if we're interrupted.
+static RUNNING_CONTAINERS: Lazy<Mutex<BTreeSet<String>>> =
+    Lazy::new(|| Mutex::new(BTreeSet::new()));
+
+/// Forcefully stop and remove containers that are still running.
+///
+/// This is called when the process is interrupted so we don't leave orphaned
+/// containers behind. Errors are logged and otherwise ignored.
+pub async fn cleanup_running_containers(logger: &Logger, docker: &Docker) {
+    let container_ids = std::mem::take(
+        &mut *RUNNING_CONTAINERS
+            .lock()
+            .expect("running containers lock should not be poisoned"),
+    );
+
+    for container_id in container_ids {
+        warn!(logger, "removing container {}", container_id);
+
+        let options = RemoveContainerOptions {
+            force: true,
+            ..Default::default()
+        };
+
+        if let Err(e) = docker.remove_container(&container_id, Some(options)).await {
+            warn!(
+                logger,
+                "failed to remove container {}: {:?}", container_id, e
+            );
+        }
+    }
+}
+
+/// How files are exchanged between the host and containers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileTransferMode {
+    /// Bind mount host directories into containers.
+    ///
+    /// Requires the Docker daemon to see the same filesystem we do.
+    Bind,
+
+    /// Copy files into and out of containers as tar archives via the Docker API.
+    ///
+    /// Works with remote and rootless daemons. Cache directories are not
+    /// synchronized in this mode, so a remote sccache backend should be used.
+    Archive,
+}
+
+impl FromStr for FileTransferMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bind" => Ok(Self::Bind),
+            "archive" => Ok(Self::Archive),
+            _ => Err(anyhow!("unsupported file transfer mode: {}", s)),
+        }
+    }
+}
+
+/// Settings influencing how containers are run.
+#[derive(Clone, Debug)]
+pub struct ContainerSettings {
+    /// How files are exchanged with containers.
+    pub transfer_mode: FileTransferMode,
+
+    /// Maximum wall clock time a container may run for.
+    pub timeout: Option<Duration>,
+
+    /// Directory to write a log file of each container's output to.
+    pub log_dir: Option<PathBuf>,
+
+    /// Named Docker volume to use for the sccache cache instead of a host directory.
+    ///
+    /// Bind mounts are slow on Docker Desktop, so volumes perform much better there.
+    pub sccache_volume: Option<String>,
+
+    /// Variables configuring the sccache backend, e.g. `SCCACHE_BUCKET`.
+    ///
+    /// Variables in the host environment take precedence.
+    pub sccache_env: BTreeMap<String, String>,
+
+    /// Security options such as `seccomp=<profile JSON>` or `apparmor=unconfined`.
+    pub security_opts: Vec<String>,
+
+    /// Whether to run containers in privileged mode.
+    pub privileged: bool,
+
+    /// Linux capabilities to add to containers. e.g. `SYS_PTRACE`.
+    pub cap_add: Vec<String>,
+
+    /// Network mode of containers. e.g. `none` or the name of a custom network.
+    ///
+    /// `none` makes builds hermetic but prevents use of remote sccache backends.
+    pub network_mode: Option<String>,
+
+    /// Proxies containers should access the network through.
+    pub proxy: ProxySettings,
+
+    /// How the build user maps to the host user.
+    pub user_mapping: UserMapping,
+
+    /// Keep containers and temporary directories of failed runs for debugging.
+    pub keep_failed: bool,
+
+    /// Forward container output to the terminal unmodified instead of logging lines.
+    ///
+    /// Containers get a TTY so tools emit colors and progress. Only takes
+    /// effect if stdout is a terminal. See [Self::stream_raw].
+    pub raw_output: bool,
+
+    /// How artifacts produced by containers are compressed.
+    pub zstd: ZstdSettings,
+
+    /// Which file metadata artifacts produced by containers record.
+    pub tar_metadata: TarMetadata,
+
+    /// Compression format of final toolchain artifacts.
+    pub artifact_format: ArtifactFormat,
+}
+
+impl Default for ContainerSettings {
+    fn default() -> Self {
+        Self {
+            transfer_mode: FileTransferMode::Bind,
+            timeout: None,
+            log_dir: None,
+            sccache_volume: None,
+            sccache_env: BTreeMap::new(),
+            security_opts: vec![],
+            privileged: false,
+            cap_add: vec![],
+            network_mode: None,
+            proxy: ProxySettings::default(),
+            user_mapping: UserMapping::Fixed,
+            keep_failed: false,
+            raw_output: false,
+            zstd: ZstdSettings::default(),
+            tar_metadata: TarMetadata::default(),
+            artifact_format: ArtifactFormat::default(),
+        }
+    }
+}
+
+impl ContainerSettings {
+    /// Whether container output is streamed raw to the terminal.
+    pub fn stream_raw(&self) -> bool {
+        self.raw_output && atty::is(atty::Stream::Stdout)
+    }
+}
+
+/// The role of a host directory made available to a container.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContainerDirKind {
+    /// Content is read by the container.
+    Input,
+    /// Content is written by the container and collected afterwards.
+    Output,
+    /// Content is persisted across container runs.
+    Cache,
+}
+
+/// A host directory made available to a container at a given path.
+pub struct ContainerDir {
+    pub host: PathBuf,
+    pub container: &'static str,
+    pub kind: ContainerDirKind,
+    /// Named Docker volume backing the directory instead of `host`.
+    pub volume: Option<String>,
+}
+
+impl ContainerDir {
+    fn new(host: impl AsRef<Path>, container: &'static str, kind: ContainerDirKind) -> Self {
+        Self {
+            host: host.as_ref().to_path_buf(),
+            container,
+            kind,
+            volume: None,
+        }
+    }
+
+    /// A cache directory backed by a named Docker volume.
+    ///
+    /// Volumes are mounted regardless of the file transfer mode.
+    fn volume(name: &str, container: &'static str) -> Self {
+        Self {
+            host: PathBuf::new(),
+            container,
+            kind: ContainerDirKind::Cache,
+            volume: Some(name.to_string()),
+        }
+    }
+}
+
+/// Default name of the Docker volume holding the sccache cache.
+pub const DEFAULT_SCCACHE_VOLUME: &str = "pclang-sccache";
+
+/// Print information about a Docker volume holding an sccache cache.
+pub async fn inspect_sccache_volume(logger: &Logger, docker: &Docker, name: &str) -> Result<()> {
+    let volume = docker
+        .inspect_volume(name)
+        .await
+        .with_context(|| format!("inspecting volume {}", name))?;
+
+    warn!(logger, "volume: {}", volume.name);
+    warn!(logger, "driver: {}", volume.driver);
+    warn!(logger, "mountpoint: {}", volume.mountpoint);
+    if let Some(created) = &volume.created_at {
+        warn!(logger, "created: {}", created);
+    }
+
+    // Only the disk usage endpoint reports volume sizes.
+    let usage = docker.df().await.context("querying Docker disk usage")?;
+    if let Some(usage) = usage
+        .volumes
+        .unwrap_or_default()
+        .into_iter()
+        .find(|v| v.name == name)
+        .and_then(|v| v.usage_data)
+    {
+        warn!(logger, "size: {} bytes", usage.size);
+        warn!(logger, "containers using: {}", usage.ref_count);
+    }
+
+    Ok(())
+}
+
+/// Remove a Docker volume holding an sccache cache.
+pub async fn remove_sccache_volume(logger: &Logger, docker: &Docker, name: &str) -> Result<()> {
+    docker
+        .remove_volume(name, Some(RemoveVolumeOptions { force: true }))
+        .await
+        .with_context(|| format!("removing volume {}", name))?;
+
+    warn!(logger, "removed volume {}", name);
+
+    Ok(())
+}
+
+/// Resolve the directory backing `/sccache` in containers.
+///
+/// This is a named volume if configured or a directory in the cache directory.
+fn sccache_container_dir(settings: &ContainerSettings, cache_dir: &Path) -> Result<ContainerDir> {
+    if let Some(volume) = &settings.sccache_volume {
+        Ok(ContainerDir::volume(volume, "/sccache"))
+    } else {
+        let sccache_dir = cache_dir.join("sccache");
+        std::fs::create_dir_all(&sccache_dir).context("creating sccache cache directory")?;
+
+        Ok(ContainerDir::new(
+            sccache_dir,
+            "/sccache",
+            ContainerDirKind::Cache,
+        ))
+    }
+}
+
+/// Append a directory owned by the build user to a tar archive.
+fn append_build_dir<W: Write>(builder: &mut tar::Builder<W>, path: &Path) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_mode(0o777);
+    header.set_mtime(1609502400);
+    header.set_uid(BUILD_UID);
+    header.set_gid(BUILD_UID);
+    header.set_size(0);
+
+    builder
+        .append_data(&mut header, path, std::io::empty())
+        .context("appending directory to tar archive")?;
+
+    Ok(())
+}
+
+/// Upload directories to a created but not yet started container.
+///
+/// Input directories have their content copied. Other directories are created
+/// empty so the build user can write to them.
+async fn upload_container_dirs(
+    docker: &Docker,
+    container_id: &str,
+    dirs: &[ContainerDir],
+) -> Result<()> {
+    let fh = tempfile::tempfile().context("creating temporary file")?;
+    let mut builder = tar::Builder::new(fh);
+
+    for dir in dirs.iter().filter(|dir| dir.volume.is_none()) {
+        let archive_root = Path::new(dir.container.trim_start_matches('/'));
+        append_build_dir(&mut builder, archive_root)?;
+
+        if dir.kind != ContainerDirKind::Input {
+            continue;
+        }
+
+        for entry in walkdir::WalkDir::new(&dir.host)
+            .min_depth(1)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+        {
+            let entry = entry?;
+            let archive_path = archive_root.join(entry.path().strip_prefix(&dir.host)?);
+
+            if entry.file_type().is_dir() {
+                append_build_dir(&mut builder, &archive_path)?;
+            } else {
+                builder
+                    .append_path_with_name(entry.path(), &archive_path)
+                    .with_context(|| format!("adding {} to tar archive", entry.path().display()))?;
+            }
+        }
+    }
+
+    let mut fh = builder.into_inner().context("finishing tar archive")?;
+    fh.seek(SeekFrom::Start(0))?;
+
+    let body = Body::wrap_stream(ReaderStream::new(tokio::fs::File::from_std(fh)));
+
+    let options = UploadToContainerOptions {
+        path: "/",
+        ..Default::default()
+    };
+
+    docker
+        .upload_to_container(container_id, Some(options), body)
+        .await
+        .context("uploading files to container")?;
+
+    Ok(())
+}
+
+/// Download the content of a container directory to its host directory.
+async fn download_container_dir(
+    docker: &Docker,
+    container_id: &str,
+    dir: &ContainerDir,
+) -> Result<()> {
+    let options = DownloadFromContainerOptions {
+        path: dir.container,
+    };
+    let mut stream = docker.download_from_container(container_id, Some(options));
+
+    let mut fh = tempfile::tempfile().context("creating temporary file")?;
+    while let Some(data) = stream
+        .try_next()
+        .await
+        .with_context(|| format!("downloading {} from container", dir.container))?
+    {
+        fh.write_all(data.as_ref())?;
+    }
+    fh.seek(SeekFrom::Start(0))?;
+
+    let mut archive = tar::Archive::new(fh);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        // Members are rooted at the directory's base name.
+        let path = entry
+            .path()?
+            .components()
+            .skip(1)
+            .map(|c| match c {
+                std::path::Component::Normal(c) => Ok(c),
+                _ => Err(anyhow!("illegal path in container archive: {:?}", c)),
+            })
+            .collect::<Result<PathBuf>>()?;
+
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let dest_path = dir.host.join(path);
+
+        if let Some(parent) = dest_path.parent() {
+            std::fs::create_dir_all(parent).context("creating parent directory")?;
+        }
+
+        entry
+            .unpack(&dest_path)
+            .with_context(|| format!("extracting {}", dest_path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Create a file to record a container's output in.
+///
+/// Files are named after the time, the command the container runs, and the
+/// container ID so concurrent and repeated runs don't collide.
+fn create_container_log_file(
+    log_dir: &Path,
+    step: &str,
+    container_id: &str,
+) -> Result<(PathBuf, std::fs::File)> {
+    std::fs::create_dir_all(log_dir).context("creating container log directory")?;
+
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let path = log_dir.join(format!(
+        "{}-{}-{}.log",
+        timestamp,
+        step,
+        &container_id[0..container_id.len().min(12)]
+    ));
+
+    let fh =
+        std::fs::File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+
+    Ok((path, fh))
+}
+
+/// Resolve a container security option.
+///
+/// The Docker API expects seccomp profiles inline. So `seccomp=<path>` is
+/// replaced with the content of the file, like the `docker` CLI does.
+pub fn resolve_security_opt(opt: &str) -> Result<String> {
+    match opt.split_once('=') {
+        Some(("seccomp", profile)) if profile != "unconfined" => {
+            let data = std::fs::read_to_string(profile)
+                .with_context(|| format!("reading seccomp profile {}", profile))?;
+
+            Ok(format!("seccomp={}", data))
+        }
+        _ => Ok(opt.to_string()),
+    }
+}
+
+/// Apply user, security, network, and proxy settings to a container.
+fn apply_host_settings(settings: &ContainerSettings, config: &mut ContainerConfig<String>) {
+    if settings.user_mapping == UserMapping::Rootless {
+        config.user = Some("0:0".to_string());
+    }
+
+    if !settings.proxy.is_empty() {
+        config.env.get_or_insert(vec![]).extend(
+            settings
+                .proxy
+                .envs()
+                .into_iter()
+                .map(|(key, value)| format!("{}={}", key, value)),
+        );
+    }
+
+    if settings.security_opts.is_empty()
+        && !settings.privileged
+        && settings.cap_add.is_empty()
+        && settings.network_mode.is_none()
+    {
+        return;
+    }
+
+    let host_config = config.host_config.get_or_insert_with(HostConfig::default);
+
+    if !settings.security_opts.is_empty() {
+        host_config.security_opt = Some(settings.security_opts.clone());
+    }
+    if settings.privileged {
+        host_config.privileged = Some(true);
+    }
+    if !settings.cap_add.is_empty() {
+        host_config.cap_add = Some(settings.cap_add.clone());
+    }
+    if let Some(network_mode) = &settings.network_mode {
+        host_config.network_mode = Some(network_mode.clone());
+    }
+}
+
+/// Mount volumes into a container, and host directories if bind transfer mode is used.
+fn bind_container_dirs(
+    settings: &ContainerSettings,
+    config: &mut ContainerConfig<String>,
+    dirs: &[ContainerDir],
+) {
+    let binds = dirs
+        .iter()
+        .filter_map(|dir| match &dir.volume {
+            Some(volume) => Some(format!("{}:{}", volume, dir.container)),
+            None if settings.transfer_mode == FileTransferMode::Bind => {
+                Some(format!("{}:{}", dir.host.display(), dir.container))
+            }
+            None => None,
+        })
+        .collect::<Vec<_>>();
+
+    if !binds.is_empty() {
+        config
+            .host_config
+            .get_or_insert_with(HostConfig::default)
+            .binds = Some(binds);
+    }
+}
+
+/// Commands to get a shell in the filesystem of a kept container.
+///
+/// The container has exited, so it is committed to an image that a new
+/// container is started from, with the same directories mounted.
+fn debug_container_commands(container_id: &str, binds: &[String]) -> Vec<String> {
+    let image = format!(
+        "pclang-debug:{}",
+        &container_id[0..container_id.len().min(12)]
+    );
+    let mounts = binds
+        .iter()
+        .map(|bind| format!(" -v {}", bind))
+        .collect::<String>();
+
+    vec![
+        format!("docker commit {} {}", container_id, image),
+        format!("docker run -it --rm{} {} /bin/bash --login", mounts, image),
+        format!("docker rm {} && docker rmi {}", container_id, image),
+    ]
+}
+
+/// Keep a temporary directory if a run failed and failed runs are kept.
+///
+/// Returns the error, annotated with the location of the directory if kept.
+fn keep_failed_temp_dir(
+    logger: &Logger,
+    settings: &ContainerSettings,
+    temp_dir: tempfile::TempDir,
+    err: anyhow::Error,
+) -> anyhow::Error {
+    if settings.keep_failed {
+        let path = temp_dir.keep();
+        warn!(logger, "kept temporary directory {}", path.display());
+
+        err.context(format!("temporary files kept in {}", path.display()))
+    } else {
+        err
+    }
+}
+
+async fn run_and_log_container(
+    logger: &Logger,
+    docker: &Docker,
+    settings: &ContainerSettings,
+    mut config: ContainerConfig<String>,
+    dirs: &[ContainerDir],
+) -> Result<()> {
+    bind_container_dirs(settings, &mut config, dirs);
+    apply_host_settings(settings, &mut config);
+
+    // Name the step after the script the container runs.
+    let step = config
+        .cmd
+        .as_ref()
+        .and_then(|cmd| cmd.first())
+        .and_then(|program| Path::new(program).file_stem())
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "container".to_string());
+
+    let binds = config
+        .host_config
+        .as_ref()
+        .and_then(|host_config| host_config.binds.clone())
+        .unwrap_or_default();
+
+    // With a TTY, stdout and stderr are merged. But tools emit colors and
+    // progress, which is what we want when streaming to a terminal.
+    let raw = settings.stream_raw();
+    if raw {
+        config.tty = Some(true);
+        let env = config.env.get_or_insert(vec![]);
+        if let Ok(term) = std::env::var("TERM") {
+            env.push(format!("TERM={}", term));
+        }
+        // The TTY of a container can't be resized before it starts. So
+        // advertise the terminal size to tools drawing progress instead.
+        if let Some((width, height)) = RawTerminal::size() {
+            env.push(format!("COLUMNS={}", width));
+            env.push(format!("LINES={}", height));
+        }
+    }
+
+    let response = docker
+        .create_container(Some(CreateContainerOptions::<String>::default()), config)
+        .await
+        .context("creating Docker container")?;
+    let container_id = response.id;
+
+    RUNNING_CONTAINERS
+        .lock()
+        .expect("running containers lock should not be poisoned")
+        .insert(container_id.clone());
+
+    // Log files are a convenience, so failing to create one isn't fatal.
+    let (log_path, mut log_file) = if let Some(log_dir) = &settings.log_dir {
+        match create_container_log_file(log_dir, &step, &container_id) {
+            Ok((path, fh)) => (Some(path), Some(fh)),
+            Err(e) => {
+                warn!(logger, "unable to create container log file: {:?}", e);
+                (None, None)
+            }
+        }
+    } else {
+        (None, None)
+    };
+
+    let res: Result<()> = async {
+        if settings.transfer_mode == FileTransferMode::Archive {
+            upload_container_dirs(docker, &container_id, dirs).await?;
+        }
+
+        if let Some(timeout) = settings.timeout {
+            match tokio::time::timeout(
+                timeout,
+                start_and_log_container(logger, docker, &container_id, raw, log_file.as_mut()),
+            )
+            .await
+            {
+                Ok(res) => res?,
+                Err(_) => {
+                    return Err(
+                        stop_timed_out_container(logger, docker, &container_id, timeout)
+                            .await
+                            .into(),
+                    );
+                }
+            }
+        } else {
+            start_and_log_container(logger, docker, &container_id, raw, log_file.as_mut()).await?;
+        }
+
+        if settings.transfer_mode == FileTransferMode::Archive {
+            for dir in dirs
+                .iter()
+                .filter(|dir| dir.kind == ContainerDirKind::Output)
+            {
+                download_container_dir(docker, &container_id, dir).await?;
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    let res = match (res, &log_path) {
+        (Err(e), Some(path)) => {
+            warn!(logger, "container output written to {}", path.display());
+            Err(e.context(format!("container output written to {}", path.display())))
+        }
+        (res, _) => res,
+    };
+
+    RUNNING_CONTAINERS
+        .lock()
+        .expect("running containers lock should not be poisoned")
+        .remove(&container_id);
+
+    if res.is_err() && settings.keep_failed {
+        warn!(
+            logger,
+            "kept failed container {}; to get a shell in it, run:", container_id
+        );
+        for command in debug_container_commands(&container_id, &binds) {
+            warn!(logger, "  {}", command);
+        }
+
+        return res;
+    }
+
+    // We remove the container ourselves instead of having Docker auto remove it
+    // so the exit code can be retrieved reliably.
+    let options = RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    };
+    if let Err(e) = docker.remove_container(&container_id, Some(options)).await {
+        warn!(
+            logger,
+            "failed to remove container {}: {:?}", container_id, e
+        );
+    }
+
+    res
+}
+
+/// Raw bytes of a chunk of container output.
+fn log_output_bytes(output: &LogOutput) -> &[u8] {
+    match output {
+        LogOutput::StdErr { message }
+        | LogOutput::StdOut { message }
+        | LogOutput::StdIn { message }
+        | LogOutput::Console { message } => message.as_ref(),
+    }
+}
+
+/// Lines of raw terminal output, as they would appear on the terminal.
+///
+/// Text before a carriage return is overwritten by text after it, as done by
+/// progress indicators.
+struct TerminalLines {
+    pending: String,
+}
+
+impl TerminalLines {
+    fn new() -> Self {
+        Self {
+            pending: String::new(),
+        }
+    }
+
+    /// Add output, returning the lines it completes.
+    fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.pending.push_str(&String::from_utf8_lossy(data));
+
+        let mut lines = vec![];
+        while let Some(pos) = self.pending.find('\n') {
+            let line = self.pending.drain(..=pos).collect::<String>();
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            let visible = line.rsplit('\r').next().unwrap_or_default();
+
+            if !visible.is_empty() {
+                lines.push(visible.to_string());
+            }
+        }
+
+        lines
+    }
+}
+
+async fn start_and_log_container(
+    logger: &Logger,
+    docker: &Docker,
+    container_id: &str,
+    raw: bool,
+    mut log_file: Option<&mut std::fs::File>,
+) -> Result<()> {
+    let options = StartContainerOptions::<String>::default();
+    docker
+        .start_container(container_id, Some(options))
+        .await
+        .context("starting Docker container")?;
+
+    let monitor = ContainerMonitor::start(logger, docker, container_id);
+
+    let options = LogsOptions::<String> {
+        follow: true,
+        stdout: true,
+        stderr: true,
+        ..Default::default()
+    };
+    let mut stream = docker.logs(container_id, Some(options));
+
+    let mut last_lines = VecDeque::with_capacity(CONTAINER_ERROR_LOG_LINES);
+
+    // stderr is logged at a higher level and tagged so errors and warnings
+    // stand out from regular build output.
+    let stderr_logger = logger.new(o!("stream" => "stderr"));
+
+    let mut stdout = std::io::stdout();
+    let mut terminal_lines = TerminalLines::new();
+
+    while let Some(output) = stream.try_next().await? {
+        if raw {
+            let data = log_output_bytes(&output);
+
+            stdout.write_all(data)?;
+            stdout.flush()?;
+
+            if let Some(fh) = log_file.as_mut() {
+                fh.write_all(data).context("writing container log file")?;
+            }
+
+            for line in terminal_lines.push(data) {
+                if last_lines.len() == CONTAINER_ERROR_LOG_LINES {
+                    last_lines.pop_front();
+                }
+                last_lines.push_back(line);
+            }
+
+            continue;
+        }
+
+        let is_stderr = matches!(output, LogOutput::StdErr { .. });
+
+        for line in output.to_string().split('\n').filter(|x| !x.is_empty()) {
+            let line = if is_stderr {
+                warn!(stderr_logger, "{}", line);
+                format!("[stderr] {}", line)
+            } else {
+                info!(logger, "{}", line);
+                line.to_string()
+            };
+
+            if let Some(fh) = log_file.as_mut() {
+                writeln!(fh, "{}", line).context("writing container log file")?;
+            }
+
+            if last_lines.len() == CONTAINER_ERROR_LOG_LINES {
+                last_lines.pop_front();
+            }
+            last_lines.push_back(line);
+        }
+    }
+
+    let exit_code = wait_container_exit(docker, container_id).await?;
+
+    monitor.finish(logger);
+
+    if exit_code != 0 {
+        return Err(ContainerExitError {
+            exit_code,
+            last_lines: last_lines.into_iter().collect::<Vec<_>>(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Wait for a container to stop and obtain its exit code.
+async fn wait_container_exit(docker: &Docker, container_id: &str) -> Result<i64> {
+    let options = WaitContainerOptions {
+        condition: "not-running",
+    };
+    let mut stream = docker.wait_container(container_id, Some(options));
+
+    let mut exit_code = None;
+    while let Some(response) = stream
+        .try_next()
+        .await
+        .context("waiting on Docker container")?
+    {
+        exit_code = Some(response.status_code);
+    }
+
+    exit_code.ok_or_else(|| anyhow!("Docker did not report container exit code"))
+}
+
+/// Puts the controlling terminal in raw mode until dropped.
+///
+/// We shell out to `stty` to avoid a dependency on platform terminal APIs.
+struct RawTerminal {
+    saved: Option<String>,
+}
+
+impl RawTerminal {
+    fn enable() -> Self {
+        let saved = std::process::Command::new("stty")
+            .arg("-g")
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+
+        if saved.is_some() {
+            let _ = std::process::Command::new("stty")
+                .args(["raw", "-echo"])
+                .status();
+        }
+
+        Self { saved }
+    }
+
+    /// Obtain the (width, height) of the terminal.
+    fn size() -> Option<(u16, u16)> {
+        let output = std::process::Command::new("stty")
+            .arg("size")
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut parts = stdout.split_whitespace();
+        let height = parts.next()?.parse().ok()?;
+        let width = parts.next()?.parse().ok()?;
+
+        Some((width, height))
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            let _ = std::process::Command::new("stty").arg(saved).status();
+        }
+    }
+}
+
+/// Run an interactive shell in a builder image.
+///
+/// The container receives the same environment variables and cache directory
+/// as builds. `/out` is backed by a `shell-out` directory in the cache
+/// directory so artifacts can be inspected afterwards. Returns the exit code
+/// of the shell.
+///
+/// The container idles while the shell runs in it via exec, since the
+/// Docker API version we target can only resize the TTY of execs.
+pub async fn interactive_shell(
+    logger: &Logger,
+    docker: &Docker,
+    settings: &ContainerSettings,
+    image_id: &str,
+    cache_dir: impl AsRef<Path>,
+) -> Result<i64> {
+    let cache_dir = cache_dir.as_ref();
+    let sccache_dir = sccache_container_dir(settings, cache_dir)?;
+
+    let out_dir = cache_dir.join("shell-out");
+    std::fs::create_dir_all(&out_dir).context("creating shell outputs directory")?;
+    prepare_output_dir(settings, &out_dir)?;
+
+    let mut config = ContainerConfig::<String> {
+        attach_stdin: Some(false),
+        attach_stdout: Some(false),
+        attach_stderr: Some(false),
+        tty: Some(false),
+        cmd: Some(vec!["sleep".into(), "infinity".into()]),
+        image: Some(image_id.into()),
+        ..Default::default()
+    };
+
+    add_container_envs(settings, &mut config)?;
+
+    let dirs = [
+        ContainerDir::new(&out_dir, "/out", ContainerDirKind::Output),
+        sccache_dir,
+    ];
+
+    bind_container_dirs(settings, &mut config, &dirs);
+    apply_host_settings(settings, &mut config);
+
+    let response = docker
+        .create_container(Some(CreateContainerOptions::<String>::default()), config)
+        .await
+        .context("creating Docker container")?;
+    let container_id = response.id;
+
+    RUNNING_CONTAINERS
+        .lock()
+        .expect("running containers lock should not be poisoned")
+        .insert(container_id.clone());
+
+    let res: Result<i64> = async {
+        if settings.transfer_mode == FileTransferMode::Archive {
+            upload_container_dirs(docker, &container_id, &dirs).await?;
+        }
 
-                // For some reason we don't get the image ID reported in any aux responses.
-                // So parse it from stdout. This is extremely hacky.
-                if let Some((_, id)) = part.split_once("Loaded image ID: ") {
-                    return Ok(id.to_string());
-                }
+        docker
+            .start_container(&container_id, None::<StartContainerOptions<String>>)
+            .await
+            .context("starting Docker container")?;
+
+        let options = CreateExecOptions::<String> {
+            attach_stdin: Some(true),
+            attach_stdout: Some(true),
+            attach_stderr: Some(true),
+            tty: Some(true),
+            cmd: Some(vec!["/bin/bash".into(), "--login".into()]),
+            ..Default::default()
+        };
+        let exec_id = docker
+            .create_exec(&container_id, options)
+            .await
+            .context("creating shell in Docker container")?
+            .id;
+
+        let (mut output, mut input) = match docker
+            .start_exec(&exec_id, None)
+            .await
+            .context("starting shell in Docker container")?
+        {
+            StartExecResults::Attached { output, input } => (output, input),
+            StartExecResults::Detached => {
+                return Err(anyhow!("Docker did not attach to the shell"));
             }
-        } else if let Some(status) = info.status {
-            if let Some(progress) = info.progress {
-                warn!(logger, "{} {}", status, progress);
-            } else {
-                warn!(logger, "{}", status);
+        };
+
+        if let Some((width, height)) = RawTerminal::size() {
+            let options = ResizeExecOptions { width, height };
+            if let Err(e) = docker.resize_exec(&exec_id, options).await {
+                warn!(logger, "unable to resize container terminal: {:?}", e);
             }
-        } else if let Some(image_id) = info.aux {
-            return image_id.id.ok_or_else(|| anyhow!("image ID not set"));
         }
-    }
 
-    Err(anyhow!("error loading image"))
-}
-
-/// Load a tar.zst file into Docker and return the image id.
-pub async fn load_image_tar_zst(
-    logger: &Logger,
-    docker: &Docker,
-    reader: impl Read,
-) -> Result<String> {
-    let tar_data = zstd::decode_all(reader).context("zstd decompressing image data")?;
+        // Reading stdin blocks. So do it on a thread we never join: it may
+        // still be waiting for input when the container exits.
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buffer = [0u8; 4096];
 
-    // In CI we see JSON decode errors intermittently. The root cause is unknown.
-    // https://github.com/fussybeaver/bollard/issues/171. We retry the operation
-    // multiple times as a workaround.
-    for attempt in 0..5 {
-        match load_image_tar(logger, docker, tar_data.clone()).await {
-            Ok(image) => {
-                return Ok(image);
+            while let Ok(count) = stdin.read(&mut buffer) {
+                if count == 0 || tx.send(buffer[0..count].to_vec()).is_err() {
+                    break;
+                }
             }
-            Err(e) => {
-                warn!(logger, "attempt #{}: image load failed: {:?}", attempt, e);
+        });
+
+        {
+            let _raw = RawTerminal::enable();
+            let mut stdout = std::io::stdout();
+
+            loop {
+                tokio::select! {
+                    chunk = output.try_next() => {
+                        match chunk.context("reading container output")? {
+                            Some(chunk) => {
+                                stdout.write_all(chunk.to_string().as_bytes())?;
+                                stdout.flush()?;
+                            }
+                            None => break,
+                        }
+                    }
+                    Some(data) = rx.recv() => {
+                        input
+                            .write_all(&data)
+                            .await
+                            .context("writing to container")?;
+                    }
+                }
             }
         }
-    }
 
-    Err(anyhow!("image load failed multiple times"))
-}
+        let exit_code = docker
+            .inspect_exec(&exec_id)
+            .await
+            .context("inspecting shell in Docker container")?
+            .exit_code
+            .ok_or_else(|| anyhow!("Docker did not report shell exit code"))?;
+
+        if settings.transfer_mode == FileTransferMode::Archive {
+            for dir in dirs
+                .iter()
+                .filter(|dir| dir.kind == ContainerDirKind::Output)
+            {
+                download_container_dir(docker, &container_id, dir).await?;
+            }
+        }
 
-async fn run_and_log_container(
-    logger: &Logger,
-    docker: &Docker,
-    options: CreateContainerOptions<String>,
-    config: ContainerConfig<String>,
-) -> Result<()> {
-    let response = docker
-        .create_container(Some(options), config)
-        .await
-        .context("creating Docker container")?;
-    let container_id = response.id;
+        Ok(exit_code)
+    }
+    .await;
 
-    let options = StartContainerOptions::<String>::default();
-    docker
-        .start_container(&container_id, Some(options))
-        .await
-        .context("starting Docker container")?;
+    RUNNING_CONTAINERS
+        .lock()
+        .expect("running containers lock should not be poisoned")
+        .remove(&container_id);
 
-    let options = LogsOptions::<String> {
-        follow: true,
-        stdout: true,
-        stderr: true,
+    let options = RemoveContainerOptions {
+        force: true,
         ..Default::default()
     };
-    let mut stream = docker.logs(&container_id, Some(options));
-
-    while let Some(output) = stream.try_next().await? {
-        for line in output.to_string().split('\n').filter(|x| !x.is_empty()) {
-            warn!(logger, "{}", line);
-        }
+    if let Err(e) = docker.remove_container(&container_id, Some(options)).await {
+        warn!(
+            logger,
+            "failed to remove container {}: {:?}", container_id, e
+        );
     }
 
-    Ok(())
+    res
 }
 
-fn derive_dockerfile_version_envs() -> String {
-    let parts = crate::downloads::DOWNLOADS
+fn derive_dockerfile_version_envs(platform: BuilderPlatform) -> Vec<(String, String)> {
+    let mut envs = crate::downloads::DOWNLOADS
         .values()
         .map(|record| {
-            format!(
-                "{}_VERSION={}",
-                record.name.to_uppercase().replace('-', "_"),
-                record.version
+            (
+                format!("{}_VERSION", record.name.to_uppercase().replace('-', "_")),
+                record.version.to_string(),
             )
         })
         .collect::<Vec<_>>();
 
-    format!("ENV {}", parts.join("\\\n    "))
+    // Scripts reference support tools by platform agnostic names.
+    for tool in crate::downloads::SUPPORT_TOOLS {
+        let key = format!("{}-linux_{}", tool, platform.arch());
+
+        if let Some(record) = crate::downloads::DOWNLOADS.get(key.as_str()) {
+            envs.push((
+                format!("{}_VERSION", tool.to_uppercase()),
+                record.version.to_string(),
+            ));
+        }
+    }
+
+    envs.push(("HOST_ARCH".to_string(), platform.arch().to_string()));
+    envs.push(("HOST_TRIPLE".to_string(), platform.triple().to_string()));
+
+    envs
 }
 
 /// Build the Docker image for building clang.
 pub async fn build_image_clang(
     logger: &Logger,
-    docker: &Docker,
-    cache_path: impl AsRef<Path>,
+    runtime: &dyn ContainerRuntime,
+    settings: &ImageSettings,
+    downloads_dir: impl AsRef<Path>,
 ) -> Result<String> {
-    let cache_path = cache_path.as_ref();
+    let platform = settings.platform;
+    let downloads_dir = downloads_dir.as_ref();
 
     let mut tar = TarBuilder::default();
 
-    for path in crate::downloads::fetch_llvm_sources(logger, cache_path)
+    for path in crate::downloads::fetch_llvm_sources(logger, &settings.downloads, downloads_dir)
         .context("fetching LLVM sources")?
         .into_iter()
         .chain(
-            crate::downloads::fetch_linux_x86_64_support(logger, cache_path)
-                .context("fetching support files")?
-                .into_iter(),
+            crate::downloads::fetch_linux_support(
+                logger,
+                &settings.downloads,
+                platform.arch(),
+                downloads_dir,
+            )
+            .context("fetching support files")?,
         )
     {
         tar.add_path_with_prefix(logger, path, "files")?;
@@ -338,42 +2757,56 @@ pub async fn build_image_clang(
         ),
     )?;
 
-    let dockerfile = format!(
-        "{}\n{}\n{}\n{}",
-        DEBIAN_JESSIE_HEADER,
-        CLANG_DOCKERFILE,
-        derive_dockerfile_version_envs(),
-        DEBIAN_JESSIE_FOOTER
-    );
-    tar.add_dockerfile_data(dockerfile.as_bytes())?;
-
-    let body = tar.as_body().context("building tar content")?;
-
-    let options = BuildImageOptions::<String> {
-        t: "portable-clang:clang".to_string(),
-        ..Default::default()
+    // Scripts reference the selected LLVM version regardless of how its
+    // sources are packaged.
+    let llvm_version = if let Some(source) = &settings.downloads.llvm_git {
+        format!("git-{}", &source.commit[0..12])
+    } else {
+        crate::downloads::llvm_version_record(&settings.downloads.llvm_version)?
+            .version
+            .clone()
     };
+    let mut envs = derive_dockerfile_version_envs(platform);
+    for (key, value) in envs.iter_mut() {
+        if key == "CLANG_VERSION" || key == "LLVM_VERSION" {
+            *value = llvm_version.clone();
+        }
+    }
+
+    let dockerfile = settings
+        .dockerfile(BaseDistribution::DebianJessie, CLANG_PACKAGES)
+        .args_env(envs);
+    let dockerfile = add_builder_footer(dockerfile).build();
+    tar.add_dockerfile_data(dockerfile.as_bytes())?;
 
-    build_image(logger, docker, options, body).await
+    runtime
+        .build_image(logger, &platform.image_name("clang"), settings, &tar)
+        .await
 }
 
 /// Build a Docker image for building GCC.
 pub async fn build_image_gcc(
     logger: &Logger,
-    docker: &Docker,
-    cache_dir: impl AsRef<Path>,
+    runtime: &dyn ContainerRuntime,
+    settings: &ImageSettings,
+    downloads_dir: impl AsRef<Path>,
 ) -> Result<String> {
-    let cache_dir = cache_dir.as_ref();
+    let platform = settings.platform;
+    let downloads_dir = downloads_dir.as_ref();
 
     let mut tar = TarBuilder::default();
 
-    for path in crate::downloads::fetch_gcc_sources(logger, cache_dir)
+    for path in crate::downloads::fetch_gcc_sources(logger, &settings.downloads, downloads_dir)
         .context("fetching GCC sources")?
         .into_iter()
         .chain(
-            crate::downloads::fetch_linux_x86_64_support(logger, cache_dir)
-                .context("fetching support files")?
-                .into_iter(),
+            crate::downloads::fetch_linux_support(
+                logger,
+                &settings.downloads,
+                platform.arch(),
+                downloads_dir,
+            )
+            .context("fetching support files")?,
         )
     {
         tar.add_path_with_prefix(logger, path, "files")?;
@@ -391,42 +2824,58 @@ pub async fn build_image_gcc(
         ),
     )?;
 
-    let dockerfile = format!(
-        "{}\n{}\n{}\n{}",
-        DEBIAN_JESSIE_HEADER,
-        GCC_DOCKERFILE,
-        derive_dockerfile_version_envs(),
-        DEBIAN_JESSIE_FOOTER
-    );
-    tar.add_dockerfile_data(dockerfile.as_bytes())?;
-
-    let body = tar.as_body().context("building tar content")?;
+    let mut packages = GCC_PACKAGES.to_vec();
+    if platform == BuilderPlatform::LinuxX86_64 {
+        packages.extend(GCC_MULTILIB_PACKAGES);
+    }
 
-    let options = BuildImageOptions::<String> {
-        t: "portable-clang:gcc".to_string(),
-        ..Default::default()
-    };
+    let dockerfile = settings
+        .dockerfile(BaseDistribution::DebianJessie, &packages)
+        .args_env(derive_dockerfile_version_envs(platform));
+    let dockerfile = add_builder_footer(dockerfile).build();
+    tar.add_dockerfile_data(dockerfile.as_bytes())?;
 
-    build_image(logger, docker, options, body).await
+    runtime
+        .build_image(logger, &platform.image_name("gcc"), settings, &tar)
+        .await
 }
 
 /// Build a Docker image for building glibc.
 pub async fn build_image_glibc(
     logger: &Logger,
-    docker: &Docker,
-    cache_dir: impl AsRef<Path>,
+    runtime: &dyn ContainerRuntime,
+    settings: &ImageSettings,
+    downloads_dir: impl AsRef<Path>,
 ) -> Result<String> {
-    let cache_dir = cache_dir.as_ref();
+    let platform = settings.platform;
+    let downloads_dir = downloads_dir.as_ref();
 
     let mut tar = TarBuilder::default();
 
-    for path in crate::downloads::fetch_linux_x86_64_support(logger, cache_dir)
-        .context("fetching support files")?
-        .into_iter()
+    for path in crate::downloads::fetch_linux_support(
+        logger,
+        &settings.downloads,
+        platform.arch(),
+        downloads_dir,
+    )
+    .context("fetching support files")?
+    .into_iter()
     {
         tar.add_path_with_prefix(logger, path, "files")?;
     }
 
+    if let Some(source) = &settings.downloads.glibc_git {
+        let path = crate::git::fetch_git_source(
+            logger,
+            &settings.downloads.proxy,
+            source,
+            "glibc",
+            downloads_dir,
+        )
+        .context("fetching glibc sources")?;
+        tar.add_path_with_prefix(logger, path, "files")?;
+    }
+
     tar.files.add_file_entry(
         "files/build-many-glibcs.py",
         FileEntry::new_from_data(include_bytes!("files/build-many-glibcs.py").to_vec(), true),
@@ -467,22 +2916,80 @@ pub async fn build_image_glibc(
         ),
     )?;
 
-    let dockerfile = format!(
-        "{}\n{}\n{}",
-        DEBIAN_BULLSEYE_HEADER,
-        GLIBC_DOCKERFILE,
-        derive_dockerfile_version_envs(),
-    );
+    let mut dockerfile = settings
+        .dockerfile(BaseDistribution::DebianBullseye, GLIBC_PACKAGES)
+        .comment(
+            "We do this one as a one-off because it takes a while to run and caching the layer is\n\
+             useful for iterative development.",
+        )
+        .copy("scripts/docker-glibc-init.sh", "/usr/bin/")
+        .copy("files/build-many-glibcs*", "/build/");
+    if settings.downloads.glibc_git.is_some() {
+        dockerfile = dockerfile.copy("files/glibc-*.src.tar", "/build/");
+    }
+    let dockerfile = dockerfile
+        .run("/usr/bin/docker-glibc-init.sh")
+        .copy("files/*", "/build/")
+        .copy("scripts/*", "/usr/bin/")
+        .user("build:build")
+        .args_env(derive_dockerfile_version_envs(platform))
+        .build();
     tar.add_dockerfile_data(dockerfile.as_bytes())?;
 
-    let body = tar.as_body().context("building tar content")?;
+    runtime
+        .build_image(logger, &platform.image_name("glibc"), settings, &tar)
+        .await
+}
+
+/// [ContainerRuntime] backed by a Docker daemon.
+#[derive(Clone)]
+pub struct DockerRuntime {
+    docker: Docker,
+}
 
-    let options = BuildImageOptions::<String> {
-        t: "portable-clang:glibc".to_string(),
-        ..Default::default()
-    };
+impl DockerRuntime {
+    pub fn new(docker: Docker) -> Self {
+        Self { docker }
+    }
+}
+
+impl ContainerRuntime for DockerRuntime {
+    fn build_image<'a>(
+        &'a self,
+        logger: &'a Logger,
+        name: &'a str,
+        settings: &'a ImageSettings,
+        context: &'a TarBuilder,
+    ) -> LocalBoxFuture<'a, Result<String>> {
+        build_image_cached(logger, &self.docker, name, settings, context).boxed_local()
+    }
 
-    build_image(logger, docker, options, body).await
+    fn run<'a>(
+        &'a self,
+        logger: &'a Logger,
+        settings: &'a ContainerSettings,
+        config: ContainerConfig<String>,
+        dirs: &'a [ContainerDir],
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        run_and_log_container(logger, &self.docker, settings, config, dirs).boxed_local()
+    }
+
+    fn export_image<'a>(
+        &'a self,
+        image_id: &'a str,
+        dest_path: &'a Path,
+        zstd: &'a ZstdSettings,
+    ) -> LocalBoxFuture<'a, Result<(u64, u64)>> {
+        export_image_to_tar_zst(&self.docker, image_id, dest_path, zstd).boxed_local()
+    }
+
+    fn import_image<'a>(
+        &'a self,
+        logger: &'a Logger,
+        reader: Box<dyn Read + 'a>,
+    ) -> LocalBoxFuture<'a, Result<String>> {
+        load_image_tar_zst(logger, &self.docker, reader).boxed_local()
+    }
 }
 
 /// Export a Docker image specified by its ID to a zstd compressed tar file at the given path.
@@ -490,6 +2997,7 @@ pub async fn export_image_to_tar_zst(
     docker: &Docker,
     image_id: &str,
     dest_path: impl AsRef<Path>,
+    zstd: &ZstdSettings,
 ) -> Result<(u64, u64)> {
     let dest_path = dest_path.as_ref();
 
@@ -498,33 +3006,184 @@ pub async fn export_image_to_tar_zst(
     }
 
     let fh = std::fs::File::create(dest_path).context("opening file for writing")?;
-    let mut cctx =
-        zstd::Encoder::new(fh, ZSTD_COMPRESSION_LEVEL).context("creating zstd encoder")?;
+    let mut cctx = zstd.encoder(fh)?;
+
+    // The exported tar is roughly the size of the image.
+    let expected_size = docker
+        .inspect_image(image_id)
+        .await
+        .ok()
+        .map(|image| image.size.max(0) as u64);
+    let progress = bytes_progress("export", expected_size);
 
     let mut stream = docker.export_image(image_id);
     let mut in_size = 0;
 
     while let Some(data) = stream.try_next().await? {
         in_size += data.len() as u64;
+        progress.inc(data.len() as u64);
         cctx.write_all(data.as_ref())
             .context("writing data to zstd")?;
     }
 
+    progress.finish_and_clear();
+
     let fh = cctx.finish().context("finishing zstd encoder")?;
     let out_size = fh.metadata().context("reading image file metadata")?.len();
 
     Ok((in_size, out_size))
 }
 
-fn add_container_envs(config: &mut ContainerConfig<String>) -> Result<()> {
+/// Export a Docker image to an OCI image layout.
+///
+/// If `dest_path` ends in `.tar`, the layout is written as a tar archive.
+/// Otherwise it is written to a directory. The image is named `ref_name`
+/// in the layout's index.
+pub async fn export_image_to_oci(
+    docker: &Docker,
+    image_id: &str,
+    ref_name: &str,
+    dest_path: impl AsRef<Path>,
+) -> Result<()> {
+    let dest_path = dest_path.as_ref();
+
+    let mut fh = tempfile::tempfile().context("creating temporary file")?;
+
+    let expected_size = docker
+        .inspect_image(image_id)
+        .await
+        .ok()
+        .map(|image| image.size.max(0) as u64);
+    let progress = bytes_progress("export", expected_size);
+
+    let mut stream = docker.export_image(image_id);
+    while let Some(data) = stream.try_next().await? {
+        progress.inc(data.len() as u64);
+        fh.write_all(data.as_ref())
+            .context("writing image archive")?;
+    }
+
+    progress.finish_and_clear();
+    fh.seek(SeekFrom::Start(0))?;
+
+    if dest_path.extension().map(|x| x == "tar").unwrap_or(false) {
+        let temp_dir = tempfile::Builder::new().prefix("pclang-oci-").tempdir()?;
+        crate::oci::docker_archive_to_oci_layout(fh, ref_name, temp_dir.path())?;
+
+        if let Some(parent) = dest_path.parent() {
+            std::fs::create_dir_all(parent).context("creating parent directory")?;
+        }
+
+        let mut builder =
+            tar::Builder::new(std::fs::File::create(dest_path).context("creating OCI archive")?);
+        builder
+            .append_dir_all(".", temp_dir.path())
+            .context("adding OCI layout to archive")?;
+        builder.finish().context("finishing OCI archive")?;
+    } else {
+        crate::oci::docker_archive_to_oci_layout(fh, ref_name, dest_path)?;
+    }
+
+    Ok(())
+}
+
+/// A remote storage backend for sccache.
+struct SccacheBackend {
+    /// Environment variable whose presence enables the backend.
+    trigger: &'static str,
+    /// Prefixes of environment variables to forward to the container.
+    prefixes: &'static [&'static str],
+    /// Environment variables to set when not defined by the user.
+    defaults: &'static [(&'static str, &'static str)],
+}
+
+/// Supported sccache backends, in order of preference.
+const SCCACHE_BACKENDS: &[SccacheBackend] = &[
+    SccacheBackend {
+        trigger: "SCCACHE_BUCKET",
+        prefixes: &[
+            "AWS_ACCESS_KEY_ID",
+            "AWS_SECRET_ACCESS_KEY",
+            "AWS_SESSION_TOKEN",
+            "SCCACHE_BUCKET",
+            "SCCACHE_ENDPOINT",
+            "SCCACHE_REGION",
+            "SCCACHE_S3_",
+        ],
+        defaults: &[("SCCACHE_S3_USE_SSL", "1")],
+    },
+    SccacheBackend {
+        trigger: "SCCACHE_GCS_BUCKET",
+        // SCCACHE_GCS_KEY_PATH must refer to a path inside the container.
+        prefixes: &["SCCACHE_GCS_"],
+        defaults: &[("SCCACHE_GCS_RW_MODE", "READ_WRITE")],
+    },
+    SccacheBackend {
+        trigger: "SCCACHE_AZURE_CONNECTION_STRING",
+        prefixes: &["SCCACHE_AZURE_"],
+        defaults: &[("SCCACHE_AZURE_BLOB_CONTAINER", "portable-clang")],
+    },
+    SccacheBackend {
+        trigger: "SCCACHE_REDIS",
+        prefixes: &["SCCACHE_REDIS"],
+        defaults: &[],
+    },
+    SccacheBackend {
+        trigger: "SCCACHE_MEMCACHED",
+        prefixes: &["SCCACHE_MEMCACHED"],
+        defaults: &[],
+    },
+];
+
+/// Resolve sccache environment variables for containers from host variables.
+///
+/// The first remote backend configured on the host is forwarded. If none is
+/// configured, sccache uses a local disk cache.
+fn sccache_envs(envs: &HashMap<String, String>) -> Vec<String> {
+    let backend = if let Some(backend) = SCCACHE_BACKENDS
+        .iter()
+        .find(|backend| envs.contains_key(backend.trigger))
+    {
+        backend
+    } else {
+        return vec!["SCCACHE_DIR=/sccache".into()];
+    };
+
+    let mut vars = envs
+        .iter()
+        .filter(|(key, _)| backend.prefixes.iter().any(|p| key.starts_with(p)))
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect::<BTreeMap<_, _>>();
+
+    for (key, value) in backend.defaults {
+        vars.entry(*key).or_insert(*value);
+    }
+
+    // Keep the server alive for the duration of the build so connections
+    // to the remote backend aren't constantly re-established.
+    vars.entry("SCCACHE_IDLE_TIMEOUT").or_insert("0");
+
+    vars.into_iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect()
+}
+
+fn add_container_envs(
+    settings: &ContainerSettings,
+    config: &mut ContainerConfig<String>,
+) -> Result<()> {
     let env = config.env.get_or_insert(vec![]);
 
     // sccache speeds up builds considerably. So build with high parallelism.
     env.push(format!("PARALLEL={}", num_cpus::get() * 2));
 
-    let mut have_remote_sccache = false;
-
-    let mut envs: HashMap<String, String> = HashMap::from_iter(std::env::vars());
+    let mut envs: HashMap<String, String> = HashMap::from_iter(
+        settings
+            .sccache_env
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .chain(std::env::vars()),
+    );
 
     // Supplement environment variables with set from a config file.
     if let Some(home) = dirs::home_dir() {
@@ -544,26 +3203,7 @@ fn add_container_envs(config: &mut ContainerConfig<String>) -> Result<()> {
         }
     }
 
-    for key in [
-        "AWS_ACCESS_KEY_ID",
-        "AWS_SECRET_ACCESS_KEY",
-        "SCCACHE_BUCKET",
-    ] {
-        if let Some(value) = envs.get(key) {
-            env.push(format!("{}={}", key, value));
-
-            if key == "SCCACHE_BUCKET" {
-                have_remote_sccache = true;
-            }
-        }
-    }
-
-    if have_remote_sccache {
-        env.push("SCCACHE_S3_USE_SSL=1".into());
-        env.push("SCCACHE_IDLE_TIMEOUT=0".into());
-    } else {
-        env.push("SCCACHE_DIR=/sccache".into());
-    }
+    env.extend(sccache_envs(&envs));
 
     Ok(())
 }
@@ -573,71 +3213,129 @@ fn add_container_envs(config: &mut ContainerConfig<String>) -> Result<()> {
 /// We produce binutils + gcc artifacts that are used to build clang.
 pub async fn bootstrap_gcc(
     logger: &Logger,
-    docker: &Docker,
+    runtime: &dyn ContainerRuntime,
+    settings: &ContainerSettings,
     image_id: &str,
     cache_dir: impl AsRef<Path>,
 ) -> Result<(Vec<u8>, Vec<u8>)> {
     let cache_dir = cache_dir.as_ref();
-    let sccache_dir = cache_dir.join("sccache");
-    std::fs::create_dir_all(&sccache_dir)?;
+    let sccache_dir = sccache_container_dir(settings, cache_dir)?;
 
     let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
     let out_dir = temp_dir.path();
-    let mut permissions = out_dir.metadata()?.permissions();
-    permissions.set_mode(0o0777);
-    std::fs::set_permissions(&out_dir, permissions)
-        .context("setting temp directory permissions")?;
-
-    let options = CreateContainerOptions::<String>::default();
+    prepare_output_dir(settings, out_dir)?;
 
     let mut config = ContainerConfig::<String> {
         attach_stdin: Some(false),
         attach_stdout: Some(true),
         attach_stderr: Some(true),
-        tty: Some(true),
+        // Without a TTY, Docker keeps stdout and stderr separate.
+        tty: Some(false),
         cmd: Some(vec!["/usr/bin/docker-gcc-build.sh".into()]),
         image: Some(image_id.into()),
-        host_config: Some(HostConfig {
-            auto_remove: Some(true),
-            binds: Some(vec![
-                format!("{}:/out", out_dir.display()),
-                format!("{}:/sccache", sccache_dir.display()),
-            ]),
-            ..Default::default()
-        }),
         ..Default::default()
     };
 
-    add_container_envs(&mut config)?;
+    add_container_envs(settings, &mut config)?;
+
+    let dirs = [
+        ContainerDir::new(out_dir, "/out", ContainerDirKind::Output),
+        sccache_dir,
+    ];
 
-    run_and_log_container(logger, docker, options, config)
+    if let Err(e) = runtime
+        .run(logger, settings, config, &dirs)
         .await
-        .context("running container")?;
+        .context("running container")
+    {
+        return Err(keep_failed_temp_dir(logger, settings, temp_dir, e));
+    }
 
+    let progress = ArchiveProgressBar::new(logger);
     let binutils_tar = tar_from_directory(
         logger,
         out_dir.join("binutils"),
         Some(Path::new("binutils")),
+        settings.tar_metadata,
+        &progress,
+    )?;
+    let gcc_tar = tar_from_directory(
+        logger,
+        out_dir.join("gcc"),
+        Some(Path::new("gcc")),
+        settings.tar_metadata,
+        &progress,
     )?;
-    let gcc_tar = tar_from_directory(logger, out_dir.join("gcc"), Some(Path::new("gcc")))?;
 
-    let binutils_tar_zst = zstd::encode_all(Cursor::new(binutils_tar), ZSTD_COMPRESSION_LEVEL)?;
-    let gcc_tar_zst = zstd::encode_all(Cursor::new(gcc_tar), ZSTD_COMPRESSION_LEVEL)?;
+    let binutils_tar_zst = crate::seekable::encode_tar(&binutils_tar, &settings.zstd, &progress)?;
+    let gcc_tar_zst = crate::seekable::encode_tar(&gcc_tar, &settings.zstd, &progress)?;
 
     Ok((binutils_tar_zst, gcc_tar_zst))
 }
 
+/// A stage of the clang bootstrap, each run in its own container.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ClangStage {
+    /// Build clang with GCC.
+    Stage1,
+    /// Build clang with the stage 1 clang and the final clang with that.
+    Stage2,
+}
+
+impl ClangStage {
+    /// Argument to the build script selecting this stage.
+    fn script_arg(&self) -> &'static str {
+        match self {
+            Self::Stage1 => "stage1",
+            Self::Stage2 => "stage2",
+        }
+    }
+
+    /// Name of the directory in `/out` the stage produces.
+    fn output(&self) -> &'static str {
+        match self {
+            Self::Stage1 => "clang-stage1",
+            Self::Stage2 => "clang",
+        }
+    }
+}
+
+impl FromStr for ClangStage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stage1" => Ok(Self::Stage1),
+            "stage2" => Ok(Self::Stage2),
+            _ => Err(anyhow!("unknown clang stage: {}", s)),
+        }
+    }
+}
+
+/// Artifacts consumed by the clang build.
+///
+/// All are zstd compressed tars.
+pub struct ClangInputs<'a> {
+    pub binutils: &'a [u8],
+    pub gcc: &'a [u8],
+    /// Output of [ClangStage::Stage1]. Required by [ClangStage::Stage2].
+    pub stage1: Option<&'a [u8]>,
+}
+
+/// Run a stage of the clang build.
+///
+/// Returns a zstd compressed tar of the directory the stage produces.
 pub async fn bootstrap_clang(
     logger: &Logger,
-    docker: &Docker,
+    runtime: &dyn ContainerRuntime,
+    settings: &ContainerSettings,
     image_id: &str,
-    binutils_tar: &[u8],
-    gcc_tar: &[u8],
+    stage: ClangStage,
+    inputs: &ClangInputs<'_>,
     cache_dir: impl AsRef<Path>,
 ) -> Result<Vec<u8>> {
     let cache_dir = cache_dir.as_ref();
-    let sccache_dir = cache_dir.join("sccache");
-    std::fs::create_dir_all(&sccache_dir).context("creating sccache cache directory")?;
+    let sccache_dir = sccache_container_dir(settings, cache_dir)?;
 
     let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
     let temp_dir_path = temp_dir.path();
@@ -645,90 +3343,104 @@ pub async fn bootstrap_clang(
     let in_dir = temp_dir_path.join("inputs");
     std::fs::create_dir_all(&in_dir).context("creating inputs directory")?;
 
-    let fh = std::fs::File::create(in_dir.join("binutils.tar"))?;
-    zstd::stream::copy_decode(binutils_tar, fh).context("zstd decompressing binutils")?;
-    let fh = std::fs::File::create(in_dir.join("gcc.tar"))?;
-    zstd::stream::copy_decode(gcc_tar, fh).context("zstd decompressing gcc")?;
+    let mut tars = vec![("binutils", inputs.binutils), ("gcc", inputs.gcc)];
+    if stage == ClangStage::Stage2 {
+        tars.push((
+            "clang-stage1",
+            inputs
+                .stage1
+                .ok_or_else(|| anyhow!("clang stage 2 requires stage 1 artifact"))?,
+        ));
+    }
+
+    for (name, data) in tars {
+        let fh = std::fs::File::create(in_dir.join(format!("{}.tar", name)))?;
+        zstd::stream::copy_decode(data, fh)
+            .with_context(|| format!("zstd decompressing {}", name))?;
+    }
 
     let out_dir = temp_dir_path.join("out");
     std::fs::create_dir_all(&out_dir).context("creating artifact outputs directory")?;
-    let mut permissions = out_dir
-        .metadata()
-        .context("retrieving outputs directory metadata")?
-        .permissions();
-    permissions.set_mode(0o0777);
-    std::fs::set_permissions(&out_dir, permissions)
-        .context("setting temp directory permissions")?;
-
-    let options = CreateContainerOptions::<String>::default();
+    prepare_output_dir(settings, &out_dir)?;
 
     let mut config = ContainerConfig::<String> {
         attach_stdin: Some(false),
         attach_stdout: Some(true),
         attach_stderr: Some(true),
-        tty: Some(true),
-        cmd: Some(vec!["/usr/bin/docker-clang-build.sh".into()]),
+        // Without a TTY, Docker keeps stdout and stderr separate.
+        tty: Some(false),
+        cmd: Some(vec![
+            "/usr/bin/docker-clang-build.sh".into(),
+            stage.script_arg().into(),
+        ]),
         image: Some(image_id.into()),
-        host_config: Some(HostConfig {
-            auto_remove: Some(true),
-            binds: Some(vec![
-                format!("{}:/inputs", in_dir.display()),
-                format!("{}:/out", out_dir.display()),
-                format!("{}:/sccache", sccache_dir.display()),
-            ]),
-            ..Default::default()
-        }),
         ..Default::default()
     };
 
-    add_container_envs(&mut config)?;
+    add_container_envs(settings, &mut config)?;
+
+    let dirs = [
+        ContainerDir::new(&in_dir, "/inputs", ContainerDirKind::Input),
+        ContainerDir::new(&out_dir, "/out", ContainerDirKind::Output),
+        sccache_dir,
+    ];
 
-    run_and_log_container(logger, docker, options, config)
+    if let Err(e) = runtime
+        .run(logger, settings, config, &dirs)
         .await
-        .context("running container")?;
+        .context("running container")
+    {
+        return Err(keep_failed_temp_dir(logger, settings, temp_dir, e));
+    }
 
-    let clang_tar = tar_from_directory(logger, out_dir.join("clang"), Some(Path::new("clang")))?;
-    warn!(logger, "compressing clang tarball");
-    let clang_tar_zst = zstd::encode_all(Cursor::new(clang_tar), ZSTD_COMPRESSION_LEVEL)?;
+    let output = stage.output();
+    let progress = ArchiveProgressBar::new(logger);
+    let tar = tar_from_directory(
+        logger,
+        out_dir.join(output),
+        Some(Path::new(output)),
+        settings.tar_metadata,
+        &progress,
+    )?;
+    warn!(logger, "compressing {} tarball", output);
 
-    Ok(clang_tar_zst)
+    crate::seekable::encode_tar(&tar, &settings.zstd, &progress)
 }
 
-pub async fn glibc_abis(logger: &Logger, docker: &Docker, image_id: &str) -> Result<FileManifest> {
+pub async fn glibc_abis(
+    logger: &Logger,
+    runtime: &dyn ContainerRuntime,
+    settings: &ContainerSettings,
+    image_id: &str,
+) -> Result<FileManifest> {
     let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
     let out_dir = temp_dir.path();
-    let mut permissions = out_dir
-        .metadata()
-        .context("retrieving outputs directory metadata")?
-        .permissions();
-    permissions.set_mode(0o0777);
-    std::fs::set_permissions(&out_dir, permissions)
-        .context("setting temp directory permissions")?;
-
-    let options = CreateContainerOptions::<String>::default();
+    prepare_output_dir(settings, out_dir)?;
 
     let config = ContainerConfig::<String> {
         attach_stdin: Some(false),
         attach_stdout: Some(true),
         attach_stderr: Some(true),
-        tty: Some(true),
+        // Without a TTY, Docker keeps stdout and stderr separate.
+        tty: Some(false),
         cmd: Some(vec![
             "/usr/bin/docker-glibc-collect-abi.py".into(),
             "/build/src/glibc".into(),
             "/out".into(),
         ]),
         image: Some(image_id.into()),
-        host_config: Some(HostConfig {
-            auto_remove: Some(true),
-            binds: Some(vec![format!("{}:/out", out_dir.display())]),
-            ..Default::default()
-        }),
         ..Default::default()
     };
 
-    run_and_log_container(logger, docker, options, config)
+    let dirs = [ContainerDir::new(out_dir, "/out", ContainerDirKind::Output)];
+
+    if let Err(e) = runtime
+        .run(logger, settings, config, &dirs)
         .await
-        .context("running container")?;
+        .context("running container")
+    {
+        return Err(keep_failed_temp_dir(logger, settings, temp_dir, e));
+    }
 
     // The script deposited .json files for each ABI.
     let mut m = FileManifest::default();
@@ -750,49 +3462,248 @@ pub async fn glibc_abis(logger: &Logger, docker: &Docker, image_id: &str) -> Res
 
 pub async fn glibc_build_single(
     logger: &Logger,
-    docker: &Docker,
+    runtime: &dyn ContainerRuntime,
+    settings: &ContainerSettings,
     image_id: &str,
     compiler: &str,
     glibc: &str,
 ) -> Result<Vec<u8>> {
     let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
     let out_dir = temp_dir.path();
-    let mut permissions = out_dir
-        .metadata()
-        .context("retrieving outputs directory metadata")?
-        .permissions();
-    permissions.set_mode(0o0777);
-    std::fs::set_permissions(&out_dir, permissions)
-        .context("setting temp directory permissions")?;
-
-    let options = CreateContainerOptions::<String>::default();
+    prepare_output_dir(settings, out_dir)?;
 
     let mut config = ContainerConfig::<String> {
         attach_stdin: Some(false),
         attach_stdout: Some(true),
         attach_stderr: Some(true),
-        tty: Some(true),
+        // Without a TTY, Docker keeps stdout and stderr separate.
+        tty: Some(false),
         cmd: Some(vec![
             "/usr/bin/docker-glibc-build.sh".into(),
             compiler.into(),
             glibc.into(),
         ]),
         image: Some(image_id.into()),
-        host_config: Some(HostConfig {
-            auto_remove: Some(true),
-            binds: Some(vec![format!("{}:/out", out_dir.display())]),
-            ..Default::default()
-        }),
         ..Default::default()
     };
 
-    add_container_envs(&mut config)?;
+    add_container_envs(settings, &mut config)?;
 
-    run_and_log_container(logger, docker, options, config)
+    let dirs = [ContainerDir::new(out_dir, "/out", ContainerDirKind::Output)];
+
+    if let Err(e) = runtime
+        .run(logger, settings, config, &dirs)
         .await
-        .context("running container")?;
+        .context("running container")
+    {
+        return Err(keep_failed_temp_dir(logger, settings, temp_dir, e));
+    }
 
     let glibc_path = out_dir.join(glibc);
 
-    tar_from_directory(logger, glibc_path, Some(Path::new(glibc)))
+    tar_from_directory(
+        logger,
+        glibc_path,
+        Some(Path::new(glibc)),
+        settings.tar_metadata,
+        &ArchiveProgressBar::new(logger),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn artifact_format() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let tar = TarBuilder::default().as_vec()?;
+        let tar_zst = ZstdSettings::default().encode_all(&tar)?;
+
+        let path = ArtifactFormat::Zstd.write_tar_zst(&tar_zst, temp_dir.path(), "clang", &())?;
+        assert_eq!(path, temp_dir.path().join("clang.tar.zst"));
+        assert_eq!(std::fs::read(&path)?, tar_zst);
+
+        let path = "xz".parse::<ArtifactFormat>()?.write_tar_zst(
+            &tar_zst,
+            temp_dir.path(),
+            "clang",
+            &(),
+        )?;
+        assert_eq!(path, temp_dir.path().join("clang.tar.xz"));
+        let mut data = vec![];
+        xz2::read::XzDecoder::new(std::fs::File::open(&path)?).read_to_end(&mut data)?;
+        assert_eq!(data, tar);
+
+        Ok(())
+    }
+
+    #[test]
+    fn base_distribution_dockerfiles() {
+        let jessie = BaseDistribution::DebianJessie
+            .dockerfile_header(1000, 1000, DEBIAN_SNAPSHOT)
+            .build();
+        assert!(jessie.starts_with("FROM debian@sha256:32ad5050"));
+        assert!(jessie.contains("useradd -o -u 1000 -g 1000 -d /build"));
+        assert!(jessie.contains("ARG DEBIAN_SNAPSHOT=20211107T145307Z\n"));
+        assert!(jessie.contains(
+            "echo \"deb http://snapshot.debian.org/archive/debian-security/${DEBIAN_SNAPSHOT}/ jessie/updates main\";"
+        ));
+        assert!(jessie.contains("echo \"quiet \\\"true\\\";\";"));
+
+        let bullseye = BaseDistribution::DebianBullseye
+            .dockerfile_header(1000, 1000, "20230101T000000Z")
+            .build();
+        assert!(bullseye.contains("ARG DEBIAN_SNAPSHOT=20230101T000000Z\n"));
+
+        let centos = BaseDistribution::Centos7.install_packages(
+            DockerfileBuilder::default(),
+            &["libc6-dev", "watch", "zlib1g-dev"],
+        );
+        assert_eq!(
+            centos.build(),
+            "RUN yum install -y \\\n    glibc-devel \\\n    zlib-devel\n"
+        );
+    }
+
+    #[test]
+    fn debian_snapshots() {
+        assert_eq!(
+            parse_debian_snapshot("20211107T145307Z").unwrap(),
+            "20211107T145307Z"
+        );
+        assert!(parse_debian_snapshot("2021-11-07").is_err());
+        assert!(parse_debian_snapshot("20211107X145307Z").is_err());
+    }
+
+    #[test]
+    fn debug_commands() {
+        let commands = debug_container_commands(
+            "0123456789abcdef",
+            &[
+                "/tmp/pclang-x/out:/out".to_string(),
+                "pclang-sccache:/sccache".to_string(),
+            ],
+        );
+
+        assert_eq!(
+            commands[0],
+            "docker commit 0123456789abcdef pclang-debug:0123456789ab"
+        );
+        assert_eq!(
+            commands[1],
+            "docker run -it --rm -v /tmp/pclang-x/out:/out -v pclang-sccache:/sccache \
+             pclang-debug:0123456789ab /bin/bash --login"
+        );
+    }
+
+    #[test]
+    fn terminal_lines() {
+        let mut lines = TerminalLines::new();
+
+        assert!(lines.push(b"[1/10] cc a.c\r").is_empty());
+        assert_eq!(
+            lines.push(b"[2/10] cc b.c\r\n\x1b[31merror\x1b[0m\npart"),
+            vec!["[2/10] cc b.c", "\x1b[31merror\x1b[0m"]
+        );
+        assert_eq!(lines.push(b"ial\n"), vec!["partial"]);
+    }
+
+    #[test]
+    fn image_provenance_labels() {
+        let mut settings = ImageSettings::default();
+        settings
+            .build_args
+            .insert("LLVM_VERSION".to_string(), "13.0.1".to_string());
+
+        let labels = provenance_labels("gcc", &settings, "abcd");
+
+        assert_eq!(labels["portable-clang.image"], "gcc");
+        assert_eq!(labels["portable-clang.input-digest"], "abcd");
+        assert_eq!(labels["portable-clang.build-arg.LLVM_VERSION"], "13.0.1");
+        assert_eq!(
+            labels["portable-clang.llvm.version"],
+            crate::downloads::DOWNLOADS["llvm"].version
+        );
+        assert_eq!(
+            labels["org.opencontainers.image.version"],
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+
+    #[test]
+    fn cached_image_files() {
+        assert!(is_cached_image_file(
+            "clang-0123456789abcdef.tar.zst",
+            "clang"
+        ));
+        assert!(!is_cached_image_file(
+            "clang-aarch64-0123456789abcdef.tar.zst",
+            "clang"
+        ));
+        assert!(is_cached_image_file(
+            "clang-aarch64-0123456789abcdef.tar.zst",
+            "clang-aarch64"
+        ));
+        assert!(!is_cached_image_file(
+            "clang-0123456789abcdef.tar.zst.tmp",
+            "clang"
+        ));
+    }
+
+    #[test]
+    fn docker_hosts() {
+        assert_eq!(
+            normalize_docker_host("/var/run/docker.sock"),
+            "unix:///var/run/docker.sock"
+        );
+        assert_eq!(
+            normalize_docker_host("//./pipe/docker_engine"),
+            "npipe:////./pipe/docker_engine"
+        );
+        assert_eq!(
+            normalize_docker_host(r"\\.\pipe\docker_engine"),
+            "npipe:////./pipe/docker_engine"
+        );
+        assert_eq!(
+            normalize_docker_host("tcp://localhost:2375"),
+            "tcp://localhost:2375"
+        );
+    }
+
+    #[test]
+    fn sccache_backends() {
+        let envs = |vars: &[(&str, &str)]| {
+            HashMap::from_iter(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+        };
+
+        assert_eq!(
+            sccache_envs(&envs(&[("HOME", "/home/user")])),
+            vec!["SCCACHE_DIR=/sccache"]
+        );
+        assert_eq!(
+            sccache_envs(&envs(&[
+                ("SCCACHE_BUCKET", "bucket"),
+                ("AWS_ACCESS_KEY_ID", "key"),
+                ("SCCACHE_REDIS", "redis://localhost"),
+            ])),
+            vec![
+                "AWS_ACCESS_KEY_ID=key",
+                "SCCACHE_BUCKET=bucket",
+                "SCCACHE_IDLE_TIMEOUT=0",
+                "SCCACHE_S3_USE_SSL=1",
+            ]
+        );
+        assert_eq!(
+            sccache_envs(&envs(&[
+                ("SCCACHE_GCS_BUCKET", "bucket"),
+                ("SCCACHE_GCS_RW_MODE", "READ_ONLY"),
+            ])),
+            vec![
+                "SCCACHE_GCS_BUCKET=bucket",
+                "SCCACHE_GCS_RW_MODE=READ_ONLY",
+                "SCCACHE_IDLE_TIMEOUT=0",
+            ]
+        );
+    }
 }
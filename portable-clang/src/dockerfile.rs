@@ -0,0 +1,195 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Programmatic construction of Dockerfiles. */
+
+/// Continuation between lines of a multi-line instruction.
+const CONTINUATION: &str = " \\\n    ";
+
+/// Builds the content of a Dockerfile one instruction at a time.
+#[derive(Clone, Debug, Default)]
+pub struct DockerfileBuilder {
+    instructions: Vec<String>,
+}
+
+impl DockerfileBuilder {
+    /// Start a Dockerfile with a `FROM` instruction.
+    pub fn from_image(image: impl AsRef<str>) -> Self {
+        Self::default().instruction(format!("FROM {}", image.as_ref()))
+    }
+
+    /// Add a `MAINTAINER` instruction.
+    pub fn maintainer(self, maintainer: impl AsRef<str>) -> Self {
+        self.instruction(format!("MAINTAINER {}", maintainer.as_ref()))
+    }
+
+    /// Add an `ENV` instruction defining multiple variables.
+    ///
+    /// No instruction is added if there are no variables.
+    pub fn env<K, V>(self, vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let vars = vars
+            .into_iter()
+            .map(|(k, v)| format!("{}={}", k.as_ref(), v.as_ref()))
+            .collect::<Vec<_>>();
+
+        if vars.is_empty() {
+            self
+        } else {
+            self.instruction(format!("ENV {}", vars.join(CONTINUATION)))
+        }
+    }
+
+    /// Add an `ARG` instruction, optionally with a default value.
+    pub fn arg(self, name: impl AsRef<str>, default: Option<&str>) -> Self {
+        if let Some(default) = default {
+            self.instruction(format!("ARG {}={}", name.as_ref(), default))
+        } else {
+            self.instruction(format!("ARG {}", name.as_ref()))
+        }
+    }
+
+    /// Define variables as `ARG`s with defaults and export them via `ENV`.
+    ///
+    /// This allows values to be overridden with `--build-arg` while still being
+    /// visible to processes in containers.
+    pub fn args_env<K, V>(self, vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let vars = vars
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_string(), v.as_ref().to_string()))
+            .collect::<Vec<_>>();
+
+        let builder = vars
+            .iter()
+            .fold(self, |builder, (k, v)| builder.arg(k, Some(v)));
+
+        builder.env(vars.iter().map(|(k, _)| (k, format!("${{{}}}", k))))
+    }
+
+    /// Add a `RUN` instruction executing a single shell command.
+    pub fn run(self, command: impl AsRef<str>) -> Self {
+        self.instruction(format!("RUN {}", command.as_ref()))
+    }
+
+    /// Add a `RUN` instruction executing multiple shell commands joined by `&&`.
+    pub fn run_all<S: AsRef<str>>(self, commands: impl IntoIterator<Item = S>) -> Self {
+        let commands = commands
+            .into_iter()
+            .map(|c| c.as_ref().to_string())
+            .collect::<Vec<_>>();
+
+        self.run(commands.join(format!(" &&{}", CONTINUATION).as_str()))
+    }
+
+    /// Add a `RUN` instruction executing a command with one argument per line.
+    pub fn run_with_args<S: AsRef<str>>(
+        self,
+        command: impl AsRef<str>,
+        args: impl IntoIterator<Item = S>,
+    ) -> Self {
+        let mut parts = vec![command.as_ref().to_string()];
+        parts.extend(args.into_iter().map(|arg| arg.as_ref().to_string()));
+
+        self.run(parts.join(CONTINUATION))
+    }
+
+    /// Add a `COPY` instruction.
+    pub fn copy(self, source: impl AsRef<str>, dest: impl AsRef<str>) -> Self {
+        self.instruction(format!("COPY {} {}", source.as_ref(), dest.as_ref()))
+    }
+
+    /// Add a `USER` instruction.
+    pub fn user(self, user: impl AsRef<str>) -> Self {
+        self.instruction(format!("USER {}", user.as_ref()))
+    }
+
+    /// Add a `WORKDIR` instruction.
+    pub fn workdir(self, path: impl AsRef<str>) -> Self {
+        self.instruction(format!("WORKDIR '{}'", path.as_ref()))
+    }
+
+    /// Add a `CMD` instruction in exec form.
+    pub fn cmd(self, args: &[&str]) -> Self {
+        let args = args
+            .iter()
+            .map(|arg| format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect::<Vec<_>>();
+
+        self.instruction(format!("CMD [{}]", args.join(", ")))
+    }
+
+    /// Add a comment, which may span multiple lines.
+    pub fn comment(self, comment: impl AsRef<str>) -> Self {
+        let lines = comment
+            .as_ref()
+            .lines()
+            .map(|line| format!("# {}", line))
+            .collect::<Vec<_>>();
+
+        self.instruction(lines.join("\n"))
+    }
+
+    /// Add verbatim Dockerfile content.
+    pub fn raw(self, content: impl AsRef<str>) -> Self {
+        self.instruction(content.as_ref().trim_end())
+    }
+
+    fn instruction(mut self, instruction: impl ToString) -> Self {
+        self.instructions.push(instruction.to_string());
+        self
+    }
+
+    /// Obtain the Dockerfile content.
+    pub fn build(&self) -> String {
+        let mut s = self.instructions.join("\n");
+        s.push('\n');
+
+        s
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build() {
+        let dockerfile = DockerfileBuilder::from_image("debian:bullseye")
+            .env([("HOME", "/build"), ("USER", "build")])
+            .env(Vec::<(&str, &str)>::new())
+            .arg("TARGET", None)
+            .args_env([("GCC_VERSION", "10.3.0")])
+            .run_all(["apt-get update", "apt-get install gcc"])
+            .run_with_args("apt-get install", ["make", "patch"])
+            .comment("Install\nscripts")
+            .copy("scripts/", "/usr/bin/")
+            .workdir("/build")
+            .cmd(&["/bin/bash", "--login"])
+            .user("build:build")
+            .build();
+
+        assert_eq!(
+            dockerfile,
+            "FROM debian:bullseye\n\
+             ENV HOME=/build \\\n    USER=build\n\
+             ARG TARGET\n\
+             ARG GCC_VERSION=10.3.0\n\
+             ENV GCC_VERSION=${GCC_VERSION}\n\
+             RUN apt-get update && \\\n    apt-get install gcc\n\
+             RUN apt-get install \\\n    make \\\n    patch\n\
+             # Install\n# scripts\n\
+             COPY scripts/ /usr/bin/\n\
+             WORKDIR '/build'\n\
+             CMD [\"/bin/bash\", \"--login\"]\n\
+             USER build:build\n"
+        );
+    }
+}
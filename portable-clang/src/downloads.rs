@@ -3,35 +3,307 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
-    anyhow::{Context, Result},
+    crate::git::GitSource,
+    anyhow::{anyhow, Context, Result},
+    fs2::FileExt,
     once_cell::sync::Lazy,
-    slog::Logger,
+    reqwest::{
+        blocking::{Client, ClientBuilder},
+        header::RANGE,
+        NoProxy, Proxy, StatusCode,
+    },
+    serde::{Deserialize, Serialize},
+    sha2::Digest,
+    slog::{info, warn, Logger},
     std::{
-        collections::BTreeMap,
+        collections::{BTreeMap, BTreeSet},
+        ffi::OsString,
+        io::{Read, Write},
+        num::NonZeroU64,
         path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
     },
-    tugger_common::http::{download_to_path, RemoteContent},
+    tugger_common::http::RemoteContent,
 };
 
+/// Default number of records to download concurrently.
+pub const DEFAULT_DOWNLOAD_JOBS: usize = 4;
+
+/// Default number of times to retry failed downloads.
+pub const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+
+/// Default delay before the first retry of a failed download. Doubles with each retry.
+pub const DEFAULT_DOWNLOAD_BACKOFF: Duration = Duration::from_secs(2);
+
+/// LLVM major version built by default.
+pub const DEFAULT_LLVM_VERSION: &str = "13";
+
+/// Proxies to access the network through.
+///
+/// Proxy URLs may use the `http`, `https`, or `socks5` schemes. Tools in
+/// build containers may not support SOCKS proxies.
+#[derive(Clone, Debug, Default)]
+pub struct ProxySettings {
+    /// Proxy for `http` URLs.
+    pub http: Option<String>,
+
+    /// Proxy for `https` URLs.
+    pub https: Option<String>,
+
+    /// Comma separated hosts and domains to access directly.
+    pub no_proxy: Option<String>,
+}
+
+impl ProxySettings {
+    pub fn is_empty(&self) -> bool {
+        self.http.is_none() && self.https.is_none()
+    }
+
+    /// Configure an HTTP client to use these proxies.
+    ///
+    /// Proxies defined by the environment are ignored once any are defined.
+    fn apply(&self, mut builder: ClientBuilder) -> Result<ClientBuilder> {
+        let no_proxy = self.no_proxy.as_deref().and_then(NoProxy::from_string);
+
+        if let Some(url) = &self.http {
+            builder = builder.proxy(
+                Proxy::http(url)
+                    .with_context(|| format!("parsing HTTP proxy {}", url))?
+                    .no_proxy(no_proxy.clone()),
+            );
+        }
+        if let Some(url) = &self.https {
+            builder = builder.proxy(
+                Proxy::https(url)
+                    .with_context(|| format!("parsing HTTPS proxy {}", url))?
+                    .no_proxy(no_proxy),
+            );
+        }
+
+        Ok(builder)
+    }
+
+    /// Environment variables conventionally used to configure these proxies.
+    ///
+    /// Both upper and lower case variants are emitted, as tools disagree on which to read.
+    pub fn envs(&self) -> Vec<(String, String)> {
+        [
+            ("HTTP_PROXY", &self.http),
+            ("HTTPS_PROXY", &self.https),
+            ("NO_PROXY", &self.no_proxy),
+        ]
+        .into_iter()
+        .filter(|_| !self.is_empty())
+        .filter_map(|(key, value)| value.as_ref().map(|value| (key, value)))
+        .flat_map(|(key, value)| {
+            [
+                (key.to_string(), value.clone()),
+                (key.to_lowercase(), value.clone()),
+            ]
+        })
+        .collect()
+    }
+}
+
+/// Limits the combined rate of the downloads sharing it.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    /// When the data received so far is due at the limited rate.
+    due: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: NonZeroU64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.get(),
+            due: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Account for received data, sleeping until it is due.
+    ///
+    /// Not reading while sleeping throttles the sender.
+    fn consume(&self, count: usize) {
+        let delay = {
+            let mut due = self.due.lock().expect("lock should not be poisoned");
+            let now = Instant::now();
+            // Idle time doesn't permit bursts afterwards.
+            *due = (*due).max(now)
+                + Duration::from_secs_f64(count as f64 / self.bytes_per_second as f64);
+
+            *due - now
+        };
+
+        std::thread::sleep(delay);
+    }
+}
+
+/// A writer limiting the rate data is written to it.
+struct RateLimitedWriter<'a, W: Write> {
+    inner: W,
+    limiter: Option<&'a RateLimiter>,
+}
+
+impl<'a, W: Write> Write for RateLimitedWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        if let Some(limiter) = self.limiter {
+            limiter.consume(count);
+        }
+
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Settings controlling how records are downloaded.
+#[derive(Clone, Debug)]
+pub struct DownloadSettings {
+    /// Maximum number of records to download concurrently.
+    pub jobs: usize,
+
+    /// OpenPGP keyring to verify signatures of records with.
+    ///
+    /// Must be in a format `gpgv` accepts, such as the output of `gpg --export`.
+    /// Signatures are only verified if a keyring is defined. Verification runs
+    /// GnuPG's `gpgv`, which must be on `PATH`.
+    pub keyring: Option<PathBuf>,
+
+    /// Whether every record must have a signature that verifies against [Self::keyring].
+    pub require_signatures: bool,
+
+    /// Directory of previously downloaded files to use instead of the network.
+    ///
+    /// When defined, nothing is downloaded. Files are named as they would be
+    /// when downloaded, including `.sig` files for signatures.
+    pub offline_dir: Option<PathBuf>,
+
+    /// LLVM major version to fetch sources for. See [llvm_source_records].
+    pub llvm_version: String,
+
+    /// Commit of LLVM to build instead of release tarballs.
+    ///
+    /// [Self::llvm_version] is still used for everything but the LLVM sources.
+    pub llvm_git: Option<GitSource>,
+
+    /// Commit of glibc to build instead of the release branch.
+    pub glibc_git: Option<GitSource>,
+
+    /// Number of times to retry a record after all its URLs fail.
+    ///
+    /// Partial downloads are resumed by retries.
+    pub retries: u32,
+
+    /// Delay before the first retry. Each subsequent retry waits twice as long.
+    pub backoff: Duration,
+
+    /// Maximum time a single download request may take.
+    ///
+    /// Unbounded by default, as large tarballs can take a long time to download.
+    pub timeout: Option<Duration>,
+
+    /// Proxies to download through.
+    pub proxy: ProxySettings,
+
+    /// Limit of the combined rate of downloads.
+    ///
+    /// Shared by clones of these settings, so concurrent downloads split the
+    /// rate. Git sources aren't limited.
+    pub rate_limit: Option<Arc<RateLimiter>>,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            jobs: DEFAULT_DOWNLOAD_JOBS,
+            keyring: None,
+            require_signatures: false,
+            offline_dir: None,
+            llvm_version: DEFAULT_LLVM_VERSION.to_string(),
+            llvm_git: None,
+            glibc_git: None,
+            retries: DEFAULT_DOWNLOAD_RETRIES,
+            backoff: DEFAULT_DOWNLOAD_BACKOFF,
+            timeout: None,
+            proxy: ProxySettings::default(),
+            rate_limit: None,
+        }
+    }
+}
+
+impl DownloadSettings {
+    /// Construct an HTTP client for downloading.
+    ///
+    /// This is a blocking client, so it must not be created or dropped on a
+    /// thread running an async runtime.
+    pub fn client(&self) -> Result<Client> {
+        let builder = Client::builder()
+            .user_agent(concat!("pclang/", env!("CARGO_PKG_VERSION")))
+            .timeout(self.timeout)
+            .connect_timeout(Duration::from_secs(30));
+
+        self.proxy
+            .apply(builder)?
+            .build()
+            .context("constructing HTTP client")
+    }
+}
+
 /// Describes a software record.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SoftwareRecord {
+    /// Populated from the record's key in the manifest.
+    #[serde(skip)]
     pub name: String,
     pub version: String,
     pub url: String,
+    /// Alternate URLs serving the same content, tried in order if `url` fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// URL of a detached OpenPGP signature of the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
     pub sha256: String,
+    /// BLAKE3 of the content.
+    ///
+    /// Much faster to compute than SHA-256, so cached files are re-verified
+    /// on every use if it is defined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
 }
 
-impl From<&SoftwareRecord> for RemoteContent {
-    fn from(r: &SoftwareRecord) -> Self {
-        Self {
-            name: r.name.clone(),
-            url: r.url.clone(),
-            sha256: r.sha256.clone(),
+impl SoftwareRecord {
+    /// [RemoteContent] for each URL the record is available from, in the order to try them.
+    pub fn remote_contents(&self) -> Vec<RemoteContent> {
+        std::iter::once(&self.url)
+            .chain(self.mirrors.iter())
+            .map(|url| RemoteContent {
+                name: self.name.clone(),
+                url: url.clone(),
+                sha256: self.sha256.clone(),
+            })
+            .collect()
+    }
+
+    /// Whether a file has this record's content.
+    ///
+    /// The BLAKE3 is checked if defined, otherwise the SHA-256.
+    pub fn verify_file(&self, path: &Path) -> Result<bool> {
+        if let Some(blake3) = &self.blake3 {
+            Ok(file_blake3(path)? == *blake3)
+        } else {
+            Ok(file_sha256(path)? == self.sha256)
         }
     }
-}
 
-impl SoftwareRecord {
     pub fn is_llvm(&self) -> bool {
         matches!(
             self.name.as_str(),
@@ -46,237 +318,1720 @@ impl SoftwareRecord {
         )
     }
 
+    /// Whether this is a library LLVM links against, built statically for portability.
+    pub fn is_llvm_dependency(&self) -> bool {
+        matches!(self.name.as_str(), "libxml2" | "zlib" | "zstd")
+    }
+
     pub fn is_gcc(&self) -> bool {
         matches!(
             self.name.as_str(),
             "binutils" | "gcc-10_3" | "gmp" | "isl" | "mpc" | "mpfr"
         )
     }
+
+    pub fn is_musl(&self) -> bool {
+        self.name == "musl"
+    }
 }
 
-pub static DOWNLOADS: Lazy<BTreeMap<&str, SoftwareRecord>> = Lazy::new(|| {
-    BTreeMap::from_iter([
-        ("binutils", SoftwareRecord {
-            name: "binutils".to_string(),
-            version: "2.36.1".to_string(),
-            url: "https://ftp.gnu.org/gnu/binutils/binutils-2.36.1.tar.xz".to_string(),
-            sha256: "e81d9edf373f193af428a0f256674aea62a9d74dfe93f65192d4eae030b0f3b0".to_string(),
-        }),
-        ("clang", SoftwareRecord {
-            name: "clang".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/clang-13.0.0.src.tar.xz".to_string(),
-            sha256: "5d611cbb06cfb6626be46eb2f23d003b2b80f40182898daa54b1c4e8b5b9e17e".to_string(),
-        }),
-        ("clang-tools-extra", SoftwareRecord {
-            name: "clang-tools-extra".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/clang-tools-extra-13.0.0.src.tar.xz".to_string(),
-            sha256: "428b6060a28b22adf0cdf5d827abbc2ba81809f4661ede3d02b1d3fedaa3ead5".to_string(),
-        }),
-        ("cmake-linux_x86_64", SoftwareRecord {
-            name: "cmake-linux_x86_64".to_string(),
-            version: "3.21.4".to_string(),
-            url: "https://github.com/Kitware/CMake/releases/download/v3.21.4/cmake-3.21.4-linux-x86_64.tar.gz".to_string(),
-            sha256: "eddba9da5b60e0b5ec5cbb1a65e504d776e247573204df14f6d004da9bc611f9".to_string(),
-        }),
-        ("compiler-rt", SoftwareRecord {
-            name: "compiler-rt".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/compiler-rt-13.0.0.src.tar.xz".to_string(),
-            sha256: "4c3602d76c7868a96b30c36165c4b7643e2a20173fced7e071b4baeb2d74db3f".to_string()
-        }),
-        ("gcc-10_3", SoftwareRecord {
-            name: "gcc-10_3".to_string(),
-            version: "10.3.0".to_string(),
-            url: "https://ftp.gnu.org/gnu/gcc/gcc-10.3.0/gcc-10.3.0.tar.xz".to_string(),
-            sha256: "64f404c1a650f27fc33da242e1f2df54952e3963a49e06e73f6940f3223ac344".to_string(),
-        }),
-        ("gmp", SoftwareRecord {
-            name: "gmp".to_string(),
-            version: "6.1.2".to_string(),
-            url: "https://ftp.gnu.org/gnu/gmp/gmp-6.1.2.tar.xz".to_string(),
-            sha256: "87b565e89a9a684fe4ebeeddb8399dce2599f9c9049854ca8c0dfbdea0e21912".to_string(),
-        }),
-        ("isl", SoftwareRecord {
-            name: "isl".to_string(),
-            version: "0.18".to_string(),
-            url: "https://gcc.gnu.org/pub/gcc/infrastructure/isl-0.18.tar.bz2".to_string(),
-            sha256: "6b8b0fd7f81d0a957beb3679c81bbb34ccc7568d5682844d8924424a0dadcb1b".to_string(),
-        }),
-        ("libcxx", SoftwareRecord {
-            name: "libcxx".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/libcxx-13.0.0.src.tar.xz".to_string(),
-            sha256: "3682f16ce33bb0a8951fc2c730af2f9b01a13b71b2b0dc1ae1e7034c7d86ca1a".to_string()
-        }),
-        ("libcxxabi", SoftwareRecord {
-            name: "libcxxabi".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/libcxxabi-13.0.0.src.tar.xz".to_string(),
-            sha256: "becd5f1cd2c03cd6187558e9b4dc8a80b6d774ff2829fede88aa1576c5234ce3".to_string()
-        }),
-        ("libunwind", SoftwareRecord {
-            name: "libunwind".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/libunwind-13.0.0.src.tar.xz".to_string(),
-            sha256: "36f819091216177a61da639244eda67306ccdd904c757d70d135e273278b65e1".to_string()
-        }),
-        ("lld", SoftwareRecord {
-            name: "lld".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/lld-13.0.0.src.tar.xz".to_string(),
-            sha256: "20d1900bcd64ff62047291f6edb6ba2fed34d782675ff68713bf0c2fc9e69386".to_string(),
-        }),
-        ("llvm", SoftwareRecord {
-            name: "llvm".to_string(),
-            version: "13.0.0".to_string(),
-            url: "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/llvm-13.0.0.src.tar.xz".to_string(),
-            sha256: "408d11708643ea826f519ff79761fcdfc12d641a2510229eec459e72f8163020".to_string()
-        }),
-        ("mpc", SoftwareRecord {
-            version: "1.0.3".to_string(),
-            name: "mpc".to_string(),
-            url: "http://www.multiprecision.org/downloads/mpc-1.0.3.tar.gz".to_string(),
-            sha256: "617decc6ea09889fb08ede330917a00b16809b8db88c29c31bfbb49cbf88ecc3".to_string(),
-        }),
-        ("mpfr", SoftwareRecord {
-            name: "mpfr".to_string(),
-            version: "3.1.6".to_string(),
-            url: "https://ftp.gnu.org/gnu/mpfr/mpfr-3.1.6.tar.xz".to_string(),
-            sha256: "7a62ac1a04408614fccdc506e4844b10cf0ad2c2b1677097f8f35d3a1344a950".to_string(),
-        }),
-        ("ninja-linux_x86_64", SoftwareRecord {
-            name: "ninja-linux_x86_64".to_string(),
-            version: "1.10.2".to_string(),
-            url: "https://github.com/ninja-build/ninja/releases/download/v1.10.2/ninja-linux.zip".to_string(),
-            sha256: "763464859c7ef2ea3a0a10f4df40d2025d3bb9438fcb1228404640410c0ec22d".to_string(),
-        }),
-        ("python-linux_x86_64", SoftwareRecord {
-            name: "python-linux_x86_64".to_string(),
-            version: "cpython-3.9.7-x86_64-unknown-linux-gnu-install_only-20211017T1616".to_string(),
-            url: "https://github.com/indygreg/python-build-standalone/releases/download/20211017/cpython-3.9.7-x86_64-unknown-linux-gnu-install_only-20211017T1616.tar.gz".to_string(),
-            sha256: "a92dfd11be92c8b5f7b50953bdb5864456d68d316f73d9cfb4bde33a68ac8239".to_string(),
-        }),
-        ("sccache-linux_x86_64", SoftwareRecord {
-            name: "sccache-linux_x86_64".to_string(),
-            version: "0.2.15".to_string(),
-            url: "https://github.com/mozilla/sccache/releases/download/v0.2.15/sccache-v0.2.15-x86_64-unknown-linux-musl.tar.gz".to_string(),
-            sha256: "e5d03a9aa3b9fac7e490391bbe22d4f42c840d31ef9eaf127a03101930cbb7ca".to_string(),
-        }),
-   ])
+/// The download manifest shipped with pclang.
+const EMBEDDED_DOWNLOADS: &str = include_str!("downloads.toml");
+
+/// Parse a download manifest.
+///
+/// The manifest is a TOML document with a table for each record.
+fn parse_downloads(data: &str) -> Result<BTreeMap<String, SoftwareRecord>> {
+    let mut records = toml::from_str::<BTreeMap<String, SoftwareRecord>>(data)?;
+
+    for (name, record) in records.iter_mut() {
+        record.name = name.clone();
+
+        let is_digest = |d: &str| d.len() == 64 && d.chars().all(|c| c.is_ascii_hexdigit());
+
+        if !is_digest(&record.sha256) {
+            return Err(anyhow!("{} does not have a valid SHA-256 digest", name));
+        }
+        if let Some(blake3) = &record.blake3 {
+            if !is_digest(blake3) {
+                return Err(anyhow!("{} does not have a valid BLAKE3 digest", name));
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+/// Path of a user provided download manifest overriding records.
+///
+/// `PCLANG_DOWNLOADS_CONFIG` takes precedence over `pclang/downloads.toml` in
+/// the user's config directory (e.g. `~/.config` on Linux).
+pub fn user_downloads_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("PCLANG_DOWNLOADS_CONFIG") {
+        Some(PathBuf::from(path))
+    } else {
+        dirs::config_dir()
+            .map(|dir| dir.join("pclang").join("downloads.toml"))
+            .filter(|path| path.exists())
+    }
+}
+
+/// Path of a user provided keyring to verify download signatures with.
+///
+/// `PCLANG_KEYRING` takes precedence over `pclang/keyring.gpg` in the user's
+/// config directory.
+pub fn user_keyring_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("PCLANG_KEYRING") {
+        Some(PathBuf::from(path))
+    } else {
+        dirs::config_dir()
+            .map(|dir| dir.join("pclang").join("keyring.gpg"))
+            .filter(|path| path.exists())
+    }
+}
+
+/// Load the download manifest, applying overrides from `user_path`.
+///
+/// Records in the user manifest replace embedded records of the same name.
+pub fn load_downloads(user_path: Option<&Path>) -> Result<BTreeMap<String, SoftwareRecord>> {
+    let mut records = parse_downloads(EMBEDDED_DOWNLOADS).context("parsing embedded downloads")?;
+
+    if let Some(path) = user_path {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("reading download manifest {}", path.display()))?;
+
+        records.extend(
+            parse_downloads(&data)
+                .with_context(|| format!("parsing download manifest {}", path.display()))?,
+        );
+    }
+
+    Ok(records)
+}
+
+/// The active download manifest.
+///
+/// Call [load_downloads] beforehand to report errors in user manifests
+/// gracefully.
+pub static DOWNLOADS: Lazy<BTreeMap<String, SoftwareRecord>> = Lazy::new(|| {
+    load_downloads(user_downloads_path().as_deref()).expect("download manifest should be valid")
 });
 
-/// [RemoteContent] records for GCC source artifacts.
-pub fn gcc_source_remote_contents() -> Vec<RemoteContent> {
+/// Records for GCC source artifacts.
+pub fn gcc_source_records() -> Vec<&'static SoftwareRecord> {
     DOWNLOADS
         .values()
-        .filter_map(|record| {
-            if record.is_gcc() {
-                Some(record.into())
-            } else {
-                None
-            }
-        })
+        .filter(|record| record.is_gcc())
         .collect::<Vec<_>>()
 }
 
-/// [RemoteContent] records for LLVM source artifacts.
-pub fn llvm_source_remote_contents() -> Vec<RemoteContent> {
+/// Records for musl libc source artifacts.
+pub fn musl_source_records() -> Vec<&'static SoftwareRecord> {
+    DOWNLOADS
+        .values()
+        .filter(|record| record.is_musl())
+        .collect::<Vec<_>>()
+}
+
+/// Record for Linux kernel sources, from which sysroot headers are installed.
+pub fn linux_headers_record() -> Result<&'static SoftwareRecord> {
+    DOWNLOADS.get("linux").ok_or_else(|| {
+        anyhow!("no Linux kernel sources are defined; define a linux record in a download manifest")
+    })
+}
+
+/// The record defining the version of an LLVM major version's sources.
+///
+/// This is the `llvm-project_<major>` record of the monorepo source tarball if
+/// defined. Otherwise it is the `llvm` record if it has the requested major
+/// version, in which case sources are split into per-project tarballs.
+pub fn llvm_version_record(major: &str) -> Result<&'static SoftwareRecord> {
+    if let Some(record) = DOWNLOADS.get(format!("llvm-project_{}", major).as_str()) {
+        return Ok(record);
+    }
+
+    match DOWNLOADS.get("llvm") {
+        Some(record) if record.version.split('.').next() == Some(major) => Ok(record),
+        _ => Err(anyhow!(
+            "no sources are defined for LLVM {}; define an llvm-project_{} record in a download manifest (available versions: {})",
+            major,
+            major,
+            llvm_versions().join(", ")
+        )),
+    }
+}
+
+/// LLVM major versions with sources defined.
+pub fn llvm_versions() -> Vec<String> {
     DOWNLOADS
         .values()
         .filter_map(|record| {
-            if record.is_llvm() {
-                Some(record.into())
+            if record.name == "llvm" {
+                record.version.split('.').next().map(String::from)
             } else {
-                None
+                record.name.strip_prefix("llvm-project_").map(String::from)
             }
         })
-        .collect::<Vec<_>>()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
 }
 
-/// [RemoteContent] records for support tools.
-pub fn support_linux_x86_64_remote_contents() -> Vec<RemoteContent> {
+/// Records for LLVM source artifacts of a major version.
+///
+/// This is either the monorepo source tarball or the per-project tarballs,
+/// plus the sources of libraries LLVM links against. See [llvm_version_record].
+pub fn llvm_source_records(major: &str) -> Result<Vec<&'static SoftwareRecord>> {
+    let record = llvm_version_record(major)?;
+
+    let mut records = if record.name == "llvm" {
+        DOWNLOADS
+            .values()
+            .filter(|record| record.is_llvm())
+            .collect::<Vec<_>>()
+    } else {
+        vec![record]
+    };
+
+    records.extend(llvm_dependency_records());
+
+    Ok(records)
+}
+
+/// Records of libraries LLVM is linked against.
+pub fn llvm_dependency_records() -> Vec<&'static SoftwareRecord> {
     DOWNLOADS
+        .values()
+        .filter(|record| record.is_llvm_dependency())
+        .collect()
+}
+
+/// Names of support tools needed inside builder images.
+pub const SUPPORT_TOOLS: &[&str] = &["cmake", "ninja", "python", "sccache"];
+
+/// Records for support tools for an operating system and architecture.
+///
+/// `os` is `linux` or `macos`. `arch` is the machine architecture. e.g.
+/// `x86_64` or `aarch64`. Records are named `<tool>-<os>_<arch>`.
+pub fn support_records(os: &str, arch: &str) -> Result<Vec<&'static SoftwareRecord>> {
+    SUPPORT_TOOLS
         .iter()
-        .filter_map(|(name, record)| {
-            if matches!(
-                *name,
-                "cmake-linux_x86_64"
-                    | "ninja-linux_x86_64"
-                    | "python-linux_x86_64"
-                    | "sccache-linux_x86_64"
+        .map(|tool| {
+            let key = format!("{}-{}_{}", tool, os, arch);
+
+            DOWNLOADS
+                .get(key.as_str())
+                .ok_or_else(|| anyhow!("no {} download defined for {}/{}", tool, os, arch))
+        })
+        .collect::<Result<Vec<_>>>()
+}
+
+/// Whether records for all support tools are defined for an operating system and architecture.
+pub fn has_support_records(os: &str, arch: &str) -> bool {
+    support_records(os, arch).is_ok()
+}
+
+/// Operating systems having support tool records for at least 1 architecture.
+pub fn support_oses() -> Vec<&'static str> {
+    ["linux", "macos"]
+        .into_iter()
+        .filter(|os| {
+            let prefix = format!("{}-{}_", SUPPORT_TOOLS[0], os);
+
+            DOWNLOADS
+                .keys()
+                .filter_map(|key| key.strip_prefix(&prefix))
+                .any(|arch| has_support_records(os, arch))
+        })
+        .collect()
+}
+
+/// Records for support tools for Linux on a given architecture.
+pub fn support_linux_records(arch: &str) -> Result<Vec<&'static SoftwareRecord>> {
+    support_records("linux", arch)
+}
+
+/// Records for support tools for macOS on a given architecture.
+///
+/// These are for building natively on macOS hosts.
+pub fn support_macos_records(arch: &str) -> Result<Vec<&'static SoftwareRecord>> {
+    support_records("macos", arch)
+}
+
+/// Append `.<suffix>` to a path.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = OsString::from(path.as_os_str());
+    s.push(".");
+    s.push(suffix);
+
+    PathBuf::from(s)
+}
+
+/// Compute the hex encoded SHA-256 of a file.
+fn file_sha256(path: &Path) -> Result<String> {
+    let fh = std::fs::File::open(path)
+        .with_context(|| format!("opening {} for hashing", path.display()))?;
+
+    reader_sha256(fh)
+}
+
+/// Compute the hex encoded SHA-256 of all data in a reader.
+pub fn reader_sha256(mut reader: impl Read) -> Result<String> {
+    let mut hasher = sha2::Sha256::new();
+    let mut buffer = [0u8; 65536];
+
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[0..count]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Compute the hex encoded BLAKE3 of all data in a reader.
+pub fn reader_blake3(mut reader: impl Read) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Compute the hex encoded BLAKE3 of a file.
+fn file_blake3(path: &Path) -> Result<String> {
+    let fh = std::fs::File::open(path)
+        .with_context(|| format!("opening {} for hashing", path.display()))?;
+
+    reader_blake3(fh)
+}
+
+/// The local path a `file://` URL or absolute path refers to.
+///
+/// Returns `None` for remote URLs.
+fn local_path(url: &str) -> Option<PathBuf> {
+    if let Some(path) = url.strip_prefix("file://") {
+        Some(PathBuf::from(path))
+    } else if Path::new(url).is_absolute() {
+        Some(PathBuf::from(url))
+    } else {
+        None
+    }
+}
+
+/// Download remote content over HTTP to a partial file.
+///
+/// An existing partial file is resumed if the server supports it.
+fn http_download(
+    logger: &Logger,
+    client: &Client,
+    limiter: Option<&RateLimiter>,
+    content: &RemoteContent,
+    partial_path: &Path,
+) -> Result<()> {
+    let offset = partial_path.metadata().map(|m| m.len()).unwrap_or(0);
+
+    let mut request = client.get(&content.url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+
+    let mut response = request
+        .send()
+        .with_context(|| format!("requesting {}", content.url))?;
+    let status = response.status();
+
+    if offset > 0 && status == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file may already be complete. The caller verifies it.
+    } else if offset > 0 && status == StatusCode::PARTIAL_CONTENT {
+        info!(
+            logger,
+            "resuming download of {} at byte {}", content.url, offset
+        );
+        let fh = std::fs::OpenOptions::new()
+            .append(true)
+            .open(partial_path)
+            .context("opening partial download")?;
+        response
+            .copy_to(&mut RateLimitedWriter { inner: fh, limiter })
+            .with_context(|| format!("downloading {}", content.url))?;
+    } else if status.is_success() {
+        info!(logger, "downloading {}", content.url);
+        let fh = std::fs::File::create(partial_path).context("creating partial download")?;
+        response
+            .copy_to(&mut RateLimitedWriter { inner: fh, limiter })
+            .with_context(|| format!("downloading {}", content.url))?;
+    } else {
+        return Err(anyhow!("HTTP {} requesting {}", status, content.url));
+    }
+
+    Ok(())
+}
+
+/// Download remote content to a path, verifying its SHA-256.
+///
+/// Data is written to a `.partial` file next to `dest_path` which is renamed
+/// into place once verified. If a previous download was interrupted, it is
+/// resumed with an HTTP `Range` request. Servers not honoring the range cause
+/// the download to restart from the beginning. Partial files failing
+/// verification are deleted so the next attempt starts over.
+///
+/// An existing file at `dest_path` with the expected hash is reused. Content
+/// with a local URL (see [local_path]) is copied instead of downloaded.
+/// Downloads are throttled by `limiter` if given.
+pub fn download_to_path(
+    logger: &Logger,
+    client: &Client,
+    limiter: Option<&RateLimiter>,
+    content: &RemoteContent,
+    dest_path: &Path,
+) -> Result<()> {
+    let lock_path = with_suffix(dest_path, "lock");
+    let lock = std::fs::File::create(&lock_path)
+        .with_context(|| format!("opening {}", lock_path.display()))?;
+    lock.lock_exclusive()
+        .with_context(|| format!("locking {}", lock_path.display()))?;
+
+    if dest_path.exists() {
+        if file_sha256(dest_path)? == content.sha256 {
+            return Ok(());
+        }
+
+        warn!(
+            logger,
+            "{} has unexpected content; downloading again",
+            dest_path.display()
+        );
+        std::fs::remove_file(dest_path).context("removing stale download")?;
+    }
+
+    let partial_path = with_suffix(dest_path, "partial");
+
+    if let Some(source_path) = local_path(&content.url) {
+        info!(logger, "copying {}", source_path.display());
+        std::fs::copy(&source_path, &partial_path)
+            .with_context(|| format!("copying {}", source_path.display()))?;
+    } else {
+        http_download(logger, client, limiter, content, &partial_path)?;
+    }
+
+    let digest = file_sha256(&partial_path)?;
+    if digest != content.sha256 {
+        std::fs::remove_file(&partial_path).context("removing corrupt partial download")?;
+
+        return Err(anyhow!(
+            "hash mismatch downloading {}; expected {}, got {}",
+            content.url,
+            content.sha256,
+            digest
+        ));
+    }
+
+    std::fs::rename(&partial_path, dest_path).context("renaming completed download")?;
+
+    Ok(())
+}
+
+/// Verify the signature of a downloaded record, if possible.
+///
+/// The signature is downloaded next to `path` unless already present or no
+/// `client` is given. Records without a signature or the absence of a keyring
+/// are errors only if signatures are required.
+fn verify_signature(
+    logger: &Logger,
+    client: Option<&Client>,
+    settings: &DownloadSettings,
+    record: &SoftwareRecord,
+    path: &Path,
+) -> Result<()> {
+    let (url, keyring) = match (&record.signature, &settings.keyring) {
+        (Some(url), Some(keyring)) => (url, keyring),
+        (None, _) if settings.require_signatures => {
+            return Err(anyhow!("no signature is defined for {}", record.name));
+        }
+        (Some(_), None) if settings.require_signatures => {
+            return Err(anyhow!(
+                "a keyring is required to verify signatures; use --keyring"
+            ));
+        }
+        _ => {
+            return Ok(());
+        }
+    };
+
+    let sig_path = with_suffix(path, "sig");
+    if !sig_path.exists() {
+        if let Some(source_path) = local_path(url) {
+            std::fs::copy(&source_path, &sig_path)
+                .with_context(|| format!("copying {}", source_path.display()))?;
+        } else {
+            let client = client.ok_or_else(|| anyhow!("signature {} is not available", url))?;
+            let mut response = client
+                .get(url)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("requesting {}", url))?;
+            let mut fh = std::fs::File::create(&sig_path).context("creating signature file")?;
+            response
+                .copy_to(&mut fh)
+                .with_context(|| format!("downloading {}", url))?;
+        }
+    }
+
+    let output = std::process::Command::new("gpgv")
+        .arg("--keyring")
+        .arg(keyring)
+        .arg(&sig_path)
+        .arg(path)
+        .output()
+        .context("running gpgv; is GnuPG installed?")?;
+
+    if !output.status.success() {
+        // The signature may have been truncated or replaced. Fetch it again next time.
+        std::fs::remove_file(&sig_path).context("removing signature file")?;
+
+        return Err(anyhow!(
+            "signature verification of {} failed:\n{}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+
+    info!(logger, "verified signature of {}", path.display());
+
+    Ok(())
+}
+
+/// Ensure `gpgv` is available if signatures will be verified.
+///
+/// Checked before downloading so a missing GnuPG doesn't surface only after
+/// every download completes.
+fn check_gpgv(settings: &DownloadSettings) -> Result<()> {
+    if settings.keyring.is_none() {
+        return Ok(());
+    }
+
+    match std::process::Command::new("gpgv").arg("--version").output() {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(anyhow!(
+            "gpgv is required to verify signatures against a keyring but was not found; install GnuPG"
+        )),
+        Err(e) => Err(e).context("running gpgv"),
+    }
+}
+
+/// Download a record to a path, trying each of its URLs in turn.
+///
+/// `path` is in a download cache, so an existing file is known to be valid.
+/// A partial download from a failed URL is resumed from the next one. If all
+/// URLs fail, they are retried as configured by [DownloadSettings].
+fn fetch_record(
+    logger: &Logger,
+    client: &Client,
+    settings: &DownloadSettings,
+    record: &SoftwareRecord,
+    path: &Path,
+) -> Result<()> {
+    if path.exists() {
+        if record.blake3.is_none() || record.verify_file(path)? {
+            return verify_signature(logger, Some(client), settings, record, path);
+        }
+
+        warn!(
+            logger,
+            "{} has unexpected content; downloading again",
+            path.display()
+        );
+        std::fs::remove_file(path).context("removing corrupt download")?;
+    }
+
+    std::fs::create_dir_all(path.parent().expect("path should have parent"))
+        .context("creating download directory")?;
+
+    let mut errors = vec![];
+    let mut backoff = settings.backoff;
+
+    for attempt in 0..=settings.retries {
+        if attempt > 0 {
+            warn!(
+                logger,
+                "retrying download of {} in {:?} (attempt {} of {})",
+                record.name,
+                backoff,
+                attempt + 1,
+                settings.retries + 1
+            );
+            std::thread::sleep(backoff);
+            backoff *= 2;
+        }
+
+        for content in record.remote_contents() {
+            match download_to_path(
+                logger,
+                client,
+                settings.rate_limit.as_deref(),
+                &content,
+                path,
             ) {
-                Some(record.into())
-            } else {
-                None
+                Ok(()) => {
+                    // The SHA-256 matched, so a mismatch is an error in the manifest.
+                    if record.blake3.is_some() && !record.verify_file(path)? {
+                        std::fs::remove_file(path).context("removing download")?;
+
+                        return Err(anyhow!(
+                            "{} matches its SHA-256 but not its BLAKE3; the manifest is wrong",
+                            record.name
+                        ));
+                    }
+
+                    return verify_signature(logger, Some(client), settings, record, path);
+                }
+                Err(e) => {
+                    warn!(logger, "failed to download {}: {:#}", content.url, e);
+                    errors.push(format!("{}: {:#}", content.url, e));
+                }
+            }
+        }
+    }
+
+    Err(anyhow!(
+        "unable to download {} from any URL:\n{}",
+        record.name,
+        errors.join("\n")
+    ))
+}
+
+/// File name a record is downloaded to.
+fn record_filename(record: &SoftwareRecord) -> &str {
+    record.url.rsplit_once('/').expect("URL should have /").1
+}
+
+/// Whether a file name looks like something [fetch_records] writes.
+fn is_download_artifact(name: &str) -> bool {
+    [
+        ".tar.gz", ".tar.xz", ".tar.bz2", ".tgz", ".zip", ".sig", ".asc", ".partial", ".lock",
+    ]
+    .iter()
+    .any(|suffix| name.ends_with(suffix))
+}
+
+/// Path a record is stored at in a download cache directory.
+///
+/// Records are stored under their SHA-256 so different versions and
+/// overridden URLs can't collide. Files are only moved into place once
+/// verified, so a file existing at this path has the expected content.
+pub fn cache_path(cache_dir: &Path, record: &SoftwareRecord) -> PathBuf {
+    cache_dir.join(&record.sha256).join(record_filename(record))
+}
+
+/// Whether a directory name is a SHA-256 of the download cache layout.
+fn is_sha256_name(name: &str) -> bool {
+    name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Delete a pruned file, returning its size.
+///
+/// Lock files held by another process are skipped.
+fn prune_file(logger: &Logger, path: &Path, dry_run: bool) -> Result<u64> {
+    if path.extension() == Some("lock".as_ref()) {
+        let fh = std::fs::File::open(path)?;
+        if fh.try_lock_exclusive().is_err() {
+            return Ok(0);
+        }
+    }
+
+    let size = path.metadata()?.len();
+    warn!(logger, "removing {} ({} bytes)", path.display(), size);
+
+    if !dry_run {
+        std::fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+    }
+
+    Ok(size)
+}
+
+/// Delete downloaded files in a download cache directory that aren't referenced by any record.
+///
+/// This removes old versions of artifacts, their signatures and partial
+/// downloads, and lock files not held by another process. Downloads stored
+/// directly in `dir`, as done before downloads were stored by SHA-256, are
+/// always removed. Other files are left alone, as the directory may be shared
+/// with other data. Returns the number of bytes reclaimed, or that would be
+/// if `dry_run` is set.
+pub fn prune_downloads<'a>(
+    logger: &Logger,
+    records: impl Iterator<Item = &'a SoftwareRecord>,
+    dir: &Path,
+    dry_run: bool,
+) -> Result<u64> {
+    let mut keep = BTreeMap::<String, BTreeSet<String>>::new();
+    for record in records {
+        let filename = record_filename(record);
+        keep.entry(record.sha256.clone()).or_default().extend([
+            filename.to_string(),
+            format!("{}.sig", filename),
+            format!("{}.partial", filename),
+        ]);
+    }
+
+    let mut reclaimed = 0;
+
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_file() && is_download_artifact(&name) {
+            reclaimed += prune_file(logger, &entry.path(), dry_run)?;
+        } else if file_type.is_dir() && is_sha256_name(&name) {
+            let keep = keep.get(&name);
+
+            for file in std::fs::read_dir(entry.path())? {
+                let file = file?;
+                let file_name = file.file_name().to_string_lossy().to_string();
+
+                if file.file_type()?.is_file()
+                    && !keep.map(|k| k.contains(&file_name)).unwrap_or(false)
+                {
+                    reclaimed += prune_file(logger, &file.path(), dry_run)?;
+                }
+            }
+
+            if !dry_run && std::fs::read_dir(entry.path())?.next().is_none() {
+                std::fs::remove_dir(entry.path())
+                    .with_context(|| format!("removing {}", entry.path().display()))?;
+            }
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+/// Result of verifying a download cache directory.
+#[derive(Debug, Default)]
+pub struct CacheVerification {
+    /// Number of files with the expected content.
+    pub valid: usize,
+
+    /// Files whose content doesn't match the digest they are stored under.
+    pub corrupt: Vec<PathBuf>,
+
+    /// Incomplete downloads. These are resumed by the next fetch.
+    pub partial: Vec<PathBuf>,
+}
+
+/// Hash every file in a download cache directory and report those with the wrong content.
+///
+/// Files are verified against the SHA-256 directory they are stored in, or
+/// the BLAKE3 of the record in `records` with that SHA-256 if it defines one.
+/// If `delete` is set, corrupt files and incomplete downloads are deleted so
+/// the next fetch starts over.
+pub fn verify_downloads<'a>(
+    logger: &Logger,
+    records: impl Iterator<Item = &'a SoftwareRecord>,
+    cache_dir: &Path,
+    delete: bool,
+) -> Result<CacheVerification> {
+    let blake3s = records
+        .filter_map(|record| Some((record.sha256.as_str(), record.blake3.as_deref()?)))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut res = CacheVerification::default();
+
+    for entry in
+        std::fs::read_dir(cache_dir).with_context(|| format!("reading {}", cache_dir.display()))?
+    {
+        let entry = entry?;
+        let sha256 = entry.file_name().to_string_lossy().to_string();
+
+        if !entry.file_type()?.is_dir() || !is_sha256_name(&sha256) {
+            continue;
+        }
+
+        for file in std::fs::read_dir(entry.path())? {
+            let file = file?;
+            let path = file.path();
+
+            match path.extension().and_then(|x| x.to_str()) {
+                Some("sig") | Some("lock") => {}
+                Some("partial") => {
+                    warn!(logger, "incomplete download: {}", path.display());
+                    res.partial.push(path);
+                }
+                _ => {
+                    let (algorithm, expected, digest) = match blake3s.get(sha256.as_str()) {
+                        Some(blake3) => ("BLAKE3", *blake3, file_blake3(&path)?),
+                        None => ("SHA-256", sha256.as_str(), file_sha256(&path)?),
+                    };
+
+                    if digest == expected {
+                        res.valid += 1;
+                    } else {
+                        warn!(
+                            logger,
+                            "corrupt download: {} has {} {}",
+                            path.display(),
+                            algorithm,
+                            digest
+                        );
+                        res.corrupt.push(path);
+                    }
+                }
+            }
+        }
+    }
+
+    if delete {
+        for path in res.corrupt.iter().chain(res.partial.iter()) {
+            warn!(logger, "removing {}", path.display());
+            std::fs::remove_file(path).with_context(|| format!("removing {}", path.display()))?;
+        }
+    }
+
+    Ok(res)
+}
+
+/// Name of the file listing digests of files copied by [copy_to_dir].
+const SHA256SUMS: &str = "SHA256SUMS";
+
+/// Record the SHA-256 of files in a directory's `SHA256SUMS` file.
+///
+/// The file is in the format of `sha256sum`, so `sha256sum -c SHA256SUMS`
+/// verifies it. Entries of other files already in it are preserved.
+fn update_sha256sums(dest_dir: &Path, paths: &[PathBuf]) -> Result<()> {
+    let sums_path = dest_dir.join(SHA256SUMS);
+
+    let mut sums = BTreeMap::new();
+    if sums_path.exists() {
+        for line in std::fs::read_to_string(&sums_path)
+            .with_context(|| format!("reading {}", sums_path.display()))?
+            .lines()
+        {
+            if let Some((digest, name)) = line.split_once("  ") {
+                sums.insert(name.to_string(), digest.to_string());
             }
+        }
+    }
+
+    for path in paths {
+        let name = path
+            .file_name()
+            .expect("path should have file name")
+            .to_string_lossy()
+            .to_string();
+        sums.insert(name, file_sha256(path)?);
+    }
+
+    let data = sums
+        .iter()
+        .map(|(name, digest)| format!("{}  {}\n", digest, name))
+        .collect::<String>();
+
+    std::fs::write(&sums_path, data).with_context(|| format!("writing {}", sums_path.display()))
+}
+
+/// Copy fetched files to a directory, returning their new paths.
+///
+/// Digests of the copies are recorded in the directory's `SHA256SUMS` file.
+pub fn copy_to_dir(paths: &[PathBuf], dest_dir: &Path) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dest_dir).context("creating destination directory")?;
+
+    let dests = paths
+        .iter()
+        .map(|path| {
+            let dest = dest_dir.join(path.file_name().expect("path should have file name"));
+            std::fs::copy(path, &dest).with_context(|| format!("copying {}", path.display()))?;
+
+            Ok(dest)
         })
-        .collect::<Vec<_>>()
+        .collect::<Result<Vec<_>>>()?;
+
+    update_sha256sums(dest_dir, &dests)?;
+
+    Ok(dests)
 }
 
-/// Fetch multiple [RemoteContent] records to a destination directory.
+/// Name of the manifest describing the records in a source bundle.
+const BUNDLE_MANIFEST: &str = "manifest.json";
+
+/// Write a source bundle containing records from a download cache directory.
+///
+/// The bundle is a tar archive with files in the download cache layout plus a
+/// manifest of the bundled records and their hashes. Records must already be
+/// fetched. Signatures of records are included if present.
+pub fn write_source_bundle(
+    records: &[&SoftwareRecord],
+    cache_dir: &Path,
+    writer: impl std::io::Write,
+) -> Result<()> {
+    let mut builder = tar::Builder::new(writer);
+    let mut manifest = vec![];
+
+    for record in records {
+        let path = cache_path(cache_dir, record);
+        let filename = record_filename(record);
+
+        builder
+            .append_path_with_name(&path, format!("{}/{}", record.sha256, filename))
+            .with_context(|| format!("adding {} to bundle", path.display()))?;
+
+        let sig_path = with_suffix(&path, "sig");
+        if sig_path.exists() {
+            builder
+                .append_path_with_name(&sig_path, format!("{}/{}.sig", record.sha256, filename))
+                .with_context(|| format!("adding {} to bundle", sig_path.display()))?;
+        }
+
+        manifest.push(serde_json::json!({
+            "name": record.name,
+            "version": record.version,
+            "filename": filename,
+            "sha256": record.sha256,
+        }));
+    }
+
+    let manifest = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, BUNDLE_MANIFEST, manifest.as_slice())?;
+
+    builder.into_inner()?;
+
+    Ok(())
+}
+
+/// Import files from a source bundle into a download cache directory.
+///
+/// Each file's content is verified against the SHA-256 it is stored under.
+/// Files already in the cache are skipped. Returns the paths of imported files.
+pub fn import_source_bundle(
+    logger: &Logger,
+    reader: impl Read,
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut imported = vec![];
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let entry_path = entry.path()?.to_path_buf();
+        if entry_path == Path::new(BUNDLE_MANIFEST) {
+            continue;
+        }
+
+        let components = entry_path
+            .components()
+            .map(|c| match c {
+                std::path::Component::Normal(s) => Ok(s.to_string_lossy().to_string()),
+                _ => Err(anyhow!("invalid path in bundle: {}", entry_path.display())),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (sha256, filename) = match components.as_slice() {
+            [sha256, filename] if is_sha256_name(sha256) => (sha256, filename),
+            _ => {
+                return Err(anyhow!(
+                    "unexpected file in bundle: {}",
+                    entry_path.display()
+                ));
+            }
+        };
+
+        let dest = cache_dir.join(sha256).join(filename);
+        if dest.exists() {
+            continue;
+        }
+
+        std::fs::create_dir_all(dest.parent().expect("path should have parent"))
+            .context("creating download directory")?;
+
+        let partial_path = with_suffix(&dest, "partial");
+        let mut fh = std::fs::File::create(&partial_path).context("creating partial file")?;
+        std::io::copy(&mut entry, &mut fh)?;
+        drop(fh);
+
+        // Signatures aren't content addressed. They are verified when used.
+        if !filename.ends_with(".sig") {
+            let digest = file_sha256(&partial_path)?;
+            if &digest != sha256 {
+                std::fs::remove_file(&partial_path).context("removing corrupt file")?;
+
+                return Err(anyhow!(
+                    "{} in bundle has SHA-256 {}",
+                    entry_path.display(),
+                    digest
+                ));
+            }
+        }
+
+        std::fs::rename(&partial_path, &dest).context("renaming imported file")?;
+        info!(logger, "imported {}", dest.display());
+        imported.push(dest);
+    }
+
+    Ok(imported)
+}
+
+/// Resolve records from a directory of previously downloaded files.
+///
+/// Files may be stored in `offline_dir` by SHA-256, as in a download cache
+/// directory, or directly. They are copied unless their destination already
+/// exists. Missing files and files with the wrong hash are reported together.
+fn resolve_offline(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    records: &[&SoftwareRecord],
+    paths: &[PathBuf],
+    offline_dir: &Path,
+) -> Result<()> {
+    let mut missing = vec![];
+
+    for (record, path) in records.iter().zip(paths) {
+        let source = [
+            cache_path(offline_dir, record),
+            offline_dir.join(record_filename(record)),
+        ]
+        .into_iter()
+        .find(|p| p.exists())
+        .unwrap_or_else(|| offline_dir.join(record_filename(record)));
+
+        if !path.exists() {
+            if !source.exists() {
+                missing.push(format!("{}: {}", record.name, source.display()));
+                continue;
+            } else if !record.verify_file(&source)? {
+                missing.push(format!(
+                    "{}: {} does not have the expected content",
+                    record.name,
+                    source.display()
+                ));
+                continue;
+            }
+
+            std::fs::create_dir_all(path.parent().expect("path should have parent"))
+                .context("creating download directory")?;
+            std::fs::copy(&source, path)
+                .with_context(|| format!("copying {}", source.display()))?;
+        }
+
+        if record.signature.is_some() && settings.keyring.is_some() {
+            let sig_path = with_suffix(path, "sig");
+            let sig_source = with_suffix(&source, "sig");
+
+            if !sig_path.exists() {
+                if sig_source.exists() {
+                    std::fs::copy(&sig_source, &sig_path)
+                        .with_context(|| format!("copying {}", sig_source.display()))?;
+                } else {
+                    missing.push(format!("{}: {}", record.name, sig_source.display()));
+                }
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "offline mode is enabled and {} files are missing or invalid in {}:\n{}",
+            missing.len(),
+            offline_dir.display(),
+            missing.join("\n")
+        ));
+    }
+
+    for (record, path) in records.iter().zip(paths) {
+        verify_signature(logger, None, settings, record, path)?;
+    }
+
+    Ok(())
+}
+
+/// Fetch multiple records to a download cache directory.
+///
+/// Files are stored at [cache_path] and named after the last path component
+/// of each record's primary URL.
+/// Up to [DownloadSettings::jobs] records are downloaded concurrently. All
+/// records are attempted even if some fail and failures are reported together.
+///
+/// If [DownloadSettings::offline_dir] is defined, records are resolved from
+/// it instead of downloaded.
 pub fn fetch_records(
     logger: &Logger,
-    records: &[RemoteContent],
-    dest_path: &Path,
+    settings: &DownloadSettings,
+    records: &[&SoftwareRecord],
+    cache_dir: &Path,
 ) -> Result<Vec<PathBuf>> {
-    std::fs::create_dir_all(dest_path).context("creating destination directory")?;
-    let mut res = vec![];
+    check_gpgv(settings)?;
 
-    for record in records {
-        let filename = record.url.rsplit_once('/').expect("URL should have /").1;
+    let paths = records
+        .iter()
+        .map(|record| cache_path(cache_dir, record))
+        .collect::<Vec<_>>();
+
+    if let Some(offline_dir) = &settings.offline_dir {
+        resolve_offline(logger, settings, records, &paths, offline_dir)?;
+
+        return Ok(paths);
+    }
+
+    let next = AtomicUsize::new(0);
+    let errors = Mutex::new(vec![]);
+
+    std::thread::scope(|scope| {
+        for _ in 0..settings.jobs.max(1).min(records.len()) {
+            scope.spawn(|| {
+                // Callers may be on an async runtime thread, where blocking
+                // clients can't be created. So each worker creates its own.
+                let client = match settings.client() {
+                    Ok(client) => client,
+                    Err(e) => {
+                        errors
+                            .lock()
+                            .expect("lock should not be poisoned")
+                            .push(format!("{:#}", e));
+                        return;
+                    }
+                };
 
-        let p = dest_path.join(filename);
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let (record, path) = match (records.get(i), paths.get(i)) {
+                        (Some(record), Some(path)) => (record, path),
+                        _ => break,
+                    };
 
-        download_to_path(logger, record, &p).context("downloading remote content")?;
+                    if let Err(e) = fetch_record(logger, &client, settings, record, path) {
+                        errors
+                            .lock()
+                            .expect("lock should not be poisoned")
+                            .push(format!("{}: {:#}", record.name, e));
+                    }
+                }
+            });
+        }
+    });
+
+    let mut errors = errors.into_inner().expect("lock should not be poisoned");
+    if !errors.is_empty() {
+        errors.sort();
+
+        return Err(anyhow!(
+            "failed to download {} of {} records:\n{}",
+            errors.len(),
+            records.len(),
+            errors.join("\n")
+        ));
+    }
 
-        let lock_path = p.with_extension("lock");
+    for p in &paths {
+        let lock_path = with_suffix(p, "lock");
         if lock_path.exists() {
             std::fs::remove_file(&lock_path).context("removing lock file")?;
         }
+    }
+
+    Ok(paths)
+}
 
-        res.push(p);
+/// Fetch GCC source tarballs to a download cache directory.
+pub fn fetch_gcc_sources(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    fetch_records(logger, settings, &gcc_source_records(), cache_dir)
+}
+
+/// Fetch LLVM source tarballs to a download cache directory.
+///
+/// If [DownloadSettings::llvm_git] is set, an archive of that commit is used
+/// instead of release tarballs.
+pub fn fetch_llvm_sources(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    if let Some(source) = &settings.llvm_git {
+        let mut paths = fetch_records(logger, settings, &llvm_dependency_records(), cache_dir)?;
+        paths.push(crate::git::fetch_git_source(
+            logger,
+            &settings.proxy,
+            source,
+            "llvm-project",
+            cache_dir,
+        )?);
+
+        Ok(paths)
+    } else {
+        fetch_records(
+            logger,
+            settings,
+            &llvm_source_records(&settings.llvm_version)?,
+            cache_dir,
+        )
     }
+}
 
-    Ok(res)
+/// Fetch musl libc source tarballs to a download cache directory.
+pub fn fetch_musl_sources(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    fetch_records(logger, settings, &musl_source_records(), cache_dir)
+}
+
+/// Fetch the Linux kernel source tarball providing headers to a download cache directory.
+pub fn fetch_linux_headers(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    cache_dir: &Path,
+) -> Result<PathBuf> {
+    let mut paths = fetch_records(logger, settings, &[linux_headers_record()?], cache_dir)?;
+
+    Ok(paths.remove(0))
 }
 
-/// Fetch GCC source tarballs to the specified destination path.
-pub fn fetch_gcc_sources(logger: &Logger, dest_path: &Path) -> Result<Vec<PathBuf>> {
-    fetch_records(logger, &gcc_source_remote_contents(), dest_path)
+/// Records needed to build toolchains for a given architecture.
+///
+/// These are the GCC sources, the sources of the configured LLVM version, and
+/// support tools.
+pub fn required_records(
+    settings: &DownloadSettings,
+    arch: &str,
+) -> Result<Vec<&'static SoftwareRecord>> {
+    let mut records = gcc_source_records();
+    records.extend(llvm_source_records(&settings.llvm_version)?);
+    records.extend(support_linux_records(arch)?);
+
+    Ok(records)
+}
+
+/// Fetch artifacts needed as support files for Linux builds on a given architecture.
+pub fn fetch_linux_support(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    arch: &str,
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    fetch_records(logger, settings, &support_linux_records(arch)?, cache_dir)
 }
 
-/// Fetch LLVM source tarballs to the specified destination path.
-pub fn fetch_llvm_sources(logger: &Logger, dest_path: &Path) -> Result<Vec<PathBuf>> {
-    fetch_records(logger, &llvm_source_remote_contents(), dest_path)
+/// Fetch artifacts needed as support files for macOS builds on a given architecture.
+pub fn fetch_macos_support(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    arch: &str,
+    cache_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    fetch_records(logger, settings, &support_macos_records(arch)?, cache_dir)
 }
 
-/// Fetch artifacts needed as support files for Linux x86_64 builds.
-pub fn fetch_linux_x86_64_support(logger: &Logger, dest_path: &Path) -> Result<Vec<PathBuf>> {
-    fetch_records(logger, &support_linux_x86_64_remote_contents(), dest_path)
+/// Name of the summary written by [fetch_all].
+pub const FETCH_SUMMARY: &str = "fetch-summary.json";
+
+/// A file copied to a destination by [fetch_all].
+#[derive(Clone, Debug, Serialize)]
+pub struct FetchedFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Fetch GCC sources, LLVM sources, and support artifacts into `dest_dir`.
+///
+/// Each group is copied to a subdirectory of `dest_dir` named after it, and
+/// a [FETCH_SUMMARY] describing all files is written to `dest_dir`. With
+/// `parallel`, groups are fetched concurrently.
+pub fn fetch_all(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    os: &str,
+    arch: &str,
+    cache_dir: &Path,
+    dest_dir: &Path,
+    parallel: bool,
+) -> Result<BTreeMap<String, Vec<FetchedFile>>> {
+    type Fetcher<'a> = Box<dyn Fn() -> Result<Vec<PathBuf>> + Send + Sync + 'a>;
+
+    let fetchers: Vec<(&str, Fetcher)> = vec![
+        (
+            "gcc",
+            Box::new(|| {
+                fetch_gcc_sources(logger, settings, cache_dir).context("fetching GCC sources")
+            }),
+        ),
+        (
+            "llvm",
+            Box::new(|| {
+                fetch_llvm_sources(logger, settings, cache_dir).context("fetching LLVM sources")
+            }),
+        ),
+        (
+            "support",
+            Box::new(|| {
+                match os {
+                    "macos" => fetch_macos_support(logger, settings, arch, cache_dir),
+                    _ => fetch_linux_support(logger, settings, arch, cache_dir),
+                }
+                .context("fetching support artifacts")
+            }),
+        ),
+    ];
+
+    let results = if parallel {
+        std::thread::scope(|scope| {
+            let handles = fetchers
+                .iter()
+                .map(|(name, fetch)| (*name, scope.spawn(fetch)))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|(name, handle)| {
+                    let paths = handle
+                        .join()
+                        .map_err(|_| anyhow!("fetching {} panicked", name))?;
+
+                    Ok((name, paths))
+                })
+                .collect::<Result<Vec<_>>>()
+        })?
+    } else {
+        fetchers
+            .iter()
+            .map(|(name, fetch)| (*name, fetch()))
+            .collect()
+    };
+
+    let mut summary = BTreeMap::new();
+
+    for (name, paths) in results {
+        let dests = copy_to_dir(&paths?, &dest_dir.join(name))?;
+
+        let files = dests
+            .iter()
+            .map(|path| {
+                Ok(FetchedFile {
+                    name: path
+                        .file_name()
+                        .expect("path should have file name")
+                        .to_string_lossy()
+                        .to_string(),
+                    size: std::fs::metadata(path)?.len(),
+                    sha256: file_sha256(path)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        summary.insert(name.to_string(), files);
+    }
+
+    let path = dest_dir.join(FETCH_SUMMARY);
+    std::fs::write(&path, serde_json::to_vec_pretty(&summary)?)
+        .with_context(|| format!("writing {}", path.display()))?;
+    warn!(logger, "wrote {}", path.display());
+
+    Ok(summary)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn manifest() -> Result<()> {
+        let records = load_downloads(None)?;
+        assert_eq!(records["llvm"].name, "llvm");
+        assert!(records.contains_key("sccache-linux_x86_64"));
+
+        let urls = records["mpc"]
+            .remote_contents()
+            .into_iter()
+            .map(|c| c.url)
+            .collect::<Vec<_>>();
+        assert_eq!(urls[0], records["mpc"].url);
+        assert_eq!(urls[1..], records["mpc"].mirrors[..]);
+
+        let td = tempfile::TempDir::new()?;
+        let path = td.path().join("downloads.toml");
+        std::fs::write(
+            &path,
+            "[llvm]\nversion = \"0.0.1\"\nurl = \"https://example.com/llvm.tar.xz\"\n\
+             sha256 = \"0000000000000000000000000000000000000000000000000000000000000000\"\n",
+        )?;
+
+        let records = load_downloads(Some(&path))?;
+        assert_eq!(records["llvm"].version, "0.0.1");
+        assert_eq!(records["clang"].version, "13.0.0");
+
+        std::fs::write(&path, "[llvm]\nversion = \"0.0.1\"\n")?;
+        assert!(load_downloads(Some(&path)).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn proxy_envs() {
+        let mut proxy = ProxySettings {
+            no_proxy: Some("localhost".to_string()),
+            ..Default::default()
+        };
+        assert!(proxy.envs().is_empty());
+
+        proxy.https = Some("socks5://127.0.0.1:1080".to_string());
+        assert_eq!(
+            proxy.envs(),
+            vec![
+                (
+                    "HTTPS_PROXY".to_string(),
+                    "socks5://127.0.0.1:1080".to_string()
+                ),
+                (
+                    "https_proxy".to_string(),
+                    "socks5://127.0.0.1:1080".to_string()
+                ),
+                ("NO_PROXY".to_string(), "localhost".to_string()),
+                ("no_proxy".to_string(), "localhost".to_string()),
+            ]
+        );
+        assert!(proxy.apply(Client::builder()).is_ok());
+
+        proxy.http = Some("not a url".to_string());
+        assert!(proxy.apply(Client::builder()).is_err());
+    }
+
+    #[test]
+    fn local_source() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let td = tempfile::TempDir::new()?;
+        let source = td.path().join("source.tar.gz");
+        std::fs::write(&source, b"data")?;
+        let client = DownloadSettings::default().client()?;
+
+        let mut content = RemoteContent {
+            name: "data".to_string(),
+            url: format!("file://{}", source.display()),
+            sha256: hex::encode(sha2::Sha256::digest(b"data")),
+        };
+        let dest = td.path().join("dest.tar.gz");
+        download_to_path(&logger, &client, None, &content, &dest)?;
+        assert_eq!(std::fs::read(&dest)?, b"data");
+
+        content.url = source.display().to_string();
+        content.sha256 = "0".repeat(64);
+        assert!(
+            download_to_path(&logger, &client, None, &content, &td.path().join("bad")).is_err()
+        );
+        assert!(!with_suffix(&td.path().join("bad"), "partial").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn resume_download() -> Result<()> {
+        use std::io::{BufRead, BufReader, Write};
+
+        let data = b"0123456789abcdef".to_vec();
+        let content_data = data.clone();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/data", listener.local_addr()?);
+
+        // Serve the requested range of the data once.
+        let server = std::thread::spawn(move || -> Result<Option<String>> {
+            let (stream, _) = listener.accept()?;
+            let mut reader = BufReader::new(stream.try_clone()?);
+            let mut range = None;
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                    range = Some(value.to_string());
+                }
+            }
+
+            let start = range
+                .as_deref()
+                .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok())
+                .unwrap_or(0);
+            let body = &content_data[start..];
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                if start > 0 {
+                    "206 Partial Content"
+                } else {
+                    "200 OK"
+                },
+                body.len()
+            )?;
+            stream.write_all(body)?;
+
+            Ok(range)
+        });
+
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let td = tempfile::TempDir::new()?;
+        let dest = td.path().join("data");
+        std::fs::write(with_suffix(&dest, "partial"), &data[0..6])?;
+
+        let content = RemoteContent {
+            name: "data".to_string(),
+            url,
+            sha256: hex::encode(sha2::Sha256::digest(&data)),
+        };
+        download_to_path(
+            &logger,
+            &DownloadSettings::default().client()?,
+            Some(&RateLimiter::new(NonZeroU64::new(1 << 20).unwrap())),
+            &content,
+            &dest,
+        )?;
+
+        assert_eq!(
+            server.join().expect("server should not panic")?.as_deref(),
+            Some("6-")
+        );
+        assert_eq!(std::fs::read(&dest)?, data);
+        assert!(!with_suffix(&dest, "partial").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn llvm_version_sets() -> Result<()> {
+        assert!(llvm_versions().contains(&DEFAULT_LLVM_VERSION.to_string()));
+        assert_eq!(llvm_source_records(DEFAULT_LLVM_VERSION)?.len(), 11);
+        assert!(llvm_source_records("1").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn prune() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let td = tempfile::TempDir::new()?;
+        let records = load_downloads(None)?;
+        let current = cache_path(td.path(), &records["llvm"]);
+        let stale_dir = td.path().join("0".repeat(64));
+
+        std::fs::create_dir_all(current.parent().unwrap())?;
+        std::fs::create_dir_all(&stale_dir)?;
+        std::fs::create_dir(td.path().join("images"))?;
+
+        for path in [
+            current.clone(),
+            with_suffix(&current, "lock"),
+            stale_dir.join("llvm-12.0.0.src.tar.xz"),
+            stale_dir.join("llvm-12.0.0.src.tar.xz.sig"),
+            td.path().join("llvm-12.0.0.src.tar.xz"),
+            td.path().join("notes.txt"),
+        ] {
+            std::fs::write(path, b"data")?;
+        }
+
+        assert_eq!(
+            prune_downloads(&logger, records.values(), td.path(), true)?,
+            16
+        );
+        assert!(stale_dir.exists());
+
+        assert_eq!(
+            prune_downloads(&logger, records.values(), td.path(), false)?,
+            16
+        );
+        assert!(current.exists());
+        assert!(!with_suffix(&current, "lock").exists());
+        assert!(!stale_dir.exists());
+        assert!(!td.path().join("llvm-12.0.0.src.tar.xz").exists());
+        assert!(td.path().join("notes.txt").exists());
+        assert!(td.path().join("images").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sha256sums() -> Result<()> {
+        let td = tempfile::TempDir::new()?;
+        let source_dir = td.path().join("source");
+        let dest_dir = td.path().join("dest");
+        std::fs::create_dir(&source_dir)?;
+        std::fs::create_dir(&dest_dir)?;
+        std::fs::write(source_dir.join("a.tar.gz"), b"a")?;
+        std::fs::write(source_dir.join("b.tar.gz"), b"b")?;
+        std::fs::write(
+            dest_dir.join(SHA256SUMS),
+            format!(
+                "{}  a.tar.gz\n{}  old.tar.gz\n",
+                "0".repeat(64),
+                "1".repeat(64)
+            ),
+        )?;
+
+        copy_to_dir(
+            &[source_dir.join("a.tar.gz"), source_dir.join("b.tar.gz")],
+            &dest_dir,
+        )?;
+
+        assert_eq!(
+            std::fs::read_to_string(dest_dir.join(SHA256SUMS))?,
+            format!(
+                "{}  a.tar.gz\n{}  b.tar.gz\n{}  old.tar.gz\n",
+                hex::encode(sha2::Sha256::digest(b"a")),
+                hex::encode(sha2::Sha256::digest(b"b")),
+                "1".repeat(64)
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn verify() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let td = tempfile::TempDir::new()?;
+
+        let good_dir = td.path().join(hex::encode(sha2::Sha256::digest(b"good")));
+        let bad_dir = td.path().join(hex::encode(sha2::Sha256::digest(b"bad")));
+        std::fs::create_dir_all(&good_dir)?;
+        std::fs::create_dir_all(&bad_dir)?;
+        std::fs::write(good_dir.join("good.tar.gz"), b"good")?;
+        std::fs::write(good_dir.join("good.tar.gz.sig"), b"sig")?;
+        std::fs::write(bad_dir.join("bad.tar.gz"), b"ba")?;
+        std::fs::write(bad_dir.join("other.tar.gz.partial"), b"o")?;
+
+        let res = verify_downloads(&logger, std::iter::empty(), td.path(), false)?;
+        assert_eq!(res.valid, 1);
+        assert_eq!(res.corrupt, vec![bad_dir.join("bad.tar.gz")]);
+        assert_eq!(res.partial.len(), 1);
+
+        // BLAKE3 digests of records take precedence.
+        let record = SoftwareRecord {
+            name: "good".to_string(),
+            version: "1.0".to_string(),
+            url: "https://example.com/good.tar.gz".to_string(),
+            mirrors: vec![],
+            signature: None,
+            sha256: hex::encode(sha2::Sha256::digest(b"good")),
+            blake3: Some(blake3::hash(b"good").to_hex().to_string()),
+        };
+        assert!(record.verify_file(&good_dir.join("good.tar.gz"))?);
+        let res = verify_downloads(&logger, [&record].into_iter(), td.path(), false)?;
+        assert_eq!(res.valid, 1);
+
+        verify_downloads(&logger, std::iter::empty(), td.path(), true)?;
+        assert!(!bad_dir.join("bad.tar.gz").exists());
+        assert!(good_dir.join("good.tar.gz").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn source_bundle() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let source_dir = tempfile::TempDir::new()?;
+        let dest_dir = tempfile::TempDir::new()?;
+
+        let record = SoftwareRecord {
+            name: "foo".to_string(),
+            version: "1.0".to_string(),
+            url: "https://example.com/foo-1.0.tar.gz".to_string(),
+            mirrors: vec![],
+            signature: None,
+            sha256: hex::encode(sha2::Sha256::digest(b"foo")),
+            blake3: None,
+        };
+        let path = cache_path(source_dir.path(), &record);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, b"foo")?;
+        std::fs::write(with_suffix(&path, "sig"), b"sig")?;
+
+        let mut bundle = vec![];
+        write_source_bundle(&[&record], source_dir.path(), &mut bundle)?;
+
+        let imported = import_source_bundle(&logger, bundle.as_slice(), dest_dir.path())?;
+        assert_eq!(imported.len(), 2);
+        assert_eq!(std::fs::read(cache_path(dest_dir.path(), &record))?, b"foo");
+
+        // Importing again is a no-op.
+        assert!(import_source_bundle(&logger, bundle.as_slice(), dest_dir.path())?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn offline() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let offline_dir = tempfile::TempDir::new()?;
+        let dest_dir = tempfile::TempDir::new()?;
+
+        let record = |name: &str, data: &[u8]| SoftwareRecord {
+            name: name.to_string(),
+            version: "1.0".to_string(),
+            url: format!("https://example.com/{}.tar.gz", name),
+            mirrors: vec![],
+            signature: None,
+            sha256: hex::encode(sha2::Sha256::digest(data)),
+            blake3: None,
+        };
+        let present = record("present", b"present");
+        let missing = record("missing", b"missing");
+        let corrupt = record("corrupt", b"corrupt");
+        std::fs::write(offline_dir.path().join("present.tar.gz"), b"present")?;
+        std::fs::write(offline_dir.path().join("corrupt.tar.gz"), b"wrong")?;
+
+        let settings = DownloadSettings {
+            offline_dir: Some(offline_dir.path().to_path_buf()),
+            ..Default::default()
+        };
+
+        let paths = fetch_records(&logger, &settings, &[&present], dest_dir.path())?;
+        assert_eq!(std::fs::read(&paths[0])?, b"present");
+
+        let err = fetch_records(
+            &logger,
+            &settings,
+            &[&present, &missing, &corrupt],
+            dest_dir.path(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("2 files are missing or invalid"));
+        assert!(err.contains("missing: "));
+        assert!(err.contains("corrupt: "));
+
+        Ok(())
+    }
+
     #[test]
     fn gcc_source_downloads() -> Result<()> {
-        let logger = crate::logging::logger();
+        let logger = crate::logging::logger(slog::Level::Info);
         let td = tempfile::TempDir::new()?;
 
-        fetch_gcc_sources(&logger, td.path())?;
+        fetch_gcc_sources(&logger, &DownloadSettings::default(), td.path())?;
 
         Ok(())
     }
 
     #[test]
     fn llvm_source_download() -> Result<()> {
-        let logger = crate::logging::logger();
+        let logger = crate::logging::logger(slog::Level::Info);
         let td = tempfile::TempDir::new()?;
 
-        fetch_llvm_sources(&logger, td.path())?;
+        fetch_llvm_sources(&logger, &DownloadSettings::default(), td.path())?;
 
         Ok(())
     }
@@ -0,0 +1,274 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Safe extraction of tar.zst artifacts.
+
+Artifacts may come from remote caches and registries, so extraction must not
+trust their content. [extract_tar_zst] refuses entries which would write
+outside the destination directory, either directly via absolute or `..`
+paths or indirectly via symlinks and hard links pointing outside it.
+Permissions are normalized to `0644` or `0755` so archives can't create
+setuid or world writable files.
+*/
+
+use {
+    anyhow::{anyhow, Context, Result},
+    std::{
+        io::Read,
+        path::{Component, Path, PathBuf},
+    },
+};
+
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::{symlink, PermissionsExt};
+#[cfg(windows)]
+use std::os::windows::fs::symlink_file as symlink;
+
+/// Resolve an archive path to a normalized relative path.
+///
+/// `.` components are dropped. Errors if the path is absolute or contains
+/// `..` components.
+fn safe_relative_path(path: &Path) -> Result<PathBuf> {
+    let mut res = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => res.push(name),
+            Component::CurDir => {}
+            _ => return Err(anyhow!("unsafe path in archive: {}", path.display())),
+        }
+    }
+
+    Ok(res)
+}
+
+/// Ensure a symlink at `path` pointing to `target` resolves within the root.
+///
+/// Both are resolved lexically, which is sound because every symlink
+/// extracted before has been validated the same way.
+fn validate_symlink(path: &Path, target: &Path) -> Result<()> {
+    let mut resolved = path
+        .parent()
+        .map(|parent| parent.components().collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop().is_some() => {}
+            _ => {
+                return Err(anyhow!(
+                    "symlink {} points outside of destination: {}",
+                    path.display(),
+                    target.display()
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Normalize permissions of an extracted file from its archive mode.
+#[cfg(target_family = "unix")]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    let mode = if mode & 0o111 != 0 { 0o755 } else { 0o644 };
+
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("setting permissions of {}", path.display()))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Extract a tar archive into a directory.
+///
+/// Only entries whose normalized path satisfies `filter` are extracted.
+/// Returns the number of extracted entries.
+pub fn extract_tar(
+    reader: impl Read,
+    dest_dir: &Path,
+    filter: impl Fn(&Path) -> bool,
+) -> Result<usize> {
+    let mut archive = tar::Archive::new(reader);
+    let mut count = 0;
+
+    std::fs::create_dir_all(dest_dir)
+        .with_context(|| format!("creating {}", dest_dir.display()))?;
+
+    for entry in archive.entries().context("reading tar archive")? {
+        let mut entry = entry?;
+        let path = safe_relative_path(&entry.path()?)?;
+
+        if path.as_os_str().is_empty() || !filter(&path) {
+            continue;
+        }
+
+        let dest_path = dest_dir.join(&path);
+        if let Some(parent) = dest_path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+
+        // Replacing an existing entry must not write through it.
+        if !entry.header().entry_type().is_dir() && dest_path.symlink_metadata().is_ok() {
+            std::fs::remove_file(&dest_path)
+                .with_context(|| format!("removing {}", dest_path.display()))?;
+        }
+
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => {
+                std::fs::create_dir_all(&dest_path)
+                    .with_context(|| format!("creating {}", dest_path.display()))?;
+            }
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let mode = entry.header().mode()?;
+                let mut fh = std::fs::File::create(&dest_path)
+                    .with_context(|| format!("creating {}", dest_path.display()))?;
+                std::io::copy(&mut entry, &mut fh)
+                    .with_context(|| format!("extracting {}", path.display()))?;
+                set_mode(&dest_path, mode)?;
+            }
+            tar::EntryType::Symlink => {
+                let target = entry
+                    .link_name()?
+                    .ok_or_else(|| anyhow!("symlink {} has no target", path.display()))?
+                    .to_path_buf();
+                validate_symlink(&path, &target)?;
+
+                symlink(&target, &dest_path)
+                    .with_context(|| format!("creating symlink {}", path.display()))?;
+            }
+            tar::EntryType::Link => {
+                let target = entry
+                    .link_name()?
+                    .ok_or_else(|| anyhow!("hard link {} has no target", path.display()))?;
+                let target = dest_dir.join(safe_relative_path(&target)?);
+
+                std::fs::hard_link(&target, &dest_path)
+                    .with_context(|| format!("creating hard link {}", path.display()))?;
+            }
+            entry_type => {
+                return Err(anyhow!(
+                    "unsupported entry type in archive: {} is {:?}",
+                    path.display(),
+                    entry_type
+                ));
+            }
+        }
+
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Extract a zstd compressed tar archive into a directory.
+///
+/// See [extract_tar].
+pub fn extract_tar_zst(
+    path: &Path,
+    dest_dir: &Path,
+    filter: impl Fn(&Path) -> bool,
+) -> Result<usize> {
+    let fh = std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let stream = zstd::stream::Decoder::new(fh).context("creating zstd decompressor")?;
+
+    extract_tar(stream, dest_dir, filter).with_context(|| format!("extracting {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Build a tar archive with entries whose paths bypass validation.
+    fn raw_archive(entries: &[(&str, tar::EntryType, &str, u32)]) -> Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(vec![]);
+
+        for (path, entry_type, content, mode) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            header.set_entry_type(*entry_type);
+            header.set_mode(*mode);
+
+            if entry_type.is_symlink() || entry_type.is_hard_link() {
+                header.as_old_mut().linkname[..content.len()].copy_from_slice(content.as_bytes());
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, std::io::empty())?;
+            } else {
+                header.set_size(content.len() as _);
+                header.set_cksum();
+                builder.append(&header, content.as_bytes())?;
+            }
+        }
+
+        Ok(builder.into_inner()?)
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn extract() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dest_dir = temp_dir.path().join("out");
+
+        let data = raw_archive(&[
+            (
+                "./lib/libc.so.6",
+                tar::EntryType::Regular,
+                "\x7fELF",
+                0o4777,
+            ),
+            ("lib/libc.so", tar::EntryType::Symlink, "libc.so.6", 0o777),
+            (
+                "lib/libc.so.copy",
+                tar::EntryType::Link,
+                "lib/libc.so.6",
+                0o644,
+            ),
+            ("share/doc", tar::EntryType::Regular, "doc", 0o666),
+        ])?;
+        let count = extract_tar(data.as_slice(), &dest_dir, |path| path.starts_with("lib"))?;
+
+        assert_eq!(count, 3);
+        let metadata = std::fs::metadata(dest_dir.join("lib/libc.so.6"))?;
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+        assert_eq!(std::fs::read(dest_dir.join("lib/libc.so"))?, b"\x7fELF");
+        assert_eq!(
+            std::fs::read(dest_dir.join("lib/libc.so.copy"))?,
+            b"\x7fELF"
+        );
+        assert!(!dest_dir.join("share").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsafe_entries() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let dest_dir = temp_dir.path().join("out");
+
+        for entry in [
+            ("../evil", tar::EntryType::Regular, "evil", 0o644),
+            ("/evil", tar::EntryType::Regular, "evil", 0o644),
+            ("lib/evil", tar::EntryType::Symlink, "../../evil", 0o777),
+            ("lib/evil", tar::EntryType::Symlink, "/etc/passwd", 0o777),
+            ("lib/evil", tar::EntryType::Link, "../evil", 0o644),
+        ] {
+            let data = raw_archive(&[entry])?;
+            assert!(extract_tar(data.as_slice(), &dest_dir, |_| true).is_err());
+        }
+
+        assert!(!temp_dir.path().join("evil").exists());
+
+        // Symlinks within the destination are fine.
+        let data = raw_archive(&[("lib/ok", tar::EntryType::Symlink, "../share/ok", 0o777)])?;
+        assert_eq!(extract_tar(data.as_slice(), &dest_dir, |_| true)?, 1);
+
+        Ok(())
+    }
+}
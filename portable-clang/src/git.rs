@@ -0,0 +1,165 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Source trees from git repositories.
+
+Instead of release tarballs, toolchains can be built from a commit of a git
+repository, such as LLVM trunk or a vendor branch. Commits are pinned by
+their full hash so builds are reproducible.
+
+The commit is shallow fetched into a bare repository in the download cache
+and exported to a tar archive, which is added to the build context like a
+release tarball. This uses the `git` executable, as libgit2 can't perform
+shallow fetches. Servers must allow fetching commits by hash, as GitHub does.
+*/
+
+use {
+    crate::downloads::ProxySettings,
+    anyhow::{anyhow, Context, Result},
+    fs2::FileExt,
+    slog::{info, warn, Logger},
+    std::{
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+    },
+};
+
+/// A commit of a git repository.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GitSource {
+    pub url: String,
+    pub commit: String,
+}
+
+impl GitSource {
+    /// Parse a `<url>#<commit>` string.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (url, commit) = value
+            .rsplit_once('#')
+            .ok_or_else(|| anyhow!("git source {} is not of the form <url>#<commit>", value))?;
+
+        if commit.len() != 40 || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow!(
+                "git source commit {} is not a full commit hash",
+                commit
+            ));
+        }
+
+        Ok(Self {
+            url: url.to_string(),
+            commit: commit.to_lowercase(),
+        })
+    }
+}
+
+/// A git command operating on a bare repository.
+fn git(repo_path: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("--git-dir").arg(repo_path);
+
+    command
+}
+
+fn run(mut command: Command) -> Result<()> {
+    let output = command.output().context("running git; is it installed?")?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "git failed:\n{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ))
+    }
+}
+
+/// Export the source tree of a commit to a tar archive in `cache_dir`.
+///
+/// Entries in the archive are under a `<name>-<commit>/` directory, matching
+/// the layout of release tarballs. The archive is reused if it exists.
+/// Commits are fetched through `proxy`.
+pub fn fetch_git_source(
+    logger: &Logger,
+    proxy: &ProxySettings,
+    source: &GitSource,
+    name: &str,
+    cache_dir: &Path,
+) -> Result<PathBuf> {
+    let git_dir = cache_dir.join("git");
+    std::fs::create_dir_all(&git_dir).context("creating git directory")?;
+
+    let repo_path = git_dir.join(format!("{}.git", name));
+    let archive_path = git_dir.join(format!("{}-{}.src.tar", name, source.commit));
+
+    let lock_path = git_dir.join(format!("{}.lock", name));
+    let lock = std::fs::File::create(&lock_path)
+        .with_context(|| format!("opening {}", lock_path.display()))?;
+    lock.lock_exclusive()
+        .with_context(|| format!("locking {}", lock_path.display()))?;
+
+    if archive_path.exists() {
+        return Ok(archive_path);
+    }
+
+    if !repo_path.exists() {
+        let mut command = Command::new("git");
+        command.arg("init").arg("--bare").arg("-q").arg(&repo_path);
+        run(command).context("initializing git repository")?;
+    }
+
+    let mut command = git(&repo_path);
+    command
+        .args(["cat-file", "-e"])
+        .arg(format!("{}^{{commit}}", source.commit));
+
+    if run(command).is_err() {
+        warn!(logger, "fetching {} from {}", source.commit, source.url);
+        let mut command = git(&repo_path);
+        command
+            .envs(proxy.envs())
+            .args(["fetch", "-q", "--depth", "1"])
+            .arg(&source.url)
+            .arg(&source.commit);
+        run(command).with_context(|| format!("fetching {} from {}", source.commit, source.url))?;
+    }
+
+    info!(
+        logger,
+        "exporting {} to {}",
+        source.commit,
+        archive_path.display()
+    );
+    let temp = tempfile::NamedTempFile::new_in(&git_dir).context("creating archive file")?;
+    let mut command = git(&repo_path);
+    command
+        .args(["archive", "--format=tar"])
+        .arg(format!("--prefix={}-{}/", name, source.commit))
+        .arg(&source.commit)
+        .stdout(Stdio::from(temp.reopen()?));
+    run(command).context("exporting git archive")?;
+
+    temp.persist(&archive_path)
+        .context("persisting git archive")?;
+
+    Ok(archive_path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let commit = "0123456789abcdef0123456789ABCDEF01234567";
+
+        let source = GitSource::parse(&format!("git@github.com:llvm/llvm-project.git#{}", commit))?;
+        assert_eq!(source.url, "git@github.com:llvm/llvm-project.git");
+        assert_eq!(source.commit, commit.to_lowercase());
+
+        assert!(GitSource::parse("https://github.com/llvm/llvm-project.git").is_err());
+        assert!(GitSource::parse("https://github.com/llvm/llvm-project.git#main").is_err());
+
+        Ok(())
+    }
+}
@@ -3,18 +3,27 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
+    crate::{docker::ZstdSettings, glibc_abi::GlibcVersion},
     anyhow::{anyhow, Context, Result},
+    globset::{GlobBuilder, GlobSet, GlobSetBuilder},
+    serde::{Deserialize, Serialize},
     sha2::Digest,
     slog::{info, warn, Logger},
     std::{
         collections::{BTreeMap, BTreeSet},
+        io::{Read, Write},
         path::{Path, PathBuf},
+        str::FromStr,
+        time::SystemTime,
     },
 };
 
 #[cfg(target_family = "unix")]
 use std::os::unix::fs::{symlink, PermissionsExt};
+#[cfg(windows)]
+use std::os::windows::fs::symlink_file as symlink;
 
+#[cfg(target_family = "unix")]
 fn normalize_file(path: &Path) -> Result<()> {
     let metadata = std::fs::metadata(path)?;
 
@@ -27,35 +36,415 @@ fn normalize_file(path: &Path) -> Result<()> {
     Ok(())
 }
 
-/// Unify directories containing glibc builds.
+#[cfg(not(target_family = "unix"))]
+fn normalize_file(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// The owner executable bit of a file's mode.
+#[cfg(target_family = "unix")]
+fn owner_executable_bit(metadata: &std::fs::Metadata) -> u32 {
+    metadata.permissions().mode() & 0o100
+}
+
+#[cfg(not(target_family = "unix"))]
+fn owner_executable_bit(_metadata: &std::fs::Metadata) -> u32 {
+    0
+}
+
+/// How [unify_glibc] materializes files duplicated between glibc builds.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DuplicateMode {
+    /// Store each unique file once and symlink duplicates to it.
+    #[default]
+    Symlink,
+
+    /// Copy every file, so the output contains no symlinks.
+    ///
+    /// Uses much more space, but works on filesystems and with tools that
+    /// don't support symlinks, such as on Windows.
+    Copy,
+}
+
+impl FromStr for DuplicateMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "symlink" => Ok(Self::Symlink),
+            "copy" => Ok(Self::Copy),
+            _ => Err(anyhow!("unsupported duplicate mode: {}", s)),
+        }
+    }
+}
+
+/// Selects glibc builds by the name of their directory.
 ///
-/// [source_dir] contains sub-directories containing individual builds of glibc.
+/// Directories are named after glibc configurations, such as
+/// `x86_64-linux-gnu`. Patterns may contain `*` wildcards, as in `x86_64-*`.
+#[derive(Clone, Debug, Default)]
+pub struct BuildFilter {
+    /// Builds to select. All builds are selected if empty.
+    pub include: Vec<String>,
+    /// Builds to skip, even if included.
+    pub exclude: Vec<String>,
+}
+
+impl BuildFilter {
+    /// Whether a build is selected.
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| wildcard_match(p, name)))
+            && !self.exclude.iter().any(|p| wildcard_match(p, name))
+    }
+}
+
+/// Whether `name` matches a pattern in which `*` matches any characters.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => match name.strip_prefix(prefix) {
+            Some(name) => (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| wildcard_match(rest, &name[i..])),
+            None => false,
+        },
+    }
+}
+
+/// Selects files by glob patterns on their path within a glibc build.
 ///
-/// We scan each individual glibc source directory and record the files that
-/// we've seen.
+/// `*` doesn't match `/`, while `**` matches any number of directories, as in
+/// `usr/include/**` or `**/crt*.o`.
+#[derive(Clone, Debug)]
+pub struct FileFilter {
+    /// Files to select. All files are selected if `None`.
+    include: Option<GlobSet>,
+    /// Files to skip, even if included.
+    exclude: GlobSet,
+}
+
+impl Default for FileFilter {
+    fn default() -> Self {
+        Self {
+            include: None,
+            exclude: GlobSet::empty(),
+        }
+    }
+}
+
+impl FileFilter {
+    pub fn new(include: &[impl AsRef<str>], exclude: &[impl AsRef<str>]) -> Result<Self> {
+        Ok(Self {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(glob_set(include)?)
+            },
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    /// Whether a file is selected.
+    pub fn matches(&self, path: &Path) -> bool {
+        self.include.as_ref().is_none_or(|set| set.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
+
+fn glob_set(patterns: &[impl AsRef<str>]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        let pattern = pattern.as_ref();
+        builder.add(
+            GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .with_context(|| format!("parsing glob pattern {}", pattern))?,
+        );
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Algorithm of the digests identifying duplicate files.
 ///
-/// The source directories and files are rematerialized in [dest_dir] except
-/// that duplicate files are normalized to symlinks to files in a shared location.
-/// This ensures that each unique file is written exactly once.
-pub fn unify_glibc(
+/// Deduplication doesn't rely on collision resistance against adversaries,
+/// so the faster non-cryptographic XXH3 is suitable.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    /// 128-bit XXH3.
+    Xxh3,
+}
+
+impl FromStr for DigestAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            "xxh3" => Ok(Self::Xxh3),
+            _ => Err(anyhow!("unsupported digest algorithm: {}", s)),
+        }
+    }
+}
+
+/// Settings controlling how glibc builds are unified.
+#[derive(Clone, Debug, Default)]
+pub struct UnifySettings {
+    /// Files within builds to unify.
+    pub files: FileFilter,
+
+    /// How files duplicated between builds are materialized.
+    pub duplicates: DuplicateMode,
+
+    /// Builds to unify.
+    pub filter: BuildFilter,
+
+    /// Algorithm of the digests identifying duplicate files.
+    pub digest: DigestAlgorithm,
+
+    /// Oldest glibc release of builds to unify.
+    pub min_glibc: Option<GlibcVersion>,
+
+    /// Newest glibc release of builds to unify.
+    pub max_glibc: Option<GlibcVersion>,
+}
+
+impl UnifySettings {
+    fn has_glibc_range(&self) -> bool {
+        self.min_glibc.is_some() || self.max_glibc.is_some()
+    }
+
+    fn glibc_in_range(&self, version: &GlibcVersion) -> bool {
+        self.min_glibc.as_ref().is_none_or(|min| version >= min)
+            && self.max_glibc.as_ref().is_none_or(|max| version <= max)
+    }
+}
+
+/// Resolve the glibc release of a build from its `features.h`.
+///
+/// Returns `None` if the build has no `features.h`.
+fn build_glibc_version(build_dir: &Path) -> Result<Option<GlibcVersion>> {
+    let path = build_dir.join("usr").join("include").join("features.h");
+
+    let data = match std::fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+
+    let mut major = None;
+    let mut minor = None;
+
+    for line in data.lines() {
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["#define", "__GLIBC__", value] => major = Some(*value),
+            ["#define", "__GLIBC_MINOR__", value] => minor = Some(*value),
+            _ => {}
+        }
+    }
+
+    match (major, minor) {
+        (Some(major), Some(minor)) => Ok(Some(format!("{}.{}", major, minor).parse()?)),
+        _ => Err(anyhow!("glibc version not defined in {}", path.display())),
+    }
+}
+
+/// Resolve the builds in [source_dir] selected by [settings].
+///
+/// Returns build names and their glibc releases, if known. When a release
+/// range is configured, builds of unknown releases are not selected.
+fn select_builds(
     logger: &Logger,
     source_dir: &Path,
-    dest_dir: &Path,
-    headers_only: bool,
-) -> Result<()> {
-    let mut input_dirs = vec![];
+    settings: &UnifySettings,
+) -> Result<BTreeMap<String, Option<GlibcVersion>>> {
+    let mut builds = BTreeMap::new();
 
     for entry in std::fs::read_dir(source_dir)? {
         let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if !entry.metadata()?.is_dir() {
+            continue;
+        }
 
-        if entry.metadata()?.is_dir() {
-            input_dirs.push(entry.path());
+        if !settings.filter.matches(&name) {
+            info!(logger, "skipping {}", entry.path().display());
+            continue;
         }
+
+        let version = build_glibc_version(&entry.path())?;
+
+        if settings.has_glibc_range() {
+            match &version {
+                Some(version) if settings.glibc_in_range(version) => {}
+                Some(version) => {
+                    warn!(logger, "skipping {} (glibc {})", name, version);
+                    continue;
+                }
+                None => {
+                    warn!(logger, "skipping {} (unknown glibc version)", name);
+                    continue;
+                }
+            }
+        }
+
+        builds.insert(name, version);
+    }
+
+    Ok(builds)
+}
+
+/// Compute the digest identifying a file in unified output.
+fn file_digest(path: &Path, executable_bit: u32, algorithm: DigestAlgorithm) -> Result<String> {
+    let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+    // Feed the executable bit into the digest to distinguish between
+    // output file modes.
+    let salt = format!("{}", executable_bit);
+
+    Ok(match algorithm {
+        DigestAlgorithm::Sha256 => {
+            let mut h = sha2::Sha256::new();
+            h.update(salt);
+            h.update(&data);
+
+            hex::encode(h.finalize().as_slice())
+        }
+        DigestAlgorithm::Blake3 => {
+            let mut h = blake3::Hasher::new();
+            h.update(salt.as_bytes());
+            h.update(&data);
+
+            h.finalize().to_hex().to_string()
+        }
+        DigestAlgorithm::Xxh3 => {
+            let mut h = xxhash_rust::xxh3::Xxh3::new();
+            h.update(salt.as_bytes());
+            h.update(&data);
+
+            format!("{:032x}", h.digest128())
+        }
+    })
+}
+
+/// A previously computed file digest.
+#[derive(Deserialize, Serialize)]
+struct IndexedFile {
+    size: u64,
+    mtime: SystemTime,
+    executable_bit: u32,
+    digest: String,
+}
+
+/// Digests of files from a previous run of [unify_glibc].
+///
+/// Files are identified by their path relative to the source directory, size,
+/// modification time, and executable bit. Files matching an entry aren't hashed again.
+#[derive(Default, Deserialize, Serialize)]
+struct DigestIndex {
+    algorithm: DigestAlgorithm,
+    files: BTreeMap<PathBuf, IndexedFile>,
+}
+
+impl DigestIndex {
+    /// Load an index, falling back to an empty one if it is missing or unreadable.
+    fn load(logger: &Logger, path: &Path) -> Self {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(_) => return Self::default(),
+        };
+
+        serde_json::from_slice(&data).unwrap_or_else(|e| {
+            warn!(logger, "ignoring unreadable {}: {}", path.display(), e);
+            Self::default()
+        })
     }
 
-    input_dirs.sort();
+    fn save(&self, path: &Path) -> Result<()> {
+        let parent = path
+            .parent()
+            .ok_or_else(|| anyhow!("failed to resolve parent directory"))?;
+        std::fs::create_dir_all(parent)?;
+
+        let mut temp = tempfile::NamedTempFile::new_in(parent)?;
+        serde_json::to_writer(&mut temp, self)?;
+        temp.persist(path)
+            .with_context(|| format!("writing {}", path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Summary of the output of [unify_glibc].
+#[derive(Debug, Default, Serialize)]
+pub struct UnifySummary {
+    /// Number of regular files in the glibc builds.
+    pub input_files: usize,
+    /// Total size of regular files in the glibc builds.
+    pub input_bytes: u64,
+    /// Number of files written as is, excluding common files.
+    pub copied_files: usize,
+    /// Number of files written as symlinks to common files.
+    pub symlinked_files: usize,
+    /// Number of common files that duplicates are symlinked to.
+    pub common_files: usize,
+    /// Number of symlinks in the glibc builds, which are written as is.
+    pub preserved_symlinks: usize,
+    /// Oldest glibc release of the unified builds, if known.
+    pub min_glibc: Option<String>,
+    /// Newest glibc release of the unified builds, if known.
+    pub max_glibc: Option<String>,
+    /// Size of file content not written due to deduplication.
+    pub bytes_saved: u64,
+    /// Paths of deduplicated files and the common files they link to.
+    pub deduplicated: BTreeMap<PathBuf, PathBuf>,
+}
+
+/// A file in unified output.
+enum UnifiedEntry {
+    /// A copy of a file in the source directory.
+    File(PathBuf),
+
+    /// A symlink to a common file or a symlink from the source directory.
+    Symlink(PathBuf),
+}
+
+/// Index glibc builds and plan the unified output.
+///
+/// Returns entries keyed by their path in the output and a summary of them.
+/// Digests in `index` are reused and it is replaced with the digests of the
+/// indexed files.
+fn plan_unified(
+    logger: &Logger,
+    source_dir: &Path,
+    settings: &UnifySettings,
+    index: &mut DigestIndex,
+) -> Result<(BTreeMap<PathBuf, UnifiedEntry>, UnifySummary)> {
+    let builds = select_builds(logger, source_dir, settings)?;
+    let input_dirs = builds.keys().map(|build| source_dir.join(build));
 
     let mut digests = BTreeMap::<String, BTreeSet<PathBuf>>::new();
+    let mut sizes = BTreeMap::<String, u64>::new();
+    let mut entries = BTreeMap::new();
+    let mut summary = UnifySummary::default();
+    let versions = builds.values().flatten();
+    summary.min_glibc = versions.clone().min().map(|v| v.to_string());
+    summary.max_glibc = versions.max().map(|v| v.to_string());
+    // Digests computed with another algorithm can't be reused.
+    let previous = if index.algorithm == settings.digest {
+        std::mem::take(&mut index.files)
+    } else {
+        index.files.clear();
+        index.algorithm = settings.digest;
+        BTreeMap::new()
+    };
+    let mut hashed_count = 0;
 
     for input_dir in input_dirs {
         warn!(logger, "indexing {}", input_dir.display());
@@ -71,100 +460,107 @@ pub fn unify_glibc(
                 continue;
             }
 
-            if headers_only {
-                if let Some(ext) = relative_path.extension() {
-                    if ext != "h" {
-                        continue;
-                    }
-                } else {
-                    continue;
-                }
+            if !settings
+                .files
+                .matches(entry.path().strip_prefix(&input_dir)?)
+            {
+                continue;
             }
 
-            let mut h = sha2::Sha256::new();
-            // Feed the executable bit into the digest to distinguish between
-            // output file modes.
-            h.update(format!("{}", metadata.permissions().mode() & 0o100));
-            h.update(&std::fs::read(entry.path())?);
+            // Symlinks in builds (e.g. libm.so -> libm.so.6) are recreated as is.
+            if metadata.file_type().is_symlink() {
+                let target = std::fs::read_link(entry.path())
+                    .with_context(|| format!("reading symlink {}", entry.path().display()))?;
+                entries.insert(relative_path.to_path_buf(), UnifiedEntry::Symlink(target));
+                summary.preserved_symlinks += 1;
+                continue;
+            }
+
+            let size = metadata.len();
+            summary.input_files += 1;
+            summary.input_bytes += size;
+
+            // Without deduplication, there is no need to index files.
+            if settings.duplicates == DuplicateMode::Copy {
+                entries.insert(
+                    relative_path.to_path_buf(),
+                    UnifiedEntry::File(entry.path().to_path_buf()),
+                );
+                summary.copied_files += 1;
+                continue;
+            }
 
-            let digest = h.finalize();
-            let sha256 = hex::encode(digest.as_slice());
+            let mtime = metadata.modified()?;
+            let executable_bit = owner_executable_bit(&metadata);
 
+            let digest = match previous.get(relative_path) {
+                Some(indexed)
+                    if indexed.size == size
+                        && indexed.mtime == mtime
+                        && indexed.executable_bit == executable_bit =>
+                {
+                    indexed.digest.clone()
+                }
+                _ => {
+                    hashed_count += 1;
+
+                    file_digest(entry.path(), executable_bit, settings.digest)?
+                }
+            };
+
+            index.files.insert(
+                relative_path.to_path_buf(),
+                IndexedFile {
+                    size,
+                    mtime,
+                    executable_bit,
+                    digest: digest.clone(),
+                },
+            );
+
+            sizes.insert(digest.clone(), size);
             digests
-                .entry(sha256)
+                .entry(digest)
                 .or_default()
                 .insert(relative_path.to_path_buf());
         }
     }
 
-    let mut copy_count = 0;
-    let mut dedupe_count = 0;
-    let mut symlink_count = 0;
+    if settings.duplicates == DuplicateMode::Symlink {
+        warn!(
+            logger,
+            "hashed {} files; reused digests of {} files",
+            hashed_count,
+            index.files.len() - hashed_count
+        );
+    }
 
     for (digest, paths) in digests {
         // Exactly 1 file is a straight file copy.
         if paths.len() == 1 {
-            copy_count += 1;
+            let path = paths.into_iter().next().expect("set has exactly 1 element");
 
-            let path = paths.iter().next().expect("set has exactly 1 element");
-
-            let source_path = source_dir.join(path);
-            let dest_path = dest_dir.join(path);
-
-            std::fs::create_dir_all(
-                dest_path
-                    .parent()
-                    .ok_or_else(|| anyhow!("failed to resolve parent directory"))?,
-            )?;
-            info!(
-                logger,
-                "copying {} -> {}",
-                source_path.display(),
-                dest_path.display()
-            );
-            std::fs::copy(&source_path, &dest_path).context("copying file")?;
-            normalize_file(&dest_path)?;
+            entries.insert(path.clone(), UnifiedEntry::File(source_dir.join(path)));
+            summary.copied_files += 1;
         }
         // Multiple files is a symlink to a common file entry.
         else {
-            dedupe_count += 1;
-
             let common_rel_path = PathBuf::from("common").join(&digest[0..2]).join(&digest);
-            let common_path = dest_dir.join(&common_rel_path);
 
-            std::fs::create_dir_all(
-                common_path
-                    .parent()
-                    .ok_or_else(|| anyhow!("failed to resolve parent of common path"))?,
-            )?;
-
-            let mut paths_iter = paths.into_iter();
-            let first_path = paths_iter
+            let first_path = paths
+                .iter()
                 .next()
                 .ok_or_else(|| anyhow!("failed to get first path"))?;
 
-            let source_path = source_dir.join(first_path);
-            info!(
-                logger,
-                "copying {} -> {}",
-                source_path.display(),
-                common_path.display()
+            entries.insert(
+                common_rel_path.clone(),
+                UnifiedEntry::File(source_dir.join(first_path)),
             );
-            std::fs::copy(&source_path, &common_path)
-                .context("copying source file to common path")?;
-            normalize_file(&common_path)?;
-
-            // Now install symlinks for remaining files.
-            for path in paths_iter {
-                symlink_count += 1;
-
-                let symlink_source = dest_dir.join(&path);
-                std::fs::create_dir_all(
-                    symlink_source
-                        .parent()
-                        .ok_or_else(|| anyhow!("failed to get parent of symlink path"))?,
-                )?;
+            summary.common_files += 1;
+            summary.bytes_saved += sizes[&digest] * (paths.len() as u64 - 1);
 
+            // Now install symlinks for every copy of the file.
+            for path in paths {
                 // The symlink target needs to be relative to the source path so the file layout
                 // is portable.
                 let mut symlink_target = PathBuf::new();
@@ -173,24 +569,1105 @@ pub fn unify_glibc(
                 }
                 let symlink_target = symlink_target.join(&common_rel_path);
 
+                summary
+                    .deduplicated
+                    .insert(path.clone(), common_rel_path.clone());
+                entries.insert(path, UnifiedEntry::Symlink(symlink_target));
+                summary.symlinked_files += 1;
+            }
+        }
+    }
+
+    Ok((entries, summary))
+}
+
+/// Materialize unified entries in a directory.
+fn write_unified_dir(
+    logger: &Logger,
+    entries: &BTreeMap<PathBuf, UnifiedEntry>,
+    dest_dir: &Path,
+) -> Result<()> {
+    for (path, entry) in entries {
+        let dest_path = dest_dir.join(path);
+
+        std::fs::create_dir_all(
+            dest_path
+                .parent()
+                .ok_or_else(|| anyhow!("failed to resolve parent directory"))?,
+        )?;
+
+        match entry {
+            UnifiedEntry::File(source_path) => {
+                info!(
+                    logger,
+                    "copying {} -> {}",
+                    source_path.display(),
+                    dest_path.display()
+                );
+                std::fs::copy(source_path, &dest_path).context("copying file")?;
+                normalize_file(&dest_path)?;
+            }
+            UnifiedEntry::Symlink(target) => {
                 info!(
                     logger,
                     "symlinking {} -> {}",
-                    symlink_source.display(),
-                    symlink_target.display()
+                    dest_path.display(),
+                    target.display()
                 );
-                symlink(&symlink_target, &symlink_source).context("creating symlink")?;
+                symlink(target, &dest_path).context("creating symlink")?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write unified entries to a zstd compressed tar archive under `prefix`.
+///
+/// Entries are in path order with normalized metadata, so the archive is
+/// deterministic.
+fn write_unified_tar_zst(
+    entries: &BTreeMap<PathBuf, UnifiedEntry>,
+    prefix: &Path,
+    writer: impl Write,
+    zstd: &ZstdSettings,
+) -> Result<()> {
+    let encoder = zstd.encoder(writer)?;
+    let mut builder = tar::Builder::new(encoder);
+
+    for (path, entry) in entries {
+        let mut header = crate::tar::deterministic_header()?;
+
+        match entry {
+            UnifiedEntry::File(source_path) => {
+                let fh = std::fs::File::open(source_path)
+                    .with_context(|| format!("opening {}", source_path.display()))?;
+                let metadata = fh.metadata()?;
+
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_mode(if owner_executable_bit(&metadata) != 0 {
+                    0o755
+                } else {
+                    0o644
+                });
+                header.set_size(metadata.len());
+                builder.append_data(&mut header, prefix.join(path), fh)?;
+            }
+            UnifiedEntry::Symlink(target) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_mode(0o755);
+                header.set_link_name(target).context("setting link name")?;
+                header.set_size(0);
+                builder.append_data(&mut header, prefix.join(path), std::io::empty())?;
             }
         }
     }
 
+    builder
+        .into_inner()?
+        .finish()
+        .context("finishing zstd stream")?
+        .flush()?;
+
+    Ok(())
+}
+
+/// Unify directories containing glibc builds.
+///
+/// [source_dir] contains sub-directories containing individual builds of glibc.
+///
+/// We scan each individual glibc source directory and record the files that
+/// we've seen.
+///
+/// The source directories and files are rematerialized in [dest_dir] except
+/// that duplicate files are normalized to symlinks to files in a shared location.
+/// This ensures that each unique file is written exactly once. With
+/// [DuplicateMode::Copy], duplicates are plain copies instead. Symlinks in the
+/// builds are recreated with their original targets and never deduplicated.
+///
+/// If [dest_tar_zst] is given, the same tree is streamed into a zstd
+/// compressed tar archive under a `glibcs/` directory. [dest_dir] may be
+/// omitted to avoid writing the tree to disk.
+///
+/// If [index_path] is given, file digests are persisted there and reused by
+/// subsequent runs for files whose size and modification time are unchanged.
+///
+/// Returns a summary of the unified output.
+pub fn unify_glibc(
+    logger: &Logger,
+    source_dir: &Path,
+    dest_dir: Option<&Path>,
+    dest_tar_zst: Option<&Path>,
+    index_path: Option<&Path>,
+    settings: &UnifySettings,
+    zstd: &ZstdSettings,
+) -> Result<UnifySummary> {
+    let mut index = index_path
+        .map(|path| DigestIndex::load(logger, path))
+        .unwrap_or_default();
+
+    let (entries, summary) = plan_unified(logger, source_dir, settings, &mut index)?;
+
+    // Copying doesn't index files, so there is nothing to persist.
+    if let (Some(index_path), DuplicateMode::Symlink) = (index_path, settings.duplicates) {
+        index.save(index_path).context("saving digest index")?;
+    }
+
+    if let Some(dest_dir) = dest_dir {
+        write_unified_dir(logger, &entries, dest_dir)?;
+    }
+
+    if let Some(dest_tar_zst) = dest_tar_zst {
+        warn!(logger, "writing {}", dest_tar_zst.display());
+        let fh = std::fs::File::create(dest_tar_zst)
+            .with_context(|| format!("creating {}", dest_tar_zst.display()))?;
+        write_unified_tar_zst(
+            &entries,
+            Path::new("glibcs"),
+            std::io::BufWriter::new(fh),
+            zstd,
+        )?;
+    }
+
     warn!(
         logger,
-        "copied {} files; symlinked {} files to {} common files",
-        copy_count,
-        symlink_count,
-        dedupe_count
+        "copied {} files; symlinked {} files to {} common files; preserved {} symlinks; saved {} bytes",
+        summary.copied_files,
+        summary.symlinked_files,
+        summary.common_files,
+        summary.preserved_symlinks,
+        summary.bytes_saved
+    );
+    if let (Some(min), Some(max)) = (&summary.min_glibc, &summary.max_glibc) {
+        warn!(logger, "unified builds of glibc {} through {}", min, max);
+    }
+
+    Ok(summary)
+}
+
+/// C runtime startup objects linked into every executable.
+const STARTUP_OBJECTS: &[&str] = &["crt1.o", "crti.o", "crtn.o", "Scrt1.o"];
+
+/// Whether a file is a linker script, such as the `libc.so` glibc installs.
+fn is_linker_script(path: &Path) -> Result<bool> {
+    let mut header = vec![];
+    std::fs::File::open(path)
+        .with_context(|| format!("opening {}", path.display()))?
+        .take(16)
+        .read_to_end(&mut header)?;
+
+    Ok(header.starts_with(b"/* GNU ld script"))
+}
+
+/// Write the startup objects and linker scripts of each glibc build to an archive.
+///
+/// With headers, these are all that is needed to link against a glibc. For
+/// each build in [source_dir] selected by [filter], `<build>-startup.tar.zst`
+/// is written to [dest_dir] with files under a `<build>/` directory.
+///
+/// Returns the paths of written archives.
+pub fn extract_startup_files(
+    logger: &Logger,
+    source_dir: &Path,
+    dest_dir: &Path,
+    filter: &BuildFilter,
+    zstd: &ZstdSettings,
+) -> Result<Vec<PathBuf>> {
+    let mut builds = vec![];
+
+    for entry in std::fs::read_dir(source_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.metadata()?.is_dir() && filter.matches(&name) {
+            builds.push(name);
+        }
+    }
+
+    builds.sort();
+
+    std::fs::create_dir_all(dest_dir).context("creating destination directory")?;
+
+    let mut paths = vec![];
+
+    for build in builds {
+        let build_dir = source_dir.join(&build);
+        let mut entries = BTreeMap::new();
+
+        for entry in walkdir::WalkDir::new(&build_dir) {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let is_startup_object = STARTUP_OBJECTS
+                .iter()
+                .any(|name| entry.file_name() == *name);
+
+            if is_startup_object || is_linker_script(entry.path())? {
+                entries.insert(
+                    entry.path().strip_prefix(&build_dir)?.to_path_buf(),
+                    UnifiedEntry::File(entry.path().to_path_buf()),
+                );
+            }
+        }
+
+        if entries.is_empty() {
+            warn!(logger, "no startup files in {}", build);
+            continue;
+        }
+
+        let dest_path = dest_dir.join(format!("{}-startup.tar.zst", build));
+        warn!(
+            logger,
+            "writing {} files to {}",
+            entries.len(),
+            dest_path.display()
+        );
+        let fh = std::fs::File::create(&dest_path)
+            .with_context(|| format!("creating {}", dest_path.display()))?;
+        write_unified_tar_zst(
+            &entries,
+            Path::new(&build),
+            std::io::BufWriter::new(fh),
+            zstd,
+        )?;
+
+        paths.push(dest_path);
+    }
+
+    Ok(paths)
+}
+
+/// Predefined compiler macros identifying the target of a glibc build.
+///
+/// Returns `None` for builds we don't know how to identify.
+fn target_condition(build: &str) -> Option<&'static str> {
+    let arch = build.split('-').next()?;
+
+    Some(match arch {
+        "x86_64" if build.contains("-x32") => "defined(__x86_64__) && defined(__ILP32__)",
+        "x86_64" => "defined(__x86_64__) && !defined(__ILP32__)",
+        "i486" | "i586" | "i686" if build.contains("-linux-") => "defined(__i386__)",
+        "aarch64" => "defined(__aarch64__) && !defined(__AARCH64EB__)",
+        "aarch64_be" => "defined(__aarch64__) && defined(__AARCH64EB__)",
+        "arm" if build.contains("hf") => {
+            "defined(__arm__) && !defined(__ARMEB__) && defined(__ARM_PCS_VFP)"
+        }
+        "arm" => "defined(__arm__) && !defined(__ARMEB__) && !defined(__ARM_PCS_VFP)",
+        "armeb" if build.contains("hf") => {
+            "defined(__arm__) && defined(__ARMEB__) && defined(__ARM_PCS_VFP)"
+        }
+        "armeb" => "defined(__arm__) && defined(__ARMEB__) && !defined(__ARM_PCS_VFP)",
+        "powerpc64le" => "defined(__powerpc64__) && defined(__LITTLE_ENDIAN__)",
+        "powerpc64" => "defined(__powerpc64__) && defined(__BIG_ENDIAN__)",
+        "powerpc" if build.ends_with("-soft") => {
+            "defined(__powerpc__) && !defined(__powerpc64__) && defined(_SOFT_FLOAT)"
+        }
+        "powerpc" => "defined(__powerpc__) && !defined(__powerpc64__) && !defined(_SOFT_FLOAT)",
+        "riscv64" if build.ends_with("lp64d") => {
+            "defined(__riscv) && __riscv_xlen == 64 && defined(__riscv_float_abi_double)"
+        }
+        "riscv64" if build.ends_with("lp64") => {
+            "defined(__riscv) && __riscv_xlen == 64 && defined(__riscv_float_abi_soft)"
+        }
+        "s390x" => "defined(__s390x__)",
+        "s390" => "defined(__s390__) && !defined(__s390x__)",
+        "sparc64" => "defined(__sparc__) && defined(__arch64__)",
+        _ => return None,
+    })
+}
+
+/// Join path components with `/`, as `#include` expects.
+fn include_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Write a multiarch include tree serving multiple glibc builds.
+///
+/// Headers of each build in [source_dir] selected by [filter] are merged
+/// into `include/` under [dest_dir]. Headers identical in every build are
+/// written as is. Others are written to `<build>/include/` and dispatched
+/// to by a header at their path in `include/` selecting a build via
+/// predefined compiler macros, similar to Debian's multiarch layout. A
+/// single `-I <dest_dir>/include` then serves every build.
+///
+/// Builds must be distinguishable by predefined macros, so variants of a
+/// target (e.g. `-disable-multi-arch`) can't be combined.
+pub fn write_multiarch_headers(
+    logger: &Logger,
+    source_dir: &Path,
+    dest_dir: &Path,
+    filter: &BuildFilter,
+) -> Result<()> {
+    let mut builds = BTreeMap::new();
+
+    for entry in std::fs::read_dir(source_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if !entry.metadata()?.is_dir() || !filter.matches(&name) {
+            continue;
+        }
+
+        let condition = target_condition(&name).ok_or_else(|| {
+            anyhow!(
+                "unable to identify {} by predefined macros; exclude it",
+                name
+            )
+        })?;
+
+        builds.insert(name, condition);
+    }
+
+    let mut conditions = BTreeMap::new();
+    for (build, condition) in &builds {
+        if let Some(other) = conditions.insert(condition, build) {
+            return Err(anyhow!(
+                "{} and {} can't be distinguished by predefined macros",
+                other,
+                build
+            ));
+        }
+    }
+
+    // Header path -> build -> digest.
+    let mut headers = BTreeMap::<PathBuf, BTreeMap<&str, String>>::new();
+
+    for build in builds.keys() {
+        let include_dir = source_dir.join(build).join("usr").join("include");
+        warn!(logger, "indexing {}", include_dir.display());
+
+        for entry in walkdir::WalkDir::new(&include_dir) {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            headers
+                .entry(entry.path().strip_prefix(&include_dir)?.to_path_buf())
+                .or_default()
+                .insert(
+                    build,
+                    file_digest(entry.path(), 0, DigestAlgorithm::default())?,
+                );
+        }
+    }
+
+    let mut shared_count = 0;
+    let mut dispatch_count = 0;
+
+    for (path, digests) in headers {
+        let source_path = |build: &str| {
+            source_dir
+                .join(build)
+                .join("usr")
+                .join("include")
+                .join(&path)
+        };
+        let dest_path = dest_dir.join("include").join(&path);
+        std::fs::create_dir_all(
+            dest_path
+                .parent()
+                .ok_or_else(|| anyhow!("failed to resolve parent directory"))?,
+        )?;
+
+        let digest_set = digests.values().collect::<BTreeSet<_>>();
+        if digests.len() == builds.len() && digest_set.len() == 1 {
+            let build = digests.keys().next().expect("builds are non-empty");
+            std::fs::copy(source_path(build), &dest_path).context("copying file")?;
+            normalize_file(&dest_path)?;
+            shared_count += 1;
+            continue;
+        }
+
+        // Relative to the dispatch header, which is under include/.
+        let mut prefix = PathBuf::new();
+        for _ in 0..path.components().count() {
+            prefix.push("..");
+        }
+
+        let mut lines = vec![
+            "/* Dispatches to the header of the compilation target. Generated by pclang. */"
+                .to_string(),
+        ];
+
+        for (i, build) in digests.keys().enumerate() {
+            let target_path = dest_dir.join(build).join("include").join(&path);
+            std::fs::create_dir_all(
+                target_path
+                    .parent()
+                    .ok_or_else(|| anyhow!("failed to resolve parent directory"))?,
+            )?;
+            std::fs::copy(source_path(build), &target_path).context("copying file")?;
+            normalize_file(&target_path)?;
+
+            lines.push(format!(
+                "#{} {}",
+                if i == 0 { "if" } else { "elif" },
+                builds[*build]
+            ));
+            lines.push(format!(
+                "#include \"{}\"",
+                include_path(&prefix.join(build).join("include").join(&path))
+            ));
+        }
+
+        lines.push("#else".to_string());
+        lines.push(format!(
+            "#error \"{} is not available for this target\"",
+            include_path(&path)
+        ));
+        lines.push("#endif".to_string());
+
+        std::fs::write(&dest_path, lines.join("\n") + "\n")
+            .with_context(|| format!("writing {}", dest_path.display()))?;
+        dispatch_count += 1;
+    }
+
+    warn!(
+        logger,
+        "wrote {} shared headers and {} dispatch headers for {} builds",
+        shared_count,
+        dispatch_count,
+        builds.len()
     );
 
     Ok(())
 }
+
+/// Result of verifying a unified glibc directory.
+#[derive(Debug, Default)]
+pub struct UnifiedVerification {
+    /// Number of files and symlinks checked.
+    pub checked: usize,
+
+    /// Symlinks that don't resolve to a file in the unified directory.
+    pub broken_symlinks: Vec<PathBuf>,
+
+    /// Common files whose content doesn't match the digest they are named after.
+    pub corrupt: Vec<PathBuf>,
+
+    /// Files in the glibc builds missing from the unified directory.
+    pub missing: Vec<PathBuf>,
+
+    /// Files in the unified directory with different content than in the glibc builds.
+    pub mismatched: Vec<PathBuf>,
+}
+
+impl UnifiedVerification {
+    pub fn is_valid(&self) -> bool {
+        self.broken_symlinks.is_empty()
+            && self.corrupt.is_empty()
+            && self.missing.is_empty()
+            && self.mismatched.is_empty()
+    }
+}
+
+/// Verify a directory written by [unify_glibc].
+///
+/// Every symlink must resolve within [unified_dir] and every common file must
+/// have the digest it is named after. If [source_dir] is given, every file of
+/// the glibc builds in it must be present in [unified_dir] with the same
+/// content and executable bit, and every symlink with the same target.
+/// [settings] must match those the directory was unified with.
+pub fn verify_unified(
+    logger: &Logger,
+    unified_dir: &Path,
+    source_dir: Option<&Path>,
+    settings: &UnifySettings,
+) -> Result<UnifiedVerification> {
+    let mut res = UnifiedVerification::default();
+
+    let root = unified_dir
+        .canonicalize()
+        .with_context(|| format!("resolving {}", unified_dir.display()))?;
+
+    for entry in walkdir::WalkDir::new(&root) {
+        let entry = entry?;
+        let path = entry.path();
+        let relative_path = path.strip_prefix(&root)?;
+
+        if entry.file_type().is_dir() {
+            continue;
+        }
+
+        res.checked += 1;
+
+        if entry.file_type().is_symlink() {
+            let resolved = path
+                .canonicalize()
+                .ok()
+                .filter(|resolved| resolved.starts_with(&root));
+
+            if resolved.is_none() {
+                warn!(logger, "broken symlink: {}", relative_path.display());
+                res.broken_symlinks.push(relative_path.to_path_buf());
+            }
+        } else if relative_path.starts_with("common") {
+            let metadata = entry.metadata()?;
+            let digest = file_digest(path, owner_executable_bit(&metadata), settings.digest)?;
+            let expected = PathBuf::from("common").join(&digest[0..2]).join(&digest);
+
+            if relative_path != expected {
+                warn!(
+                    logger,
+                    "corrupt common file: {} has digest {}",
+                    relative_path.display(),
+                    digest
+                );
+                res.corrupt.push(relative_path.to_path_buf());
+            }
+        }
+    }
+
+    if let Some(source_dir) = source_dir {
+        let builds = select_builds(logger, source_dir, settings)?;
+
+        for entry in walkdir::WalkDir::new(source_dir).min_depth(2) {
+            let entry = entry?;
+            let relative_path = entry.path().strip_prefix(source_dir)?;
+
+            let mut components = relative_path.iter();
+            let build = components.next().expect("path is at least 2 deep");
+
+            if entry.file_type().is_dir()
+                || !builds.contains_key(&*build.to_string_lossy())
+                || !settings.files.matches(components.as_path())
+            {
+                continue;
+            }
+
+            let unified_path = root.join(relative_path);
+
+            if entry.file_type().is_symlink() {
+                if std::fs::symlink_metadata(&unified_path).is_err() {
+                    warn!(logger, "missing: {}", relative_path.display());
+                    res.missing.push(relative_path.to_path_buf());
+                } else if std::fs::read_link(&unified_path).ok()
+                    != Some(std::fs::read_link(entry.path())?)
+                {
+                    warn!(
+                        logger,
+                        "symlink target differs: {}",
+                        relative_path.display()
+                    );
+                    res.mismatched.push(relative_path.to_path_buf());
+                }
+
+                continue;
+            }
+
+            // Follows symlinks to compare against the common file.
+            let unified_metadata = match std::fs::metadata(&unified_path) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    warn!(logger, "missing: {}", relative_path.display());
+                    res.missing.push(relative_path.to_path_buf());
+                    continue;
+                }
+            };
+
+            let source_digest = file_digest(
+                entry.path(),
+                owner_executable_bit(&entry.metadata()?),
+                settings.digest,
+            )?;
+            let unified_digest = file_digest(
+                &unified_path,
+                owner_executable_bit(&unified_metadata),
+                settings.digest,
+            )?;
+
+            if source_digest != unified_digest {
+                warn!(logger, "content differs: {}", relative_path.display());
+                res.mismatched.push(relative_path.to_path_buf());
+            }
+        }
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn unify_tar_zst() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+
+        for glibc in ["glibc-2.17", "glibc-2.18"] {
+            let include_dir = source_dir.join(glibc).join("usr/include");
+            std::fs::create_dir_all(&include_dir)?;
+            std::fs::write(include_dir.join("stdio.h"), "shared")?;
+            std::fs::write(include_dir.join("version.h"), glibc)?;
+        }
+
+        let digest = hex::encode(sha2::Sha256::digest(b"0shared"));
+        let dest_tar_zst = temp_dir.path().join("unified.tar.zst");
+        let summary = unify_glibc(
+            &logger,
+            &source_dir,
+            None,
+            Some(&dest_tar_zst),
+            None,
+            &UnifySettings {
+                files: FileFilter::new(&["**/*.h"], &[] as &[&str])?,
+                ..Default::default()
+            },
+            &ZstdSettings::default(),
+        )?;
+        let data = std::fs::read(&dest_tar_zst)?;
+
+        assert_eq!(summary.input_files, 4);
+        assert_eq!(summary.copied_files, 2);
+        assert_eq!(summary.symlinked_files, 2);
+        assert_eq!(summary.common_files, 1);
+        assert_eq!(summary.bytes_saved, 6);
+        assert_eq!(
+            summary
+                .deduplicated
+                .get(Path::new("glibc-2.18/usr/include/stdio.h")),
+            Some(&PathBuf::from("common").join(&digest[0..2]).join(&digest))
+        );
+
+        let mut archive = tar::Archive::new(zstd::stream::Decoder::new(data.as_slice())?);
+        let entries = archive
+            .entries()?
+            .map(|entry| {
+                let entry = entry?;
+                Ok((
+                    entry.path()?.to_string_lossy().to_string(),
+                    entry.header().entry_type(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    format!("glibcs/common/{}/{}", &digest[0..2], digest),
+                    tar::EntryType::Regular
+                ),
+                (
+                    "glibcs/glibc-2.17/usr/include/stdio.h".to_string(),
+                    tar::EntryType::Symlink
+                ),
+                (
+                    "glibcs/glibc-2.17/usr/include/version.h".to_string(),
+                    tar::EntryType::Regular
+                ),
+                (
+                    "glibcs/glibc-2.18/usr/include/stdio.h".to_string(),
+                    tar::EntryType::Symlink
+                ),
+                (
+                    "glibcs/glibc-2.18/usr/include/version.h".to_string(),
+                    tar::EntryType::Regular
+                ),
+            ]
+        );
+
+        // Output is deterministic.
+        unify_glibc(
+            &logger,
+            &source_dir,
+            None,
+            Some(&dest_tar_zst),
+            None,
+            &UnifySettings {
+                files: FileFilter::new(&["**/*.h"], &[] as &[&str])?,
+                ..Default::default()
+            },
+            &ZstdSettings::default(),
+        )?;
+        assert_eq!(std::fs::read(&dest_tar_zst)?, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn index_reuse() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        let index_path = temp_dir.path().join("index.json");
+
+        let include_dir = source_dir.join("glibc-2.17");
+        std::fs::create_dir_all(&include_dir)?;
+        std::fs::write(include_dir.join("stdio.h"), "shared")?;
+
+        let mut index = DigestIndex::load(&logger, &index_path);
+        plan_unified(&logger, &source_dir, &UnifySettings::default(), &mut index)?;
+        index.save(&index_path)?;
+
+        // Digests of files with matching metadata are reused as-is.
+        let mut index = DigestIndex::load(&logger, &index_path);
+        let path = PathBuf::from("glibc-2.17/stdio.h");
+        index.files.get_mut(&path).unwrap().digest = "cached".to_string();
+        let (entries, _) =
+            plan_unified(&logger, &source_dir, &UnifySettings::default(), &mut index)?;
+        assert_eq!(index.files[&path].digest, "cached");
+        assert_eq!(entries.len(), 1);
+
+        // Changed files are hashed again.
+        index.files.get_mut(&path).unwrap().size += 1;
+        plan_unified(&logger, &source_dir, &UnifySettings::default(), &mut index)?;
+        assert_ne!(index.files[&path].digest, "cached");
+
+        // Digests computed with another algorithm aren't reused.
+        index.files.get_mut(&path).unwrap().digest = "cached".to_string();
+        plan_unified(
+            &logger,
+            &source_dir,
+            &UnifySettings {
+                digest: DigestAlgorithm::Xxh3,
+                ..Default::default()
+            },
+            &mut index,
+        )?;
+        assert_eq!(index.algorithm, DigestAlgorithm::Xxh3);
+        assert_ne!(index.files[&path].digest, "cached");
+
+        Ok(())
+    }
+
+    #[test]
+    fn digest_algorithms() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("stdio.h");
+        std::fs::write(&path, "shared")?;
+
+        assert_eq!(
+            file_digest(&path, 0, DigestAlgorithm::Sha256)?,
+            hex::encode(sha2::Sha256::digest(b"0shared"))
+        );
+        assert_eq!(
+            file_digest(&path, 0, DigestAlgorithm::Blake3)?,
+            blake3::hash(b"0shared").to_hex().to_string()
+        );
+        assert_eq!(file_digest(&path, 0, DigestAlgorithm::Xxh3)?.len(), 32);
+        assert_ne!(
+            file_digest(&path, 0, DigestAlgorithm::Xxh3)?,
+            file_digest(&path, 0o100, DigestAlgorithm::Xxh3)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn build_filter() {
+        let filter = BuildFilter {
+            include: vec!["x86_64-*".to_string(), "aarch64-linux-gnu".to_string()],
+            exclude: vec!["*-x32*".to_string()],
+        };
+
+        assert!(filter.matches("x86_64-linux-gnu"));
+        assert!(filter.matches("aarch64-linux-gnu"));
+        assert!(!filter.matches("aarch64_be-linux-gnu"));
+        assert!(!filter.matches("x86_64-linux-gnu-x32"));
+        assert!(!filter.matches("x86_64-linux-gnu-x32-static-pie"));
+        assert!(BuildFilter::default().matches("i686-linux-gnu"));
+    }
+
+    #[test]
+    fn glibc_range() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path();
+
+        for (build, minor) in [("a", 12), ("b", 17), ("c", 34)] {
+            let include_dir = source_dir.join(build).join("usr/include");
+            std::fs::create_dir_all(&include_dir)?;
+            std::fs::write(
+                include_dir.join("features.h"),
+                format!(
+                    "#define\t__GLIBC__\t2\n#define\t__GLIBC_MINOR__\t{}\n",
+                    minor
+                ),
+            )?;
+        }
+        std::fs::create_dir_all(source_dir.join("d"))?;
+
+        let builds = select_builds(&logger, source_dir, &UnifySettings::default())?;
+        assert_eq!(builds.len(), 4);
+        assert_eq!(builds["c"], Some(GlibcVersion::from_str("2.34")?));
+        assert_eq!(builds["d"], None);
+
+        let settings = UnifySettings {
+            min_glibc: Some(GlibcVersion::from_str("2.17")?),
+            ..Default::default()
+        };
+        let builds = select_builds(&logger, source_dir, &settings)?;
+        assert_eq!(builds.keys().collect::<Vec<_>>(), vec!["b", "c"]);
+
+        let (_, summary) = plan_unified(&logger, source_dir, &settings, &mut Default::default())?;
+        assert_eq!(summary.min_glibc.as_deref(), Some("2.17"));
+        assert_eq!(summary.max_glibc.as_deref(), Some("2.34"));
+
+        let settings = UnifySettings {
+            max_glibc: Some(GlibcVersion::from_str("2.17")?),
+            ..settings
+        };
+        let builds = select_builds(&logger, source_dir, &settings)?;
+        assert_eq!(builds.keys().collect::<Vec<_>>(), vec!["b"]);
+
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn verify() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        let unified_dir = temp_dir.path().join("unified");
+
+        for glibc in ["glibc-2.17", "glibc-2.18"] {
+            let include_dir = source_dir.join(glibc).join("usr/include");
+            std::fs::create_dir_all(&include_dir)?;
+            std::fs::write(include_dir.join("stdio.h"), "shared")?;
+            std::fs::write(include_dir.join("version.h"), glibc)?;
+        }
+
+        unify_glibc(
+            &logger,
+            &source_dir,
+            Some(&unified_dir),
+            None,
+            None,
+            &UnifySettings::default(),
+            &ZstdSettings::default(),
+        )?;
+
+        let res = verify_unified(
+            &logger,
+            &unified_dir,
+            Some(&source_dir),
+            &UnifySettings::default(),
+        )?;
+        assert!(res.is_valid());
+        assert_eq!(res.checked, 5);
+
+        // Corrupt the common file and remove a file.
+        let common_dir = unified_dir.join("common");
+        let common_path = walkdir::WalkDir::new(&common_dir)
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_type().is_file())
+            .ok_or_else(|| anyhow!("no common file"))?
+            .into_path();
+        std::fs::write(&common_path, "modified")?;
+        std::fs::remove_file(unified_dir.join("glibc-2.18/usr/include/version.h"))?;
+
+        let res = verify_unified(
+            &logger,
+            &unified_dir,
+            Some(&source_dir),
+            &UnifySettings::default(),
+        )?;
+        assert!(!res.is_valid());
+        assert_eq!(res.corrupt, vec![common_path.strip_prefix(&unified_dir)?]);
+        assert_eq!(
+            res.missing,
+            vec![PathBuf::from("glibc-2.18/usr/include/version.h")]
+        );
+        assert_eq!(res.mismatched.len(), 2);
+
+        // Dangling symlinks are detected.
+        std::fs::remove_file(&common_path)?;
+        let res = verify_unified(&logger, &unified_dir, None, &UnifySettings::default())?;
+        assert_eq!(res.broken_symlinks.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn file_filter() -> Result<()> {
+        let filter = FileFilter::new(&["usr/include/**", "**/crt*.o"], &["**/*_debug.h"])?;
+
+        assert!(filter.matches(Path::new("usr/include/stdio.h")));
+        assert!(filter.matches(Path::new("usr/include/sys/types.h")));
+        assert!(filter.matches(Path::new("usr/lib64/crt1.o")));
+        assert!(!filter.matches(Path::new("usr/include/malloc_debug.h")));
+        assert!(!filter.matches(Path::new("usr/share/locale/locale.alias")));
+        assert!(!filter.matches(Path::new("usr/lib64/libc.a")));
+
+        let headers = FileFilter::new(&["**/*.h"], &[] as &[&str])?;
+        assert!(headers.matches(Path::new("usr/include/stdio.h")));
+        assert!(headers.matches(Path::new("stdio.h")));
+        assert!(!headers.matches(Path::new("usr/lib64/crt1.o")));
+
+        assert!(FileFilter::default().matches(Path::new("usr/lib64/libc.a")));
+
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn preserve_symlinks() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        let unified_dir = temp_dir.path().join("unified");
+
+        for glibc in ["glibc-2.17", "glibc-2.18"] {
+            let lib_dir = source_dir.join(glibc).join("lib");
+            std::fs::create_dir_all(&lib_dir)?;
+            std::fs::write(lib_dir.join("libm.so.6"), "libm")?;
+            symlink("libm.so.6", lib_dir.join("libm.so"))?;
+            symlink("lib", source_dir.join(glibc).join("lib64"))?;
+        }
+
+        let summary = unify_glibc(
+            &logger,
+            &source_dir,
+            Some(&unified_dir),
+            None,
+            None,
+            &UnifySettings::default(),
+            &ZstdSettings::default(),
+        )?;
+        assert_eq!(summary.input_files, 2);
+        assert_eq!(summary.symlinked_files, 2);
+        assert_eq!(summary.preserved_symlinks, 4);
+
+        for glibc in ["glibc-2.17", "glibc-2.18"] {
+            assert_eq!(
+                std::fs::read_link(unified_dir.join(glibc).join("lib/libm.so"))?,
+                PathBuf::from("libm.so.6")
+            );
+            assert_eq!(
+                std::fs::read_link(unified_dir.join(glibc).join("lib64"))?,
+                PathBuf::from("lib")
+            );
+            assert_eq!(
+                std::fs::read(unified_dir.join(glibc).join("lib64/libm.so"))?,
+                b"libm"
+            );
+        }
+
+        let res = verify_unified(
+            &logger,
+            &unified_dir,
+            Some(&source_dir),
+            &UnifySettings::default(),
+        )?;
+        assert!(res.is_valid());
+
+        Ok(())
+    }
+
+    #[test]
+    fn startup_files() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        let dest_dir = temp_dir.path().join("startup");
+
+        let lib_dir = source_dir.join("x86_64-linux-gnu/usr/lib64");
+        std::fs::create_dir_all(&lib_dir)?;
+        for name in ["crt1.o", "crti.o", "libc.so.6", "libc_nonshared.a"] {
+            std::fs::write(lib_dir.join(name), "\x7fELF")?;
+        }
+        std::fs::write(
+            lib_dir.join("libc.so"),
+            "/* GNU ld script\n   Use the shared library.  */\nGROUP ( libc.so.6 )\n",
+        )?;
+        std::fs::create_dir_all(source_dir.join("aarch64-linux-gnu"))?;
+
+        let paths = extract_startup_files(
+            &logger,
+            &source_dir,
+            &dest_dir,
+            &BuildFilter::default(),
+            &ZstdSettings::default(),
+        )?;
+        assert_eq!(
+            paths,
+            vec![dest_dir.join("x86_64-linux-gnu-startup.tar.zst")]
+        );
+
+        let mut archive =
+            tar::Archive::new(zstd::stream::Decoder::new(std::fs::File::open(&paths[0])?)?);
+        let names = archive
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            names,
+            vec![
+                "x86_64-linux-gnu/usr/lib64/crt1.o",
+                "x86_64-linux-gnu/usr/lib64/crti.o",
+                "x86_64-linux-gnu/usr/lib64/libc.so",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn multiarch_headers() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path().join("glibcs");
+        let dest_dir = temp_dir.path().join("multiarch");
+
+        for (build, wordsize) in [("x86_64-linux-gnu", 64), ("i686-linux-gnu", 32)] {
+            let include_dir = source_dir.join(build).join("usr/include");
+            std::fs::create_dir_all(include_dir.join("bits"))?;
+            std::fs::write(include_dir.join("stdio.h"), "stdio")?;
+            std::fs::write(
+                include_dir.join("bits/wordsize.h"),
+                format!("#define __WORDSIZE {}", wordsize),
+            )?;
+        }
+        std::fs::write(
+            source_dir.join("x86_64-linux-gnu/usr/include/bits/x86_64.h"),
+            "x86_64",
+        )?;
+
+        write_multiarch_headers(&logger, &source_dir, &dest_dir, &BuildFilter::default())?;
+
+        assert_eq!(std::fs::read(dest_dir.join("include/stdio.h"))?, b"stdio");
+        assert!(!dest_dir.join("x86_64-linux-gnu/include/stdio.h").exists());
+
+        assert_eq!(
+            std::fs::read_to_string(dest_dir.join("include/bits/wordsize.h"))?,
+            "/* Dispatches to the header of the compilation target. Generated by pclang. */\n\
+             #if defined(__i386__)\n\
+             #include \"../../i686-linux-gnu/include/bits/wordsize.h\"\n\
+             #elif defined(__x86_64__) && !defined(__ILP32__)\n\
+             #include \"../../x86_64-linux-gnu/include/bits/wordsize.h\"\n\
+             #else\n\
+             #error \"bits/wordsize.h is not available for this target\"\n\
+             #endif\n"
+        );
+        assert_eq!(
+            std::fs::read(dest_dir.join("i686-linux-gnu/include/bits/wordsize.h"))?,
+            b"#define __WORDSIZE 32"
+        );
+        // Headers missing from some builds need dispatching too.
+        assert!(
+            std::fs::read_to_string(dest_dir.join("include/bits/x86_64.h"))?.contains("#error")
+        );
+
+        std::fs::create_dir_all(source_dir.join("x86_64-linux-gnu-static-pie"))?;
+        assert!(
+            write_multiarch_headers(&logger, &source_dir, &dest_dir, &BuildFilter::default())
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
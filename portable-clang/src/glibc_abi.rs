@@ -0,0 +1,695 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Queryable glibc ABI metadata.
+
+`glibc-abis` writes a JSON file per glibc target describing the symbols each
+library exports and the symbol versions they are exported under. This is
+derived from the `.abilist` files of a glibc source tree, which record the
+entire history of the ABI.
+
+[GlibcAbiDatabase] parses these files to answer questions such as which
+glibc release first provided a symbol and which release a set of symbols
+requires. It can also generate headers that pin symbols to the versions of
+an older release, so binaries compiled against new headers run on systems
+with that release.
+
+Linking against an older release also needs its libraries. Rather than
+shipping those, [build_stub_libraries] generates stub shared libraries
+which define every versioned symbol of a release but contain no code. These
+are only suitable for linking; binaries load the real glibc at runtime.
+*/
+
+use {
+    crate::glibc::BuildFilter,
+    anyhow::{anyhow, Context, Result},
+    serde::Deserialize,
+    slog::{warn, Logger},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fmt::{Display, Formatter},
+        io::Read,
+        path::Path,
+        str::FromStr,
+    },
+    tugger_file_manifest::FileManifest,
+};
+
+/// A glibc release, such as `2.17`.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct GlibcVersion(Vec<u32>);
+
+impl GlibcVersion {
+    /// Resolve the release of a symbol version, such as `GLIBC_2.2.5`.
+    ///
+    /// Returns `None` for symbol versions not tied to a release, such as
+    /// `GLIBC_PRIVATE`.
+    pub fn from_symbol_version(symbol_version: &str) -> Option<Self> {
+        symbol_version.strip_prefix("GLIBC_")?.parse().ok()
+    }
+}
+
+impl FromStr for GlibcVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split('.')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+            .map_err(|_| anyhow!("invalid glibc version: {}", s))
+    }
+}
+
+impl Display for GlibcVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts = self.0.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+
+        f.write_str(&parts.join("."))
+    }
+}
+
+#[derive(Deserialize)]
+struct SymbolAbi {
+    version: String,
+    /// All symbol versions the symbol is exported under.
+    ///
+    /// Missing from files written before this was recorded.
+    #[serde(default)]
+    versions: Vec<String>,
+    /// Size of data symbols, in hex.
+    ///
+    /// The `.abilist` files misleadingly call this the address.
+    #[serde(default, rename = "address")]
+    size: Option<String>,
+}
+
+impl SymbolAbi {
+    fn versions(&self) -> impl Iterator<Item = &str> {
+        let versions = if self.versions.is_empty() {
+            std::slice::from_ref(&self.version)
+        } else {
+            self.versions.as_slice()
+        };
+
+        versions.iter().map(|x| x.as_str())
+    }
+
+    /// The release providing the default version of this symbol.
+    ///
+    /// Unversioned references bind to the default version when linking,
+    /// which is the newest.
+    fn default_release(&self) -> Option<GlibcVersion> {
+        self.versions()
+            .filter_map(GlibcVersion::from_symbol_version)
+            .max()
+    }
+
+    /// Symbol versions provided as of a glibc release, oldest first.
+    fn versions_as_of(&self, glibc: &GlibcVersion) -> Vec<(GlibcVersion, &str)> {
+        let mut versions = self
+            .versions()
+            .filter_map(|symbol_version| {
+                Some((
+                    GlibcVersion::from_symbol_version(symbol_version)?,
+                    symbol_version,
+                ))
+            })
+            .filter(|(release, _)| release <= glibc)
+            .collect::<Vec<_>>();
+        versions.sort();
+
+        versions
+    }
+}
+
+#[derive(Deserialize)]
+struct LibraryAbi {
+    functions: BTreeMap<String, SymbolAbi>,
+    data: BTreeMap<String, SymbolAbi>,
+}
+
+impl LibraryAbi {
+    fn symbol(&self, name: &str) -> Option<&SymbolAbi> {
+        self.functions.get(name).or_else(|| self.data.get(name))
+    }
+}
+
+/// Sources of a stub shared library.
+pub struct StubLibrary {
+    /// Name of the library, such as `libc`.
+    pub name: String,
+    /// Assembly defining each versioned symbol.
+    pub assembly: String,
+    /// Linker version script defining the symbol versions.
+    pub version_script: String,
+}
+
+/// glibc ABIs of multiple targets.
+///
+/// Targets are named after glibc build configurations, such as
+/// `x86_64-linux-gnu`.
+#[derive(Default)]
+pub struct GlibcAbiDatabase {
+    targets: BTreeMap<String, BTreeMap<String, LibraryAbi>>,
+}
+
+impl GlibcAbiDatabase {
+    /// Construct an instance from the JSON files collected by `glibc_abis`.
+    pub fn from_manifest(manifest: &FileManifest) -> Result<Self> {
+        let mut db = Self::default();
+
+        for (path, entry) in manifest.iter_entries() {
+            db.add_file(path, &entry.resolve_content()?)?;
+        }
+
+        Ok(db)
+    }
+
+    /// Construct an instance from a tar.zst file written by `glibc-abis`.
+    pub fn from_tar_zst(path: &Path) -> Result<Self> {
+        let fh =
+            std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let stream = zstd::stream::Decoder::new(fh).context("creating zstd decompressor")?;
+        let mut archive = tar::Archive::new(stream);
+
+        let mut db = Self::default();
+
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_path_buf();
+
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+
+            db.add_file(&path, &data)?;
+        }
+
+        Ok(db)
+    }
+
+    /// Add the ABI of a target from a `<target>.json` file.
+    fn add_file(&mut self, path: &Path, data: &[u8]) -> Result<()> {
+        if path.extension().map(|x| x.to_string_lossy()) != Some("json".into()) {
+            return Ok(());
+        }
+
+        let target = path
+            .file_stem()
+            .ok_or_else(|| anyhow!("failed to resolve target of {}", path.display()))?
+            .to_string_lossy()
+            .to_string();
+
+        let libs =
+            serde_json::from_slice(data).with_context(|| format!("parsing {}", path.display()))?;
+
+        self.targets.insert(target, libs);
+
+        Ok(())
+    }
+
+    fn libraries(&self, target: &str) -> Result<&BTreeMap<String, LibraryAbi>> {
+        self.targets
+            .get(target)
+            .ok_or_else(|| anyhow!("unknown glibc target: {}", target))
+    }
+
+    /// The glibc release that first provided a symbol on a target.
+    ///
+    /// Returns `None` if the target doesn't provide the symbol.
+    pub fn first_version(&self, target: &str, symbol: &str) -> Result<Option<GlibcVersion>> {
+        Ok(self
+            .libraries(target)?
+            .values()
+            .filter_map(|lib| lib.symbol(symbol))
+            .flat_map(|symbol| symbol.versions())
+            .filter_map(GlibcVersion::from_symbol_version)
+            .min())
+    }
+
+    /// The newest glibc release required by a list of symbols on a target.
+    ///
+    /// Symbols may be qualified with a symbol version, as in
+    /// `memcpy@GLIBC_2.2.5`. Unqualified symbols resolve to their default
+    /// version. If multiple libraries export a symbol, the newest default
+    /// version is used.
+    ///
+    /// Returns `None` if no symbol version is tied to a release.
+    pub fn max_version<'s>(
+        &self,
+        target: &str,
+        symbols: impl IntoIterator<Item = &'s str>,
+    ) -> Result<Option<GlibcVersion>> {
+        let libs = self.libraries(target)?;
+
+        let mut max = None;
+
+        for symbol in symbols {
+            let (name, symbol_version) = match symbol.split_once('@') {
+                Some((name, version)) => (name, Some(version)),
+                None => (symbol, None),
+            };
+
+            let abis = libs
+                .values()
+                .filter_map(|lib| lib.symbol(name))
+                .collect::<Vec<_>>();
+
+            if abis.is_empty() {
+                return Err(anyhow!("{} does not provide {}", target, name));
+            }
+
+            let version = if let Some(symbol_version) = symbol_version {
+                if !abis
+                    .iter()
+                    .any(|abi| abi.versions().any(|v| v == symbol_version))
+                {
+                    return Err(anyhow!("{} does not provide {}", target, symbol));
+                }
+
+                GlibcVersion::from_symbol_version(symbol_version)
+            } else {
+                abis.iter().filter_map(|abi| abi.default_release()).max()
+            };
+
+            max = max.max(version);
+        }
+
+        Ok(max)
+    }
+
+    /// Names of targets in the database.
+    pub fn targets(&self) -> impl Iterator<Item = &str> {
+        self.targets.keys().map(|x| x.as_str())
+    }
+
+    /// Generate a C header pinning symbols of a target to a glibc release.
+    ///
+    /// Symbols whose default version is newer than `glibc` are bound to the
+    /// newest version `glibc` provides via `.symver` directives. This allows
+    /// compiling against newer headers while linking against an older glibc's
+    /// symbol set. Symbols `glibc` doesn't provide at all can't be pinned and
+    /// are listed in a comment; referencing them will fail at runtime.
+    pub fn symver_header(&self, target: &str, glibc: &GlibcVersion) -> Result<String> {
+        let mut symbols = BTreeMap::<&str, Vec<(GlibcVersion, &str)>>::new();
+
+        for lib in self.libraries(target)?.values() {
+            for (name, symbol) in lib.functions.iter().chain(lib.data.iter()) {
+                symbols
+                    .entry(name.as_str())
+                    .or_default()
+                    .extend(symbol.versions().filter_map(|symbol_version| {
+                        Some((
+                            GlibcVersion::from_symbol_version(symbol_version)?,
+                            symbol_version,
+                        ))
+                    }));
+            }
+        }
+
+        let mut pins = vec![];
+        let mut unavailable = vec![];
+
+        for (name, versions) in symbols {
+            let default = match versions.iter().map(|(release, _)| release).max() {
+                Some(release) if release > glibc => release,
+                _ => continue,
+            };
+
+            match versions
+                .iter()
+                .filter(|(release, _)| release <= glibc)
+                .max()
+            {
+                Some((_, symbol_version)) => pins.push(format!(
+                    "__asm__(\".symver {},{}@{}\");",
+                    name, name, symbol_version
+                )),
+                None => unavailable.push(format!(" *   {} (glibc {})", name, default)),
+            }
+        }
+
+        let guard = format!(
+            "PCLANG_GLIBC_SYMVER_{}_H",
+            target
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                })
+                .collect::<String>()
+        );
+
+        let mut lines = vec![
+            format!(
+                "/* Pins glibc symbols on {} to versions provided by glibc {}.",
+                target, glibc
+            ),
+            " *".to_string(),
+            " * Generated by pclang. Include before any other header, e.g. via -include."
+                .to_string(),
+        ];
+        if !unavailable.is_empty() {
+            lines.push(" *".to_string());
+            lines.push(format!(" * Symbols not provided by glibc {}:", glibc));
+            lines.extend(unavailable);
+        }
+        lines.push(" */".to_string());
+        lines.push(format!("#ifndef {}", guard));
+        lines.push(format!("#define {}", guard));
+        lines.push(String::new());
+        lines.extend(pins);
+        lines.push(String::new());
+        lines.push(format!("#endif /* {} */", guard));
+
+        Ok(lines.join("\n") + "\n")
+    }
+
+    /// Generate sources of stub libraries of a target as of a glibc release.
+    ///
+    /// Every symbol version the release provides is defined by its own
+    /// label bound via `.symver`, the newest being the default. Functions
+    /// are empty and data is zero filled. Libraries the release doesn't
+    /// provide any symbols of are omitted.
+    pub fn stub_libraries(&self, target: &str, glibc: &GlibcVersion) -> Result<Vec<StubLibrary>> {
+        let mut res = vec![];
+
+        for (name, lib) in self.libraries(target)? {
+            let mut symbol_versions = BTreeSet::new();
+            let mut text = vec![];
+            let mut data = vec![];
+
+            let symbols = lib
+                .functions
+                .iter()
+                .map(|x| (x, false))
+                .chain(lib.data.iter().map(|x| (x, true)));
+
+            for ((symbol, abi), is_data) in symbols {
+                let versions = abi.versions_as_of(glibc);
+
+                let size = if is_data {
+                    let size = abi.size.as_deref().unwrap_or("0x0");
+                    u64::from_str_radix(size.trim_start_matches("0x"), 16)
+                        .with_context(|| format!("parsing size of {}: {}", symbol, size))?
+                } else {
+                    0
+                };
+
+                for (i, (release, symbol_version)) in versions.iter().enumerate() {
+                    symbol_versions.insert((release.clone(), *symbol_version));
+
+                    let label = format!("{}_{}", symbol, symbol_version.replace('.', "_"));
+                    let binding = if i == versions.len() - 1 { "@@" } else { "@" };
+
+                    let lines = if is_data { &mut data } else { &mut text };
+                    lines.push(format!(".globl {}", label));
+                    lines.push(format!(
+                        ".type {}, {}",
+                        label,
+                        if is_data { "STT_OBJECT" } else { "STT_FUNC" }
+                    ));
+                    if is_data {
+                        lines.push(format!(".size {}, {}", label, size));
+                    }
+                    lines.push(format!(
+                        ".symver {}, {}{}{}",
+                        label, symbol, binding, symbol_version
+                    ));
+                    lines.push(format!("{}:", label));
+                    if is_data {
+                        lines.push(format!(".zero {}", size.max(1)));
+                    }
+                }
+            }
+
+            if symbol_versions.is_empty() {
+                continue;
+            }
+
+            let mut assembly = vec![
+                format!(
+                    "/* Stub of {} for {} as of glibc {}. Generated by pclang. */",
+                    name, target, glibc
+                ),
+                ".text".to_string(),
+            ];
+            assembly.extend(text);
+            assembly.push(".data".to_string());
+            assembly.push(".p2align 4".to_string());
+            assembly.extend(data);
+
+            // Each version inherits the previous one, as in glibc's own
+            // version scripts. Labels are hidden by the first node.
+            let mut version_script = vec![];
+            let mut previous = None;
+            for (_, symbol_version) in symbol_versions {
+                version_script.push(match previous {
+                    None => format!("{} {{ local: *; }};", symbol_version),
+                    Some(previous) => format!("{} {{ }} {};", symbol_version, previous),
+                });
+                previous = Some(symbol_version);
+            }
+
+            res.push(StubLibrary {
+                name: name.clone(),
+                assembly: assembly.join("\n") + "\n",
+                version_script: version_script.join("\n") + "\n",
+            });
+        }
+
+        Ok(res)
+    }
+}
+
+/// The shared library name glibc uses for a library on a target.
+///
+/// Returns `None` for dynamic loaders of targets we don't know the name of.
+fn soname(target: &str, lib: &str) -> Option<String> {
+    let arch = target.split('-').next()?;
+
+    let soname = match lib {
+        "ld" => match arch {
+            "x86_64" if target.contains("-x32") => "ld-linux-x32.so.2",
+            "x86_64" => "ld-linux-x86-64.so.2",
+            "i486" | "i586" | "i686" if target.contains("-linux-") => "ld-linux.so.2",
+            "i686" => "ld.so.1",
+            "aarch64" => "ld-linux-aarch64.so.1",
+            "aarch64_be" => "ld-linux-aarch64_be.so.1",
+            "arm" | "armeb" if target.contains("hf") => "ld-linux-armhf.so.3",
+            "arm" | "armeb" => "ld-linux.so.3",
+            "powerpc64le" => "ld64.so.2",
+            "powerpc64" | "s390x" => "ld64.so.1",
+            "powerpc" | "s390" => "ld.so.1",
+            "riscv64" if target.ends_with("lp64d") => "ld-linux-riscv64-lp64d.so.1",
+            "riscv64" => "ld-linux-riscv64-lp64.so.1",
+            _ => return None,
+        }
+        .to_string(),
+        "libc" | "libm" if matches!(arch, "alpha" | "ia64") => format!("{}.so.6.1", lib),
+        "libc" | "libm" => format!("{}.so.6", lib),
+        "libpthread" | "libc_malloc_debug" => format!("{}.so.0", lib),
+        "libdl" | "libresolv" => format!("{}.so.2", lib),
+        _ if lib.starts_with("libnss_") => format!("{}.so.2", lib),
+        _ => format!("{}.so.1", lib),
+    };
+
+    Some(soname)
+}
+
+/// Build stub libraries of targets as of a glibc release.
+///
+/// Libraries are assembled and linked by `clang`, which must support each
+/// target. They are written to `<dest_dir>/<target>/<lib>.so`.
+pub fn build_stub_libraries(
+    logger: &Logger,
+    db: &GlibcAbiDatabase,
+    glibc: &GlibcVersion,
+    clang: &Path,
+    filter: &BuildFilter,
+    dest_dir: &Path,
+) -> Result<()> {
+    let temp_dir = tempfile::Builder::new().prefix("pclang-").tempdir()?;
+
+    for target in db.targets().filter(|target| filter.matches(target)) {
+        // Targets are glibc build configurations, which may qualify a triple.
+        let triple = target.split('-').take(3).collect::<Vec<_>>().join("-");
+
+        let target_dir = dest_dir.join(target);
+        std::fs::create_dir_all(&target_dir)
+            .with_context(|| format!("creating {}", target_dir.display()))?;
+
+        for lib in db.stub_libraries(target, glibc)? {
+            let soname = match soname(target, &lib.name) {
+                Some(soname) => soname,
+                None => {
+                    warn!(
+                        logger,
+                        "{}: unknown shared library name of {}; skipping", target, lib.name
+                    );
+                    continue;
+                }
+            };
+
+            let source_path = temp_dir.path().join(format!("{}-{}.s", target, lib.name));
+            let script_path = temp_dir.path().join(format!("{}-{}.map", target, lib.name));
+            std::fs::write(&source_path, &lib.assembly)?;
+            std::fs::write(&script_path, &lib.version_script)?;
+
+            let dest_path = target_dir.join(format!("{}.so", lib.name));
+            warn!(logger, "writing {}", dest_path.display());
+
+            let output = std::process::Command::new(clang)
+                .arg(format!("--target={}", triple))
+                .args(["-nostdlib", "-shared", "-fuse-ld=lld"])
+                .arg(format!("-Wl,--version-script={}", script_path.display()))
+                .arg(format!("-Wl,-soname,{}", soname))
+                .arg("-o")
+                .arg(&dest_path)
+                .arg(&source_path)
+                .output()
+                .with_context(|| format!("running {}", clang.display()))?;
+
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "building stub {} for {} failed:\n{}",
+                    lib.name,
+                    target,
+                    String::from_utf8_lossy(&output.stderr).trim_end()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const X86_64: &str = r#"{
+        "libc": {
+            "data": {
+                "stdin": {"address": "0x8", "version": "GLIBC_2.2.5"}
+            },
+            "functions": {
+                "memcpy": {"version": "GLIBC_2.2.5", "versions": ["GLIBC_2.14", "GLIBC_2.2.5"]},
+                "__libc_start_main": {"version": "GLIBC_2.34", "versions": ["GLIBC_2.2.5", "GLIBC_2.34"]},
+                "__libc_freeres": {"version": "GLIBC_PRIVATE", "versions": ["GLIBC_PRIVATE"]},
+                "statx": {"version": "GLIBC_2.28", "versions": ["GLIBC_2.28"]}
+            }
+        }
+    }"#;
+
+    fn database() -> Result<GlibcAbiDatabase> {
+        let mut db = GlibcAbiDatabase::default();
+        db.add_file(Path::new("x86_64-linux-gnu.json"), X86_64.as_bytes())?;
+
+        Ok(db)
+    }
+
+    #[test]
+    fn versions() -> Result<()> {
+        assert!(GlibcVersion::from_str("2.2.5")? < GlibcVersion::from_str("2.14")?);
+        assert_eq!(
+            GlibcVersion::from_symbol_version("GLIBC_2.2.5"),
+            Some(GlibcVersion(vec![2, 2, 5]))
+        );
+        assert_eq!(GlibcVersion::from_symbol_version("GLIBC_PRIVATE"), None);
+        assert_eq!(GlibcVersion(vec![2, 17]).to_string(), "2.17");
+
+        Ok(())
+    }
+
+    #[test]
+    fn queries() -> Result<()> {
+        let db = database()?;
+        let target = "x86_64-linux-gnu";
+        let version = |s: &str| GlibcVersion::from_str(s).ok();
+
+        assert_eq!(db.first_version(target, "memcpy")?, version("2.2.5"));
+        assert_eq!(db.first_version(target, "stdin")?, version("2.2.5"));
+        assert_eq!(db.first_version(target, "strlcpy")?, None);
+        assert!(db.first_version("sparc64-linux-gnu", "memcpy").is_err());
+
+        assert_eq!(db.max_version(target, ["stdin"])?, version("2.2.5"));
+        assert_eq!(
+            db.max_version(target, ["stdin", "memcpy"])?,
+            version("2.14")
+        );
+        assert_eq!(
+            db.max_version(target, ["memcpy@GLIBC_2.2.5", "__libc_freeres"])?,
+            version("2.2.5")
+        );
+        assert_eq!(
+            db.max_version(target, ["__libc_start_main"])?,
+            version("2.34")
+        );
+        assert!(db.max_version(target, ["memcpy@GLIBC_2.17"]).is_err());
+        assert!(db.max_version(target, ["strlcpy"]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn symver_header() -> Result<()> {
+        let db = database()?;
+        // memcpy gained a new version in 2.14, so older targets pin the original.
+        let header = db.symver_header("x86_64-linux-gnu", &GlibcVersion::from_str("2.12")?)?;
+
+        assert!(header.contains("#ifndef PCLANG_GLIBC_SYMVER_X86_64_LINUX_GNU_H\n"));
+        assert!(header.contains("__asm__(\".symver memcpy,memcpy@GLIBC_2.2.5\");\n"));
+        assert!(header
+            .contains("__asm__(\".symver __libc_start_main,__libc_start_main@GLIBC_2.2.5\");\n"));
+        assert!(header.contains(" *   statx (glibc 2.28)\n"));
+        assert!(!header.contains("stdin"));
+        assert!(!header.contains("__libc_freeres"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn stub_libraries() -> Result<()> {
+        let db = database()?;
+        let libs = db.stub_libraries("x86_64-linux-gnu", &GlibcVersion::from_str("2.17")?)?;
+
+        assert_eq!(libs.len(), 1);
+        let lib = &libs[0];
+        assert_eq!(lib.name, "libc");
+
+        assert!(lib
+            .assembly
+            .contains(".symver memcpy_GLIBC_2_2_5, memcpy@GLIBC_2.2.5\n"));
+        assert!(lib
+            .assembly
+            .contains(".symver memcpy_GLIBC_2_14, memcpy@@GLIBC_2.14\n"));
+        assert!(lib.assembly.contains(
+            ".size stdin_GLIBC_2_2_5, 8\n.symver stdin_GLIBC_2_2_5, stdin@@GLIBC_2.2.5\n"
+        ));
+        assert!(!lib.assembly.contains("statx"));
+        assert!(!lib.assembly.contains("GLIBC_PRIVATE"));
+        assert_eq!(
+            lib.version_script,
+            "GLIBC_2.2.5 { local: *; };\nGLIBC_2.14 { } GLIBC_2.2.5;\n"
+        );
+
+        assert_eq!(
+            soname("x86_64-linux-gnu", "ld").as_deref(),
+            Some("ld-linux-x86-64.so.2")
+        );
+        assert_eq!(
+            soname("arm-linux-gnueabihf-v7a", "ld").as_deref(),
+            Some("ld-linux-armhf.so.3")
+        );
+        assert_eq!(
+            soname("ia64-linux-gnu", "libc").as_deref(),
+            Some("libc.so.6.1")
+        );
+        assert_eq!(soname("hppa-linux-gnu", "ld"), None);
+
+        Ok(())
+    }
+}
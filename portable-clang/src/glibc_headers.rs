@@ -0,0 +1,347 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Analysis of headers differing between glibc builds.
+
+Unification shares a header between builds only if it is byte for byte
+identical. Headers that differ must stay per build, but not all differences
+are equal: a changed comment is harmless while a changed struct layout makes
+code compiled against one build's header incompatible with another build.
+
+[find_header_conflicts] classifies the differences of each header using
+lightweight heuristics on the preprocessor-level text. It does not parse C,
+so its report is a guide for which headers can reasonably be shared rather
+than a proof.
+*/
+
+use {
+    crate::glibc::BuildFilter,
+    anyhow::Result,
+    serde::Serialize,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        path::{Path, PathBuf},
+    },
+};
+
+/// How risky the differences of a header are, from least to most.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictSeverity {
+    /// Only comments or whitespace differ.
+    Cosmetic,
+    /// Declarations differ, such as function prototypes.
+    Declarations,
+    /// Macros are defined differently.
+    Definitions,
+    /// A struct or union is defined differently.
+    Layout,
+}
+
+/// A header whose content differs between glibc builds.
+#[derive(Debug, Serialize)]
+pub struct HeaderConflict {
+    /// Path of the header relative to `usr/include`.
+    pub path: PathBuf,
+    /// How risky the differences are.
+    pub severity: ConflictSeverity,
+    /// Builds grouped by identical header content.
+    pub variants: Vec<Vec<String>>,
+    /// Structs, unions, and macros defined differently.
+    pub symbols: Vec<String>,
+}
+
+/// Remove comments and insignificant whitespace from a header.
+///
+/// Returns preprocessor directives as individual lines, with continuation
+/// lines joined. Code between directives is joined into a single line, so
+/// line breaks within declarations are insignificant.
+fn normalize_header(source: &str) -> Vec<String> {
+    let mut text = String::with_capacity(source.len());
+    let mut rest = source;
+
+    // Comments are replaced by a space, preserving newlines of block comments
+    // so preprocessor directives stay on their own lines.
+    while let Some(i) = rest.find('/') {
+        text.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment.find("*/").map_or(comment.len(), |end| end + 2);
+            text.push(' ');
+            text.extend(comment[..end].chars().filter(|c| *c == '\n'));
+            rest = &comment[end..];
+        } else if let Some(comment) = rest.strip_prefix("//") {
+            rest = &comment[comment.find('\n').unwrap_or(comment.len())..];
+        } else {
+            text.push('/');
+            rest = &rest[1..];
+        }
+    }
+    text.push_str(rest);
+
+    let mut res = vec![];
+    let mut code = vec![];
+
+    for line in text.replace("\\\n", " ").lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        if line.starts_with('#') {
+            if !code.is_empty() {
+                res.push(code.join(" "));
+                code.clear();
+            }
+            res.push(line);
+        } else if !line.is_empty() {
+            code.push(line);
+        }
+    }
+
+    if !code.is_empty() {
+        res.push(code.join(" "));
+    }
+
+    res
+}
+
+/// Resolve the macros defined by normalized header lines.
+fn macros(lines: &[String]) -> BTreeMap<String, String> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            let line = line
+                .strip_prefix('#')?
+                .trim_start()
+                .strip_prefix("define ")?;
+            let end = line.find(['(', ' ']).unwrap_or(line.len());
+
+            Some((line[..end].to_string(), line[end..].trim().to_string()))
+        })
+        .collect()
+}
+
+/// Resolve the bodies of named structs and unions in normalized header lines.
+fn aggregates(lines: &[String]) -> BTreeMap<String, String> {
+    let text = lines.join(" ");
+    let mut res = BTreeMap::new();
+
+    for keyword in ["struct ", "union "] {
+        for (start, _) in text.match_indices(keyword) {
+            let rest = &text[start + keyword.len()..];
+
+            let name_end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..name_end];
+            let rest = rest[name_end..].trim_start();
+
+            if name.is_empty() || !rest.starts_with('{') {
+                continue;
+            }
+
+            let mut depth = 0;
+            let end = rest.char_indices().find_map(|(i, c)| {
+                match c {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                (depth == 0).then_some(i + 1)
+            });
+
+            if let Some(end) = end {
+                res.insert(format!("{}{}", keyword, name), rest[..end].to_string());
+            }
+        }
+    }
+
+    res
+}
+
+/// Names of entries whose values differ between maps.
+///
+/// With `require_all`, entries missing from some maps also differ.
+fn differing_names(maps: &[BTreeMap<String, String>], require_all: bool) -> BTreeSet<String> {
+    let names = maps
+        .iter()
+        .flat_map(|map| map.keys())
+        .collect::<BTreeSet<_>>();
+
+    names
+        .into_iter()
+        .filter(|name| {
+            let values = maps.iter().map(|map| map.get(*name)).collect::<Vec<_>>();
+            let present = values.iter().flatten().collect::<BTreeSet<_>>();
+
+            present.len() > 1 || (require_all && values.contains(&None))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Classify the differences between variants of a header.
+fn classify(variants: &[&str]) -> (ConflictSeverity, Vec<String>) {
+    let normalized = variants
+        .iter()
+        .map(|source| normalize_header(source))
+        .collect::<Vec<_>>();
+
+    if normalized.iter().collect::<BTreeSet<_>>().len() == 1 {
+        return (ConflictSeverity::Cosmetic, vec![]);
+    }
+
+    // Structs may move between headers, so only conflicting bodies count.
+    let layouts = differing_names(
+        &normalized
+            .iter()
+            .map(|lines| aggregates(lines))
+            .collect::<Vec<_>>(),
+        false,
+    );
+    let definitions = differing_names(
+        &normalized
+            .iter()
+            .map(|lines| macros(lines))
+            .collect::<Vec<_>>(),
+        true,
+    );
+
+    let severity = if !layouts.is_empty() {
+        ConflictSeverity::Layout
+    } else if !definitions.is_empty() {
+        ConflictSeverity::Definitions
+    } else {
+        ConflictSeverity::Declarations
+    };
+
+    (severity, layouts.into_iter().chain(definitions).collect())
+}
+
+/// Find headers whose content differs between glibc builds.
+///
+/// Headers of the builds in [source_dir] selected by [filter] are compared
+/// by path. Headers missing from some builds are only compared between the
+/// builds having them.
+pub fn find_header_conflicts(
+    source_dir: &Path,
+    filter: &BuildFilter,
+) -> Result<Vec<HeaderConflict>> {
+    let mut builds = vec![];
+
+    for entry in std::fs::read_dir(source_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.metadata()?.is_dir() && filter.matches(&name) {
+            builds.push(name);
+        }
+    }
+
+    builds.sort();
+
+    // Header path -> content -> builds.
+    let mut headers = BTreeMap::<PathBuf, BTreeMap<String, Vec<String>>>::new();
+
+    for build in builds {
+        let include_dir = source_dir.join(&build).join("usr").join("include");
+
+        for entry in walkdir::WalkDir::new(&include_dir) {
+            let entry = entry?;
+
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let data = std::fs::read(entry.path())?;
+
+            headers
+                .entry(entry.path().strip_prefix(&include_dir)?.to_path_buf())
+                .or_default()
+                .entry(String::from_utf8_lossy(&data).to_string())
+                .or_default()
+                .push(build.clone());
+        }
+    }
+
+    Ok(headers
+        .into_iter()
+        .filter(|(_, variants)| variants.len() > 1)
+        .map(|(path, variants)| {
+            let (severity, symbols) =
+                classify(&variants.keys().map(|x| x.as_str()).collect::<Vec<_>>());
+
+            HeaderConflict {
+                path,
+                severity,
+                variants: variants.into_values().collect(),
+                symbols,
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classify_conflicts() {
+        let base = "/* Copyright 2020 */\n#define X 1\nstruct stat {\n  int a;\n};\nint f(void);\n";
+
+        assert_eq!(
+            classify(&[
+                base,
+                "/* Copyright\n   2021 */\n#define  X 1 // one\nstruct stat { int a; };\nint f(void);\n"
+            ]),
+            (ConflictSeverity::Cosmetic, vec![])
+        );
+        assert_eq!(
+            classify(&[base, &base.replace("int f(void)", "long f(void)")]),
+            (ConflictSeverity::Declarations, vec![])
+        );
+        assert_eq!(
+            classify(&[base, &base.replace("#define X 1", "#define X \\\n  2")]),
+            (ConflictSeverity::Definitions, vec!["X".to_string()])
+        );
+        assert_eq!(
+            classify(&[base, &base.replace("int a;", "long a;")]),
+            (ConflictSeverity::Layout, vec!["struct stat".to_string()])
+        );
+        // A struct defined by only one variant is not a layout conflict.
+        assert_eq!(
+            classify(&[base, &base.replace("struct stat {\n  int a;\n};", "")]),
+            (ConflictSeverity::Declarations, vec![])
+        );
+    }
+
+    #[test]
+    fn find_conflicts() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let source_dir = temp_dir.path();
+
+        for (build, size) in [("a", "int"), ("b", "int"), ("c", "long")] {
+            let include_dir = source_dir.join(build).join("usr/include");
+            std::fs::create_dir_all(&include_dir)?;
+            std::fs::write(include_dir.join("shared.h"), "int f(void);\n")?;
+            std::fs::write(
+                include_dir.join("stat.h"),
+                format!("struct stat {{ {} st_size; }};\n", size),
+            )?;
+        }
+
+        let conflicts = find_header_conflicts(source_dir, &BuildFilter::default())?;
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, PathBuf::from("stat.h"));
+        assert_eq!(conflicts[0].severity, ConflictSeverity::Layout);
+        assert_eq!(
+            conflicts[0].variants,
+            vec![
+                vec!["a".to_string(), "b".to_string()],
+                vec!["c".to_string()]
+            ]
+        );
+
+        Ok(())
+    }
+}
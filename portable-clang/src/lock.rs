@@ -0,0 +1,115 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Locking of resources shared by processes using the same cache directory.
+
+Concurrent `pclang` processes sharing a cache directory would otherwise
+corrupt downloads and the sccache cache. Locks are advisory file locks, so
+they are released by the operating system if a process dies.
+*/
+
+use {
+    anyhow::{anyhow, Context, Result},
+    fs2::FileExt,
+    slog::{warn, Logger},
+    std::{
+        fs::File,
+        path::{Path, PathBuf},
+        time::Duration,
+    },
+};
+
+/// How often to retry acquiring a contended lock.
+///
+/// We poll instead of blocking so the async runtime can still handle signals.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An exclusive lock on a named resource. Released when dropped.
+pub struct CacheLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl CacheLock {
+    /// Acquire the lock named `name` in `lock_dir`.
+    ///
+    /// If another process holds the lock, this waits for it to be released
+    /// when `wait` is set and fails otherwise.
+    pub async fn acquire(logger: &Logger, lock_dir: &Path, name: &str, wait: bool) -> Result<Self> {
+        std::fs::create_dir_all(lock_dir).context("creating lock directory")?;
+
+        let path = lock_dir.join(format!("{}.lock", name));
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("opening lock file {}", path.display()))?;
+
+        let mut waiting = false;
+
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => {
+                    return Ok(Self { file, path });
+                }
+                Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                    if !wait {
+                        return Err(anyhow!(
+                            "{} is locked by another pclang process; retry later or use --wait",
+                            path.display()
+                        ));
+                    }
+
+                    if !waiting {
+                        warn!(
+                            logger,
+                            "waiting for another pclang process to release {}",
+                            path.display()
+                        );
+                        waiting = true;
+                    }
+
+                    tokio::time::sleep(LOCK_POLL_INTERVAL).await;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("locking {}", path.display()));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        // Closing the file also releases the lock. This just makes it explicit.
+        if let Err(e) = self.file.unlock() {
+            eprintln!("failed to unlock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn exclusive() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+
+        let lock = CacheLock::acquire(&logger, temp_dir.path(), "images", false).await?;
+        assert!(
+            CacheLock::acquire(&logger, temp_dir.path(), "images", false)
+                .await
+                .is_err()
+        );
+        CacheLock::acquire(&logger, temp_dir.path(), "sccache", false).await?;
+
+        drop(lock);
+        CacheLock::acquire(&logger, temp_dir.path(), "images", false).await?;
+
+        Ok(())
+    }
+}
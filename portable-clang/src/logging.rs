@@ -2,7 +2,23 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use slog::{Drain, Level, Logger};
+use {
+    anyhow::{anyhow, Result},
+    slog::{Drain, Key, Level, Logger, Serializer, KV},
+    std::fmt::Write,
+};
+
+/// A slog Serializer rendering values as a `[value] ` prefix.
+#[derive(Default)]
+struct PrefixSerializer(String);
+
+impl Serializer for PrefixSerializer {
+    fn emit_arguments(&mut self, _key: Key, val: &std::fmt::Arguments) -> slog::Result {
+        write!(self.0, "[{}] ", val)?;
+
+        Ok(())
+    }
+}
 
 /// A slog Drain that uses println!.
 pub struct PrintlnDrain {
@@ -18,22 +34,72 @@ impl Drain for PrintlnDrain {
     fn log(
         &self,
         record: &slog::Record,
-        _values: &slog::OwnedKVList,
+        values: &slog::OwnedKVList,
     ) -> Result<Self::Ok, Self::Err> {
         if record.level().is_at_least(self.min_level) {
-            println!("{}", record.msg());
+            // Values attached to child loggers identify the source of a message.
+            let mut prefix = PrefixSerializer::default();
+            values
+                .serialize(record, &mut prefix)
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+            println!("{}{}", prefix.0, record.msg());
         }
 
         Ok(())
     }
 }
 
-pub fn logger() -> Logger {
-    Logger::root(
-        PrintlnDrain {
-            min_level: Level::Info,
-        }
-        .fuse(),
-        slog::o!(),
-    )
+/// Parse a logging level name as accepted by `PCLANG_LOG`.
+fn parse_level(value: &str) -> Result<Level> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "critical" => Ok(Level::Critical),
+        "error" => Ok(Level::Error),
+        "warn" | "warning" => Ok(Level::Warning),
+        "info" => Ok(Level::Info),
+        "debug" => Ok(Level::Debug),
+        "trace" => Ok(Level::Trace),
+        _ => Err(anyhow!("unsupported log level: {}", value)),
+    }
+}
+
+/// Resolve the minimum logging level.
+///
+/// `-v` and `-q` flags take precedence over the `PCLANG_LOG` level, which
+/// defaults to info. Most status output is logged as warnings, so quiet
+/// mode only emits errors.
+pub fn resolve_level(verbose: u64, quiet: bool, env_level: Option<&str>) -> Result<Level> {
+    if quiet {
+        Ok(Level::Error)
+    } else if verbose >= 2 {
+        Ok(Level::Trace)
+    } else if verbose == 1 {
+        Ok(Level::Debug)
+    } else if let Some(value) = env_level {
+        parse_level(value)
+    } else {
+        Ok(Level::Info)
+    }
+}
+
+pub fn logger(min_level: Level) -> Logger {
+    Logger::root(PrintlnDrain { min_level }.fuse(), slog::o!())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn levels() -> Result<()> {
+        assert_eq!(resolve_level(0, false, None)?, Level::Info);
+        assert_eq!(resolve_level(0, false, Some("DEBUG"))?, Level::Debug);
+        assert_eq!(resolve_level(0, false, Some("warn"))?, Level::Warning);
+        assert_eq!(resolve_level(1, false, Some("error"))?, Level::Debug);
+        assert_eq!(resolve_level(3, false, None)?, Level::Trace);
+        assert_eq!(resolve_level(0, true, Some("trace"))?, Level::Error);
+        assert!(resolve_level(0, false, Some("loud")).is_err());
+
+        Ok(())
+    }
 }
@@ -4,18 +4,42 @@
 
 mod build;
 mod cli;
+mod config;
 mod docker;
+mod dockerfile;
 mod downloads;
+mod extract;
+mod git;
 mod glibc;
+mod glibc_abi;
+mod glibc_headers;
+mod lock;
 mod logging;
+mod oci;
+mod preflight;
+mod progress;
+mod resources;
+mod runtime;
+mod seekable;
+mod split;
 mod tar;
+mod upstream;
 
 fn main() {
     let exit_code = match cli::run() {
         Ok(code) => code,
         Err(e) => {
             eprintln!("error: {:#?}", e);
-            1
+
+            // Mirror the exit code of a failed container so callers can act on it.
+            if let Some(container) = e.downcast_ref::<docker::ContainerExitError>() {
+                i32::try_from(container.exit_code).unwrap_or(1)
+            } else if e.is::<docker::ContainerTimeoutError>() {
+                // Same as timeout(1).
+                124
+            } else {
+                1
+            }
         }
     };
 
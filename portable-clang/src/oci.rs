@@ -0,0 +1,229 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Conversion of images to the OCI image layout.
+
+The Docker API exports images in the `docker save` format. This converts
+such archives to an [OCI image layout](https://github.com/opencontainers/image-spec/blob/main/image-layout.md),
+which podman, containerd, skopeo, and registries can consume directly.
+*/
+
+use {
+    anyhow::{anyhow, Context, Result},
+    serde_json::{json, Value},
+    sha2::Digest,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        io::{Read, Write},
+        path::Path,
+    },
+};
+
+const MEDIA_TYPE_MANIFEST: &str = "application/vnd.oci.image.manifest.v1+json";
+const MEDIA_TYPE_CONFIG: &str = "application/vnd.oci.image.config.v1+json";
+const MEDIA_TYPE_LAYER: &str = "application/vnd.oci.image.layer.v1.tar";
+
+/// A content addressed blob in an OCI layout.
+#[derive(Clone, Debug)]
+struct Blob {
+    digest: String,
+    size: u64,
+}
+
+impl Blob {
+    fn descriptor(&self, media_type: &str) -> Value {
+        json!({
+            "mediaType": media_type,
+            "digest": format!("sha256:{}", self.digest),
+            "size": self.size,
+        })
+    }
+}
+
+/// Write data to the blobs directory of a layout, returning its digest and size.
+fn write_blob(blobs_dir: &Path, mut reader: impl Read) -> Result<Blob> {
+    let mut temp = tempfile::NamedTempFile::new_in(blobs_dir).context("creating blob file")?;
+    let mut hasher = sha2::Sha256::new();
+    let mut size = 0;
+    let mut buffer = [0u8; 65536];
+
+    loop {
+        let count = reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[0..count]);
+        temp.write_all(&buffer[0..count])?;
+        size += count as u64;
+    }
+
+    let digest = hex::encode(hasher.finalize());
+    temp.persist(blobs_dir.join(&digest))
+        .context("persisting blob file")?;
+
+    Ok(Blob { digest, size })
+}
+
+/// Convert a `docker save` archive to an OCI image layout directory.
+///
+/// The image is referenced in the layout's index as `ref_name`.
+pub fn docker_archive_to_oci_layout(
+    archive: impl Read,
+    ref_name: &str,
+    dest_dir: &Path,
+) -> Result<()> {
+    let blobs_dir = dest_dir.join("blobs").join("sha256");
+    std::fs::create_dir_all(&blobs_dir).context("creating blobs directory")?;
+
+    // The manifest may come after the files it references. So we store every
+    // file as a blob and remove the ones that aren't referenced afterwards.
+    let mut blobs = BTreeMap::new();
+    let mut docker_manifest = None;
+
+    let mut archive = tar::Archive::new(archive);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        let path = entry.path()?.to_string_lossy().to_string();
+
+        if path == "manifest.json" {
+            let mut data = vec![];
+            entry.read_to_end(&mut data)?;
+            docker_manifest =
+                Some(serde_json::from_slice::<Value>(&data).context("parsing Docker manifest")?);
+        } else {
+            blobs.insert(path, write_blob(&blobs_dir, &mut entry)?);
+        }
+    }
+
+    let docker_manifest =
+        docker_manifest.ok_or_else(|| anyhow!("image archive does not contain manifest.json"))?;
+    let image = docker_manifest
+        .as_array()
+        .and_then(|images| images.first())
+        .ok_or_else(|| anyhow!("image archive manifest does not define an image"))?;
+
+    let lookup = |path: &Value| -> Result<Blob> {
+        let path = path
+            .as_str()
+            .ok_or_else(|| anyhow!("invalid path in Docker manifest"))?;
+
+        blobs
+            .get(path)
+            .cloned()
+            .ok_or_else(|| anyhow!("image archive does not contain {}", path))
+    };
+
+    let config = lookup(&image["Config"])?;
+    let layers = image["Layers"]
+        .as_array()
+        .ok_or_else(|| anyhow!("Docker manifest does not define layers"))?
+        .iter()
+        .map(lookup)
+        .collect::<Result<Vec<_>>>()?;
+
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": MEDIA_TYPE_MANIFEST,
+        "config": config.descriptor(MEDIA_TYPE_CONFIG),
+        "layers": layers
+            .iter()
+            .map(|layer| layer.descriptor(MEDIA_TYPE_LAYER))
+            .collect::<Vec<_>>(),
+    });
+    let manifest_blob = write_blob(&blobs_dir, serde_json::to_vec(&manifest)?.as_slice())?;
+
+    let referenced = layers
+        .iter()
+        .chain([&config, &manifest_blob])
+        .map(|blob| blob.digest.as_str())
+        .collect::<BTreeSet<_>>();
+
+    for blob in blobs.values() {
+        if !referenced.contains(blob.digest.as_str()) {
+            let path = blobs_dir.join(&blob.digest);
+            if path.exists() {
+                std::fs::remove_file(&path).context("removing unreferenced blob")?;
+            }
+        }
+    }
+
+    let mut descriptor = manifest_blob.descriptor(MEDIA_TYPE_MANIFEST);
+    descriptor["annotations"] = json!({ "org.opencontainers.image.ref.name": ref_name });
+
+    let index = json!({
+        "schemaVersion": 2,
+        "manifests": [descriptor],
+    });
+
+    std::fs::write(dest_dir.join("index.json"), serde_json::to_vec(&index)?)
+        .context("writing index.json")?;
+    std::fs::write(
+        dest_dir.join("oci-layout"),
+        serde_json::to_vec(&json!({ "imageLayoutVersion": "1.0.0" }))?,
+    )
+    .context("writing oci-layout")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn append(builder: &mut tar::Builder<Vec<u8>>, path: &str, data: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+
+    #[test]
+    fn convert_docker_archive() -> Result<()> {
+        let mut builder = tar::Builder::new(vec![]);
+        append(&mut builder, "abc/layer.tar", b"layer");
+        append(&mut builder, "abc/VERSION", b"1.0");
+        append(&mut builder, "config.json", b"{}");
+        append(
+            &mut builder,
+            "manifest.json",
+            br#"[{"Config": "config.json", "RepoTags": null, "Layers": ["abc/layer.tar"]}]"#,
+        );
+        let archive = builder.into_inner()?;
+
+        let temp_dir = tempfile::tempdir()?;
+        docker_archive_to_oci_layout(archive.as_slice(), "clang", temp_dir.path())?;
+
+        let index: Value =
+            serde_json::from_slice(&std::fs::read(temp_dir.path().join("index.json"))?)?;
+        let descriptor = &index["manifests"][0];
+        assert_eq!(
+            descriptor["annotations"]["org.opencontainers.image.ref.name"],
+            "clang"
+        );
+
+        let manifest_digest = descriptor["digest"].as_str().unwrap();
+        let manifest: Value = serde_json::from_slice(&std::fs::read(
+            temp_dir
+                .path()
+                .join("blobs/sha256")
+                .join(manifest_digest.trim_start_matches("sha256:")),
+        )?)?;
+        assert_eq!(manifest["layers"][0]["size"], 5);
+        assert_eq!(manifest["config"]["mediaType"], MEDIA_TYPE_CONFIG);
+
+        // Only the config, layer, and manifest are retained.
+        assert_eq!(
+            std::fs::read_dir(temp_dir.path().join("blobs/sha256"))?.count(),
+            3
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Validation of the build environment before doing expensive work. */
+
+use {
+    crate::docker::{ContainerSettings, FileTransferMode, UserMapping},
+    anyhow::{anyhow, Context, Result},
+    bollard::Docker,
+    std::path::Path,
+};
+
+/// Minimum free disk space we want for images and build artifacts.
+///
+/// A full LLVM build with its builder images consumes well over 10 GiB.
+pub const MIN_FREE_SPACE: u64 = 20 * 1024 * 1024 * 1024;
+
+/// Outcome of a single preflight check.
+pub struct PreflightCheck {
+    /// Short name of what was checked.
+    pub name: &'static str,
+    /// Details on success. Actionable description of the problem on failure.
+    pub result: Result<String>,
+}
+
+/// The outcome of all preflight checks.
+#[derive(Default)]
+pub struct PreflightReport {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    fn record(&mut self, name: &'static str, result: Result<String>) {
+        self.checks.push(PreflightCheck { name, result });
+    }
+
+    /// Whether all checks passed.
+    pub fn is_ok(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    /// Convert failed checks into an error.
+    pub fn into_result(self) -> Result<()> {
+        let failures = self
+            .checks
+            .into_iter()
+            .filter_map(|check| {
+                check
+                    .result
+                    .err()
+                    .map(|e| format!("{}: {:#}", check.name, e))
+            })
+            .collect::<Vec<_>>();
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("preflight checks failed:\n{}", failures.join("\n")))
+        }
+    }
+}
+
+/// Obtain the free space in bytes of the filesystem holding a path.
+///
+/// Uses `df` so we don't need platform specific filesystem APIs.
+fn free_space(path: &Path) -> Result<u64> {
+    let output = std::process::Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .context("running df")?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "df failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // The 4th column of the last line is the available 1024 byte blocks.
+    let available = stdout
+        .lines()
+        .last()
+        .and_then(|line| line.split_whitespace().nth(3))
+        .ok_or_else(|| anyhow!("unable to parse df output"))?
+        .parse::<u64>()
+        .context("parsing available blocks")?;
+
+    Ok(available * 1024)
+}
+
+fn check_free_space(path: &Path) -> Result<String> {
+    let free = free_space(path)?;
+    let gib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0 * 1024.0);
+
+    if free < MIN_FREE_SPACE {
+        Err(anyhow!(
+            "only {:.1} GiB free on the filesystem holding {}; at least {:.1} GiB is needed \
+             (free up space or run `pclang docker-gc`)",
+            gib(free),
+            path.display(),
+            gib(MIN_FREE_SPACE)
+        ))
+    } else {
+        Ok(format!("{:.1} GiB free at {}", gib(free), path.display()))
+    }
+}
+
+/// Parse a Docker API version like `1.41`.
+fn parse_api_version(version: &str) -> Option<(usize, usize)> {
+    let (major, minor) = version.split_once('.')?;
+
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Run preflight checks against a Docker daemon.
+///
+/// `host` is the resolved address of the daemon. `cache_dir` is where
+/// downloads and artifacts are stored.
+pub async fn preflight(
+    docker: &Docker,
+    host: &str,
+    settings: &ContainerSettings,
+    cache_dir: &Path,
+) -> PreflightReport {
+    let mut report = PreflightReport::default();
+
+    if let Err(e) = docker.ping().await {
+        report.record(
+            "daemon connectivity",
+            Err(anyhow!(
+                "unable to reach Docker daemon at {}: {}; is it running? \
+                 (use --docker-socket or DOCKER_HOST to point elsewhere)",
+                host,
+                e
+            )),
+        );
+
+        // Nothing else can be checked without a daemon.
+        return report;
+    }
+    report.record("daemon connectivity", Ok(format!("connected to {}", host)));
+
+    let client = docker.client_version();
+    let version = docker
+        .version()
+        .await
+        .context("querying daemon version")
+        .and_then(|version| {
+            let server = version
+                .api_version
+                .ok_or_else(|| anyhow!("daemon did not report its API version"))?;
+
+            match parse_api_version(&server) {
+                Some(server_version)
+                    if server_version >= (client.major_version, client.minor_version) =>
+                {
+                    Ok(format!(
+                        "daemon API {} supports client API {}",
+                        server, client
+                    ))
+                }
+                Some(_) => Err(anyhow!(
+                    "daemon API version {} is older than required version {}; upgrade Docker",
+                    server,
+                    client
+                )),
+                None => Err(anyhow!("unable to parse daemon API version {}", server)),
+            }
+        });
+    report.record("API version", version);
+
+    let info = docker.info().await.context("querying daemon info");
+
+    let local = host.starts_with("unix://") || host.starts_with("npipe://");
+
+    if let Ok(info) = &info {
+        if let Some(root) = info.docker_root_dir.as_deref().map(Path::new) {
+            if local && root.exists() {
+                report.record("image disk space", check_free_space(root));
+            }
+        }
+    }
+    report.record("cache disk space", check_free_space(cache_dir));
+
+    if settings.transfer_mode == FileTransferMode::Bind {
+        let rootless = info
+            .as_ref()
+            .ok()
+            .and_then(|info| info.security_options.as_ref())
+            .map(|options| options.iter().any(|o| o.contains("rootless")))
+            .unwrap_or(false);
+
+        let binds = if !local {
+            Err(anyhow!(
+                "bind mounts require a local daemon but {} is remote; \
+                 use --transfer-mode archive",
+                host
+            ))
+        } else if rootless && settings.user_mapping != UserMapping::Rootless {
+            Err(anyhow!(
+                "daemon is rootless so bind mounted directories may not be writable; \
+                 use --user-mapping rootless or --transfer-mode archive"
+            ))
+        } else {
+            Ok("local daemon can bind mount host directories".to_string())
+        };
+        report.record("bind mounts", binds);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn api_version() {
+        assert_eq!(parse_api_version("1.41"), Some((1, 41)));
+        assert_eq!(parse_api_version("1"), None);
+        assert!(parse_api_version("1.40") < parse_api_version("1.41"));
+    }
+}
@@ -0,0 +1,266 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Progress reporting for long running Docker and archive operations.
+
+Progress bars are only drawn when stderr is a terminal. Otherwise status
+updates are sent to the logger, one line per event, as before.
+*/
+
+use {
+    bollard::models::ProgressDetail,
+    indicatif::{ProgressBar, ProgressStyle},
+    slog::{warn, Logger},
+    std::{cell::RefCell, collections::BTreeMap, path::Path},
+};
+
+/// Tracks progress of transferring image layers during builds, pulls, and pushes.
+///
+/// Docker reports progress per layer. We aggregate all layers into a single bar.
+pub struct LayerProgress<'a> {
+    logger: &'a Logger,
+    bar: ProgressBar,
+    layers: BTreeMap<String, (u64, u64)>,
+}
+
+impl<'a> LayerProgress<'a> {
+    pub fn new(logger: &'a Logger, prefix: &str) -> Self {
+        let bar = ProgressBar::new(0);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{prefix} [{bar:40}] {bytes}/{total_bytes} {wide_msg}")
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(prefix.to_string());
+
+        Self {
+            logger,
+            bar,
+            layers: BTreeMap::new(),
+        }
+    }
+
+    /// Record a status event for a layer.
+    pub fn update(
+        &mut self,
+        id: Option<&str>,
+        status: &str,
+        progress: Option<&str>,
+        detail: Option<&ProgressDetail>,
+    ) {
+        if self.bar.is_hidden() {
+            if let Some(progress) = progress {
+                warn!(self.logger, "{} {}", status, progress);
+            } else {
+                warn!(self.logger, "{}", status);
+            }
+
+            return;
+        }
+
+        if let (Some(id), Some(detail)) = (id, detail) {
+            if let (Some(current), Some(total)) = (detail.current, detail.total) {
+                let (current, total) = self.record_layer(id, current, total);
+
+                self.bar.set_length(total);
+                self.bar.set_position(current);
+            }
+        }
+
+        if let Some(id) = id {
+            self.bar.set_message(format!("{}: {}", id, status));
+        } else {
+            self.bar.set_message(status.to_string());
+        }
+    }
+
+    /// Record progress of a layer and return the progress of all layers.
+    fn record_layer(&mut self, id: &str, current: i64, total: i64) -> (u64, u64) {
+        self.layers
+            .insert(id.to_string(), (current.max(0) as u64, total.max(0) as u64));
+
+        self.layers
+            .values()
+            .fold((0, 0), |(c, t), (current, total)| (c + current, t + total))
+    }
+
+    /// Emit a line of output without disturbing the progress bar.
+    pub fn println(&self, line: &str) {
+        if self.bar.is_hidden() {
+            warn!(self.logger, "{}", line);
+        } else {
+            self.bar.println(line);
+        }
+    }
+}
+
+impl<'a> Drop for LayerProgress<'a> {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+/// Obtain a progress bar for a transfer of a known or unknown number of bytes.
+pub fn bytes_progress(prefix: &str, total: Option<u64>) -> ProgressBar {
+    let bar = if let Some(total) = total {
+        let bar = ProgressBar::new(total);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{prefix} [{bar:40}] {bytes}/{total_bytes} ({binary_bytes_per_sec})")
+                .progress_chars("=> "),
+        );
+        bar
+    } else {
+        let bar = ProgressBar::new_spinner();
+        bar.set_style(
+            ProgressStyle::default_spinner()
+                .template("{prefix} {spinner} {bytes} ({binary_bytes_per_sec})"),
+        );
+        bar
+    };
+
+    bar.set_prefix(prefix.to_string());
+
+    bar
+}
+
+/// Receives progress of creating and compressing archives.
+///
+/// All methods default to doing nothing, so `()` reports no progress.
+pub trait ArchiveProgress {
+    /// Begin a phase processing `total` bytes.
+    fn start(&self, _phase: &str, _total: u64) {}
+
+    /// Record that `bytes` more bytes were processed, optionally of `file`.
+    fn advance(&self, _bytes: u64, _file: Option<&Path>) {}
+
+    /// End the current phase.
+    fn finish(&self) {}
+}
+
+impl ArchiveProgress for () {}
+
+/// Reports archive progress with a progress bar.
+///
+/// When progress bars aren't drawn, phases and files are logged instead.
+pub struct ArchiveProgressBar<'a> {
+    logger: &'a Logger,
+    /// The current phase and its progress bar.
+    bar: RefCell<Option<(String, ProgressBar)>>,
+}
+
+impl<'a> ArchiveProgressBar<'a> {
+    pub fn new(logger: &'a Logger) -> Self {
+        Self {
+            logger,
+            bar: RefCell::new(None),
+        }
+    }
+}
+
+impl<'a> ArchiveProgress for ArchiveProgressBar<'a> {
+    fn start(&self, phase: &str, total: u64) {
+        self.finish();
+
+        let bar = ProgressBar::new(total);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{prefix} [{bar:40}] {bytes}/{total_bytes} ({binary_bytes_per_sec}) {wide_msg}",
+                )
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(phase.to_string());
+
+        if bar.is_hidden() {
+            warn!(self.logger, "{} ({} bytes)", phase, total);
+        }
+
+        self.bar.replace(Some((phase.to_string(), bar)));
+    }
+
+    fn advance(&self, bytes: u64, file: Option<&Path>) {
+        if let Some((phase, bar)) = self.bar.borrow().as_ref() {
+            bar.inc(bytes);
+
+            if let Some(file) = file {
+                if bar.is_hidden() {
+                    warn!(self.logger, "{}: {}", phase, file.display());
+                } else {
+                    bar.set_message(file.display().to_string());
+                }
+            }
+        }
+    }
+
+    fn finish(&self) {
+        if let Some((_, bar)) = self.bar.take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+impl<'a> Drop for ArchiveProgressBar<'a> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layer_totals() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let mut progress = LayerProgress::new(&logger, "pulling");
+
+        assert_eq!(progress.record_layer("a", 10, 100), (10, 100));
+        assert_eq!(progress.record_layer("b", 5, 50), (15, 150));
+
+        // Later events replace earlier ones for the same layer.
+        assert_eq!(progress.record_layer("a", 100, 100), (105, 150));
+
+        // Docker reports negative values for unknown sizes.
+        assert_eq!(progress.record_layer("c", -1, -1), (105, 150));
+    }
+
+    #[test]
+    fn bytes_progress_length() {
+        assert_eq!(bytes_progress("downloading", Some(42)).length(), 42);
+        assert_eq!(bytes_progress("downloading", None).length(), !0);
+    }
+
+    #[test]
+    fn archive_progress_bar() {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let progress = ArchiveProgressBar::new(&logger);
+
+        // Advancing without a phase is ignored.
+        progress.advance(10, None);
+        assert!(progress.bar.borrow().is_none());
+
+        progress.start("archiving", 100);
+        progress.advance(10, Some(Path::new("bin/clang")));
+        progress.advance(20, None);
+        {
+            let bar = progress.bar.borrow();
+            let (phase, bar) = bar.as_ref().expect("phase should be started");
+            assert_eq!(phase, "archiving");
+            assert_eq!((bar.position(), bar.length()), (30, 100));
+        }
+
+        // Starting a phase replaces the previous one.
+        progress.start("compressing", 200);
+        {
+            let bar = progress.bar.borrow();
+            let (phase, bar) = bar.as_ref().expect("phase should be started");
+            assert_eq!(phase, "compressing");
+            assert_eq!((bar.position(), bar.length()), (0, 200));
+        }
+
+        progress.finish();
+        assert!(progress.bar.borrow().is_none());
+    }
+}
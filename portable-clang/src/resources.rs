@@ -0,0 +1,265 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Monitoring of container resource usage.
+
+Statistics help tune `PARALLEL` and memory limits for builds.
+*/
+
+use {
+    bollard::{
+        container::{Stats, StatsOptions},
+        Docker,
+    },
+    futures_util::stream::StreamExt,
+    indicatif::HumanBytes,
+    slog::{warn, Logger},
+    std::{
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// How often to log a summary of resource usage.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Resource usage observed for a container.
+#[derive(Clone, Debug, Default)]
+pub struct ResourceUsage {
+    /// Most recent CPU utilization. 100% is one fully utilized CPU.
+    pub cpu_percent: f64,
+    pub peak_cpu_percent: f64,
+    /// Most recent memory usage in bytes.
+    pub memory: u64,
+    pub peak_memory: u64,
+    pub memory_limit: u64,
+    /// Bytes read from and written to block devices.
+    pub block_read: u64,
+    pub block_write: u64,
+}
+
+impl ResourceUsage {
+    fn record(&mut self, stats: &Stats) {
+        let cpu_delta = stats
+            .cpu_stats
+            .cpu_usage
+            .total_usage
+            .saturating_sub(stats.precpu_stats.cpu_usage.total_usage);
+        let system_delta = stats
+            .cpu_stats
+            .system_cpu_usage
+            .unwrap_or_default()
+            .saturating_sub(stats.precpu_stats.system_cpu_usage.unwrap_or_default());
+        let cpus = stats.cpu_stats.online_cpus.unwrap_or(1);
+
+        if system_delta > 0 {
+            self.cpu_percent = cpu_delta as f64 / system_delta as f64 * cpus as f64 * 100.0;
+            self.peak_cpu_percent = self.peak_cpu_percent.max(self.cpu_percent);
+        }
+
+        if let Some(usage) = stats.memory_stats.usage {
+            self.memory = usage;
+            self.peak_memory = self.peak_memory.max(usage);
+        }
+        if let Some(limit) = stats.memory_stats.limit {
+            self.memory_limit = limit;
+        }
+
+        if let Some(entries) = &stats.blkio_stats.io_service_bytes_recursive {
+            let total = |op: &str| {
+                entries
+                    .iter()
+                    .filter(|entry| entry.op.eq_ignore_ascii_case(op))
+                    .map(|entry| entry.value)
+                    .sum()
+            };
+
+            self.block_read = total("read");
+            self.block_write = total("write");
+        }
+    }
+
+    /// Describe the most recent usage.
+    fn summary(&self) -> String {
+        self.describe(self.cpu_percent, self.memory)
+    }
+
+    /// Describe the peak usage.
+    fn peak_summary(&self) -> String {
+        self.describe(self.peak_cpu_percent, self.peak_memory)
+    }
+
+    fn describe(&self, cpu_percent: f64, memory: u64) -> String {
+        format!(
+            "{:.0}% CPU; {} / {} memory; {} read, {} written",
+            cpu_percent,
+            HumanBytes(memory),
+            HumanBytes(self.memory_limit),
+            HumanBytes(self.block_read),
+            HumanBytes(self.block_write)
+        )
+    }
+}
+
+/// Records resource usage of a running container in the background.
+///
+/// Monitoring stops when the container stops or this is dropped.
+pub struct ContainerMonitor {
+    usage: Arc<Mutex<ResourceUsage>>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl ContainerMonitor {
+    /// Start monitoring a started container.
+    pub fn start(logger: &Logger, docker: &Docker, container_id: &str) -> Self {
+        let usage = Arc::new(Mutex::new(ResourceUsage::default()));
+
+        let logger = logger.clone();
+        let docker = docker.clone();
+        let container_id = container_id.to_string();
+        let task_usage = usage.clone();
+
+        let handle = tokio::spawn(async move {
+            let options = StatsOptions {
+                stream: true,
+                ..Default::default()
+            };
+            let mut stream = docker.stats(&container_id, Some(options));
+            let mut last_summary = Instant::now();
+
+            // Errors are ignored because monitoring is best effort.
+            while let Some(Ok(stats)) = stream.next().await {
+                let usage = {
+                    let mut usage = task_usage
+                        .lock()
+                        .expect("resource usage lock should not be poisoned");
+                    usage.record(&stats);
+                    usage.clone()
+                };
+
+                if last_summary.elapsed() >= SUMMARY_INTERVAL {
+                    last_summary = Instant::now();
+
+                    warn!(logger, "resources: {}", usage.summary());
+                }
+            }
+        });
+
+        Self { usage, handle }
+    }
+
+    /// Stop monitoring and log peak usage.
+    pub fn finish(self, logger: &Logger) -> ResourceUsage {
+        let usage = self
+            .usage
+            .lock()
+            .expect("resource usage lock should not be poisoned")
+            .clone();
+
+        warn!(logger, "peak resources: {}", usage.peak_summary());
+
+        usage
+    }
+}
+
+impl Drop for ContainerMonitor {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, serde_json::json};
+
+    /// Docker stats with the given cumulative CPU and current memory usage.
+    fn stats(total_usage: u64, system_cpu_usage: u64, memory: u64) -> Stats {
+        let cpu_stats = |total_usage: u64, system_cpu_usage: u64| {
+            json!({
+                "cpu_usage": {
+                    "total_usage": total_usage,
+                    "usage_in_usermode": total_usage,
+                    "usage_in_kernelmode": 0,
+                },
+                "system_cpu_usage": system_cpu_usage,
+                "online_cpus": 4,
+                "throttling_data": {
+                    "periods": 0,
+                    "throttled_periods": 0,
+                    "throttled_time": 0,
+                },
+            })
+        };
+
+        serde_json::from_value(json!({
+            "read": "2021-01-01T00:00:01Z",
+            "preread": "2021-01-01T00:00:00Z",
+            "num_procs": 0,
+            "pids_stats": {},
+            "memory_stats": {
+                "usage": memory,
+                "limit": 8u64 << 30,
+            },
+            "blkio_stats": {
+                "io_service_bytes_recursive": [
+                    {"major": 8, "minor": 0, "op": "Read", "value": 1 << 20},
+                    {"major": 8, "minor": 16, "op": "read", "value": 1 << 20},
+                    {"major": 8, "minor": 0, "op": "Write", "value": 1 << 10},
+                    {"major": 8, "minor": 0, "op": "Total", "value": 1u64 << 30},
+                ],
+            },
+            "cpu_stats": cpu_stats(total_usage, system_cpu_usage),
+            "precpu_stats": cpu_stats(1000, 10000),
+            "storage_stats": {},
+            "name": "/pclang",
+            "id": "0123456789ab",
+        }))
+        .expect("stats should deserialize")
+    }
+
+    #[test]
+    fn record() {
+        let mut usage = ResourceUsage::default();
+
+        // Half of the system's CPU time on 4 CPUs is 2 fully utilized CPUs.
+        usage.record(&stats(6000, 20000, 3 << 30));
+        assert_eq!(usage.cpu_percent, 200.0);
+        assert_eq!(usage.memory, 3 << 30);
+        assert_eq!(usage.memory_limit, 8 << 30);
+        assert_eq!(usage.block_read, 2 << 20);
+        assert_eq!(usage.block_write, 1 << 10);
+
+        usage.record(&stats(2000, 20000, 1 << 30));
+        assert_eq!(usage.cpu_percent, 40.0);
+        assert_eq!(usage.peak_cpu_percent, 200.0);
+        assert_eq!(usage.memory, 1 << 30);
+        assert_eq!(usage.peak_memory, 3 << 30);
+
+        // Without elapsed system time the previous CPU usage is kept.
+        usage.record(&stats(2000, 10000, 1 << 30));
+        assert_eq!(usage.cpu_percent, 40.0);
+    }
+
+    #[test]
+    fn summaries() {
+        let usage = ResourceUsage {
+            cpu_percent: 99.6,
+            peak_cpu_percent: 350.2,
+            memory: 1 << 30,
+            peak_memory: 3 << 30,
+            memory_limit: 8 << 30,
+            block_read: 2 << 20,
+            block_write: 0,
+        };
+
+        assert_eq!(
+            usage.summary(),
+            "100% CPU; 1.00GiB / 8.00GiB memory; 2.00MiB read, 0B written"
+        );
+        assert_eq!(
+            usage.peak_summary(),
+            "350% CPU; 3.00GiB / 8.00GiB memory; 2.00MiB read, 0B written"
+        );
+    }
+}
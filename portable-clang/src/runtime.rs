@@ -0,0 +1,252 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Abstraction over container runtimes.
+
+Builds need to build images, run containers from them, and move images in
+and out of the runtime. [ContainerRuntime] describes these operations so
+backends other than the Docker daemon can be plugged in. The Docker
+implementation is [crate::docker::DockerRuntime].
+
+Containers are described with bollard's container `Config`. It is a superset
+of what builds use, so other backends are expected to translate the fields
+they support and reject the rest.
+*/
+
+use {
+    crate::{
+        docker::{ContainerDir, ContainerSettings, ImageSettings, ZstdSettings},
+        tar::TarBuilder,
+    },
+    anyhow::Result,
+    bollard::container::Config as ContainerConfig,
+    futures_util::future::LocalBoxFuture,
+    slog::Logger,
+    std::{io::Read, path::Path},
+};
+
+/// Operations builds need from a container runtime.
+///
+/// Methods return boxed futures so the trait can be used as a trait object.
+/// The CLI runs on a single threaded runtime, so futures needn't be `Send`.
+pub trait ContainerRuntime {
+    /// Build an image from a build context containing a `Dockerfile`.
+    ///
+    /// `name` is the platform qualified image name. Returns the image ID.
+    /// Implementations should skip the build if an identical image exists.
+    fn build_image<'a>(
+        &'a self,
+        logger: &'a Logger,
+        name: &'a str,
+        settings: &'a ImageSettings,
+        context: &'a TarBuilder,
+    ) -> LocalBoxFuture<'a, Result<String>>;
+
+    /// Run a container to completion, making `dirs` available to it.
+    ///
+    /// Output should be logged. A non-zero exit code is an error.
+    fn run<'a>(
+        &'a self,
+        logger: &'a Logger,
+        settings: &'a ContainerSettings,
+        config: ContainerConfig<String>,
+        dirs: &'a [ContainerDir],
+    ) -> LocalBoxFuture<'a, Result<()>>;
+
+    /// Export an image to a zstd compressed tar at `dest_path`.
+    ///
+    /// Returns the uncompressed and compressed sizes.
+    fn export_image<'a>(
+        &'a self,
+        image_id: &'a str,
+        dest_path: &'a Path,
+        zstd: &'a ZstdSettings,
+    ) -> LocalBoxFuture<'a, Result<(u64, u64)>>;
+
+    /// Import an image from a zstd compressed tar, returning its ID.
+    fn import_image<'a>(
+        &'a self,
+        logger: &'a Logger,
+        reader: Box<dyn Read + 'a>,
+    ) -> LocalBoxFuture<'a, Result<String>>;
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::docker::{ClangInputs, ClangStage, ContainerDirKind},
+        anyhow::anyhow,
+        std::{cell::RefCell, io::Cursor},
+    };
+
+    /// Runs containers by writing canned files to their `/out` directory.
+    #[derive(Default)]
+    struct FakeRuntime {
+        /// Commands of containers that were run.
+        commands: RefCell<Vec<Vec<String>>>,
+    }
+
+    impl ContainerRuntime for FakeRuntime {
+        fn build_image<'a>(
+            &'a self,
+            _logger: &'a Logger,
+            name: &'a str,
+            _settings: &'a ImageSettings,
+            context: &'a TarBuilder,
+        ) -> LocalBoxFuture<'a, Result<String>> {
+            Box::pin(async move { Ok(format!("{}-{}", name, context.content_digest()?)) })
+        }
+
+        fn run<'a>(
+            &'a self,
+            _logger: &'a Logger,
+            _settings: &'a ContainerSettings,
+            config: ContainerConfig<String>,
+            dirs: &'a [ContainerDir],
+        ) -> LocalBoxFuture<'a, Result<()>> {
+            Box::pin(async move {
+                self.commands
+                    .borrow_mut()
+                    .push(config.cmd.unwrap_or_default());
+
+                let inputs = dirs
+                    .iter()
+                    .find(|dir| dir.kind == ContainerDirKind::Input)
+                    .ok_or_else(|| anyhow!("no input directory"))?;
+                let out = dirs
+                    .iter()
+                    .find(|dir| dir.container == "/out")
+                    .ok_or_else(|| anyhow!("no output directory"))?;
+
+                let gcc = std::fs::read(inputs.host.join("gcc.tar"))?;
+                let bin_dir = out.host.join("clang-stage1").join("bin");
+                std::fs::create_dir_all(&bin_dir)?;
+                std::fs::write(bin_dir.join("clang"), gcc)?;
+
+                Ok(())
+            })
+        }
+
+        fn export_image<'a>(
+            &'a self,
+            _image_id: &'a str,
+            _dest_path: &'a Path,
+            _zstd: &'a ZstdSettings,
+        ) -> LocalBoxFuture<'a, Result<(u64, u64)>> {
+            Box::pin(async { Err(anyhow!("export not supported")) })
+        }
+
+        fn import_image<'a>(
+            &'a self,
+            _logger: &'a Logger,
+            mut reader: Box<dyn Read + 'a>,
+        ) -> LocalBoxFuture<'a, Result<String>> {
+            Box::pin(async move {
+                let mut data = vec![];
+                reader.read_to_end(&mut data)?;
+
+                Ok(format!("imported-{}", data.len()))
+            })
+        }
+    }
+
+    fn block_on<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("runtime should build")
+            .block_on(future)
+    }
+
+    #[test]
+    fn trait_object() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let runtime: &dyn ContainerRuntime = &FakeRuntime::default();
+
+        let mut context = TarBuilder::default();
+        context.add_dockerfile_data(b"FROM scratch")?;
+        let settings = ImageSettings::default();
+
+        let id = block_on(runtime.build_image(&logger, "clang", &settings, &context))?;
+        assert_eq!(
+            id,
+            block_on(runtime.build_image(&logger, "clang", &settings, &context))?
+        );
+        assert!(id.starts_with("clang-"));
+
+        assert_eq!(
+            block_on(runtime.import_image(&logger, Box::new(Cursor::new(vec![0u8; 4]))))?,
+            "imported-4"
+        );
+        assert!(block_on(runtime.export_image(
+            &id,
+            Path::new("image.tar.zst"),
+            &ZstdSettings::default()
+        ))
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn bootstrap_clang() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let runtime = FakeRuntime::default();
+        let cache_dir = tempfile::tempdir()?;
+
+        let binutils = zstd::encode_all(b"binutils".as_slice(), 3)?;
+        let gcc = zstd::encode_all(b"gcc".as_slice(), 3)?;
+        let inputs = ClangInputs {
+            binutils: &binutils,
+            gcc: &gcc,
+            stage1: None,
+        };
+
+        let tar_zst = block_on(crate::docker::bootstrap_clang(
+            &logger,
+            &runtime,
+            &ContainerSettings::default(),
+            "image",
+            ClangStage::Stage1,
+            &inputs,
+            cache_dir.path(),
+        ))?;
+        assert_eq!(
+            runtime.commands.borrow().as_slice(),
+            &[vec![
+                "/usr/bin/docker-clang-build.sh".to_string(),
+                "stage1".to_string()
+            ]]
+        );
+
+        // Inputs are decompressed and the stage output is archived.
+        let tar = zstd::decode_all(tar_zst.as_slice())?;
+        let mut archive = tar::Archive::new(Cursor::new(tar));
+        let mut clang = None;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if entry.path()?.as_ref() == Path::new("clang-stage1/bin/clang") {
+                let mut data = vec![];
+                entry.read_to_end(&mut data)?;
+                clang = Some(data);
+            }
+        }
+        assert_eq!(clang.as_deref(), Some(b"gcc".as_slice()));
+
+        // Stage 2 requires the stage 1 artifact and fails before running.
+        assert!(block_on(crate::docker::bootstrap_clang(
+            &logger,
+            &runtime,
+            &ContainerSettings::default(),
+            "image",
+            ClangStage::Stage2,
+            &inputs,
+            cache_dir.path(),
+        ))
+        .is_err());
+        assert_eq!(runtime.commands.borrow().len(), 1);
+
+        Ok(())
+    }
+}
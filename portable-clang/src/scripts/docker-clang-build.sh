@@ -2,6 +2,16 @@
 
 set -ex
 
+# stage1 builds Clang with GCC and saves it to /out/clang-stage1.
+# stage2 builds the final Clang to /out/clang using the stage1 Clang from
+# /inputs/clang-stage1.tar.
+STAGE=$1
+
+if [ "${STAGE}" != "stage1" ] && [ "${STAGE}" != "stage2" ]; then
+    echo "usage: $0 stage1|stage2"
+    exit 1
+fi
+
 ROOT=$(pwd)
 
 docker-extract-sccache.sh
@@ -10,50 +20,89 @@ tar -C /toolchains -xf /inputs/binutils.tar
 tar -C /toolchains -xf /inputs/gcc.tar
 
 mkdir /toolchains/cmake
-tar -C /toolchains/cmake --strip-components=1 -xf ${ROOT}/cmake-${CMAKE_LINUX_X86_64_VERSION}-linux-x86_64.tar.gz
+tar -C /toolchains/cmake --strip-components=1 -xf ${ROOT}/cmake-${CMAKE_VERSION}-linux-${HOST_ARCH}.tar.gz
 
 mkdir /toolchains/ninja
-unzip ${ROOT}/ninja-linux.zip
+unzip ${ROOT}/ninja-linux*.zip
 mv ninja /toolchains/bin/
 
-tar -C /toolchains -xf ${ROOT}/${PYTHON_LINUX_X86_64_VERSION}.tar.gz
+tar -C /toolchains -xf ${ROOT}/${PYTHON_VERSION}.tar.gz
 
 export PATH=/toolchains/cmake/bin:/toolchains/bin:/toolchains/python/bin:/toolchains/binutils/bin:/toolchains/gcc/bin:$PATH
 
-mkdir llvm
-pushd llvm
-tar --strip-components=1 -xf ${ROOT}/llvm-${CLANG_VERSION}.src.tar.xz
-popd
+# Libraries LLVM links against are built statically so the toolchain doesn't
+# depend on the builder's libraries.
+DEPS=/toolchains/deps
 
-mkdir llvm/tools/clang
-pushd llvm/tools/clang
-tar --strip-components=1 -xf ${ROOT}/clang-${CLANG_VERSION}.src.tar.xz
+tar -xf ${ROOT}/zlib-${ZLIB_VERSION}.tar.gz
+pushd zlib-${ZLIB_VERSION}
+CC=gcc CFLAGS=-fPIC ./configure --prefix=${DEPS} --static
+make -j ${PARALLEL} install
 popd
 
-mkdir llvm/tools/lld
-pushd llvm/tools/lld
-tar --strip-components=1 -xf ${ROOT}/lld-${CLANG_VERSION}.src.tar.xz
+tar -xf ${ROOT}/zstd-${ZSTD_VERSION}.tar.gz
+pushd zstd-${ZSTD_VERSION}
+CC=gcc CFLAGS=-fPIC make -j ${PARALLEL} -C lib install-static install-includes PREFIX=${DEPS}
 popd
 
-mkdir llvm/projects/compiler-rt
-pushd llvm/projects/compiler-rt
-tar --strip-components=1 -xf ${ROOT}/compiler-rt-${CLANG_VERSION}.src.tar.xz
+tar -xf ${ROOT}/libxml2-${LIBXML2_VERSION}.tar.gz
+pushd libxml2-${LIBXML2_VERSION}
+CC=gcc CFLAGS=-fPIC ./configure --prefix=${DEPS} --enable-static --disable-shared \
+    --without-python --without-zlib --without-lzma --without-icu
+make -j ${PARALLEL} install
 popd
 
-mkdir llvm/projects/libcxx
-pushd llvm/projects/libcxx
-tar --strip-components=1 -xf ${ROOT}/libcxx-${CLANG_VERSION}.src.tar.xz
-popd
-
-mkdir llvm/projects/libcxxabi
-pushd llvm/projects/libcxxabi
-tar --strip-components=1 -xf ${ROOT}/libcxxabi-${CLANG_VERSION}.src.tar.xz
-popd
-
-mkdir libunwind
-pushd libunwind
-tar --strip-components=1 -xf ${ROOT}/libunwind-${CLANG_VERSION}.src.tar.xz
-popd
+DEPS_FLAGS="-DCMAKE_PREFIX_PATH=${DEPS} -DLLVM_ENABLE_ZLIB=FORCE_ON -DLLVM_ENABLE_LIBXML2=FORCE_ON -DLLVM_ENABLE_ZSTD=ON -DLLVM_USE_STATIC_ZSTD=ON"
+
+if ls ${ROOT}/llvm-project-*.src.tar* >/dev/null 2>&1; then
+    # Newer LLVM versions and git commits are built from a monorepo source
+    # tarball. Runtimes are built with the just built Clang.
+    mkdir llvm-project
+    pushd llvm-project
+    tar --strip-components=1 -xf ${ROOT}/llvm-project-*.src.tar*
+    popd
+
+    LLVM_SRC=${ROOT}/llvm-project/llvm
+    PROJECT_FLAGS="-DLLVM_ENABLE_PROJECTS=clang;lld -DLLVM_ENABLE_RUNTIMES=compiler-rt;libcxx;libcxxabi;libunwind"
+else
+    mkdir llvm
+    pushd llvm
+    tar --strip-components=1 -xf ${ROOT}/llvm-${CLANG_VERSION}.src.tar.xz
+    popd
+
+    mkdir llvm/tools/clang
+    pushd llvm/tools/clang
+    tar --strip-components=1 -xf ${ROOT}/clang-${CLANG_VERSION}.src.tar.xz
+    popd
+
+    mkdir llvm/tools/lld
+    pushd llvm/tools/lld
+    tar --strip-components=1 -xf ${ROOT}/lld-${CLANG_VERSION}.src.tar.xz
+    popd
+
+    mkdir llvm/projects/compiler-rt
+    pushd llvm/projects/compiler-rt
+    tar --strip-components=1 -xf ${ROOT}/compiler-rt-${CLANG_VERSION}.src.tar.xz
+    popd
+
+    mkdir llvm/projects/libcxx
+    pushd llvm/projects/libcxx
+    tar --strip-components=1 -xf ${ROOT}/libcxx-${CLANG_VERSION}.src.tar.xz
+    popd
+
+    mkdir llvm/projects/libcxxabi
+    pushd llvm/projects/libcxxabi
+    tar --strip-components=1 -xf ${ROOT}/libcxxabi-${CLANG_VERSION}.src.tar.xz
+    popd
+
+    mkdir libunwind
+    pushd libunwind
+    tar --strip-components=1 -xf ${ROOT}/libunwind-${CLANG_VERSION}.src.tar.xz
+    popd
+
+    LLVM_SRC=${ROOT}/llvm
+    PROJECT_FLAGS=
+fi
 
 mkdir llvm-objdir
 pushd llvm-objdir
@@ -61,41 +110,55 @@ pushd llvm-objdir
 SCCACHE_ERROR_LOG=~/sccache.txt SCCACHE_LOG=info sccache --start-server
 EXTRA_FLAGS="-DCMAKE_C_COMPILER_LAUNCHER=sccache -DCMAKE_CXX_COMPILER_LAUNCHER=sccache"
 
-# Stage 1: Build with GCC.
-mkdir stage1
-pushd stage1
-cmake \
-    -G Ninja \
-    -DCMAKE_BUILD_TYPE=Release \
-    -DCMAKE_INSTALL_PREFIX=/toolchains/clang-stage1 \
-    -DCMAKE_C_COMPILER=gcc \
-    -DCMAKE_CXX_COMPILER=g++ \
-    -DCMAKE_ASM_COMPILER=gcc \
-    -DCMAKE_CXX_FLAGS="-Wno-cast-function-type" \
-    -DCMAKE_EXE_LINKER_FLAGS="-Wl,-Bsymbolic-functions" \
-    -DCMAKE_SHARED_LINKER_FLAGS="-Wl,-Bsymbolic-functions" \
-    -DLLVM_TARGETS_TO_BUILD=X86 \
-    -DLLVM_TOOL_LIBCXX_BUILD=ON \
-    -DLIBCXX_LIBCPPABI_VERSION="" \
-    -DLLVM_BINUTILS_INCDIR=/toolchains/binutils/include \
-    -DLLVM_LINK_LLVM_DYLIB=ON \
-    -DLLVM_INSTALL_UTILS=ON \
-    ${EXTRA_FLAGS} \
-    ../../llvm
-
-LD_LIBRARY_PATH=/toolchains/gcc/lib64 ninja -j ${PARALLEL} install
-
-sccache -s
-sccache -z >/dev/null
-
-mkdir -p /toolchains/clang-stage1/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}
-cp -a /toolchains/gcc/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}/* /toolchains/clang-stage1/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}/
-cp -a /toolchains/gcc/lib64/* /toolchains/clang-stage1/lib/
-mkdir -p /toolchains/clang-stage1/lib32
-cp -a /toolchains/gcc/lib32/* /toolchains/clang-stage1/lib32/
-cp -a /toolchains/gcc/include/* /toolchains/binutils/include/* /toolchains/clang-stage1/include/
-
-popd
+if [ "${STAGE}" = "stage1" ]; then
+    # Stage 1: Build with GCC.
+    mkdir stage1
+    pushd stage1
+    cmake \
+        -G Ninja \
+        -DCMAKE_BUILD_TYPE=Release \
+        -DCMAKE_INSTALL_PREFIX=/toolchains/clang-stage1 \
+        -DCMAKE_C_COMPILER=gcc \
+        -DCMAKE_CXX_COMPILER=g++ \
+        -DCMAKE_ASM_COMPILER=gcc \
+        -DCMAKE_CXX_FLAGS="-Wno-cast-function-type" \
+        -DCMAKE_EXE_LINKER_FLAGS="-Wl,-Bsymbolic-functions" \
+        -DCMAKE_SHARED_LINKER_FLAGS="-Wl,-Bsymbolic-functions" \
+        -DLLVM_TARGETS_TO_BUILD=X86 \
+        -DLLVM_TOOL_LIBCXX_BUILD=ON \
+        -DLIBCXX_LIBCPPABI_VERSION="" \
+        -DLLVM_BINUTILS_INCDIR=/toolchains/binutils/include \
+        -DLLVM_LINK_LLVM_DYLIB=ON \
+        -DLLVM_INSTALL_UTILS=ON \
+        ${EXTRA_FLAGS} \
+        ${PROJECT_FLAGS} \
+        ${DEPS_FLAGS} \
+        ${LLVM_SRC}
+
+    LD_LIBRARY_PATH=/toolchains/gcc/lib64 ninja -j ${PARALLEL} install
+
+    sccache -s
+    sccache -z >/dev/null
+
+    mkdir -p /toolchains/clang-stage1/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}
+    cp -a /toolchains/gcc/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}/* /toolchains/clang-stage1/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}/
+    cp -a /toolchains/gcc/lib64/* /toolchains/clang-stage1/lib/
+    # 32-bit multilib libraries only exist on x86_64.
+    if [ -d /toolchains/gcc/lib32 ]; then
+        mkdir -p /toolchains/clang-stage1/lib32
+        cp -a /toolchains/gcc/lib32/* /toolchains/clang-stage1/lib32/
+    fi
+    cp -a /toolchains/gcc/include/* /toolchains/binutils/include/* /toolchains/clang-stage1/include/
+
+    popd
+
+    sccache --stop-server
+
+    cp -a /toolchains/clang-stage1 /out/
+    exit 0
+fi
+
+tar -C /toolchains -xf /inputs/clang-stage1.tar
 
 # Stage 2: Build with GCC built Clang.
 mkdir stage2
@@ -118,18 +181,22 @@ cmake \
     -DLLVM_LINK_LLVM_DYLIB=ON \
     -DLLVM_INSTALL_UTILS=ON \
     ${EXTRA_FLAGS} \
-    ../../llvm
+    ${PROJECT_FLAGS} \
+    ${DEPS_FLAGS} \
+    ${LLVM_SRC}
 
 LD_LIBRARY_PATH=/toolchains/clang-stage1/lib ninja -j ${PARALLEL} install
 
 sccache -s
 sccache -z >/dev/null
 
-mkdir -p /toolchains/clang-stage2/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}
-cp -a /toolchains/gcc/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}/* /toolchains/clang-stage2/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}/
+mkdir -p /toolchains/clang-stage2/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}
+cp -a /toolchains/gcc/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}/* /toolchains/clang-stage2/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}/
 cp -a /toolchains/gcc/lib64/* /toolchains/clang-stage2/lib/
-mkdir -p /toolchains/clang-stage2/lib32
-cp -a /toolchains/gcc/lib32/* /toolchains/clang-stage2/lib32/
+if [ -d /toolchains/gcc/lib32 ]; then
+    mkdir -p /toolchains/clang-stage2/lib32
+    cp -a /toolchains/gcc/lib32/* /toolchains/clang-stage2/lib32/
+fi
 cp -a /toolchains/gcc/include/* /toolchains/binutils/include/* /toolchains/clang-stage2/include/
 
 popd
@@ -164,17 +231,21 @@ cmake \
     -DLLVM_LINK_LLVM_DYLIB=ON \
     -DLLVM_INSTALL_UTILS=ON \
     ${EXTRA_FLAGS} \
-    ../../llvm
+    ${PROJECT_FLAGS} \
+    ${DEPS_FLAGS} \
+    ${LLVM_SRC}
 
 LD_LIBRARY_PATH=/toolchains/clang-stage2/lib DESTDIR=/out ninja -j ${PARALLEL} install
 
 sccache --stop-server
 
-mkdir -p ${OUT_DIR}/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}
-cp -a /toolchains/gcc/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}/* ${OUT_DIR}/lib/gcc/x86_64-unknown-linux-gnu/${GCC_VERSION}/
+mkdir -p ${OUT_DIR}/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}
+cp -a /toolchains/gcc/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}/* ${OUT_DIR}/lib/gcc/${HOST_TRIPLE}/${GCC_VERSION}/
 cp -a /toolchains/gcc/lib64/* ${OUT_DIR}/lib/
-mkdir -p ${OUT_DIR}/lib32/
-cp -a /toolchains/gcc/lib32/* ${OUT_DIR}/lib32/
+if [ -d /toolchains/gcc/lib32 ]; then
+    mkdir -p ${OUT_DIR}/lib32
+    cp -a /toolchains/gcc/lib32/* ${OUT_DIR}/lib32/
+fi
 cp -a /toolchains/gcc/include/* /toolchains/binutils/include/* ${OUT_DIR}/include/
 
 popd
@@ -2,7 +2,7 @@
 
 set -ex
 
-VERSION_STRING=sccache-v${SCCACHE_LINUX_X86_64_VERSION}-x86_64-unknown-linux-musl
+VERSION_STRING=sccache-v${SCCACHE_VERSION}-${HOST_ARCH}-unknown-linux-musl
 
 mkdir -p /toolchains/bin
 
@@ -39,7 +39,7 @@ pushd binutils-objdir
 
 STAGE_CC_WRAPPER=sccache \
     ../binutils-${BINUTILS_VERSION}/configure \
-    --build=x86_64-unknown-linux-gnu \
+    --build=${HOST_TRIPLE} \
     --prefix=/ \
     --enable-gold \
     --enable-plugins \
@@ -62,7 +62,7 @@ pushd gcc-objdir
 # We don't use GCC for anything other than building llvm/clang. So
 # we can skip the 3 stage bootstrap to save time.
 ../gcc-${GCC_10_3_VERSION}/configure \
-    --build=x86_64-unknown-linux-gnu \
+    --build=${HOST_TRIPLE} \
     --prefix=/ \
     --disable-bootstrap \
     --enable-languages=c,c++ \
@@ -282,7 +282,11 @@ def find_abilist_files(source: pathlib.Path) -> list[pathlib.Path]:
 
 
 def parse_abilist(path: pathlib.Path):
-    """Parse a .abilist file into a data structure."""
+    """Parse a .abilist file into a data structure.
+
+    Symbols can be listed under multiple symbol versions. ``version`` is the
+    last one listed and ``versions`` contains all of them.
+    """
     functions = {}
     data = {}
 
@@ -295,11 +299,17 @@ def parse_abilist(path: pathlib.Path):
             typ = parts[2]
 
             if typ == "F":
-                functions[symbol] = {"version": symver}
+                versions = functions.get(symbol, {}).get("versions", [])
+                functions[symbol] = {
+                    "version": symver,
+                    "versions": versions + [symver],
+                }
             elif typ == "D":
                 address = parts[3]
+                versions = data.get(symbol, {}).get("versions", [])
                 data[symbol] = {
                     "version": symver,
+                    "versions": versions + [symver],
                     "address": address,
                 }
             else:
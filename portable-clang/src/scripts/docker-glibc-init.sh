@@ -13,4 +13,12 @@ cp build-many-glibcs.py /usr/bin/
 popd
 
 su - build -c "build-many-glibcs.py --shallow /build checkout glibc-vcs-2.34"
+
+# Replace the checked out glibc with a pinned commit if one was provided.
+if ls /build/glibc-*.src.tar >/dev/null 2>&1; then
+    su - build -c "rm -rf /build/src/glibc && mkdir /build/src/glibc && tar -C /build/src/glibc --strip-components=1 -xf /build/glibc-*.src.tar"
+    # As build-many-glibcs.py does, prevent builds from regenerating files in the source tree.
+    su - build -c "find /build/src/glibc \( -name configure -o -name preconfigure -o -name '*-kw.h' \) -exec touch {} +"
+fi
+
 su - build -c "build-many-glibcs.py /build host-libraries"
@@ -0,0 +1,370 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Seekable tar.zst archives.
+
+Toolchain artifacts are large, but consumers often only want a single file
+out of them. [encode_tar] compresses a tar archive as independent zstd frames
+followed by a seek table in the
+[zstd seekable format](https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md).
+Before the seek table, a skippable frame holds an index of the tar entries
+by path, recording where the content of each entry is in the uncompressed
+archive.
+
+The result is a regular zstd stream, so existing tools decompress it as
+usual. [SeekableArchive] uses the seek table and entry index to decompress
+only the frames holding the content of a requested file.
+*/
+
+use {
+    crate::{docker::ZstdSettings, progress::ArchiveProgress},
+    anyhow::{anyhow, Context, Result},
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        io::{Read, Seek, SeekFrom},
+        path::{Component, Path, PathBuf},
+    },
+};
+
+/// Uncompressed size of frames.
+const FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/// Magic number of the skippable frame holding the seek table.
+const SEEK_TABLE_MAGIC: u32 = 0x184d2a5e;
+
+/// Magic number of the seek table footer.
+const SEEKABLE_MAGIC: u32 = 0x8f92eab1;
+
+/// Magic number of the skippable frame holding the entry index.
+const INDEX_MAGIC: u32 = 0x184d2a5d;
+
+/// Size of the seek table footer.
+const FOOTER_SIZE: u64 = 9;
+
+/// Maximum number of symlinks followed when reading a file.
+const MAX_SYMLINKS: usize = 40;
+
+/// Location of a tar entry in the uncompressed archive.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct IndexEntry {
+    /// Offset of the entry content.
+    pub offset: u64,
+    /// Size of the entry content.
+    pub size: u64,
+    /// Permission bits.
+    pub mode: u32,
+    /// Target of symlinks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_name: Option<PathBuf>,
+}
+
+/// Index regular files and symlinks of an uncompressed tar archive by path.
+fn index_tar(tar: &[u8]) -> Result<BTreeMap<PathBuf, IndexEntry>> {
+    let mut archive = tar::Archive::new(tar);
+    let mut index = BTreeMap::new();
+
+    for entry in archive.entries().context("reading tar archive")? {
+        let entry = entry?;
+        let entry_type = entry.header().entry_type();
+
+        if !(entry_type.is_file() || entry_type.is_symlink()) {
+            continue;
+        }
+
+        index.insert(
+            entry.path()?.to_path_buf(),
+            IndexEntry {
+                offset: entry.raw_file_position(),
+                size: entry.header().entry_size()?,
+                mode: entry.header().mode()?,
+                link_name: if entry_type.is_symlink() {
+                    entry.link_name()?.map(|x| x.to_path_buf())
+                } else {
+                    None
+                },
+            },
+        );
+    }
+
+    Ok(index)
+}
+
+/// Append a skippable frame to `dest`.
+fn push_skippable_frame(dest: &mut Vec<u8>, magic: u32, data: &[u8]) {
+    dest.extend(magic.to_le_bytes());
+    dest.extend((data.len() as u32).to_le_bytes());
+    dest.extend(data);
+}
+
+fn encode_tar_with_frame_size(
+    tar: &[u8],
+    zstd: &ZstdSettings,
+    frame_size: usize,
+    progress: &dyn ArchiveProgress,
+) -> Result<Vec<u8>> {
+    let index = serde_json::to_vec(&index_tar(tar)?)?;
+    progress.start("compressing", tar.len() as u64);
+
+    let mut res = vec![];
+    let mut seek_table = vec![];
+    let mut frames = 0u32;
+
+    for chunk in tar.chunks(frame_size) {
+        let frame = zstd.encode_all(chunk)?;
+
+        seek_table.extend((frame.len() as u32).to_le_bytes());
+        seek_table.extend((chunk.len() as u32).to_le_bytes());
+        res.extend(frame);
+        frames += 1;
+        progress.advance(chunk.len() as u64, None);
+    }
+    progress.finish();
+
+    push_skippable_frame(&mut res, INDEX_MAGIC, &index);
+
+    seek_table.extend(frames.to_le_bytes());
+    // Descriptor without checksums.
+    seek_table.push(0);
+    seek_table.extend(SEEKABLE_MAGIC.to_le_bytes());
+    push_skippable_frame(&mut res, SEEK_TABLE_MAGIC, &seek_table);
+
+    Ok(res)
+}
+
+/// Compress an uncompressed tar archive to a seekable tar.zst.
+///
+/// `progress` receives the bytes of the tar archive compressed.
+pub fn encode_tar(
+    tar: &[u8],
+    zstd: &ZstdSettings,
+    progress: &dyn ArchiveProgress,
+) -> Result<Vec<u8>> {
+    encode_tar_with_frame_size(tar, zstd, FRAME_SIZE, progress)
+}
+
+/// A frame of a seekable archive.
+struct Frame {
+    /// Offset of the compressed frame in the file.
+    compressed_offset: u64,
+    compressed_size: u64,
+    /// Offset of the frame content in the uncompressed archive.
+    offset: u64,
+    size: u64,
+}
+
+/// Normalize a path in the archive, resolving `.` and `..` lexically.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => res.push(name),
+            Component::ParentDir => {
+                res.pop();
+            }
+            _ => {}
+        }
+    }
+
+    res
+}
+
+/// A seekable tar.zst archive written by [encode_tar].
+pub struct SeekableArchive<R> {
+    reader: R,
+    frames: Vec<Frame>,
+    index: BTreeMap<PathBuf, IndexEntry>,
+}
+
+impl SeekableArchive<std::fs::File> {
+    /// Open a seekable archive file.
+    pub fn open(path: &Path) -> Result<Self> {
+        let fh =
+            std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+
+        Self::new(fh).with_context(|| format!("reading seek table of {}", path.display()))
+    }
+}
+
+impl<R: Read + Seek> SeekableArchive<R> {
+    /// Read the seek table and entry index of an archive.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        if len < FOOTER_SIZE + 8 {
+            return Err(anyhow!("archive too small to be seekable"));
+        }
+
+        let mut footer = [0u8; FOOTER_SIZE as usize];
+        reader.seek(SeekFrom::Start(len - FOOTER_SIZE))?;
+        reader.read_exact(&mut footer)?;
+
+        if u32::from_le_bytes(footer[5..9].try_into()?) != SEEKABLE_MAGIC {
+            return Err(anyhow!("archive has no seek table"));
+        }
+        let checksums = footer[4] & 0x80 != 0;
+        let entry_size = if checksums { 12 } else { 8 };
+        let frame_count = u32::from_le_bytes(footer[0..4].try_into()?) as u64;
+
+        let table_size = frame_count * entry_size;
+        let table_start = (len - FOOTER_SIZE)
+            .checked_sub(table_size)
+            .ok_or_else(|| anyhow!("seek table exceeds archive"))?;
+        let mut table = vec![0u8; table_size as usize];
+        reader.seek(SeekFrom::Start(table_start))?;
+        reader.read_exact(&mut table)?;
+
+        let mut frames = vec![];
+        let (mut compressed_offset, mut offset) = (0, 0);
+
+        for entry in table.chunks(entry_size as usize) {
+            let compressed_size = u32::from_le_bytes(entry[0..4].try_into()?) as u64;
+            let size = u32::from_le_bytes(entry[4..8].try_into()?) as u64;
+
+            frames.push(Frame {
+                compressed_offset,
+                compressed_size,
+                offset,
+                size,
+            });
+            compressed_offset += compressed_size;
+            offset += size;
+        }
+
+        // The entry index directly follows the last frame.
+        let mut header = [0u8; 8];
+        reader.seek(SeekFrom::Start(compressed_offset))?;
+        reader.read_exact(&mut header)?;
+
+        if u32::from_le_bytes(header[0..4].try_into()?) != INDEX_MAGIC {
+            return Err(anyhow!("archive has no entry index"));
+        }
+        let mut index = vec![0u8; u32::from_le_bytes(header[4..8].try_into()?) as usize];
+        reader.read_exact(&mut index)?;
+        let index = serde_json::from_slice(&index).context("parsing entry index")?;
+
+        Ok(Self {
+            reader,
+            frames,
+            index,
+        })
+    }
+
+    /// Entries of the archive, keyed by path.
+    pub fn entries(&self) -> &BTreeMap<PathBuf, IndexEntry> {
+        &self.index
+    }
+
+    /// Read a range of the uncompressed archive.
+    fn read_range(&mut self, offset: u64, size: u64) -> Result<Vec<u8>> {
+        let end = offset + size;
+        let mut res = Vec::with_capacity(size as usize);
+
+        for frame in &self.frames {
+            if frame.offset + frame.size <= offset || frame.offset >= end || frame.size == 0 {
+                continue;
+            }
+
+            let mut compressed = vec![0u8; frame.compressed_size as usize];
+            self.reader.seek(SeekFrom::Start(frame.compressed_offset))?;
+            self.reader.read_exact(&mut compressed)?;
+            let data =
+                zstd::stream::decode_all(compressed.as_slice()).context("decompressing frame")?;
+
+            let start = offset.saturating_sub(frame.offset) as usize;
+            let stop = (end.min(frame.offset + frame.size) - frame.offset) as usize;
+            res.extend_from_slice(&data[start..stop]);
+        }
+
+        if res.len() as u64 != size {
+            return Err(anyhow!("entry exceeds archive content"));
+        }
+
+        Ok(res)
+    }
+
+    /// Read the content of a file, following symlinks within the archive.
+    pub fn read(&mut self, path: &Path) -> Result<Vec<u8>> {
+        let mut path = normalize_path(path);
+
+        for _ in 0..MAX_SYMLINKS {
+            let entry = self
+                .index
+                .get(&path)
+                .ok_or_else(|| anyhow!("{} not found in archive", path.display()))?
+                .clone();
+
+            match entry.link_name {
+                Some(target) => {
+                    path = normalize_path(
+                        &path
+                            .parent()
+                            .map(|parent| parent.join(&target))
+                            .unwrap_or(target),
+                    );
+                }
+                None => return self.read_range(entry.offset, entry.size),
+            }
+        }
+
+        Err(anyhow!("too many levels of symlinks"))
+    }
+}
+
+/// Read a single file from a seekable tar.zst archive file.
+pub fn extract_file(archive: &Path, path: &Path) -> Result<Vec<u8>> {
+    SeekableArchive::open(archive)?
+        .read(path)
+        .with_context(|| format!("extracting {} from {}", path.display(), archive.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, std::io::Cursor};
+
+    #[test]
+    fn seekable() -> Result<()> {
+        let large = (0..10000u32)
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        let mut builder = tar::Builder::new(vec![]);
+        for (path, data) in [
+            ("Dockerfile", b"FROM scratch".as_slice()),
+            ("clang/bin/clang", &large),
+            ("clang/include/stddef.h", b"#pragma once"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as _);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data)?;
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        builder.append_link(&mut header, "clang/bin/cc", "../bin/./clang")?;
+        let tar = builder.into_inner()?;
+
+        let data = encode_tar_with_frame_size(&tar, &ZstdSettings::default(), 4096, &())?;
+        // Regular zstd decoders skip the index and seek table.
+        assert_eq!(zstd::decode_all(data.as_slice())?, tar);
+
+        let mut archive = SeekableArchive::new(Cursor::new(data))?;
+        assert!(archive.frames.len() > 10);
+        assert_eq!(archive.entries().len(), 4);
+        assert_eq!(
+            archive.read(Path::new("clang/include/stddef.h"))?,
+            b"#pragma once"
+        );
+        assert_eq!(archive.read(Path::new("./clang/bin/cc"))?, large);
+        assert!(archive.read(Path::new("clang/bin/missing")).is_err());
+
+        // Plain zstd streams are rejected.
+        let data = ZstdSettings::default().encode_all(&tar)?;
+        assert!(SeekableArchive::new(Cursor::new(data)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Splitting large artifacts into parts.
+
+Release hosting often limits the size of individual files, e.g. GitHub
+release assets must be under 2 GiB. [split_file] cuts an artifact into parts
+of a maximum size and writes a JSON manifest recording the digest of every
+part and of the whole artifact. [join_parts] reassembles the artifact and
+[extract_parts] extracts a tar.zst artifact directly from its parts, both
+verifying content against the manifest.
+*/
+
+use {
+    anyhow::{anyhow, Context, Result},
+    serde::{Deserialize, Serialize},
+    sha2::Digest,
+    std::{
+        io::{Read, Write},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Default maximum size of parts, the GitHub release asset limit.
+pub const DEFAULT_PART_SIZE: u64 = 2 * 1024 * 1024 * 1024;
+
+/// A part of a split artifact.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SplitPart {
+    /// File name of the part, relative to the manifest.
+    pub file: String,
+    pub size: u64,
+    /// Hex SHA-256 digest of the part.
+    pub sha256: String,
+}
+
+/// Describes how an artifact was split into parts.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SplitManifest {
+    /// File name of the original artifact.
+    pub name: String,
+    pub size: u64,
+    /// Hex SHA-256 digest of the original artifact.
+    pub sha256: String,
+    pub parts: Vec<SplitPart>,
+}
+
+/// Parse a size like `2GiB`, `500M`, or `1048576`.
+///
+/// Suffixes are binary multiples.
+pub fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(digits);
+
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| anyhow!("invalid size: {}", value))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        "g" | "gb" | "gib" => 1024 * 1024 * 1024,
+        _ => return Err(anyhow!("invalid size suffix: {}", value)),
+    };
+
+    match number.checked_mul(multiplier) {
+        Some(0) | None => Err(anyhow!("invalid size: {}", value)),
+        Some(size) => Ok(size),
+    }
+}
+
+/// Path of the manifest of an artifact split into `dest_dir`.
+pub fn manifest_path(dest_dir: &Path, name: &str) -> PathBuf {
+    dest_dir.join(format!("{}.parts.json", name))
+}
+
+/// Split a file into parts of at most `part_size` bytes in `dest_dir`.
+///
+/// Parts are named `<name>.partNNN` next to a `<name>.parts.json` manifest.
+pub fn split_file(path: &Path, dest_dir: &Path, part_size: u64) -> Result<SplitManifest> {
+    if part_size == 0 {
+        return Err(anyhow!("part size must be positive"));
+    }
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("could not resolve file name of {}", path.display()))?
+        .to_string_lossy()
+        .to_string();
+    let mut fh =
+        std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+
+    std::fs::create_dir_all(dest_dir)
+        .with_context(|| format!("creating {}", dest_dir.display()))?;
+
+    let mut h = sha2::Sha256::new();
+    let mut parts = vec![];
+    let mut size = 0;
+    let mut buffer = vec![0u8; 1024 * 1024];
+
+    loop {
+        let file = format!("{}.part{:03}", name, parts.len());
+        let part_path = dest_dir.join(&file);
+        let mut part = std::fs::File::create(&part_path)
+            .with_context(|| format!("creating {}", part_path.display()))?;
+        let mut part_h = sha2::Sha256::new();
+        let mut part_size_written = 0;
+
+        while part_size_written < part_size {
+            let max = buffer.len().min((part_size - part_size_written) as usize);
+            let count = fh.read(&mut buffer[..max])?;
+            if count == 0 {
+                break;
+            }
+
+            part.write_all(&buffer[..count])
+                .with_context(|| format!("writing {}", part_path.display()))?;
+            h.update(&buffer[..count]);
+            part_h.update(&buffer[..count]);
+            part_size_written += count as u64;
+        }
+
+        // Empty files still get a single empty part.
+        if part_size_written == 0 && !parts.is_empty() {
+            drop(part);
+            std::fs::remove_file(&part_path)?;
+            break;
+        }
+
+        size += part_size_written;
+        parts.push(SplitPart {
+            file,
+            size: part_size_written,
+            sha256: hex::encode(part_h.finalize().as_slice()),
+        });
+
+        if part_size_written < part_size {
+            break;
+        }
+    }
+
+    let manifest = SplitManifest {
+        name: name.clone(),
+        size,
+        sha256: hex::encode(h.finalize().as_slice()),
+        parts,
+    };
+
+    let path = manifest_path(dest_dir, &name);
+    std::fs::write(&path, serde_json::to_vec_pretty(&manifest)?)
+        .with_context(|| format!("writing {}", path.display()))?;
+
+    Ok(manifest)
+}
+
+/// Read a manifest written by [split_file].
+pub fn read_manifest(path: &Path) -> Result<SplitManifest> {
+    let data = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+
+    serde_json::from_slice(&data).with_context(|| format!("parsing {}", path.display()))
+}
+
+/// A reader over the concatenated parts of a split artifact.
+///
+/// Digests are verified as parts are read to their end.
+struct PartsReader {
+    dir: PathBuf,
+    manifest: SplitManifest,
+    /// Index of the next part to open.
+    next: usize,
+    current: Option<(std::fs::File, sha2::Sha256, u64)>,
+    total: sha2::Sha256,
+}
+
+impl PartsReader {
+    fn new(manifest_path: &Path) -> Result<Self> {
+        let manifest = read_manifest(manifest_path)?;
+
+        for part in &manifest.parts {
+            if Path::new(&part.file).components().count() != 1 {
+                return Err(anyhow!("invalid part file name: {}", part.file));
+            }
+        }
+
+        Ok(Self {
+            dir: manifest_path
+                .parent()
+                .map(|x| x.to_path_buf())
+                .unwrap_or_default(),
+            manifest,
+            next: 0,
+            current: None,
+            total: sha2::Sha256::new(),
+        })
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+impl Read for PartsReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.current.is_none() {
+                let part = match self.manifest.parts.get(self.next) {
+                    Some(part) => part,
+                    None => {
+                        let digest = hex::encode(
+                            std::mem::replace(&mut self.total, sha2::Sha256::new())
+                                .finalize()
+                                .as_slice(),
+                        );
+                        if self.next == self.manifest.parts.len() && digest != self.manifest.sha256
+                        {
+                            return Err(invalid_data(format!(
+                                "{} digest mismatch",
+                                self.manifest.name
+                            )));
+                        }
+                        self.next += 1;
+
+                        return Ok(0);
+                    }
+                };
+
+                let fh = std::fs::File::open(self.dir.join(&part.file))?;
+                self.current = Some((fh, sha2::Sha256::new(), 0));
+            }
+
+            let (fh, h, size) = self.current.as_mut().expect("current part is open");
+            let count = fh.read(buf)?;
+
+            if count > 0 {
+                h.update(&buf[..count]);
+                self.total.update(&buf[..count]);
+                *size += count as u64;
+
+                return Ok(count);
+            }
+
+            let (_, h, size) = self.current.take().expect("current part is open");
+            let part = &self.manifest.parts[self.next];
+            if size != part.size || hex::encode(h.finalize().as_slice()) != part.sha256 {
+                return Err(invalid_data(format!("{} is corrupt", part.file)));
+            }
+            self.next += 1;
+        }
+    }
+}
+
+/// Reassemble an artifact from the parts described by a manifest.
+///
+/// Returns the path of the written artifact in `dest_dir`.
+pub fn join_parts(manifest_path: &Path, dest_dir: &Path) -> Result<PathBuf> {
+    let mut reader = PartsReader::new(manifest_path)?;
+    let dest_path = dest_dir.join(&reader.manifest.name);
+
+    std::fs::create_dir_all(dest_dir)
+        .with_context(|| format!("creating {}", dest_dir.display()))?;
+
+    // Write to a temporary file so a corrupt part leaves no partial artifact.
+    let mut temp = tempfile::NamedTempFile::new_in(dest_dir)?;
+    std::io::copy(&mut reader, &mut temp)
+        .with_context(|| format!("joining parts of {}", reader.manifest.name))?;
+    temp.persist(&dest_path)
+        .with_context(|| format!("writing {}", dest_path.display()))?;
+
+    Ok(dest_path)
+}
+
+/// Extract a tar.zst artifact from its parts without joining them on disk.
+///
+/// Returns the number of extracted entries. See [crate::extract::extract_tar].
+pub fn extract_parts(manifest_path: &Path, dest_dir: &Path) -> Result<usize> {
+    let mut reader = PartsReader::new(manifest_path)?;
+    let stream = zstd::stream::Decoder::new(&mut reader).context("creating zstd decompressor")?;
+
+    let count = crate::extract::extract_tar(stream, dest_dir, |_| true)
+        .with_context(|| format!("extracting parts of {}", manifest_path.display()))?;
+
+    // Read any trailing data, such as a seek table, to verify the whole artifact.
+    std::io::copy(&mut reader, &mut std::io::sink())?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sizes() -> Result<()> {
+        assert_eq!(parse_size("2GiB")?, DEFAULT_PART_SIZE);
+        assert_eq!(parse_size("500M")?, 500 * 1024 * 1024);
+        assert_eq!(parse_size("4096")?, 4096);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("2TB").is_err());
+        assert!(parse_size("GiB").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_join() -> Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let parts_dir = temp_dir.path().join("parts");
+
+        let mut builder = tar::Builder::new(vec![]);
+        let content = (0..5000u32)
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as _);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "clang/lib/data", content.as_slice())?;
+        let tar_zst = zstd::encode_all(builder.into_inner()?.as_slice(), 1)?;
+
+        let path = temp_dir.path().join("clang.tar.zst");
+        std::fs::write(&path, &tar_zst)?;
+
+        let manifest = split_file(&path, &parts_dir, 1024)?;
+        assert_eq!(manifest.parts.len(), tar_zst.len().div_ceil(1024));
+        assert!(manifest.parts.iter().all(|part| part.size <= 1024));
+
+        let manifest_path = manifest_path(&parts_dir, "clang.tar.zst");
+        let joined = join_parts(&manifest_path, &temp_dir.path().join("joined"))?;
+        assert_eq!(std::fs::read(joined)?, tar_zst);
+
+        let extract_dir = temp_dir.path().join("extracted");
+        assert_eq!(extract_parts(&manifest_path, &extract_dir)?, 1);
+        assert_eq!(std::fs::read(extract_dir.join("clang/lib/data"))?, content);
+
+        // Corrupt parts are detected.
+        let part_path = parts_dir.join(&manifest.parts[0].file);
+        let mut data = std::fs::read(&part_path)?;
+        data[100] ^= 0xff;
+        std::fs::write(&part_path, data)?;
+        assert!(join_parts(&manifest_path, &temp_dir.path().join("corrupt")).is_err());
+        assert!(!temp_dir.path().join("corrupt/clang.tar.zst").exists());
+
+        Ok(())
+    }
+}
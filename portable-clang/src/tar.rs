@@ -3,32 +3,224 @@
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
+    crate::progress::ArchiveProgress,
     anyhow::{anyhow, Context, Result},
     hyper::Body,
+    serde::Serialize,
+    sha2::Digest,
     slog::{warn, Logger},
-    std::{io::Cursor, path::Path},
+    std::{
+        collections::BTreeMap,
+        io::{Cursor, Read},
+        path::{Path, PathBuf},
+        str::FromStr,
+    },
     tugger_file_manifest::{is_executable, FileEntry, FileManifest},
 };
 
 #[cfg(target_family = "unix")]
+use std::os::unix::fs::MetadataExt;
+
+/// Modification time of archive entries if `SOURCE_DATE_EPOCH` isn't set.
+const DEFAULT_MTIME: u64 = 1609502400;
+
+/// Resolve the modification time of archive entries from `SOURCE_DATE_EPOCH`.
+fn parse_mtime(source_date_epoch: Option<&str>) -> Result<u64> {
+    match source_date_epoch {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("invalid SOURCE_DATE_EPOCH: {}", value)),
+        None => Ok(DEFAULT_MTIME),
+    }
+}
+
+/// Create a GNU tar header with normalized metadata.
+///
+/// Entries are owned by uid and gid 0 without user or group names and are
+/// modified at `SOURCE_DATE_EPOCH` or a fixed time, so archive content
+/// doesn't depend on who built it or when.
+pub fn deterministic_header() -> Result<tar::Header> {
+    let mut header = tar::Header::new_gnu();
+    header.set_mtime(parse_mtime(
+        std::env::var("SOURCE_DATE_EPOCH").ok().as_deref(),
+    )?);
+    header.set_uid(0);
+    header.set_gid(0);
+
+    Ok(header)
+}
+
+/// Which metadata of source files [tar_from_directory] records.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TarMetadata {
+    /// Modes collapsed to 0644 or 0755 and [deterministic_header] metadata.
+    ///
+    /// Archives are reproducible.
+    #[default]
+    Normalized,
+
+    /// Mode bits other than setuid and setgid, and modification times.
+    Preserve,
+
+    /// Like [Self::Preserve], plus owner and group IDs and names.
+    ///
+    /// Names are resolved from `/etc/passwd` and `/etc/group` of the host.
+    PreserveOwners,
+}
+
+impl FromStr for TarMetadata {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "normalized" => Ok(Self::Normalized),
+            "preserve" => Ok(Self::Preserve),
+            "preserve-owners" => Ok(Self::PreserveOwners),
+            _ => Err(anyhow!("unsupported tar metadata mode: {}", s)),
+        }
+    }
+}
+
+/// Parse names keyed by ID from a file formatted like `/etc/passwd`.
+fn parse_id_names(data: &str) -> BTreeMap<u64, String> {
+    data.lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            let id = fields.nth(1)?.parse().ok()?;
+
+            Some((id, name.to_string()))
+        })
+        .collect()
+}
+
+/// Names of users and groups, keyed by ID.
+#[derive(Default)]
+struct OwnerNames {
+    users: BTreeMap<u64, String>,
+    groups: BTreeMap<u64, String>,
+}
+
+impl OwnerNames {
+    fn load() -> Self {
+        let load = |path: &str| {
+            std::fs::read_to_string(path)
+                .map(|data| parse_id_names(&data))
+                .unwrap_or_default()
+        };
+
+        Self {
+            users: load("/etc/passwd"),
+            groups: load("/etc/group"),
+        }
+    }
+}
+
+/// Create the header of a file as configured by [TarMetadata].
+#[cfg(target_family = "unix")]
+fn file_header(
+    metadata: &std::fs::Metadata,
+    mode: TarMetadata,
+    owners: &OwnerNames,
+) -> Result<tar::Header> {
+    let mut header = deterministic_header()?;
+
+    if mode == TarMetadata::Normalized {
+        header.set_mode(if is_executable(metadata) {
+            0o755
+        } else {
+            0o644
+        });
+        return Ok(header);
+    }
+
+    header.set_mode(metadata.mode() & 0o1777);
+    header.set_mtime(metadata.mtime().max(0) as u64);
+
+    if mode == TarMetadata::PreserveOwners {
+        header.set_uid(metadata.uid() as u64);
+        header.set_gid(metadata.gid() as u64);
+        if let Some(name) = owners.users.get(&(metadata.uid() as u64)) {
+            header.set_username(name).context("setting user name")?;
+        }
+        if let Some(name) = owners.groups.get(&(metadata.gid() as u64)) {
+            header.set_groupname(name).context("setting group name")?;
+        }
+    }
+
+    Ok(header)
+}
+
+/// Create the header of a file as configured by [TarMetadata].
+///
+/// Only modification times can be preserved on this platform.
+#[cfg(not(target_family = "unix"))]
+fn file_header(
+    metadata: &std::fs::Metadata,
+    mode: TarMetadata,
+    _owners: &OwnerNames,
+) -> Result<tar::Header> {
+    let mut header = deterministic_header()?;
+    header.set_mode(if is_executable(metadata) {
+        0o755
+    } else {
+        0o644
+    });
+
+    if mode != TarMetadata::Normalized {
+        let mtime = metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or(0);
+        header.set_mtime(mtime);
+    }
+
+    Ok(header)
+}
 
 /// Obtain contents of a GNU tar archive from a source directory.
+///
+/// Symlinks are recorded as symlinks, not followed. Special files such as
+/// sockets and FIFOs can't be meaningfully archived and are skipped with a
+/// warning. Entries are in path order. With [TarMetadata::Normalized], they
+/// have [deterministic_header] metadata, so the same directory content always
+/// yields the same archive.
+///
+/// `progress` receives the bytes of file content archived.
 pub fn tar_from_directory(
     logger: &Logger,
     path: impl AsRef<Path>,
     path_prefix: Option<&Path>,
+    metadata_mode: TarMetadata,
+    progress: &dyn ArchiveProgress,
 ) -> Result<Vec<u8>> {
     let root_dir = path.as_ref();
     let path_prefix = path_prefix.map(|x| x.to_path_buf());
+    let owners = if metadata_mode == TarMetadata::PreserveOwners {
+        OwnerNames::load()
+    } else {
+        OwnerNames::default()
+    };
 
     let mut builder = tar::Builder::new(vec![]);
 
-    for entry in walkdir::WalkDir::new(root_dir)
+    let mut entries = walkdir::WalkDir::new(root_dir)
         .follow_links(false)
-        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
-    {
-        let entry = entry?;
-
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by(|a, b| a.path().cmp(b.path()));
+
+    let total = entries
+        .iter()
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+    progress.start("archiving", total);
+
+    for entry in entries {
         let archive_path = entry.path().strip_prefix(root_dir)?;
 
         let archive_path = if let Some(prefix) = &path_prefix {
@@ -43,43 +235,182 @@ pub fn tar_from_directory(
             continue;
         }
 
-        warn!(logger, "adding {} to tar archive", archive_path.display());
-
-        let mut header = tar::Header::new_gnu();
-
-        header.set_mode(if is_executable(&metadata) {
-            0o755
-        } else {
-            0o644
-        });
+        if !metadata.is_file() && !metadata.file_type().is_symlink() {
+            warn!(
+                logger,
+                "not adding {} to tar archive: not a regular file or symlink",
+                archive_path.display()
+            );
+            continue;
+        }
 
-        header.set_mtime(1609502400);
-        header.set_uid(0);
-        header.set_gid(0);
+        let mut header = file_header(&metadata, metadata_mode, &owners)?;
 
-        let data = if metadata.file_type().is_symlink() {
+        if metadata.file_type().is_symlink() {
             let link_name = std::fs::read_link(entry.path()).context("reading link")?;
-            header
-                .set_link_name(&link_name)
-                .context("setting link name")?;
             header.set_entry_type(tar::EntryType::Symlink);
-
-            vec![]
+            header.set_size(0);
+            // Unlike set_link_name(), this handles targets too long for the header.
+            builder
+                .append_link(&mut header, &archive_path, &link_name)
+                .context("adding symlink")?;
+            progress.advance(0, Some(&archive_path));
         } else {
             header.set_entry_type(tar::EntryType::Regular);
 
-            std::fs::read(entry.path())?
-        };
-
-        header.set_size(data.len() as _);
-        builder.append_data(&mut header, archive_path, Cursor::new(data))?;
+            let data = std::fs::read(entry.path())?;
+            header.set_size(data.len() as _);
+            builder.append_data(&mut header, &archive_path, Cursor::new(&data))?;
+            progress.advance(data.len() as u64, Some(&archive_path));
+        }
     }
 
     builder.finish()?;
+    progress.finish();
 
     Ok(builder.into_inner()?)
 }
 
+/// The compared properties of an archive entry.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct TarEntrySummary {
+    /// Entry type, such as `Regular` or `Symlink`.
+    pub entry_type: String,
+    /// Permission bits.
+    pub mode: u32,
+    /// Size of the content in bytes.
+    pub size: u64,
+    /// Hex SHA-256 digest of the content.
+    pub sha256: String,
+    /// Target of symlinks and hard links.
+    pub link_name: Option<PathBuf>,
+}
+
+/// A difference between two archives found by [diff_tar].
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TarDifference {
+    /// An entry only present in the first archive.
+    Removed {
+        path: PathBuf,
+        entry: TarEntrySummary,
+    },
+    /// An entry only present in the second archive.
+    Added {
+        path: PathBuf,
+        entry: TarEntrySummary,
+    },
+    /// An entry whose properties differ between archives.
+    Changed {
+        path: PathBuf,
+        old: TarEntrySummary,
+        new: TarEntrySummary,
+    },
+}
+
+impl TarDifference {
+    /// The path of the differing entry.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Removed { path, .. } | Self::Added { path, .. } | Self::Changed { path, .. } => {
+                path
+            }
+        }
+    }
+}
+
+/// Summarize the entries of a tar archive, keyed by path.
+///
+/// Later entries replace earlier entries having the same path, as they do
+/// on extraction.
+pub fn summarize_tar(reader: impl Read) -> Result<BTreeMap<PathBuf, TarEntrySummary>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut res = BTreeMap::new();
+
+    for entry in archive.entries().context("reading tar archive")? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let header = entry.header().clone();
+        let link_name = entry.link_name()?.map(|x| x.to_path_buf());
+
+        let mut h = sha2::Sha256::new();
+        let size = std::io::copy(&mut entry, &mut h)
+            .with_context(|| format!("reading {}", path.display()))?;
+
+        res.insert(
+            path,
+            TarEntrySummary {
+                entry_type: format!("{:?}", header.entry_type()),
+                mode: header.mode()?,
+                size,
+                sha256: hex::encode(h.finalize().as_slice()),
+                link_name,
+            },
+        );
+    }
+
+    Ok(res)
+}
+
+/// Compare two tar archives entry by entry.
+///
+/// Differences are in path order. Metadata not affecting extracted content,
+/// such as modification times and owners, is ignored.
+pub fn diff_tar(old: impl Read, new: impl Read) -> Result<Vec<TarDifference>> {
+    let mut old = summarize_tar(old).context("summarizing first archive")?;
+    let new = summarize_tar(new).context("summarizing second archive")?;
+    let mut res = vec![];
+
+    for (path, new_entry) in new {
+        match old.remove(&path) {
+            Some(old_entry) if old_entry == new_entry => {}
+            Some(old_entry) => res.push(TarDifference::Changed {
+                path,
+                old: old_entry,
+                new: new_entry,
+            }),
+            None => res.push(TarDifference::Added {
+                path,
+                entry: new_entry,
+            }),
+        }
+    }
+
+    res.extend(
+        old.into_iter()
+            .map(|(path, entry)| TarDifference::Removed { path, entry }),
+    );
+    res.sort_by(|a, b| a.path().cmp(b.path()));
+
+    Ok(res)
+}
+
+/// Open a tar archive, decompressing it if it is zstd compressed.
+fn open_tar(path: &Path) -> Result<Box<dyn Read>> {
+    let mut fh =
+        std::fs::File::open(path).with_context(|| format!("opening {}", path.display()))?;
+
+    let mut magic = [0u8; 4];
+    let n = fh.read(&mut magic)?;
+    let prefix = Cursor::new(magic[..n].to_vec());
+
+    if magic[..n] == [0x28, 0xb5, 0x2f, 0xfd] {
+        Ok(Box::new(
+            zstd::stream::Decoder::new(prefix.chain(fh)).context("creating zstd decompressor")?,
+        ))
+    } else {
+        Ok(Box::new(prefix.chain(fh)))
+    }
+}
+
+/// Compare two tar or tar.zst archive files.
+///
+/// See [diff_tar].
+pub fn diff_tar_files(old: &Path, new: &Path) -> Result<Vec<TarDifference>> {
+    diff_tar(open_tar(old)?, open_tar(new)?)
+        .with_context(|| format!("comparing {} and {}", old.display(), new.display()))
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct TarBuilder {
     pub(crate) files: FileManifest,
@@ -127,17 +458,39 @@ impl TarBuilder {
             .context("adding support file to tar archive")
     }
 
+    /// Compute a SHA-256 digest of the content of this archive.
+    ///
+    /// The digest covers the path, executable bit, and content of every file.
+    /// It is stable across runs since entries are iterated in sorted order.
+    pub fn content_digest(&self) -> Result<String> {
+        let mut h = sha2::Sha256::new();
+
+        for (path, entry) in self.files.iter_entries() {
+            let data = entry.resolve_content()?;
+
+            h.update(path.to_string_lossy().as_bytes());
+            h.update([0, entry.is_executable() as u8]);
+            h.update((data.len() as u64).to_le_bytes());
+            h.update(&data);
+        }
+
+        Ok(hex::encode(h.finalize().as_slice()))
+    }
+
     /// Obtain an uncompressed tarball of content.
+    ///
+    /// Entries are in path order with [deterministic_header] metadata.
     pub fn as_vec(&self) -> Result<Vec<u8>> {
         let mut builder = tar::Builder::new(vec![]);
 
         for (path, entry) in self.files.iter_entries() {
             let data = entry.resolve_content()?;
-            let mut header = tar::Header::new_gnu();
+            let mut header = deterministic_header()?;
+            header.set_entry_type(tar::EntryType::Regular);
             header.set_mode(if entry.is_executable() { 0o755 } else { 0o644 });
             header.set_size(data.len() as _);
 
-            builder.append_data(&mut header, &path, Cursor::new(data))?;
+            builder.append_data(&mut header, path, Cursor::new(data))?;
         }
 
         builder.finish()?;
@@ -150,3 +503,194 @@ impl TarBuilder {
         Ok(Body::from(self.as_vec()?))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mtime() -> Result<()> {
+        assert_eq!(parse_mtime(None)?, DEFAULT_MTIME);
+        assert_eq!(parse_mtime(Some("1700000000\n"))?, 1700000000);
+        assert!(parse_mtime(Some("yesterday")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_directory() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+
+        std::fs::create_dir_all(root.join("a/b"))?;
+        std::fs::write(root.join("a/b/c"), "c")?;
+        std::fs::write(root.join("a-b"), "a-b")?;
+        std::fs::write(root.join("a.h"), "a.h")?;
+
+        let data = tar_from_directory(&logger, root, None, TarMetadata::Normalized, &())?;
+
+        let mut archive = tar::Archive::new(Cursor::new(&data));
+        let paths = archive
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(paths, vec!["a/b/c", "a-b", "a.h"]);
+
+        // Modification times and ownership don't leak into the archive.
+        std::fs::write(root.join("a.h"), "a.h")?;
+        assert_eq!(
+            tar_from_directory(&logger, root, None, TarMetadata::Normalized, &())?,
+            data
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn diff() -> Result<()> {
+        let archive = |files: &[(&str, &str, bool)]| -> Result<Vec<u8>> {
+            let mut manifest = FileManifest::default();
+            for (path, content, executable) in files {
+                manifest.add_file_entry(
+                    path,
+                    FileEntry::new_from_data(content.as_bytes(), *executable),
+                )?;
+            }
+
+            TarBuilder::from(manifest).as_vec()
+        };
+
+        let old = archive(&[
+            ("bin/clang", "clang", true),
+            ("lib/a.so", "a", false),
+            ("lib/b.so", "b", false),
+            ("share/doc", "doc", false),
+        ])?;
+        let new = archive(&[
+            ("bin/clang", "clang", false),
+            ("lib/a.so", "a2", false),
+            ("lib/b.so", "b", false),
+            ("lib/c.so", "c", false),
+        ])?;
+
+        assert!(diff_tar(old.as_slice(), old.as_slice())?.is_empty());
+
+        let diff = diff_tar(old.as_slice(), new.as_slice())?;
+        assert_eq!(
+            diff.iter()
+                .map(|x| match x {
+                    TarDifference::Removed { path, .. } => format!("-{}", path.display()),
+                    TarDifference::Added { path, .. } => format!("+{}", path.display()),
+                    TarDifference::Changed { path, .. } => format!("~{}", path.display()),
+                })
+                .collect::<Vec<_>>(),
+            vec!["~bin/clang", "~lib/a.so", "+lib/c.so", "-share/doc"]
+        );
+
+        if let TarDifference::Changed { old, new, .. } = &diff[0] {
+            assert_eq!((old.mode, new.mode), (0o755, 0o644));
+            assert_eq!(old.sha256, new.sha256);
+        }
+
+        // Compressed and uncompressed archives compare equal.
+        let temp_dir = tempfile::tempdir()?;
+        let old_path = temp_dir.path().join("old.tar");
+        let new_path = temp_dir.path().join("new.tar.zst");
+        std::fs::write(&old_path, &old)?;
+        std::fs::write(&new_path, zstd::encode_all(old.as_slice(), 3)?)?;
+        assert!(diff_tar_files(&old_path, &new_path)?.is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn preserve_metadata() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+
+        let path = root.join("tool");
+        std::fs::write(&path, "tool")?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o4750))?;
+        let metadata = std::fs::metadata(&path)?;
+
+        let data = tar_from_directory(&logger, root, None, TarMetadata::Preserve, &())?;
+        let mut archive = tar::Archive::new(Cursor::new(data));
+        let entry = archive.entries()?.next().expect("archive has an entry")?;
+        assert_eq!(entry.header().mode()?, 0o750);
+        assert_eq!(entry.header().mtime()?, metadata.mtime() as u64);
+        assert_eq!(entry.header().uid()?, 0);
+
+        let data = tar_from_directory(&logger, root, None, TarMetadata::PreserveOwners, &())?;
+        let mut archive = tar::Archive::new(Cursor::new(data));
+        let entry = archive.entries()?.next().expect("archive has an entry")?;
+        assert_eq!(entry.header().uid()?, metadata.uid() as u64);
+
+        assert_eq!(
+            parse_id_names(
+                "root:x:0:0:root:/root:/bin/bash\nbuild:x:1000:1000::/home/build:/bin/sh\n"
+            ),
+            BTreeMap::from([(0, "root".to_string()), (1000, "build".to_string())])
+        );
+
+        Ok(())
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn directory_special_files() -> Result<()> {
+        let logger = slog::Logger::root(slog::Discard, slog::o!());
+        let temp_dir = tempfile::tempdir()?;
+        let root = temp_dir.path();
+
+        std::fs::write(root.join("libc.so.6"), "\x7fELF")?;
+        std::os::unix::fs::symlink("libc.so.6", root.join("libc.so"))?;
+        let long_target = "x".repeat(200);
+        std::os::unix::fs::symlink(&long_target, root.join("long"))?;
+        let _socket = std::os::unix::net::UnixListener::bind(root.join("socket"))?;
+
+        let data = tar_from_directory(
+            &logger,
+            root,
+            Some(Path::new("lib")),
+            TarMetadata::Normalized,
+            &(),
+        )?;
+
+        let mut archive = tar::Archive::new(Cursor::new(data));
+        let entries = archive
+            .entries()?
+            .map(|entry| {
+                let entry = entry?;
+                Ok((
+                    entry.path()?.to_string_lossy().to_string(),
+                    entry.header().entry_type(),
+                    entry.link_name()?.map(|x| x.to_string_lossy().to_string()),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(
+            entries,
+            vec![
+                (
+                    "lib/libc.so".to_string(),
+                    tar::EntryType::Symlink,
+                    Some("libc.so.6".to_string())
+                ),
+                ("lib/libc.so.6".to_string(), tar::EntryType::Regular, None),
+                (
+                    "lib/long".to_string(),
+                    tar::EntryType::Symlink,
+                    Some(long_target)
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Discovery of newer upstream versions of downloads.
+
+Bumping a download means finding the new release, deriving its URLs, and
+hashing it. This automates that for records hosted on GitHub releases and
+the GNU FTP server. Other records are left alone.
+
+Hashes are computed from whatever the upstream server returns, so the
+resulting manifest must be reviewed (ideally against signatures) before
+it is committed.
+*/
+
+use {
+    crate::downloads::{reader_blake3, reader_sha256, DownloadSettings, SoftwareRecord},
+    anyhow::{anyhow, Context, Result},
+    reqwest::blocking::Client,
+    serde::Deserialize,
+    slog::{info, warn, Logger},
+    std::{
+        cmp::Ordering,
+        collections::BTreeMap,
+        io::{Seek, SeekFrom},
+    },
+};
+
+/// Where newer versions of a record can be discovered.
+#[derive(Clone, Debug, PartialEq)]
+enum Upstream {
+    /// Releases of a GitHub `owner/repo`.
+    ///
+    /// `tag` is the release tag of the current version.
+    GitHub { repo: String, tag: String },
+    /// A directory listing at `url`.
+    ///
+    /// Entries for each version match `pattern` with `{}` standing in for the
+    /// version.
+    Listing { url: String, pattern: String },
+}
+
+/// The subset of the GitHub release API response we use.
+#[derive(Deserialize)]
+struct GitHubRelease {
+    tag_name: String,
+}
+
+/// Determine where newer versions of a record can be discovered.
+fn upstream(record: &SoftwareRecord) -> Option<Upstream> {
+    if let Some(path) = record.url.strip_prefix("https://github.com/") {
+        if let [owner, repo, "releases", "download", tag, _] =
+            path.split('/').collect::<Vec<_>>().as_slice()
+        {
+            return Some(Upstream::GitHub {
+                repo: format!("{}/{}", owner, repo),
+                tag: tag.to_string(),
+            });
+        }
+    }
+
+    if let Some(path) = record.url.strip_prefix("https://ftp.gnu.org/gnu/") {
+        let (package, path) = path.split_once('/')?;
+        // Either the file itself or a per-version directory containing it.
+        let entry = path.split('/').next()?;
+
+        if entry.contains(&record.version) {
+            return Some(Upstream::Listing {
+                url: format!("https://ftp.gnu.org/gnu/{}/", package),
+                pattern: entry.replace(&record.version, "{}"),
+            });
+        }
+    }
+
+    None
+}
+
+/// Compare dotted version strings numerically.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parse = |v: &str| {
+        v.split('.')
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+
+    parse(a).cmp(&parse(b))
+}
+
+fn is_version(s: &str) -> bool {
+    !s.is_empty()
+        && s.starts_with(|c: char| c.is_ascii_digit())
+        && s.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+/// Find the newest version in an HTML directory listing.
+fn listing_latest_version(html: &str, pattern: &str) -> Option<String> {
+    let (prefix, suffix) = pattern.split_once("{}")?;
+
+    html.split("href=\"")
+        .skip(1)
+        .filter_map(|s| s.split('"').next())
+        .map(|href| href.trim_end_matches('/'))
+        .filter_map(|entry| entry.strip_prefix(prefix)?.strip_suffix(suffix))
+        .filter(|version| is_version(version))
+        .max_by(|a, b| compare_versions(a, b))
+        .map(|version| version.to_string())
+}
+
+/// Derive the version of a GitHub release tag.
+///
+/// The tag is assumed to decorate versions the same way `current_tag` decorates
+/// `current_version` (e.g. `llvmorg-13.0.0`).
+fn tag_version(current_tag: &str, current_version: &str, tag: &str) -> Option<String> {
+    let (prefix, suffix) = current_tag.split_once(current_version)?;
+
+    tag.strip_prefix(prefix)?
+        .strip_suffix(suffix)
+        .filter(|version| is_version(version))
+        .map(|version| version.to_string())
+}
+
+/// Find the latest upstream version of a record.
+fn latest_version(client: &Client, record: &SoftwareRecord) -> Result<Option<String>> {
+    match upstream(record) {
+        Some(Upstream::GitHub { repo, tag }) => {
+            let mut request = client.get(format!(
+                "https://api.github.com/repos/{}/releases/latest",
+                repo
+            ));
+            // Unauthenticated requests are heavily rate limited.
+            if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+                request = request.bearer_auth(token);
+            }
+
+            let res = request
+                .send()
+                .and_then(|res| res.error_for_status())
+                .with_context(|| format!("querying latest release of {}", repo))?;
+            let release = serde_json::from_reader::<_, GitHubRelease>(res)
+                .with_context(|| format!("parsing latest release of {}", repo))?;
+
+            Ok(tag_version(&tag, &record.version, &release.tag_name))
+        }
+        Some(Upstream::Listing { url, pattern }) => {
+            let html = client
+                .get(&url)
+                .send()
+                .and_then(|res| res.error_for_status())
+                .and_then(|res| res.text())
+                .with_context(|| format!("fetching {}", url))?;
+
+            Ok(listing_latest_version(&html, &pattern))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Derive a record for `version` from a record of an older version.
+///
+/// Digests are not updated.
+fn bump_record(record: &SoftwareRecord, version: &str) -> SoftwareRecord {
+    let bump = |url: &String| url.replace(&record.version, version);
+
+    SoftwareRecord {
+        name: record.name.clone(),
+        version: version.to_string(),
+        url: bump(&record.url),
+        mirrors: record.mirrors.iter().map(bump).collect(),
+        signature: record.signature.as_ref().map(bump),
+        sha256: record.sha256.clone(),
+        blake3: record.blake3.clone(),
+    }
+}
+
+/// Update a record to its latest upstream version.
+///
+/// Returns `None` if the record is current or its upstream is unknown.
+fn update_record(
+    logger: &Logger,
+    client: &Client,
+    record: &SoftwareRecord,
+) -> Result<Option<SoftwareRecord>> {
+    let version = match latest_version(client, record)? {
+        Some(version) => version,
+        None => {
+            info!(
+                logger,
+                "{}: unable to determine upstream version", record.name
+            );
+            return Ok(None);
+        }
+    };
+
+    if compare_versions(&version, &record.version) != Ordering::Greater {
+        info!(logger, "{}: {} is current", record.name, record.version);
+        return Ok(None);
+    }
+
+    let mut bumped = bump_record(record, &version);
+
+    warn!(
+        logger,
+        "{}: {} -> {}; downloading {}", record.name, record.version, version, bumped.url
+    );
+    let mut res = client
+        .get(&bumped.url)
+        .send()
+        .and_then(|res| res.error_for_status())
+        .with_context(|| format!("downloading {}", bumped.url))?;
+
+    let mut fh = tempfile::tempfile().context("creating temporary file")?;
+    res.copy_to(&mut fh)
+        .with_context(|| format!("downloading {}", bumped.url))?;
+
+    fh.seek(SeekFrom::Start(0))?;
+    bumped.sha256 = reader_sha256(&fh)?;
+    // Only records already using BLAKE3 get it.
+    if bumped.blake3.is_some() {
+        fh.seek(SeekFrom::Start(0))?;
+        bumped.blake3 = Some(reader_blake3(&fh)?);
+    }
+
+    Ok(Some(bumped))
+}
+
+/// Check records for newer upstream versions.
+///
+/// Returns the manifest with updated records substituted. Failures to
+/// update individual records are logged and leave them unchanged.
+pub fn update_records<'a>(
+    logger: &Logger,
+    settings: &DownloadSettings,
+    records: impl Iterator<Item = &'a SoftwareRecord>,
+) -> Result<BTreeMap<String, SoftwareRecord>> {
+    let records = records.cloned().collect::<Vec<_>>();
+
+    // The blocking client can't live on the async runtime's thread.
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                let client = settings.client()?;
+
+                Ok(records
+                    .iter()
+                    .map(|record| {
+                        let updated = update_record(logger, &client, record).unwrap_or_else(|e| {
+                            warn!(logger, "{}: {:?}", record.name, e);
+                            None
+                        });
+
+                        (
+                            record.name.clone(),
+                            updated.unwrap_or_else(|| record.clone()),
+                        )
+                    })
+                    .collect())
+            })
+            .join()
+            .map_err(|_| anyhow!("update thread panicked"))?
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(version: &str, url: &str) -> SoftwareRecord {
+        SoftwareRecord {
+            name: "test".to_string(),
+            version: version.to_string(),
+            url: url.to_string(),
+            mirrors: vec![],
+            signature: None,
+            sha256: "0".repeat(64),
+            blake3: None,
+        }
+    }
+
+    #[test]
+    fn detect_upstream() {
+        assert_eq!(
+            upstream(&record(
+                "13.0.0",
+                "https://github.com/llvm/llvm-project/releases/download/llvmorg-13.0.0/llvm-project-13.0.0.src.tar.xz"
+            )),
+            Some(Upstream::GitHub {
+                repo: "llvm/llvm-project".to_string(),
+                tag: "llvmorg-13.0.0".to_string(),
+            })
+        );
+        assert_eq!(
+            upstream(&record(
+                "10.3.0",
+                "https://ftp.gnu.org/gnu/gcc/gcc-10.3.0/gcc-10.3.0.tar.xz"
+            )),
+            Some(Upstream::Listing {
+                url: "https://ftp.gnu.org/gnu/gcc/".to_string(),
+                pattern: "gcc-{}".to_string(),
+            })
+        );
+        assert_eq!(
+            upstream(&record(
+                "1.2.11",
+                "https://zlib.net/fossils/zlib-1.2.11.tar.gz"
+            )),
+            None
+        );
+    }
+
+    #[test]
+    fn latest_versions() {
+        let html = r#"<a href="gmp-6.2.0.tar.xz">x</a> <a href="gmp-6.2.1.tar.xz">x</a>
+            <a href="gmp-6.10.0.tar.xz.sig">x</a> <a href="gmp-6.3.0-rc1.tar.xz">x</a>"#;
+        assert_eq!(
+            listing_latest_version(html, "gmp-{}.tar.xz"),
+            Some("6.2.1".to_string())
+        );
+
+        assert_eq!(
+            tag_version("llvmorg-13.0.0", "13.0.0", "llvmorg-14.0.6"),
+            Some("14.0.6".to_string())
+        );
+        assert_eq!(tag_version("20211017", "3.9.7", "20220101"), None);
+    }
+}